@@ -80,9 +80,14 @@ impl NativeAudioData {
             .chunks_mut(h_ochannels)
             .zip(local.chunks(self.dsp_ochannels))
         {
-            let _rc = self
+            let rc = self
                 .vmdata
                 .run_dsp(Time(self.count.load(Ordering::Relaxed)));
+            if rc < 0 {
+                if let Some(e) = self.vmdata.vm.take_runtime_error() {
+                    log::error!("{e}");
+                }
+            }
             let res =
                 vm::Machine::get_as_array::<f64>(self.vmdata.vm.get_top_n(self.dsp_ochannels));
             self.count.fetch_add(1, Ordering::Relaxed);
@@ -4,7 +4,10 @@ use std::{
     path::Path,
 };
 
-use mimium_lang::{runtime::vm, ExecContext};
+use mimium_lang::{
+    runtime::{self, vm},
+    ExecContext,
+};
 
 use crate::driver::Driver;
 
@@ -95,6 +98,10 @@ impl Driver for CsvDriver {
     fn is_playing(&self) -> bool {
         self.driver.is_playing()
     }
+
+    fn take_runtime_error(&mut self) -> Option<runtime::Error> {
+        self.driver.take_runtime_error()
+    }
 }
 
 pub fn csv_driver<P: AsRef<Path>>(times: usize, path: P) -> Box<dyn Driver<Sample = f64>> {
@@ -5,7 +5,7 @@ use std::sync::{
 
 use mimium_lang::{
     interner::ToSymbol,
-    runtime::{vm, Time},
+    runtime::{self, vm, Time},
     ExecContext,
 };
 
@@ -119,6 +119,10 @@ impl Driver for LocalBufferDriver {
     fn is_playing(&self) -> bool {
         false
     }
+
+    fn take_runtime_error(&mut self) -> Option<runtime::Error> {
+        self.vmdata.as_mut()?.vm.take_runtime_error()
+    }
 }
 
 pub fn local_buffer_driver(times: usize) -> Box<dyn Driver<Sample = f64>> {
@@ -1,11 +1,14 @@
+#[cfg(feature = "jit")]
+use mimium_lang::log;
 use mimium_lang::{
     interner::ToSymbol,
     plugin::{DynSystemPlugin, InstantPlugin},
     runtime::{
+        self,
         vm::{self, ExtClsInfo, FuncProto, ReturnCode},
         Time,
     },
-    utils::error::ReportableError,
+    utils::{error::ReportableError, metadata::Span},
     ExecContext,
 };
 use num_traits::Float;
@@ -39,19 +42,21 @@ pub trait Component {
 pub struct SampleRate(pub u32);
 #[derive(Debug)]
 pub enum Error {
-    Unknown,
+    Unknown(Span),
 }
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Error::Unknown => write!(f, "unknown runtime error"),
+            Error::Unknown(_) => write!(f, "unknown runtime error"),
         }
     }
 }
 impl std::error::Error for Error {}
 impl ReportableError for Error {
-    fn get_span(&self) -> std::ops::Range<usize> {
-        0..0 //todo!
+    fn get_span(&self) -> Span {
+        match self {
+            Error::Unknown(span) => span.clone(),
+        }
     }
 }
 
@@ -68,6 +73,11 @@ pub trait Driver {
     fn get_samplerate(&self) -> SampleRate;
     fn get_current_sample(&self) -> Time;
     fn is_playing(&self) -> bool;
+    /// Takes the error which stopped the program while playing, to report it after [`Self::play`]
+    /// returned. The drivers playing in the realtime threads log the errors instead.
+    fn take_runtime_error(&mut self) -> Option<runtime::Error> {
+        None
+    }
     fn get_as_plugin(&self) -> InstantPlugin {
         InstantPlugin {
             extfns: vec![],
@@ -83,6 +93,8 @@ pub struct RuntimeData {
 }
 impl RuntimeData {
     pub fn new(vm: vm::Machine, sys_plugins: Vec<DynSystemPlugin>) -> Self {
+        #[cfg(feature = "jit")]
+        let vm = enable_jit(vm);
         //todo:error handling
         let dsp_i = vm.prog.get_fun_index(&"dsp".to_symbol()).unwrap_or(0);
         Self {
@@ -121,6 +133,19 @@ impl RuntimeData {
     }
 }
 
+/// Compiles the functions of the VM to the native code. The functions which cannot be compiled
+/// are still interpreted.
+#[cfg(feature = "jit")]
+fn enable_jit(mut vm: vm::Machine) -> vm::Machine {
+    match vm.enable_jit() {
+        Ok(errs) => errs.iter().for_each(|(name, e)| {
+            log::info!("function {name} is interpreted: {e}");
+        }),
+        Err(e) => log::warn!("{e}"),
+    }
+    vm
+}
+
 pub fn load_default_runtime() -> Box<dyn Driver<Sample = f64>> {
     crate::backends::cpal::native_driver(4096 * 2)
 }
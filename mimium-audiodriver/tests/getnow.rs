@@ -47,7 +47,7 @@ fn getnow_test() {
     let mut driver = LocalBufferDriver::new(times);
     let p: Box<dyn Plugin> = Box::new(driver.get_as_plugin());
     let mut ctx = ExecContext::new([p].into_iter(), None);
-    ctx.prepare_machine_with_bytecode(prog);
+    ctx.prepare_machine_with_bytecode(prog).unwrap();
     driver.init(ctx, Some(SampleRate(48000)));
     driver.play();
 
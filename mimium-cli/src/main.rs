@@ -4,25 +4,37 @@ use std::path::{Path, PathBuf};
 // pub mod wcalculus;
 use clap::{Parser, ValueEnum};
 use mimium_audiodriver::backends::csv::{csv_driver, csv_driver_stdout};
-use mimium_audiodriver::driver::{load_default_runtime, SampleRate};
+use mimium_audiodriver::driver::{load_default_runtime, Driver, SampleRate};
+use mimium_lang::compiler::docgen::{render_html, render_markdown};
 use mimium_lang::compiler::emit_ast;
+use mimium_lang::compiler::parser::format_source;
 use mimium_lang::interner::{ExprNodeId, Symbol, ToSymbol};
 use mimium_lang::log;
+use mimium_lang::mir::optimize::OptLevel;
 use mimium_lang::plugin::Plugin;
-use mimium_lang::utils::error::ReportableError;
+use mimium_lang::runtime::vm::Program;
+use mimium_lang::utils::error::{ReportableError, ReportableErrorDyn};
+use mimium_lang::utils::metadata::Span;
 use mimium_lang::utils::miniprint::MiniPrint;
-use mimium_lang::utils::{error::report, fileloader};
+use mimium_lang::utils::{
+    error::{report, report_json},
+    fileloader,
+};
 use mimium_lang::ExecContext;
 use mimium_lang::{compiler::mirgen::convert_pronoun, repl};
 use mimium_midi;
 use mimium_symphonia::{self, SamplerPlugin};
+/// The extension of the bytecode files, which are run without compiling.
+const BYTECODE_EXTENSION: &str = "mmmbc";
+
 #[derive(clap::Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
     #[command(flatten)]
     pub mode: Mode,
 
-    /// File name
+    /// File name. A bytecode file (.mmmbc) written by --compile is run without
+    /// compiling it again.
     #[clap(value_parser)]
     pub file: Option<String>,
 
@@ -38,6 +50,40 @@ pub struct Args {
     /// is specified.
     #[arg(long, default_value_t = 10)]
     pub times: usize,
+
+    /// Check if the file is already formatted instead of rewriting it. This is
+    /// only effective when --fmt is specified.
+    #[arg(long, requires = "fmt")]
+    pub check: bool,
+
+    /// Format of the reference page. This is only effective when --emit-docs
+    /// is specified.
+    #[arg(long, value_enum, default_value_t = DocFormat::Markdown, requires = "emit_docs")]
+    pub doc_format: DocFormat,
+
+    /// Also print the type of every expression in the file. This is only
+    /// effective when --emit-types is specified.
+    #[arg(long, requires = "emit_types")]
+    pub annotate_exprs: bool,
+
+    /// Optimization level of the MIR. 0 disables the optimizations, and 1
+    /// runs constant folding, copy propagation and dead code elimination.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(0..=1))]
+    pub opt_level: u8,
+
+    /// Also print the MIR before the optimizations. This is only effective
+    /// when --emit-mir is specified.
+    #[arg(long, requires = "emit_mir")]
+    pub mir_before_opt: bool,
+
+    /// Treat the warnings as errors, to make the compilation fail on them (e.g. in CI).
+    #[arg(long, default_value_t = false)]
+    pub deny_warnings: bool,
+
+    /// How the errors and warnings are printed. `json` prints a JSON object
+    /// for each of them to stderr, line by line.
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Human)]
+    pub error_format: ErrorFormat,
 }
 
 #[derive(Clone, Debug, ValueEnum)]
@@ -45,6 +91,18 @@ pub enum OutputFileFormat {
     Csv,
 }
 
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+#[derive(Clone, Debug, ValueEnum)]
+pub enum DocFormat {
+    Markdown,
+    Html,
+}
+
 #[derive(clap::Args, Debug)]
 #[group(required = false, multiple = false)]
 pub struct Mode {
@@ -59,6 +117,26 @@ pub struct Mode {
     /// Print bytecode and exit
     #[arg(long, default_value_t = false)]
     pub emit_bytecode: bool,
+
+    /// Write the bytecode to the file (e.g. out.mmmbc) and exit, to run it later
+    #[arg(long, value_name = "FILE")]
+    pub compile: Option<PathBuf>,
+
+    /// Print a standalone Rust module of the program, which runs without the compiler, and exit
+    #[arg(long, default_value_t = false)]
+    pub emit_rust: bool,
+
+    /// Print the inferred types of the toplevel definitions and exit
+    #[arg(long, default_value_t = false)]
+    pub emit_types: bool,
+
+    /// Print the reference page generated from the doc comments and exit
+    #[arg(long, default_value_t = false)]
+    pub emit_docs: bool,
+
+    /// Format the file in place and exit
+    #[arg(long, default_value_t = false)]
+    pub fmt: bool,
 }
 
 fn emit_ast_local(src: &str, filepath: &Path) -> Result<ExprNodeId, Vec<Box<dyn ReportableError>>> {
@@ -83,7 +161,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     match &args.file {
         Some(file) => {
             let fullpath = fileloader::get_canonical_path(".", &file)?;
+            if fullpath
+                .extension()
+                .is_some_and(|ext| ext == BYTECODE_EXTENSION)
+            {
+                return run_bytecode_file(&args, &fullpath);
+            }
             let content = fileloader::load(fullpath.to_str().unwrap())?;
+            if args.mode.fmt {
+                return format_file(&args, &content, &fullpath);
+            }
             match run_file(&args, &content, &fullpath) {
                 Ok(_) => {}
                 Err(e) => {
@@ -91,7 +178,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     // struct around ReportableError and directly return it,
                     // however, std::error::Error cannot be so color-rich as
                     // ariadne because it just uses std::fmt::Display.
-                    report(&content, fullpath, &e);
+                    report_errors(args.error_format, &e);
                     return Err(format!("Failed to process {file}").into());
                 }
             }
@@ -103,6 +190,28 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+fn format_file(
+    args: &Args,
+    content: &str,
+    fullpath: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let formatted = match format_source(content, Some(fullpath.to_path_buf())) {
+        Ok(formatted) => formatted,
+        Err(e) => {
+            report_errors(args.error_format, &e);
+            return Err(format!("Failed to format {}", fullpath.display()).into());
+        }
+    };
+    if formatted == content {
+        Ok(())
+    } else if args.check {
+        Err(format!("{} is not formatted", fullpath.display()).into())
+    } else {
+        std::fs::write(fullpath, formatted)?;
+        Ok(())
+    }
+}
+
 fn get_default_context(path: Option<Symbol>) -> ExecContext {
     let plugins: Vec<Box<dyn Plugin>> = vec![Box::new(SamplerPlugin)];
     let mut ctx = ExecContext::new(plugins.into_iter(), path);
@@ -114,6 +223,83 @@ fn get_default_context(path: Option<Symbol>) -> ExecContext {
     ctx
 }
 
+fn report_errors(format: ErrorFormat, errs: &[Box<dyn ReportableError>]) {
+    match format {
+        ErrorFormat::Human => report(errs),
+        ErrorFormat::Json => report_json(errs),
+    }
+}
+
+/// Reports the warnings of the last compilation, or turns them into errors if `deny` is set.
+fn check_warnings<T>(
+    args: &Args,
+    compiler: &mimium_lang::compiler::Context,
+    res: Result<T, Vec<Box<dyn ReportableError>>>,
+) -> Result<T, Vec<Box<dyn ReportableError>>> {
+    let warnings = compiler.take_warnings();
+    if !args.deny_warnings {
+        let warnings = warnings
+            .into_iter()
+            .map(|w| Box::new(w) as Box<dyn ReportableError>)
+            .collect::<Vec<_>>();
+        report_errors(args.error_format, &warnings);
+        return res;
+    }
+    let mut denied = warnings
+        .into_iter()
+        .map(|w| {
+            Box::new(ReportableErrorDyn {
+                message: w.to_string(),
+                span: w.1,
+            }) as Box<dyn ReportableError>
+        })
+        .collect::<Vec<_>>();
+    match res {
+        Ok(v) if denied.is_empty() => Ok(v),
+        Ok(_) => Err(denied),
+        Err(errs) => {
+            denied.extend(errs);
+            Err(denied)
+        }
+    }
+}
+
+fn run_bytecode_file(args: &Args, fullpath: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let file = fullpath.display();
+    let mode = &args.mode;
+    if mode.emit_ast
+        || mode.emit_mir
+        || mode.emit_rust
+        || mode.emit_types
+        || mode.emit_docs
+        || mode.fmt
+    {
+        return Err(format!("{file} is a bytecode file, which can only be run or printed").into());
+    }
+    if mode.compile.is_some() {
+        return Err(format!("{file} is already compiled").into());
+    }
+    let bytes = std::fs::read(fullpath)?;
+    let prog = Program::from_bytes(&bytes).map_err(|e| format!("Failed to load {file}: {e}"))?;
+    if mode.emit_bytecode {
+        println!("{prog}");
+        return Ok(());
+    }
+    let mut ctx = get_default_context(prog.file_path);
+    // the functions of the driver are linked together with the ones of the plugins.
+    let driver = get_driver(args);
+    ctx.add_plugin(driver.get_as_plugin());
+    if let Err(e) = ctx.prepare_machine_with_bytecode(prog) {
+        report_errors(args.error_format, &e);
+        return Err(format!("Failed to link {file}").into());
+    }
+    if let Err(e) = play(ctx, driver) {
+        report_errors(args.error_format, &e);
+        return Err(format!("Failed to run {file}").into());
+    }
+    Ok(())
+}
+
 fn run_file(
     args: &Args,
     content: &str,
@@ -122,46 +308,101 @@ fn run_file(
     log::debug!("Filename: {}", fullpath.display());
     let path_sym = fullpath.to_string_lossy().to_symbol();
     let mut ctx = get_default_context(Some(path_sym));
+    ctx.prepare_compiler();
+    let opt_level = match args.opt_level {
+        0 => OptLevel::None,
+        _ => OptLevel::Basic,
+    };
+    ctx.compiler.as_mut().unwrap().set_opt_level(opt_level);
     if args.mode.emit_ast {
         let ast = emit_ast_local(content, fullpath)?;
         println!("{}", ast.pretty_print());
     } else if args.mode.emit_mir {
-        ctx.prepare_compiler();
-        let mir = ctx.compiler.as_ref().unwrap().emit_mir(content)?;
-        println!("{mir}");
+        let compiler = ctx.compiler.as_ref().unwrap();
+        let mir = check_warnings(args, compiler, compiler.emit_mir(content))?;
+        if args.mir_before_opt {
+            println!("// before optimization\n{mir}");
+            println!("// after optimization");
+        }
+        println!("{}", compiler.optimize_mir(mir));
+    } else if args.mode.emit_rust {
+        let compiler = ctx.compiler.as_ref().unwrap();
+        let code = check_warnings(args, compiler, compiler.emit_rust(content))?;
+        print!("{code}");
+    } else if args.mode.emit_types {
+        let compiler = ctx.compiler.as_ref().unwrap();
+        let list = compiler.emit_types(content, args.annotate_exprs)?;
+        print!("{list}");
+    } else if args.mode.emit_docs {
+        let entries = ctx.compiler.as_ref().unwrap().emit_docs(content)?;
+        let title = fullpath
+            .file_stem()
+            .map_or("mimium".into(), |s| s.to_string_lossy());
+        let page = match args.doc_format {
+            DocFormat::Markdown => render_markdown(&title, &entries),
+            DocFormat::Html => render_html(&title, &entries),
+        };
+        print!("{page}");
+    } else if let Some(out) = &args.mode.compile {
+        let compiler = ctx.compiler.as_ref().unwrap();
+        let prog = check_warnings(args, compiler, compiler.emit_bytecode(content))?;
+        std::fs::write(out, prog.to_bytes()).map_err(|e| {
+            let err: Box<dyn ReportableError> = Box::new(ReportableErrorDyn {
+                message: format!("Failed to write {}: {e}", out.display()),
+                span: Span::default(),
+            });
+            vec![err]
+        })?;
     } else {
-        ctx.prepare_machine(content)?;
+        let res = ctx.prepare_machine(content);
+        check_warnings(args, ctx.compiler.as_ref().unwrap(), res)?;
 
         if args.mode.emit_bytecode {
             println!("{}", ctx.vm.unwrap().prog);
             return Ok(());
         }
-
-        let mut driver = match (&args.output_format, &args.output) {
-            // if none of the output options is specified, make sounds.
-            (None, None) => load_default_runtime(),
-            // When --output-format is explicitly specified, use it.
-            (Some(OutputFileFormat::Csv), Some(output)) => csv_driver(args.times, output),
-            (Some(OutputFileFormat::Csv), None) => csv_driver_stdout(args.times),
-            // Otherwise, guess from the file extension.
-            (None, Some(output)) => match output.extension() {
-                Some(x) if &x.to_os_string() == "csv" => csv_driver(args.times, output),
-                _ => panic!("cannot determine the output file format"),
-            },
-        };
-        let audiodriver_plug = driver.get_as_plugin();
-        ctx.add_plugin(audiodriver_plug);
-        let _res = ctx.run_main();
-        let mainloop = ctx.try_get_main_loop().unwrap_or(Box::new(|| {
-            //wait until input something
-            let mut dummy = String::new();
-            eprintln!("Press Enter to exit");
-            let _size = stdin().read_line(&mut dummy).expect("stdin read error.");
-        }));
-        driver.init(ctx, Some(SampleRate(48000)));
-        driver.play();
-        mainloop()
+        let driver = get_driver(args);
+        ctx.add_plugin(driver.get_as_plugin());
+        play(ctx, driver)?;
     }
 
     Ok(())
 }
+
+/// Makes the audio driver chosen by the output options.
+fn get_driver(args: &Args) -> Box<dyn Driver<Sample = f64>> {
+    match (&args.output_format, &args.output) {
+        // if none of the output options is specified, make sounds.
+        (None, None) => load_default_runtime(),
+        // When --output-format is explicitly specified, use it.
+        (Some(OutputFileFormat::Csv), Some(output)) => csv_driver(args.times, output),
+        (Some(OutputFileFormat::Csv), None) => csv_driver_stdout(args.times),
+        // Otherwise, guess from the file extension.
+        (None, Some(output)) => match output.extension() {
+            Some(x) if &x.to_os_string() == "csv" => csv_driver(args.times, output),
+            _ => panic!("cannot determine the output file format"),
+        },
+    }
+}
+
+/// Runs the prepared VM with the driver, whose functions must be added to `ctx` already.
+/// Returns the error which stopped the program, if any.
+fn play(
+    mut ctx: ExecContext,
+    mut driver: Box<dyn Driver<Sample = f64>>,
+) -> Result<(), Vec<Box<dyn ReportableError>>> {
+    let _res = ctx.run_main();
+    let mainloop = ctx.try_get_main_loop().unwrap_or(Box::new(|| {
+        //wait until input something
+        let mut dummy = String::new();
+        eprintln!("Press Enter to exit");
+        let _size = stdin().read_line(&mut dummy).expect("stdin read error.");
+    }));
+    driver.init(ctx, Some(SampleRate(48000)));
+    driver.play();
+    mainloop();
+    match driver.take_runtime_error() {
+        Some(e) => Err(vec![Box::new(e)]),
+        None => Ok(()),
+    }
+}
@@ -34,6 +34,15 @@ impl GuiToolPlugin {
         function!(vec![numeric!()], numeric!())
     }
 
+    /// The functions given by the plugin. They are available without opening the window, e.g. for
+    /// the tools which only need their types.
+    pub fn signatures() -> Vec<SysPluginSignature> {
+        let ty = function!(vec![string_t!()], Self::get_closure_type());
+        let fptr: SystemPluginFnType<Self> = Self::make_probe;
+        let make_probe = SysPluginSignature::new("make_probe", fptr, ty);
+        vec![make_probe]
+    }
+
     /// This method is exposed as "make_probe(label:String)->(float)->float".
     pub fn make_probe(&mut self, vm: &mut Machine) -> ReturnCode {
         if let Some(app) = self.window.as_mut() {
@@ -84,9 +93,6 @@ impl SystemPlugin for GuiToolPlugin {
         })
     }
     fn gen_interfaces(&self) -> Vec<SysPluginSignature> {
-        let ty = function!(vec![string_t!()], Self::get_closure_type());
-        let fptr: SystemPluginFnType<Self> = Self::make_probe;
-        let make_probe = SysPluginSignature::new("make_probe", fptr, ty);
-        vec![make_probe]
+        Self::signatures()
     }
 }
@@ -19,7 +19,7 @@ pub enum Literal {
 
 impl Expr {
     fn into_id_inner(self, span: Option<Span>) -> ExprNodeId {
-        let span = span.unwrap_or(0..0);
+        let span = span.unwrap_or_default();
         with_session_globals(|session_globals| session_globals.store_expr_with_span(self, span))
     }
 
@@ -33,6 +33,16 @@ impl Expr {
     }
 }
 
+/// Declaration of a type name at the top level.
+/// An alias (`type alias Stereo = (float, float)`) is interchangeable with its definition,
+/// while a nominal type (`type Meter = float`) is distinguished from any other type.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeDecl {
+    pub name: Symbol,
+    pub ty: TypeNodeId,
+    pub is_alias: bool,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Expr {
     Literal(Literal), // literal, or special symbols (self, now, _)
@@ -40,6 +50,9 @@ pub enum Expr {
     Block(Option<ExprNodeId>),
     Tuple(Vec<ExprNodeId>),
     Proj(ExprNodeId, i64),
+    RecordLiteral(Vec<(Symbol, ExprNodeId)>),
+    FieldAccess(ExprNodeId, Symbol),
+    ArrayLiteral(Vec<ExprNodeId>),
     ArrayAccess(ExprNodeId, ExprNodeId),
     Apply(ExprNodeId, Vec<ExprNodeId>),
     PipeApply(ExprNodeId, ExprNodeId), // LHS and RHS
@@ -49,7 +62,11 @@ pub enum Expr {
     Feed(Symbol, ExprNodeId), //feedback connection primitive operation. This will be shown only after self-removal stage
     Let(TypedPattern, ExprNodeId, Option<ExprNodeId>),
     LetRec(TypedId, ExprNodeId, Option<ExprNodeId>),
+    TypeDecl(TypeDecl, Option<ExprNodeId>),
     If(ExprNodeId, ExprNodeId, Option<ExprNodeId>),
+    Match(ExprNodeId, Vec<(TypedPattern, ExprNodeId)>),
+    // early return from the function with the value.
+    Return(ExprNodeId),
     //exprimental macro system using multi-stage computation
     Bracket(ExprNodeId),
     Escape(ExprNodeId),
@@ -57,6 +74,35 @@ pub enum Expr {
     Error,
 }
 
+/// The expressions directly contained in the expression.
+pub fn sub_exprs(e: &Expr) -> Vec<ExprNodeId> {
+    match e {
+        Expr::Literal(_) | Expr::Var(_) | Expr::Error => vec![],
+        Expr::Block(e) => e.iter().copied().collect(),
+        Expr::Tuple(es) | Expr::ArrayLiteral(es) => es.clone(),
+        Expr::RecordLiteral(fields) => fields.iter().map(|(_, e)| *e).collect(),
+        Expr::Proj(e, _)
+        | Expr::FieldAccess(e, _)
+        | Expr::Lambda(_, _, e)
+        | Expr::Feed(_, e)
+        | Expr::Return(e)
+        | Expr::Bracket(e)
+        | Expr::Escape(e) => vec![*e],
+        Expr::ArrayAccess(e1, e2) | Expr::PipeApply(e1, e2) | Expr::Assign(e1, e2) => {
+            vec![*e1, *e2]
+        }
+        Expr::Apply(f, args) => std::iter::once(*f).chain(args.iter().copied()).collect(),
+        Expr::Then(e, then) | Expr::Let(_, e, then) | Expr::LetRec(_, e, then) => {
+            std::iter::once(*e).chain(*then).collect()
+        }
+        Expr::If(cond, then, opt_else) => vec![*cond, *then].into_iter().chain(*opt_else).collect(),
+        Expr::TypeDecl(_, then) => then.iter().copied().collect(),
+        Expr::Match(scrutinee, arms) => std::iter::once(*scrutinee)
+            .chain(arms.iter().map(|(_, e)| *e))
+            .collect(),
+    }
+}
+
 impl fmt::Display for Literal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -89,8 +135,8 @@ impl MiniPrint for ExprNodeId {
         format!(
             "{}:{}..{}",
             self.to_expr().simple_print(),
-            span.start,
-            span.end
+            span.start(),
+            span.end()
         )
     }
 }
@@ -117,9 +163,19 @@ impl MiniPrint for Expr {
                 format!("(tuple ({}))", concat_vec(&e1))
             }
             Expr::Proj(e, idx) => format!("(proj {} {})", e.simple_print(), idx),
+            Expr::RecordLiteral(fields) => {
+                let fields = fields
+                    .iter()
+                    .map(|(name, e)| format!("({name} {})", e.simple_print()))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!("(record {fields})")
+            }
+            Expr::FieldAccess(e, name) => format!("(field {} {name})", e.simple_print()),
             Expr::Apply(e1, e2) => {
                 format!("(app {} ({}))", e1.simple_print(), concat_vec(e2))
             }
+            Expr::ArrayLiteral(items) => format!("(array {})", concat_vec(items)),
             Expr::ArrayAccess(e, i) => {
                 format!("(arrayaccess {} ({}))", e.simple_print(), i.simple_print())
             }
@@ -142,6 +198,13 @@ impl MiniPrint for Expr {
                 body.simple_print(),
                 then.simple_print()
             ),
+            Expr::TypeDecl(decl, then) => format!(
+                "({} {} {} {})",
+                if decl.is_alias { "typealias" } else { "type" },
+                decl.name,
+                decl.ty.to_type(),
+                then.simple_print()
+            ),
             Expr::Assign(lid, rhs) => format!("(assign {lid} {})", rhs.simple_print()),
             Expr::Then(first, second) => {
                 format!("(then {} {})", first.simple_print(), second.simple_print())
@@ -152,8 +215,17 @@ impl MiniPrint for Expr {
                 then.simple_print(),
                 optelse.simple_print()
             ),
-            Expr::Bracket(_) => todo!(),
-            Expr::Escape(_) => todo!(),
+            Expr::Match(scrutinee, arms) => {
+                let arms = arms
+                    .iter()
+                    .map(|(tpat, body)| format!("({} {})", tpat.pat, body.simple_print()))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!("(match {} {arms})", scrutinee.simple_print())
+            }
+            Expr::Return(e) => format!("(return {})", e.simple_print()),
+            Expr::Bracket(e) => format!("(bracket {})", e.simple_print()),
+            Expr::Escape(e) => format!("(escape {})", e.simple_print()),
             Expr::Error => "(error)".to_string(),
         }
     }
@@ -10,34 +10,35 @@ pub fn str_to_symbol<T: ToString>(x: T) -> Symbol {
 #[macro_export]
 macro_rules! dummy_span {
     () => {
-        0..0
+        $crate::utils::metadata::Span::default()
     };
 }
 
 #[macro_export]
 macro_rules! number {
     ($n:literal) => {
-        Expr::Literal(Literal::Float(crate::ast::builder::str_to_symbol($n))).into_id(0..0)
+        Expr::Literal(Literal::Float(crate::ast::builder::str_to_symbol($n)))
+            .into_id($crate::dummy_span!())
     };
 }
 
 #[macro_export]
 macro_rules! string {
     ($n:expr) => {
-        Expr::Literal(Literal::String($n)).into_id(0..0)
+        Expr::Literal(Literal::String($n)).into_id($crate::dummy_span!())
     };
 }
 #[macro_export]
 macro_rules! var {
     ($n:literal) => {
-        Expr::Var($crate::ast::builder::str_to_symbol($n)).into_id(0..0)
+        Expr::Var($crate::ast::builder::str_to_symbol($n)).into_id($crate::dummy_span!())
     };
 }
 
 #[macro_export]
 macro_rules! app {
     ($a:expr,$b:expr) => {
-        Expr::Apply($a, $b).into_id(0..0)
+        Expr::Apply($a, $b).into_id($crate::dummy_span!())
     };
 }
 
@@ -49,7 +50,7 @@ macro_rules! lambda_args {
             .iter()
             .map(|a| TypedId {
                 id: $crate::ast::builder::str_to_symbol(a),
-                ty: $crate::types::Type::Unknown.into_id_with_span(0..0),
+                ty: $crate::types::Type::Unknown.into_id_with_span($crate::dummy_span!()),
             })
             .collect::<Vec<_>>()
     };
@@ -63,13 +64,13 @@ macro_rules! lambda {
                 .iter()
                 .map(|a: &&'static str| $crate::pattern::TypedId {
                     id: $crate::ast::builder::str_to_symbol(a),
-                    ty: $crate::types::Type::Unknown.into_id_with_span(0..0),
+                    ty: $crate::types::Type::Unknown.into_id_with_span($crate::dummy_span!()),
                 })
                 .collect::<Vec<_>>(),
             None,
             $body,
         )
-        .into_id(0..0)
+        .into_id($crate::dummy_span!())
     };
 }
 
@@ -79,23 +80,23 @@ macro_rules! let_ {
         Expr::Let(
             $crate::pattern::TypedPattern {
                 pat: $crate::pattern::Pattern::Single($crate::ast::builder::str_to_symbol($id)),
-                ty: $crate::types::Type::Unknown.into_id_with_span(0..0),
+                ty: $crate::types::Type::Unknown.into_id_with_span($crate::dummy_span!()),
             },
             $body,
             Some($then),
         )
-        .into_id(0..0)
+        .into_id($crate::dummy_span!())
     };
     ($id:literal,$body:expr) => {
         Expr::Let(
             $crate::pattern::TypedPattern {
                 pat: $crate::pattern::Pattern::Single($crate::ast::builder::str_to_symbol($id)),
-                ty: $crate::types::Type::Unknown.into_id_with_span(0..0),
+                ty: $crate::types::Type::Unknown.into_id_with_span($crate::dummy_span!()),
             },
             Box::new($body),
             None,
         )
-        .into_id(0..0)
+        .into_id($crate::dummy_span!())
     };
 }
 
@@ -105,31 +106,34 @@ macro_rules! letrec {
         Expr::LetRec(
             TypedId {
                 id: $crate::ast::builder::str_to_symbol($id),
-                ty: $ty.unwrap_or($crate::types::Type::Unknown.into_id_with_span(0..0)),
+                ty: $ty.unwrap_or(
+                    $crate::types::Type::Unknown.into_id_with_span($crate::dummy_span!()),
+                ),
             },
             $body,
             $then,
         )
-        .into_id(0..0)
+        .into_id($crate::dummy_span!())
     };
 }
 
 #[macro_export]
 macro_rules! assign {
     ($lhs:literal,$rhs:expr) => {
-        Expr::Assign($crate::ast::builder::str_to_symbol($lhs), Box::new($rhs)).into_id(0..0)
+        Expr::Assign($crate::ast::builder::str_to_symbol($lhs), Box::new($rhs))
+            .into_id($crate::dummy_span!())
     };
 }
 #[macro_export]
 macro_rules! then {
     ($first:expr,$second:expr) => {
-        Expr::Then(Box::new($first), Box::new($second)).into_id(0..0)
+        Expr::Then(Box::new($first), Box::new($second)).into_id($crate::dummy_span!())
     };
 }
 
 #[macro_export]
 macro_rules! ifexpr {
     ($cond:expr,$then:expr,$else_:expr) => {
-        Expr::If($cond, $then, Some($else_)).into_id(0..0)
+        Expr::If($cond, $then, Some($else_)).into_id($crate::dummy_span!())
     };
 }
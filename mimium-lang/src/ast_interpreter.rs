@@ -13,6 +13,9 @@ use crate::{
 };
 use itertools::Itertools;
 
+mod staging;
+pub use staging::{contains_macro, expand_macros};
+
 #[derive(Debug, Clone, Copy)]
 pub enum PValue {
     Unit,
@@ -25,10 +28,13 @@ pub enum Value {
     Primitive(PValue),
     String(String),
     Tuple(Vec<Value>),
+    Array(Vec<Value>),
     //Function value holds return type
     Function(Vec<TypedId>, ExprNodeId, Context, Option<TypeNodeId>),
     FixPoint(TypedId, ExprNodeId),
     External(Symbol),
+    //code generated by the evaluation of the bracket, which will be spliced in the next stage.
+    Code(ExprNodeId),
 }
 impl PValue {
     pub fn get_type(&self) -> Type {
@@ -49,6 +55,11 @@ impl Value {
             Value::Primitive(p) => p.get_type_id(),
             Value::String(_) => string_t!(),
             Value::Tuple(v) => Type::Tuple(v.iter().map(|t| t.get_type_id()).collect()).into_id(),
+            Value::Array(v) => Type::Array(
+                v.first()
+                    .map_or_else(|| Type::Unknown.into_id(), |t| t.get_type_id()),
+            )
+            .into_id(),
             Value::Function(a, _e, _ctx, r_type) => Type::Function(
                 a.iter()
                     .map(|tid| {
@@ -71,6 +82,7 @@ impl Value {
             }
             //todo!
             Value::External(_id) => Type::Unknown.into_id(),
+            Value::Code(_) => Type::Code(Type::Unknown.into_id()).into_id(),
         }
     }
 
@@ -100,9 +112,9 @@ impl Context {
 }
 
 const EXTERN_SYMS: [&str; 28] = [
-    "neg", "add", "sub", "mult", "div", "mod", "eq", "ne", "le", "lt", "ge", "gt", "atan2", "sin",
-    "cos", "not", "round", "floor", "ceil", "atan", "sqrt", "abs", "min", "max", "pow", "log",
-    "print", "println",
+    "neg", "add", "sub", "mult", "div", "modulo", "eq", "ne", "le", "lt", "ge", "gt", "atan2",
+    "sin", "cos", "not", "round", "floor", "ceil", "atan", "sqrt", "abs", "min", "max", "pow",
+    "log", "print", "println",
 ];
 
 fn eval_literal(e: &ast::Literal) -> Value {
@@ -235,7 +247,7 @@ pub fn eval_extern(n: Symbol, argv: &[Value], span: Span) -> Result<Value, Compi
                 }
                 // (i64,i64)->i64
                 (
-                    Type::Primitive(PType::Numeric),
+                    Type::Primitive(PType::Int),
                     Value::Primitive(PValue::Integer(iv1)),
                     Value::Primitive(PValue::Integer(iv2)),
                 ) => {
@@ -249,6 +261,36 @@ pub fn eval_extern(n: Symbol, argv: &[Value], span: Span) -> Result<Value, Compi
     }
 }
 
+fn apply_value(
+    func: Value,
+    argv: Vec<Value>,
+    ctx: &mut Context,
+    fspan: Span,
+    span: Span,
+) -> Result<Value, CompileError> {
+    match func {
+        Value::Function(params, b, mut n_ctx, _rt) => {
+            let mut argvec: Vec<_> = argv
+                .into_iter()
+                .zip(params.iter())
+                .map(|(v, TypedId { id, .. })| (*id, v))
+                .collect();
+            eval_with_new_env(b, &mut n_ctx, &mut argvec)
+        }
+        Value::FixPoint(tid, e) => {
+            // unroll the fixpoint into the function and then apply it.
+            let f =
+                eval_with_new_env(e, ctx, &mut vec![(tid.id, Value::FixPoint(tid.clone(), e))])?;
+            apply_value(f, argv, ctx, fspan, span)
+        }
+        Value::External(n) => {
+            //todo: appropreate error type
+            eval_extern(n, &argv, span)
+        }
+        _ => Err(CompileError(ErrorKind::NotApplicable, fspan)),
+    }
+}
+
 pub fn eval_ast(e_meta: ExprNodeId, ctx: &mut Context) -> Result<Value, CompileError> {
     let env = &mut ctx.env;
     let span = e_meta.to_span();
@@ -296,25 +338,7 @@ pub fn eval_ast(e_meta: ExprNodeId, ctx: &mut Context) -> Result<Value, CompileE
         ast::Expr::Apply(f, args) => {
             let argv: Vec<_> = args.iter().map(|e| eval_ast(*e, ctx)).try_collect()?;
             let func = eval_ast(*f, ctx)?;
-            let res = match func.clone() {
-                Value::Function(params, b, mut n_ctx, _rt) => {
-                    let mut argvec: Vec<_> = argv
-                        .iter()
-                        .zip(params.iter())
-                        .map(|(v, TypedId { id, .. })| (id.clone(), v.clone()))
-                        .collect();
-                    eval_with_new_env(b, &mut n_ctx, &mut argvec)
-                }
-                Value::FixPoint(TypedId { id, .. }, e) => {
-                    eval_with_new_env(e, ctx, &mut vec![(id, func)])
-                }
-                Value::External(n) => {
-                    //todo: appropreate error type
-                    eval_extern(n, &argv, span.clone())
-                }
-                _ => Err(CompileError(ErrorKind::NotApplicable, f.to_span().clone())),
-            };
-            res
+            apply_value(func, argv, ctx, f.to_span(), span)
         }
         ast::Expr::PipeApply(_, _) => {
             panic!("|> should not be shown in evaluation stage.")
@@ -336,6 +360,10 @@ pub fn eval_ast(e_meta: ExprNodeId, ctx: &mut Context) -> Result<Value, CompileE
             ctx.history.0 += 1;
             Ok(res)
         }
+        ast::Expr::TypeDecl(_decl, then) => match then {
+            Some(t) => eval_ast(*t, ctx),
+            None => Ok(Value::Primitive(PValue::Unit)),
+        },
         ast::Expr::Let(TypedPattern { pat, .. }, e, then) => {
             let e_v = eval_ast(*e, ctx)?;
             todo!()
@@ -363,11 +391,55 @@ pub fn eval_ast(e_meta: ExprNodeId, ctx: &mut Context) -> Result<Value, CompileE
                     .unwrap_or(Ok(Value::Primitive(PValue::Unit)))
             }
         }
-        ast::Expr::Bracket(_) => todo!(),
-        ast::Expr::Escape(_) => todo!(),
+        ast::Expr::Match(_, _) => Err(CompileError(
+            ErrorKind::NotEvaluable("Match expression"),
+            span.clone(),
+        )),
+        ast::Expr::Return(_) => Err(CompileError(
+            ErrorKind::NotEvaluable("Return statement"),
+            span.clone(),
+        )),
+        ast::Expr::Bracket(e) => Ok(Value::Code(staging::gen_code(*e, ctx)?)),
+        ast::Expr::Escape(_) => Err(CompileError(ErrorKind::InvalidStage, span.clone())),
         ast::Expr::Error => panic!("Some Error happend in previous stages"),
         ast::Expr::Assign(_, _) => todo!(),
         ast::Expr::Then(_, _) => todo!(),
-        ast::Expr::ArrayAccess(_, _) => todo!(),
+        ast::Expr::ArrayLiteral(v) => {
+            let res = v.iter().map(|e| eval_ast(*e, ctx)).try_collect()?;
+            Ok(Value::Array(res))
+        }
+        ast::Expr::ArrayAccess(arr, idx) => {
+            let v = eval_ast(*arr, ctx)?;
+            let i = match eval_ast(*idx, ctx)? {
+                Value::Primitive(PValue::Integer(i)) => i,
+                iv => {
+                    return Err(CompileError(
+                        ErrorKind::TypeMismatch(integer!().to_type().clone(), iv.get_type()),
+                        idx.to_span().clone(),
+                    ))
+                }
+            };
+            match v {
+                Value::Array(a) => (i >= 0)
+                    .then(|| a.get(i as usize).cloned())
+                    .flatten()
+                    .ok_or(CompileError(
+                        ErrorKind::ArrayIndexOutOfRange(a.len(), i),
+                        span.clone(),
+                    )),
+                _ => Err(CompileError(
+                    ErrorKind::IndexForNonArray(v.get_type()),
+                    arr.to_span().clone(),
+                )),
+            }
+        }
+        ast::Expr::RecordLiteral(_) => Err(CompileError(
+            ErrorKind::NotEvaluable("Record literal"),
+            span.clone(),
+        )),
+        ast::Expr::FieldAccess(_, _) => Err(CompileError(
+            ErrorKind::NotEvaluable("Field access"),
+            span.clone(),
+        )),
     }
 }
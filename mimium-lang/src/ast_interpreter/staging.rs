@@ -0,0 +1,279 @@
+//! Macro expansion by evaluating the first stage of the multi-stage computation.
+//!
+//! A macro `macro name(params){ body }` is a compile-time function which returns a code,
+//! and `name!(args)` splices the code it returns into the program of the next stage.
+//! The first stage is evaluated with the AST interpreter and every macro call is replaced
+//! with the generated code, so that the following passes never see `Bracket` nor `Escape`.
+//!
+//! While generating a code, an expression which depends only on the compile-time values
+//! (like `n - 1` where `n` is a parameter of the macro) is evaluated and embedded as a literal,
+//! and `if` whose condition is such an expression is resolved at the compile time. This lets
+//! a macro call itself recursively to unroll a computation by its parameter.
+//! An argument of a macro call which refers to a runtime value is passed as a code.
+
+use super::{apply_value, eval_ast, eval_condition, eval_with_new_env, Context, PValue, Value};
+use crate::{
+    ast::{sub_exprs, Expr, Literal},
+    compiler::{Error as CompileError, ErrorKind},
+    interner::{ExprNodeId, Symbol, ToSymbol},
+    pattern::{pattern_names, Pattern, TypedPattern},
+    utils::metadata::Span,
+};
+use itertools::Itertools;
+
+/// Returns true if the expression contains macro definitions or macro expansions.
+pub fn contains_macro(e: ExprNodeId) -> bool {
+    match e.to_expr() {
+        Expr::Bracket(_) | Expr::Escape(_) => true,
+        e => sub_exprs(&e).into_iter().any(contains_macro),
+    }
+}
+
+// Whether the expression can be evaluated at the compile time, that is, all the variables
+// in it are bound to compile-time values or builtin functions.
+fn is_static(e: ExprNodeId, ctx: &Context) -> bool {
+    let all = |es: &[ExprNodeId]| es.iter().all(|e| is_static(*e, ctx));
+    match e.to_expr() {
+        Expr::Literal(Literal::Float(_) | Literal::Int(_) | Literal::String(_)) => true,
+        Expr::Var(v) => match ctx.env.lookup(&v) {
+            Some(Value::Code(_)) => false,
+            Some(_) => true,
+            None => ctx.extern_syms.contains(&v),
+        },
+        Expr::Tuple(es) | Expr::ArrayLiteral(es) => all(&es),
+        Expr::Proj(e, _) | Expr::Block(Some(e)) => is_static(e, ctx),
+        Expr::ArrayAccess(e1, e2) => all(&[e1, e2]),
+        Expr::Apply(f, args) => is_static(f, ctx) && all(&args),
+        Expr::If(cond, then, opt_else) => {
+            is_static(cond, ctx)
+                && is_static(then, ctx)
+                && opt_else.is_none_or(|e| is_static(e, ctx))
+        }
+        _ => false,
+    }
+}
+
+// Whether the expression refers to a compile-time value which is not a function.
+// A static expression without such variables, like `sin(1.0)`, is left for the runtime.
+fn uses_stage_value(e: ExprNodeId, ctx: &Context) -> bool {
+    match e.to_expr() {
+        Expr::Var(v) => matches!(
+            ctx.env.lookup(&v),
+            Some(Value::Primitive(_) | Value::String(_) | Value::Tuple(_) | Value::Array(_))
+        ),
+        e => sub_exprs(&e).into_iter().any(|e| uses_stage_value(e, ctx)),
+    }
+}
+
+fn lift_value(v: Value, span: Span) -> Result<ExprNodeId, CompileError> {
+    let lift_vec = |vs: Vec<Value>| -> Result<Vec<_>, CompileError> {
+        vs.into_iter()
+            .map(|v| lift_value(v, span.clone()))
+            .try_collect()
+    };
+    let e = match v {
+        Value::Primitive(PValue::Unit) => Expr::Block(None),
+        Value::Primitive(PValue::Numeric(f)) => {
+            Expr::Literal(Literal::Float(f.to_string().to_symbol()))
+        }
+        Value::Primitive(PValue::Integer(i)) => Expr::Literal(Literal::Int(i)),
+        Value::String(s) => Expr::Literal(Literal::String(s.to_symbol())),
+        Value::Tuple(vs) => Expr::Tuple(lift_vec(vs)?),
+        Value::Array(vs) => Expr::ArrayLiteral(lift_vec(vs)?),
+        Value::Code(c) => return copy_code(c),
+        Value::Function(..) | Value::FixPoint(..) | Value::External(_) => {
+            return Err(CompileError(ErrorKind::NonLiftableValue, span))
+        }
+    };
+    Ok(e.into_id(span))
+}
+
+// A code value may be spliced into several places, so every splice makes a fresh copy of it.
+fn copy_code(c: ExprNodeId) -> Result<ExprNodeId, CompileError> {
+    let mut ctx = Context::new();
+    ctx.env.extend();
+    gen_code(c, &mut ctx)
+}
+
+fn gen_in_scope(
+    e: ExprNodeId,
+    names: &[Symbol],
+    ctx: &mut Context,
+) -> Result<ExprNodeId, CompileError> {
+    // variables bound in the generated code shadow the compile-time variables of the same name.
+    let binds = names
+        .iter()
+        .map(|name| (*name, Value::Code(Expr::Var(*name).into_id(e.to_span()))))
+        .collect::<Vec<_>>();
+    ctx.env.extend();
+    ctx.env.add_bind(&binds);
+    let res = gen_code(e, ctx);
+    ctx.env.to_outer();
+    res
+}
+
+fn gen_opt(e: Option<ExprNodeId>, ctx: &mut Context) -> Result<Option<ExprNodeId>, CompileError> {
+    e.map(|e| gen_code(e, ctx)).transpose()
+}
+
+fn gen_vec(es: &[ExprNodeId], ctx: &mut Context) -> Result<Vec<ExprNodeId>, CompileError> {
+    es.iter().map(|e| gen_code(*e, ctx)).try_collect()
+}
+
+fn is_macro_def(e: ExprNodeId) -> bool {
+    matches!(e.to_expr(), Expr::Lambda(_, _, body) if matches!(body.to_expr(), Expr::Bracket(_)))
+}
+
+// Defines a macro in the compile-time environment and generates the code of the rest.
+// The definition itself is removed from the generated code.
+fn gen_with_macro(
+    name: Symbol,
+    f: Value,
+    then: Option<ExprNodeId>,
+    span: Span,
+    ctx: &mut Context,
+) -> Result<ExprNodeId, CompileError> {
+    match then {
+        Some(t) => {
+            ctx.env.extend();
+            ctx.env.add_bind(&[(name, f)]);
+            let res = gen_code(t, ctx);
+            ctx.env.to_outer();
+            res
+        }
+        None => Ok(Expr::Block(None).into_id(span)),
+    }
+}
+
+fn gen_macro_arg(e: ExprNodeId, ctx: &mut Context) -> Result<Value, CompileError> {
+    if is_static(e, ctx) {
+        eval_ast(e, ctx)
+    } else {
+        gen_code(e, ctx).map(Value::Code)
+    }
+}
+
+fn splice(e: ExprNodeId, ctx: &mut Context) -> Result<ExprNodeId, CompileError> {
+    let span = e.to_span();
+    let v = match e.to_expr() {
+        Expr::Apply(f, args) => {
+            let func = eval_ast(f, ctx)?;
+            let argv: Vec<_> = args.iter().map(|a| gen_macro_arg(*a, ctx)).try_collect()?;
+            apply_value(func, argv, ctx, f.to_span(), span.clone())?
+        }
+        _ => eval_ast(e, ctx)?,
+    };
+    match v {
+        Value::Code(c) => copy_code(c),
+        _ => Err(CompileError(ErrorKind::EscapeNonCode, span)),
+    }
+}
+
+/// Generates the code of the next stage from the body of `Bracket`.
+pub(super) fn gen_code(e: ExprNodeId, ctx: &mut Context) -> Result<ExprNodeId, CompileError> {
+    let span = e.to_span();
+    if is_static(e, ctx) && uses_stage_value(e, ctx) {
+        let v = eval_ast(e, ctx)?;
+        return lift_value(v, span);
+    }
+    let res = match e.to_expr() {
+        Expr::Var(v) => match ctx.env.lookup(&v) {
+            Some(value) => return lift_value(value.clone(), span),
+            None => Expr::Var(v),
+        },
+        Expr::Escape(e) => return splice(e, ctx),
+        Expr::Bracket(_) => return Err(CompileError(ErrorKind::InvalidStage, span)),
+        Expr::If(cond, then, opt_else) if is_static(cond, ctx) && uses_stage_value(cond, ctx) => {
+            return if eval_condition(cond, ctx)? {
+                gen_code(then, ctx)
+            } else {
+                gen_opt(opt_else, ctx).map(|e| e.unwrap_or_else(|| Expr::Block(None).into_id(span)))
+            };
+        }
+        Expr::Let(
+            TypedPattern {
+                pat: Pattern::Single(name),
+                ..
+            },
+            body,
+            then,
+        ) if is_macro_def(body) => {
+            let f = eval_ast(body, ctx)?;
+            return gen_with_macro(name, f, then, span, ctx);
+        }
+        Expr::LetRec(id, body, then) if is_macro_def(body) => {
+            let f = eval_with_new_env(
+                body,
+                ctx,
+                &mut vec![(id.id, Value::FixPoint(id.clone(), body))],
+            )?;
+            return gen_with_macro(id.id, f, then, span, ctx);
+        }
+        Expr::Lambda(params, r_type, body) => {
+            let names = params.iter().map(|p| p.id).collect::<Vec<_>>();
+            Expr::Lambda(params, r_type, gen_in_scope(body, &names, ctx)?)
+        }
+        Expr::Feed(id, body) => Expr::Feed(id, gen_in_scope(body, &[id], ctx)?),
+        Expr::Let(tpat, body, then) => {
+            let mut names = vec![];
+            pattern_names(&tpat.pat, &mut names);
+            let body = gen_code(body, ctx)?;
+            let then = then.map(|t| gen_in_scope(t, &names, ctx)).transpose()?;
+            Expr::Let(tpat, body, then)
+        }
+        Expr::LetRec(id, body, then) => {
+            let body = gen_in_scope(body, &[id.id], ctx)?;
+            let then = then.map(|t| gen_in_scope(t, &[id.id], ctx)).transpose()?;
+            Expr::LetRec(id, body, then)
+        }
+        Expr::Match(scrutinee, arms) => {
+            let scrutinee = gen_code(scrutinee, ctx)?;
+            let arms = arms
+                .into_iter()
+                .map(|(tpat, body)| {
+                    let mut names = vec![];
+                    pattern_names(&tpat.pat, &mut names);
+                    gen_in_scope(body, &names, ctx).map(|body| (tpat, body))
+                })
+                .try_collect()?;
+            Expr::Match(scrutinee, arms)
+        }
+        Expr::Literal(l) => Expr::Literal(l),
+        Expr::Block(e) => Expr::Block(gen_opt(e, ctx)?),
+        Expr::Tuple(es) => Expr::Tuple(gen_vec(&es, ctx)?),
+        Expr::Proj(e, i) => Expr::Proj(gen_code(e, ctx)?, i),
+        Expr::RecordLiteral(fields) => Expr::RecordLiteral(
+            fields
+                .into_iter()
+                .map(|(name, e)| gen_code(e, ctx).map(|e| (name, e)))
+                .try_collect()?,
+        ),
+        Expr::FieldAccess(e, name) => Expr::FieldAccess(gen_code(e, ctx)?, name),
+        Expr::ArrayLiteral(es) => Expr::ArrayLiteral(gen_vec(&es, ctx)?),
+        Expr::ArrayAccess(e1, e2) => Expr::ArrayAccess(gen_code(e1, ctx)?, gen_code(e2, ctx)?),
+        Expr::Apply(f, args) => Expr::Apply(gen_code(f, ctx)?, gen_vec(&args, ctx)?),
+        Expr::PipeApply(e1, e2) => Expr::PipeApply(gen_code(e1, ctx)?, gen_code(e2, ctx)?),
+        Expr::Assign(e1, e2) => Expr::Assign(gen_code(e1, ctx)?, gen_code(e2, ctx)?),
+        Expr::Then(e, then) => Expr::Then(gen_code(e, ctx)?, gen_opt(then, ctx)?),
+        Expr::Return(e) => Expr::Return(gen_code(e, ctx)?),
+        Expr::TypeDecl(decl, then) => Expr::TypeDecl(decl, gen_opt(then, ctx)?),
+        Expr::If(cond, then, opt_else) => Expr::If(
+            gen_code(cond, ctx)?,
+            gen_code(then, ctx)?,
+            gen_opt(opt_else, ctx)?,
+        ),
+        Expr::Error => Expr::Error,
+    };
+    Ok(res.into_id(span))
+}
+
+/// Evaluates the macros in the program and returns the program in which every macro
+/// expansion is replaced with the generated code.
+pub fn expand_macros(e: ExprNodeId) -> Result<ExprNodeId, CompileError> {
+    if !contains_macro(e) {
+        return Ok(e);
+    }
+    let mut ctx = Context::new();
+    ctx.env.extend();
+    gen_code(e, &mut ctx)
+}
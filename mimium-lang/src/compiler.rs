@@ -2,8 +2,12 @@ pub mod parser;
 pub mod typing;
 // pub mod hirgen;
 pub mod bytecodegen;
+pub mod docgen;
 mod intrinsics;
+pub mod lint;
 pub mod mirgen;
+pub mod rustgen;
+pub mod typelist;
 
 #[derive(Debug, Clone)]
 pub enum ErrorKind {
@@ -11,9 +15,15 @@ pub enum ErrorKind {
     CircularType,
     IndexOutOfRange(u16, u16),
     IndexForNonTuple(Type),
+    ArrayIndexOutOfRange(usize, i64),
+    IndexForNonArray(Type),
     VariableNotFound(String),
     NonPrimitiveInFeed,
     NotApplicable, //need?
+    EscapeNonCode,
+    NonLiftableValue,
+    InvalidStage,
+    NotEvaluable(&'static str),
     Unknown,
 }
 #[derive(Debug, Clone)]
@@ -43,11 +53,36 @@ impl std::fmt::Display for ErrorKind {
                     r, a
                 )
             }
+            ErrorKind::ArrayIndexOutOfRange(len, i) => {
+                write!(
+                    f,
+                    "Array index out of range, the length is {} but accessed with {}.",
+                    len, i
+                )
+            }
+            ErrorKind::IndexForNonArray(t) => {
+                write!(f, "Index access for non array-type {t}.")
+            }
             ErrorKind::NotApplicable => {
                 write!(f, "Application to non-function type value.")
             }
             ErrorKind::CircularType => write!(f, "Circular loop of type definition"),
             ErrorKind::NonPrimitiveInFeed => write!(f, "Feed can take only non-funtion type."),
+            ErrorKind::EscapeNonCode => write!(
+                f,
+                "Macro expansion requires an expression which returns a code."
+            ),
+            ErrorKind::NonLiftableValue => write!(
+                f,
+                "This compile-time value cannot be embedded into the generated code."
+            ),
+            ErrorKind::InvalidStage => write!(
+                f,
+                "Nested code quotation or expansion outside of macros is not supported."
+            ),
+            ErrorKind::NotEvaluable(what) => {
+                write!(f, "{what} cannot be evaluated at compile time.")
+            }
             ErrorKind::Unknown => write!(f, "unknwon error."),
         }
     }
@@ -61,19 +96,19 @@ impl std::fmt::Display for Error {
 impl std::error::Error for Error {}
 
 impl ReportableError for Error {
-    fn get_span(&self) -> std::ops::Range<usize> {
+    fn get_span(&self) -> Span {
         self.1.clone()
     }
 }
 
-use std::path::PathBuf;
+use std::{cell::RefCell, path::PathBuf};
 
 use mirgen::recursecheck;
 
 use crate::{
     ast_interpreter,
     interner::{ExprNodeId, Symbol, TypeNodeId},
-    mir::Mir,
+    mir::{optimize, InlineHint, Mir},
     runtime::vm,
     types::Type,
     utils::{error::ReportableError, metadata::Span},
@@ -94,7 +129,13 @@ pub struct ExtFunTypeInfo {
 
 pub struct Context {
     ext_fns: Vec<ExtFunTypeInfo>,
+    // the functions given by the plugins not loaded, with the names of the plugins. They are
+    // used only for the hints of the errors.
+    unloaded_fns: Vec<(Symbol, Symbol)>,
     file_path: Option<Symbol>,
+    opt_level: optimize::OptLevel,
+    // the warnings found in the last compilation, until they are taken.
+    warnings: RefCell<Vec<lint::Warning>>,
 }
 impl Context {
     pub fn new(
@@ -103,10 +144,28 @@ impl Context {
     ) -> Self {
         Self {
             ext_fns: ext_fns.into_iter().collect(),
+            unloaded_fns: vec![],
 
             file_path,
+            opt_level: Default::default(),
+            warnings: RefCell::new(vec![]),
         }
     }
+    pub fn set_opt_level(&mut self, level: optimize::OptLevel) {
+        self.opt_level = level;
+    }
+    /// Registers the functions of the plugin which is not loaded, so that the errors tell the
+    /// plugin to load when they are used.
+    pub fn add_unloaded_plugin(
+        &mut self,
+        plugin: Symbol,
+        fns: impl IntoIterator<Item = ExtFunTypeInfo>,
+    ) {
+        self.unloaded_fns.extend(
+            fns.into_iter()
+                .map(|ExtFunTypeInfo { name, .. }| (name, plugin)),
+        );
+    }
     fn get_ext_typeinfos(&self) -> Vec<(Symbol, TypeNodeId)> {
         self.ext_fns
             .clone()
@@ -115,20 +174,115 @@ impl Context {
             .collect()
     }
     pub fn emit_mir(&self, src: &str) -> Result<Mir, Vec<Box<dyn ReportableError>>> {
-        let ast = parser::parse(
+        let (ast, attributes, mut errs) = parser::parse_recovery_with_attributes(
             src,
             self.file_path.map(|sym| PathBuf::from(sym.to_string())),
-        )
-        .map(|ast| parser::add_global_context(ast))?;
+        );
+        if let (Some(ast), true) = (ast, errs.is_empty()) {
+            *self.warnings.borrow_mut() = lint::lint(ast);
+        }
+        let ast = match ast.map(parser::add_global_context) {
+            Some(ast) if errs.is_empty() => ast,
+            Some(ast) => {
+                // the parts parsed successfully are still type-checked so that their errors
+                // are reported together with the syntax errors.
+                if let Err(e) =
+                    mirgen::typecheck(ast, &self.get_ext_typeinfos(), &self.unloaded_fns)
+                {
+                    errs.push(e);
+                }
+                return Err(errs);
+            }
+            None => return Err(errs),
+        };
 
-        mirgen::compile(ast, &self.get_ext_typeinfos(), self.file_path).map_err(|e| {
+        let mut mir = mirgen::compile(
+            ast,
+            &self.get_ext_typeinfos(),
+            &self.unloaded_fns,
+            self.file_path,
+        )
+        .map_err(|e| {
             let bres = e as Box<dyn ReportableError>;
             vec![bres]
-        })
+        })?;
+        for attr in attributes {
+            let hint = InlineHint::from_attribute(attr.name).expect("checked by the parser");
+            mir.functions
+                .iter_mut()
+                .filter(|f| f.label == attr.target)
+                .for_each(|f| f.inline_hint = hint);
+        }
+        Ok(mir)
+    }
+    /// Optimizes the MIR generated by [`Self::emit_mir`] at the optimization level of the context.
+    pub fn optimize_mir(&self, mir: Mir) -> Mir {
+        optimize::optimize(mir, self.opt_level)
+    }
+    /// Takes the warnings found by the last call of [`Self::emit_mir`]. They are not included in
+    /// the errors even if the compilation failed.
+    pub fn take_warnings(&self) -> Vec<lint::Warning> {
+        self.warnings.take()
+    }
+    /// Collects the documented toplevel definitions in the source with their inferred types.
+    pub fn emit_docs(
+        &self,
+        src: &str,
+    ) -> Result<Vec<docgen::DocEntry>, Vec<Box<dyn ReportableError>>> {
+        let (ast, docs) = parser::parse_with_docs(
+            src,
+            self.file_path.map(|sym| PathBuf::from(sym.to_string())),
+        )?;
+        let ast = parser::add_global_context(ast);
+        let types =
+            mirgen::infer_toplevel_types(ast, &self.get_ext_typeinfos(), &self.unloaded_fns)
+                .map_err(|e| vec![e])?;
+        Ok(docgen::DocEntry::from_docs(docs, &types))
+    }
+    /// Infers the types of the toplevel definitions in the source, and the ones of all the
+    /// expressions in it if `with_exprs` is set.
+    pub fn emit_types(
+        &self,
+        src: &str,
+        with_exprs: bool,
+    ) -> Result<typelist::TypeList, Vec<Box<dyn ReportableError>>> {
+        let ast = parser::parse(
+            src,
+            self.file_path.map(|sym| PathBuf::from(sym.to_string())),
+        )?;
+        let file = ast.to_span().file;
+        let ast = parser::add_global_context(ast);
+        let builtin_types = self.get_ext_typeinfos();
+        let definitions = mirgen::infer_toplevel_types(ast, &builtin_types, &self.unloaded_fns)
+            .map_err(|e| vec![e])?;
+        let exprs = if with_exprs {
+            let (exprs, _bindings) =
+                mirgen::infer_expr_types(ast, &builtin_types, &self.unloaded_fns)
+                    .map_err(|e| vec![e])?;
+            let mut exprs = exprs
+                .into_iter()
+                .map(|(e, t)| (e.to_span(), t))
+                .filter(|(span, _)| span.file == file)
+                .collect::<Vec<_>>();
+            // the outer expression first, and only one of the expressions converted from the
+            // same code.
+            exprs.sort_by_key(|(span, _)| (span.start(), std::cmp::Reverse(span.end())));
+            exprs.dedup_by(|(s1, _), (s2, _)| s1 == s2);
+            Some(exprs)
+        } else {
+            None
+        };
+        Ok(typelist::TypeList { definitions, exprs })
     }
     pub fn emit_bytecode(&self, src: &str) -> Result<vm::Program, Vec<Box<dyn ReportableError>>> {
         let mir = self.emit_mir(src)?;
-        bytecodegen::gen_bytecode(mir)
+        bytecodegen::gen_bytecode(self.optimize_mir(mir))
+    }
+    /// Generates a standalone Rust module of the program, which runs `dsp` without the VM. See
+    /// [`rustgen`] for the layout of the module.
+    pub fn emit_rust(&self, src: &str) -> Result<String, Vec<Box<dyn ReportableError>>> {
+        let mir = self.emit_mir(src)?;
+        rustgen::gen_rust(&self.optimize_mir(mir))
     }
 }
 
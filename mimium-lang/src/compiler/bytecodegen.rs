@@ -1,53 +1,152 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 use crate::interner::{Symbol, TypeNodeId};
-use crate::mir::{self, Mir, StateSize};
-use crate::runtime::vm::bytecode::{ConstPos, GlobalPos, Reg};
+use crate::mir::{self, optimize, Mir, StateSize};
+use crate::runtime::vm::bytecode::{ConstPos, GlobalPos, Reg, SpillPos};
 use crate::runtime::vm::{self};
 use crate::types::{PType, Type, TypeSize};
-use crate::utils::{error::ReportableError,half_float::HFloat};
+use crate::utils::metadata::Span;
+use crate::utils::{error::ReportableError, half_float::HFloat};
 use vm::bytecode::Instruction as VmInstruction;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone)]
+pub enum ErrorKind {
+    /// The values alive at once in the function do not fit in the registers.
+    TooManyRegisters(Symbol),
+    /// The variables spilled out of the registers exceed the spill slots.
+    TooManySpills(Symbol),
+    TooManyGlobals,
+}
+#[derive(Debug, Clone)]
+pub struct Error(pub ErrorKind, pub Span);
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::TooManyRegisters(name) => write!(
+                f,
+                "Function {name} needs more than {REGISTER_COUNT} registers. Split it into smaller functions."
+            ),
+            ErrorKind::TooManySpills(name) => write!(
+                f,
+                "Function {name} has more variables than {SPILL_COUNT} words."
+            ),
+            ErrorKind::TooManyGlobals => {
+                write!(f, "Global values exceed {GLOBAL_COUNT} words.")
+            }
+        }
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ReportableError for Error {
+    fn get_span(&self) -> Span {
+        self.1.clone()
+    }
+}
+
+const REGISTER_COUNT: u64 = Reg::MAX as u64 + 1;
+const SPILL_COUNT: u64 = SpillPos::MAX as u64 + 1;
+const GLOBAL_COUNT: u64 = GlobalPos::MAX as u64 + 1;
+// the variables beyond this are spilled, so that the rest of the registers are left for the
+// temporary values and the arguments of the calls.
+const VARIABLE_REGISTERS: u64 = 192;
+
+#[derive(Debug, Default, Clone, Copy)]
 struct MemoryRegion(Reg, TypeSize);
 #[derive(Debug, Default)]
-struct VRegister(HashMap<Arc<mir::Value>, MemoryRegion>);
+struct SpillSlot(SpillPos, TypeSize);
+
+// the lowest position where `size` words are free between the regions.
+fn find_space(regions: impl Iterator<Item = (u64, u64)>, size: u64) -> u64 {
+    let mut regions = regions.filter(|(_, s)| *s > 0).collect::<Vec<_>>();
+    regions.sort();
+    let mut pos = 0;
+    for (start, s) in regions {
+        if start >= pos + size {
+            break;
+        }
+        pos = pos.max(start + s);
+    }
+    pos
+}
+
+#[derive(Debug, Default)]
+struct VRegister {
+    regions: HashMap<Arc<mir::Value>, MemoryRegion>,
+    // the variables placed in the spill slots instead of the registers.
+    spilled: HashMap<Arc<mir::Value>, SpillSlot>,
+    // the number of the spill slots used at most.
+    spill_size: u64,
+}
 
 impl VRegister {
-    pub fn push_stack(&mut self, v: &Arc<mir::Value>, size: u64) -> Reg {
-        self.add_newvalue_range(v, size)
+    fn top(&self) -> u64 {
+        self.regions
+            .values()
+            .map(|MemoryRegion(address, size)| *address as u64 + *size as u64)
+            .max()
+            .unwrap_or(0)
     }
-    pub fn add_newvalue(&mut self, v: &Arc<mir::Value>) -> Reg {
-        let pos = self
-            .0
-            .iter()
-            .max_by_key(|(_v, MemoryRegion(address, size))| address + size)
-            .map(|(_, MemoryRegion(address, size))| address + size)
-            .unwrap_or(0);
-        self.0.insert(v.clone(), MemoryRegion(pos, 1));
-        log::trace!("add {:?}", self.0);
-        pos as Reg
-    }
-    pub fn add_newvalue_range(&mut self, v: &Arc<mir::Value>, size: u64) -> Reg {
-        let pos = self
-            .0
-            .iter()
-            .max_by_key(|(_v, MemoryRegion(address, size))| address + size)
-            .map(|(_, MemoryRegion(address, size))| address + size)
-            .unwrap_or(0);
-        self.0.insert(v.clone(), MemoryRegion(pos, size as _));
-        log::trace!("add_range {:#?}", self.0);
-        pos as Reg
+    // the free registers are reused. Returns None if they are exhausted.
+    pub fn push_stack(&mut self, v: &Arc<mir::Value>, size: u64) -> Option<Reg> {
+        let regions = self.regions.values().map(|r| (r.0 as u64, r.1 as u64));
+        let pos = find_space(regions, size);
+        self.insert(v, pos, size)
+    }
+    // allocates above all the values alive, for the call which uses the registers after it.
+    pub fn push_top(&mut self, v: &Arc<mir::Value>, size: u64) -> Option<Reg> {
+        let pos = self.top();
+        self.insert(v, pos, size)
+    }
+    fn insert(&mut self, v: &Arc<mir::Value>, pos: u64, size: u64) -> Option<Reg> {
+        if pos + size > REGISTER_COUNT {
+            // placed at 0 to continue the generation, which fails with the error.
+            self.regions.insert(v.clone(), MemoryRegion(0, size as _));
+            return None;
+        }
+        self.regions
+            .insert(v.clone(), MemoryRegion(pos as Reg, size as _));
+        log::trace!("add_range {:#?}", self.regions);
+        Some(pos as Reg)
+    }
+    // allocates the variable in the registers if it fits below `VARIABLE_REGISTERS`, or in the
+    // spill slots. Returns None if the spill slots are exhausted.
+    pub fn push_variable(&mut self, v: &Arc<mir::Value>, size: u64) -> Option<()> {
+        let regions = self.regions.values().map(|r| (r.0 as u64, r.1 as u64));
+        let pos = find_space(regions, size);
+        if pos + size <= VARIABLE_REGISTERS {
+            return self.insert(v, pos, size).map(|_| ());
+        }
+        self.push_spill(v, size).map(|_| ())
+    }
+    pub fn push_spill(&mut self, v: &Arc<mir::Value>, size: u64) -> Option<SpillPos> {
+        let slots = self.spilled.values().map(|s| (s.0 as u64, s.1 as u64));
+        let pos = find_space(slots, size);
+        if pos + size > SPILL_COUNT {
+            self.spilled.insert(v.clone(), SpillSlot(0, size as _));
+            return None;
+        }
+        self.spilled
+            .insert(v.clone(), SpillSlot(pos as SpillPos, size as _));
+        self.spill_size = self.spill_size.max(pos + size);
+        Some(pos as SpillPos)
     }
     pub fn find(&mut self, v: &Arc<mir::Value>) -> Option<Reg> {
         log::trace!("find {v}");
-        let res = self.0.get(v).map(|r| r.0);
+        let res = self.regions.get(v).map(|r| r.0);
         match (res, v.as_ref()) {
             //argument is registered in absolute position
             (Some(_), mir::Value::Argument(_, _)) | (Some(_), mir::Value::Global(_)) => res,
             (Some(_), _) => {
-                self.0.remove(v);
+                self.regions.remove(v);
                 res
             }
             _ => None,
@@ -56,7 +155,15 @@ impl VRegister {
     //find for load and store instruction
     pub fn find_keep(&self, v: &Arc<mir::Value>) -> Option<Reg> {
         log::trace!("findkeep {v}");
-        self.0.get(v).map(|r| r.0)
+        self.regions.get(v).map(|r| r.0)
+    }
+    pub fn find_spilled(&self, v: &Arc<mir::Value>) -> Option<SpillPos> {
+        self.spilled.get(v).map(|s| s.0)
+    }
+    // frees the registers or the spill slots of the value no longer used.
+    pub fn release(&mut self, v: &Arc<mir::Value>) {
+        self.regions.remove(v);
+        self.spilled.remove(v);
     }
 }
 
@@ -73,11 +180,11 @@ impl VStack {
             .skip(1)
             .find_map(|vreg| vreg.find_keep(v))
     }
-    pub fn push_stack(&mut self, v: &Arc<mir::Value>, size: u64) -> Reg {
+    pub fn push_stack(&mut self, v: &Arc<mir::Value>, size: u64) -> Option<Reg> {
         self.get_top().push_stack(v, size)
     }
-    pub fn add_newvalue(&mut self, v: &Arc<mir::Value>) -> Reg {
-        self.get_top().add_newvalue(v)
+    pub fn push_top(&mut self, v: &Arc<mir::Value>, size: u64) -> Option<Reg> {
+        self.get_top().push_top(v, size)
     }
     pub fn find(&mut self, v: &Arc<mir::Value>) -> Option<Reg> {
         self.get_top().find(v)
@@ -87,12 +194,133 @@ impl VStack {
     }
 }
 
+// The values no longer used after each instruction, given by (block, index). The blocks are
+// visited in the order of the generation: `then`, `else` and the rest from the phi.
+fn dead_values(
+    mirfunc: &mir::Function,
+    kept: &HashSet<Arc<mir::Value>>,
+) -> HashMap<(usize, usize), Vec<Arc<mir::Value>>> {
+    fn visit(f: &mir::Function, bidx: usize, skip: usize, order: &mut Vec<(usize, usize)>) {
+        for (i, (_, inst)) in f.body[bidx].0.iter().enumerate().skip(skip) {
+            order.push((bidx, i));
+            if let mir::Instruction::JmpIf(_, t, e, p) = inst {
+                visit(f, *t as usize, 0, order);
+                visit(f, *e as usize, 0, order);
+                order.push((*p as usize, 0));
+                visit(f, *p as usize, 1, order);
+            }
+        }
+    }
+    let mut order = vec![];
+    visit(mirfunc, 0, 0, &mut order);
+    let mut last_use = HashMap::new();
+    for pos in order.iter() {
+        let (dst, inst) = &mirfunc.body[pos.0].0[pos.1];
+        for v in std::iter::once(dst).chain(optimize::operands(inst)) {
+            if matches!(v.as_ref(), mir::Value::Register(_)) && !kept.contains(v) {
+                last_use.insert(v.clone(), *pos);
+            }
+        }
+    }
+    let mut res = HashMap::<_, Vec<_>>::new();
+    for (v, pos) in last_use {
+        res.entry(pos).or_default().push(v);
+    }
+    res
+}
+
+// the variables which can be placed in the spill slots, read and written only by `load` and
+// `store`, which access the slots directly. The elements of a spilled tuple are accessed in the
+// same way through the addresses given by `getelement`.
+fn spillable_variables(
+    mirfunc: &mir::Function,
+    kept: &HashSet<Arc<mir::Value>>,
+) -> HashSet<Arc<mir::Value>> {
+    let insts = mirfunc.body.iter().flat_map(|b| b.0.iter());
+    let mut res = insts
+        .clone()
+        .filter(|(dst, inst)| matches!(inst, mir::Instruction::Alloc(_)) && !kept.contains(dst))
+        .map(|(dst, _)| dst.clone())
+        .collect::<HashSet<_>>();
+    let bases = insts
+        .clone()
+        .filter_map(|(dst, inst)| match inst {
+            mir::Instruction::GetElement { value, .. } => Some((dst.clone(), value.clone())),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+    let root = |v: &Arc<mir::Value>| {
+        let mut v = v.clone();
+        while let Some(base) = bases.get(&v) {
+            v = base.clone();
+        }
+        v
+    };
+    for v in kept.iter().filter(|v| bases.contains_key(*v)) {
+        res.remove(&root(v));
+    }
+    for (_, inst) in insts {
+        let address = match inst {
+            mir::Instruction::Load(a, _)
+            | mir::Instruction::Store(a, _, _)
+            | mir::Instruction::GetElement { value: a, .. } => Some(a),
+            _ => None,
+        };
+        for v in optimize::operands(inst) {
+            if address != Some(v) || matches!(inst, mir::Instruction::Store(a, src, _) if a == src)
+            {
+                res.remove(&root(v));
+            }
+        }
+    }
+    res
+}
+
+// the temporary values which can be spilled after the definition, as they are read only once
+// and moved back to a register just before it.
+fn spillable_temporaries(
+    mirfunc: &mir::Function,
+    kept: &HashSet<Arc<mir::Value>>,
+) -> HashSet<Arc<mir::Value>> {
+    let mut uses = HashMap::<_, usize>::new();
+    let mut excluded = HashSet::new();
+    for (_, inst) in mirfunc.body.iter().flat_map(|b| b.0.iter()) {
+        for v in optimize::operands(inst) {
+            *uses.entry(v.clone()).or_default() += 1;
+        }
+        // the address is kept in the register, and the operands of the phi are read at the end
+        // of the branches.
+        excluded.extend(optimize::address_operand(inst).cloned());
+        if let mir::Instruction::Phi(t, e) = inst {
+            excluded.extend([t.clone(), e.clone()]);
+        }
+    }
+    uses.into_iter()
+        .filter(|(v, n)| {
+            *n == 1
+                && matches!(v.as_ref(), mir::Value::Register(_))
+                && !excluded.contains(v)
+                && !kept.contains(v)
+        })
+        .map(|(v, _)| v)
+        .collect()
+}
+
 #[derive(Debug, Default)]
 pub struct ByteCodeGenerator {
     vregister: VStack,
     fnmap: HashMap<Symbol, usize>,
-    globals: Vec<Arc<mir::Value>>,
+    globals: Vec<(Arc<mir::Value>, GlobalPos)>,
     program: vm::Program,
+    // the values captured by the closures, whose registers are never reused.
+    captured: HashSet<Arc<mir::Value>>,
+    // the liveness and the spillable variables of the function being generated.
+    dead_values: HashMap<(usize, usize), Vec<Arc<mir::Value>>>,
+    spillable: HashSet<Arc<mir::Value>>,
+    spillable_temporaries: HashSet<Arc<mir::Value>>,
+    current_fn: Option<Symbol>,
+    current_span: Span,
+    errors: Vec<Error>,
 }
 
 fn gen_raw_int(n: &i64) -> vm::RawVal {
@@ -121,12 +349,22 @@ impl ByteCodeGenerator {
             Type::Primitive(PType::Unit) => 0,
             Type::Primitive(PType::String) => 1,
             Type::Primitive(_) => 1,
-            Type::Array(_ty) => todo!(),
+            // arrays are passed as a reference to the heap object.
+            Type::Array(_ty) => 1,
             Type::Tuple(types) => types.iter().map(|t| Self::word_size_for_type(*t)).sum(),
             Type::Struct(types) => types
                 .iter()
                 .map(|(_s, t)| Self::word_size_for_type(*t))
                 .sum(),
+            Type::Union(variants) => {
+                let max_fields = variants
+                    .iter()
+                    .map(|(_, fields)| fields.iter().map(|t| Self::word_size_for_type(*t)).sum())
+                    .max()
+                    .unwrap_or(0);
+                // 1 word for the tag
+                1 + max_fields
+            }
             Type::Function(_, _, _) => 1,
             Type::Ref(_) => 1,
             Type::Code(_) => todo!(),
@@ -178,30 +416,65 @@ impl ByteCodeGenerator {
         let i = inst(dst, r1, r2);
         Some(i)
     }
+    // reports the error only once for a function, as the rest of it is generated anyway.
+    fn report(&mut self, kind: ErrorKind) {
+        let reported = self.errors.iter().any(|Error(k, _)| match (k, &kind) {
+            (ErrorKind::TooManyRegisters(a), ErrorKind::TooManyRegisters(b))
+            | (ErrorKind::TooManySpills(a), ErrorKind::TooManySpills(b)) => a == b,
+            (ErrorKind::TooManyGlobals, ErrorKind::TooManyGlobals) => true,
+            _ => false,
+        });
+        if !reported {
+            self.errors.push(Error(kind, self.current_span.clone()));
+        }
+    }
+    fn check_register(&mut self, r: Option<Reg>) -> Reg {
+        r.unwrap_or_else(|| {
+            self.report(ErrorKind::TooManyRegisters(self.current_fn.unwrap()));
+            0
+        })
+    }
     fn get_destination(&mut self, dst: Arc<mir::Value>, size: TypeSize) -> Reg {
-        self.vregister.push_stack(&dst, size as _)
+        let r = self.vregister.push_stack(&dst, size as _);
+        self.check_register(r)
     }
-    fn get_or_insert_global(&mut self, gv: Arc<mir::Value>) -> GlobalPos {
-        match self.globals.iter().position(|v| gv == *v) {
-            Some(idx) => idx as GlobalPos,
+    fn get_destination_top(&mut self, dst: Arc<mir::Value>, size: TypeSize) -> Reg {
+        let r = self.vregister.push_top(&dst, size as _);
+        self.check_register(r)
+    }
+    fn get_or_insert_global(&mut self, gv: Arc<mir::Value>, size: TypeSize) -> GlobalPos {
+        match self.globals.iter().find(|(v, _)| gv == *v) {
+            Some((_, pos)) => *pos,
             None => {
-                self.globals.push(gv.clone());
-                let idx = (self.globals.len() - 1) as GlobalPos;
-                self.program.global_vals.push(idx as u64);
-                idx
+                let pos = self.program.global_vals.len() as u64;
+                if pos + size as u64 > GLOBAL_COUNT {
+                    self.report(ErrorKind::TooManyGlobals);
+                    return 0;
+                }
+                self.program
+                    .global_vals
+                    .resize((pos + size as u64) as usize, 0);
+                self.globals.push((gv, pos as GlobalPos));
+                pos as GlobalPos
             }
         }
     }
+    fn find_global(&self, v: &Arc<mir::Value>) -> Option<Reg> {
+        self.globals
+            .iter()
+            .position(|(gv, _)| v == gv)
+            .map(|v| v as Reg)
+    }
     fn find(&mut self, v: &Arc<mir::Value>) -> Reg {
         self.vregister
             .find(v)
-            .or_else(|| self.globals.iter().position(|gv| v == gv).map(|v| v as Reg))
+            .or_else(|| self.find_global(v))
             .expect(format!("value {v} not found").as_str())
     }
     fn find_keep(&mut self, v: &Arc<mir::Value>) -> Reg {
         self.vregister
             .find_keep(v)
-            .or_else(|| self.globals.iter().position(|gv| v == gv).map(|v| v as Reg))
+            .or_else(|| self.find_global(v))
             .expect(format!("value {v} not found").as_str())
     }
     fn find_upvalue(&self, upval: &Arc<mir::Value>) -> Reg {
@@ -209,10 +482,11 @@ impl ByteCodeGenerator {
             .find_upvalue(upval)
             .expect("failed to find upvalue")
     }
+    // moves the arguments after the function at `faddress`, which is above all the values alive.
     fn prepare_function(
         &mut self,
         bytecodes_dst: &mut Vec<VmInstruction>,
-        faddress: &Arc<mir::Value>,
+        faddress: Reg,
         args: &[(Arc<mir::Value>, TypeNodeId)],
     ) -> (Reg, TypeSize) {
         let mut aoffsets = vec![];
@@ -223,7 +497,10 @@ impl ByteCodeGenerator {
             aoffsets.push((offset, src, size));
             offset += size;
         }
-        let faddress = self.find_keep(faddress);
+        if faddress as u64 + 1 + offset as u64 > REGISTER_COUNT {
+            self.report(ErrorKind::TooManyRegisters(self.current_fn.unwrap()));
+            return (faddress, offset);
+        }
         // bytecodes_dst.push(VmInstruction::Move())
         for (adst, src, size) in aoffsets.iter() {
             let address = *adst + faddress + 1;
@@ -270,11 +547,12 @@ impl ByteCodeGenerator {
         let fi = funcproto.add_new_constant(idx as u64);
         let rsize = Self::word_size_for_type(ty);
         let bytecodes_dst = bytecodes_dst.unwrap_or_else(|| funcproto.bytecodes.as_mut());
-        let f = self.vregister.push_stack(&dst, rsize as _);
+        let f = self.get_destination_top(dst, rsize);
         bytecodes_dst.push(VmInstruction::MoveConst(f, fi as ConstPos));
-        let (dst, argsize) = self.prepare_function(bytecodes_dst, &dst, args);
+        let (dst, argsize) = self.prepare_function(bytecodes_dst, f, args);
         (dst, argsize, rsize)
     }
+    #[allow(clippy::too_many_arguments)]
     fn prepare_extfun(
         &mut self,
         funcproto: &mut vm::FuncProto,
@@ -282,10 +560,12 @@ impl ByteCodeGenerator {
         dst: Arc<mir::Value>,
         args: &[(Arc<mir::Value>, TypeNodeId)],
         label: Symbol,
-        ty: TypeNodeId,
+        fty: TypeNodeId,
+        rty: TypeNodeId,
     ) -> (Reg, Reg, TypeSize) {
-        let idx = self.get_or_insert_extfunid(label, ty);
-        self.prepare_extfun_or_cls(funcproto, bytecodes_dst, dst, args, idx, ty)
+        // the table keeps the type of the function to check it on linking.
+        let idx = self.get_or_insert_extfunid(label, fty);
+        self.prepare_extfun_or_cls(funcproto, bytecodes_dst, dst, args, idx, rty)
     }
     // fn prepare_extcls(
     //     &mut self,
@@ -325,7 +605,7 @@ impl ByteCodeGenerator {
             }
             mir::Instruction::Float(n) => {
                 let dst = self.get_destination(dst, 1);
-                if let Ok(half_f) = HFloat::try_from(*n){
+                if let Ok(half_f) = HFloat::try_from(*n) {
                     Some(VmInstruction::MoveImmF(dst, half_f))
                 } else {
                     let pos = funcproto.add_new_constant(gen_raw_float(n));
@@ -341,14 +621,31 @@ impl ByteCodeGenerator {
                 ))
             }
             mir::Instruction::Alloc(t) => {
-                let size = Self::word_size_for_type(*t) as u64;
-                let _ = self.vregister.push_stack(&dst, size);
+                let size = Self::word_size_for_type(*t);
+                if !self.spillable.contains(&dst) {
+                    let _ = self.get_destination(dst, size);
+                } else if self
+                    .vregister
+                    .get_top()
+                    .push_variable(&dst, size as _)
+                    .is_none()
+                {
+                    self.report(ErrorKind::TooManySpills(self.current_fn.unwrap()));
+                }
                 None
             }
             mir::Instruction::Load(ptr, ty) => {
                 let d = self.get_destination(dst, Self::word_size_for_type(*ty));
-                let s = self.find_keep(ptr);
                 let size = Self::word_size_for_type(*ty);
+                if let Some(slot) = self.vregister.get_top().find_spilled(ptr) {
+                    let bytecodes_dst =
+                        bytecodes_dst.unwrap_or_else(|| funcproto.bytecodes.as_mut());
+                    bytecodes_dst.extend(
+                        (0..size).map(|i| VmInstruction::Reload(d + i, slot + i as SpillPos)),
+                    );
+                    return None;
+                }
+                let s = self.find_keep(ptr);
                 match (d, s, size) {
                     (d, s, 1) if d != s => Some(VmInstruction::Move(d, s)),
                     (d, s, size) if d != s => Some(VmInstruction::MoveRange(d, s, size)),
@@ -357,8 +654,16 @@ impl ByteCodeGenerator {
             }
             mir::Instruction::Store(dst, src, ty) => {
                 let s = self.find(src);
-                let d = self.find_keep(dst);
                 let size = Self::word_size_for_type(*ty);
+                if let Some(slot) = self.vregister.get_top().find_spilled(dst) {
+                    let bytecodes_dst =
+                        bytecodes_dst.unwrap_or_else(|| funcproto.bytecodes.as_mut());
+                    bytecodes_dst.extend(
+                        (0..size).map(|i| VmInstruction::Spill(slot + i as SpillPos, s + i)),
+                    );
+                    return None;
+                }
+                let d = self.find_keep(dst);
                 match (d, s, size) {
                     (d, s, 1) if d != s => Some(VmInstruction::Move(d, s)),
                     (d, s, size) if d != s => Some(VmInstruction::MoveRange(d, s, size)),
@@ -366,22 +671,25 @@ impl ByteCodeGenerator {
                 }
             }
             mir::Instruction::GetGlobal(v, ty) => {
-                let dst = self.get_destination(dst, Self::word_size_for_type(*ty));
-                let idx = self.get_or_insert_global(v.clone());
-                Some(VmInstruction::GetGlobal(
-                    dst,
-                    idx,
-                    Self::word_size_for_type(*ty),
-                ))
+                let size = Self::word_size_for_type(*ty);
+                let dst = self.get_destination(dst, size);
+                let pos = self.get_or_insert_global(v.clone(), size);
+                let bytecodes_dst = bytecodes_dst.unwrap_or_else(|| funcproto.bytecodes.as_mut());
+                // the globals are moved by words.
+                bytecodes_dst.extend(
+                    (0..size).map(|i| VmInstruction::GetGlobal(dst + i, pos + i as GlobalPos)),
+                );
+                None
             }
             mir::Instruction::SetGlobal(v, src, ty) => {
-                let idx = self.get_or_insert_global(v.clone());
+                let size = Self::word_size_for_type(*ty);
+                let pos = self.get_or_insert_global(v.clone(), size);
                 let s = self.find(src);
-                Some(VmInstruction::SetGlobal(
-                    idx,
-                    s,
-                    Self::word_size_for_type(*ty),
-                ))
+                let bytecodes_dst = bytecodes_dst.unwrap_or_else(|| funcproto.bytecodes.as_mut());
+                bytecodes_dst.extend(
+                    (0..size).map(|i| VmInstruction::SetGlobal(pos + i as GlobalPos, s + i)),
+                );
+                None
             }
             mir::Instruction::GetElement {
                 value,
@@ -389,42 +697,76 @@ impl ByteCodeGenerator {
                 array_idx,
                 tuple_offset,
             } => {
-                let ptr = self.find_keep(value) as usize;
                 let t_size = Self::word_size_for_type(*ty);
                 let ty = ty.to_type();
-                let tvec = ty.get_as_tuple().unwrap();
+                let tvec = ty.get_aggregate_elems().unwrap();
                 let tsize = Self::word_size_for_type(tvec[*tuple_offset as usize]);
                 let t_offset: u64 = tvec[0..(*tuple_offset as _)]
                     .iter()
                     .map(|t| Self::word_size_for_type(*t) as u64)
                     .sum();
                 let offset = t_size as u64 * *array_idx + t_offset;
+                let vregister = self.vregister.get_top();
+                if let Some(slot) = vregister.find_spilled(value) {
+                    let slot = SpillSlot(slot + offset as SpillPos, tsize);
+                    vregister.spilled.insert(dst, slot);
+                    return None;
+                }
+                let ptr = self.find_keep(value) as usize;
                 let address = (ptr + offset as usize) as Reg;
                 self.vregister
                     .get_top()
-                    .0
+                    .regions
                     .insert(dst, MemoryRegion(address, tsize));
                 None
             }
+            mir::Instruction::AllocArray(len, elem_ty) => {
+                let len = self.find(len);
+                let dst = self.get_destination(dst, 1);
+                Some(VmInstruction::AllocArray(
+                    dst,
+                    len,
+                    Self::word_size_for_type(*elem_ty),
+                ))
+            }
+            mir::Instruction::GetArrayElem(arr, idx, elem_ty) => {
+                let (arr, idx) = self.get_binop(arr, idx);
+                let dst = self.get_destination(dst, Self::word_size_for_type(*elem_ty));
+                Some(VmInstruction::GetArrayElem(dst, arr, idx))
+            }
+            mir::Instruction::SetArrayElem(arr, idx, src, _elem_ty) => {
+                // the array is kept alive because it is referred repeatedly while initializing.
+                let arr = self.find_keep(arr);
+                let idx = self.find(idx);
+                let src = self.find(src);
+                Some(VmInstruction::SetArrayElem(arr, idx, src))
+            }
             mir::Instruction::Call(v, args, r_ty) => {
                 let rsize = Self::word_size_for_type(*r_ty);
                 match v.as_ref() {
                     mir::Value::Register(_address) => {
                         let bytecodes_dst =
                             bytecodes_dst.unwrap_or_else(|| funcproto.bytecodes.as_mut());
-                        let d = self.get_destination(dst.clone(), rsize);
+                        let d = self.get_destination_top(dst.clone(), rsize);
                         let s = self.find(v);
                         bytecodes_dst.push(VmInstruction::Move(d, s));
-                        let (fadd, argsize) = self.prepare_function(bytecodes_dst, &dst, args);
+                        let (fadd, argsize) = self.prepare_function(bytecodes_dst, d, args);
                         Some(VmInstruction::Call(fadd, argsize, rsize))
                     }
                     mir::Value::Function(_idx) => {
                         unreachable!();
                     }
-                    mir::Value::ExtFunction(label, _ty) => {
+                    mir::Value::ExtFunction(label, fty) => {
                         //todo: use btreemap
-                        let (dst, argsize, nret) =
-                            self.prepare_extfun(funcproto, bytecodes_dst, dst, args, *label, *r_ty);
+                        let (dst, argsize, nret) = self.prepare_extfun(
+                            funcproto,
+                            bytecodes_dst,
+                            dst,
+                            args,
+                            *label,
+                            *fty,
+                            *r_ty,
+                        );
                         Some(VmInstruction::CallExtFun(dst, argsize, nret))
                     }
                     _ => unreachable!(),
@@ -436,23 +778,27 @@ impl ByteCodeGenerator {
                     mir::Value::Register(_address) => {
                         let bytecodes_dst =
                             bytecodes_dst.unwrap_or_else(|| funcproto.bytecodes.as_mut());
-
-                        let (fadd, argsize) = self.prepare_function(bytecodes_dst, f, args);
+                        // the closure is moved above the values alive like the function of `call`,
+                        // as the registers after it are used for the arguments.
+                        let d = self.get_destination_top(dst.clone(), rsize);
                         let s = self.find(f);
-                        let d = self.get_destination(dst.clone(), rsize);
-                        bytecodes_dst.push(VmInstruction::CallCls(fadd, argsize, rsize));
-                        match rsize {
-                            0 => None,
-                            1 => Some(VmInstruction::Move(d, s)),
-                            n => Some(VmInstruction::MoveRange(d, s, n)),
-                        }
+                        bytecodes_dst.push(VmInstruction::Move(d, s));
+                        let (fadd, argsize) = self.prepare_function(bytecodes_dst, d, args);
+                        Some(VmInstruction::CallCls(fadd, argsize, rsize))
                     }
                     mir::Value::Function(_idx) => {
                         unreachable!();
                     }
-                    mir::Value::ExtFunction(label, _ty) => {
-                        let (dst, argsize, nret) =
-                            self.prepare_extfun(funcproto, bytecodes_dst, dst, args, *label, *r_ty);
+                    mir::Value::ExtFunction(label, fty) => {
+                        let (dst, argsize, nret) = self.prepare_extfun(
+                            funcproto,
+                            bytecodes_dst,
+                            dst,
+                            args,
+                            *label,
+                            *fty,
+                            *r_ty,
+                        );
                         Some(VmInstruction::CallExtFun(dst, argsize, nret))
                     }
                     _ => unreachable!(),
@@ -493,7 +839,7 @@ impl ByteCodeGenerator {
                 } else {
                     funcproto.upindexes.push(ouv);
                 }
-                let d = self.vregister.get_top().add_newvalue_range(&dst, size as _);
+                let d = self.get_destination(dst, size);
                 Some(VmInstruction::GetUpValue(
                     d,
                     *i as Reg,
@@ -531,9 +877,13 @@ impl ByteCodeGenerator {
             }
             mir::Instruction::GetState(ty) => {
                 let size = Self::word_size_for_type(*ty);
-                let d = self.vregister.push_stack(&dst, size as _);
+                let d = self.get_destination(dst, size);
                 Some(VmInstruction::GetState(d, size))
             }
+            mir::Instruction::SetState(src, ty) => {
+                let s = self.find(src);
+                Some(VmInstruction::SetState(s, Self::word_size_for_type(*ty)))
+            }
 
             mir::Instruction::JmpIf(cond, tbb, ebb, pbb) => {
                 let c = self.find(cond);
@@ -543,45 +893,30 @@ impl ByteCodeGenerator {
 
                 let mut then_bytecodes: Vec<VmInstruction> = vec![];
                 let mut else_bytecodes: Vec<VmInstruction> = vec![];
-                mirfunc.body[*tbb as usize]
-                    .0
-                    .iter()
-                    .for_each(|(dst, t_inst)| {
-                        if let Some(inst) = self.emit_instruction(
-                            funcproto,
-                            Some(&mut then_bytecodes),
-                            fidx,
-                            mirfunc,
-                            dst.clone(),
-                            t_inst,
-                        ) {
-                            then_bytecodes.push(inst);
-                        }
-                    });
-
-                mirfunc.body[*ebb as usize]
-                    .0
-                    .iter()
-                    .for_each(|(dst, t_inst)| {
-                        if let Some(inst) = self.emit_instruction(
-                            funcproto,
-                            Some(&mut else_bytecodes),
-                            fidx,
-                            mirfunc,
-                            dst.clone(),
-                            t_inst,
-                        ) {
-                            else_bytecodes.push(inst);
-                        };
-                    });
+                let then_dst = Some(&mut then_bytecodes);
+                self.emit_block(funcproto, then_dst, fidx, mirfunc, *tbb as usize, 0);
+                let else_dst = Some(&mut else_bytecodes);
+                self.emit_block(funcproto, else_dst, fidx, mirfunc, *ebb as usize, 0);
                 let phiblock = &mirfunc.body[*pbb as usize].0;
                 let (phidst, pinst) = phiblock.first().unwrap();
-                let phi = self.vregister.add_newvalue(phidst);
+                let phi = self.get_destination(phidst.clone(), 1);
+                // nothing is moved to the phi from a block which ends with `return`,
+                // or which has no value like `if` without `else`.
+                let reaches_phi = |b: &[VmInstruction], v: &Arc<mir::Value>| {
+                    !matches!(
+                        b.last(),
+                        Some(VmInstruction::Return(..) | VmInstruction::Return0)
+                    ) && !matches!(v.as_ref(), mir::Value::None)
+                };
                 if let mir::Instruction::Phi(t, e) = pinst {
-                    let t = self.find(t);
-                    then_bytecodes.push(VmInstruction::Move(phi, t));
-                    let e = self.find(e);
-                    else_bytecodes.push(VmInstruction::Move(phi, e));
+                    if reaches_phi(&then_bytecodes, t) {
+                        let t = self.find(t);
+                        then_bytecodes.push(VmInstruction::Move(phi, t));
+                    }
+                    if reaches_phi(&else_bytecodes, e) {
+                        let e = self.find(e);
+                        else_bytecodes.push(VmInstruction::Move(phi, e));
+                    }
                 } else {
                     unreachable!("Unexpected inst: {pinst:?}");
                 }
@@ -604,16 +939,10 @@ impl ByteCodeGenerator {
                     }
                 }
 
-                phiblock.iter().skip(1).for_each(|(dst, p_inst)| {
-                    if let Some(inst) =
-                        self.emit_instruction(funcproto, None, fidx, mirfunc, dst.clone(), p_inst)
-                    {
-                        match &mut bytecodes_dst {
-                            Some(dst) => dst.push(inst),
-                            None => funcproto.bytecodes.push(inst),
-                        }
-                    };
-                });
+                self.spill_if_high(funcproto, bytecodes_dst.as_deref_mut(), phidst);
+                self.release_dead_values(*pbb as usize, 0);
+                // the merge block of the nested `if` belongs to the outer branch.
+                self.emit_block(funcproto, bytecodes_dst, fidx, mirfunc, *pbb as usize, 1);
                 None
             }
             mir::Instruction::Jmp(offset) => Some(VmInstruction::Jmp(*offset)),
@@ -630,9 +959,9 @@ impl ByteCodeGenerator {
             }
             mir::Instruction::ReturnFeed(new, rty) => {
                 //for returning always 0 at t=0
-                let old = self.vregister.add_newvalue(&dst);
-                let bytecodes_dst = bytecodes_dst.unwrap_or_else(|| funcproto.bytecodes.as_mut());
                 let size = Self::word_size_for_type(*rty);
+                let old = self.get_destination(dst, size);
+                let bytecodes_dst = bytecodes_dst.unwrap_or_else(|| funcproto.bytecodes.as_mut());
                 bytecodes_dst.push(VmInstruction::GetState(old, size));
                 let new = self.find(new);
                 bytecodes_dst.push(VmInstruction::SetState(new, size));
@@ -643,13 +972,13 @@ impl ByteCodeGenerator {
                 let s = self.find(src);
                 let t = self.find(time);
 
-                let dst = self.vregister.add_newvalue(&dst);
+                let dst = self.get_destination(dst, 1);
                 funcproto.delay_sizes.push(*max as u64);
                 Some(VmInstruction::Delay(dst, s, t))
             }
             mir::Instruction::Mem(src) => {
                 let s = self.find(src);
-                let dst = self.vregister.add_newvalue(&dst);
+                let dst = self.get_destination(dst, 1);
                 Some(VmInstruction::Mem(dst, s))
             }
             mir::Instruction::NegF(v1) => self.emit_binop1(VmInstruction::NegF, &dst, v1),
@@ -668,6 +997,11 @@ impl ByteCodeGenerator {
             mir::Instruction::MulI(v1, v2) => self.emit_binop2(VmInstruction::MulI, &dst, v1, v2),
             mir::Instruction::DivI(v1, v2) => self.emit_binop2(VmInstruction::DivI, &dst, v1, v2),
             mir::Instruction::ModI(v1, v2) => self.emit_binop2(VmInstruction::ModI, &dst, v1, v2),
+            mir::Instruction::PowI(v1, v2) => self.emit_binop2(VmInstruction::PowI, &dst, v1, v2),
+            mir::Instruction::NegI(v1) => self.emit_binop1(VmInstruction::NegI, &dst, v1),
+            mir::Instruction::AbsI(v1) => self.emit_binop1(VmInstruction::AbsI, &dst, v1),
+            mir::Instruction::CastFtoI(v1) => self.emit_binop1(VmInstruction::CastFtoI, &dst, v1),
+            mir::Instruction::CastItoF(v1) => self.emit_binop1(VmInstruction::CastItoF, &dst, v1),
             mir::Instruction::Gt(v1, v2) => self.emit_binop2(VmInstruction::Gt, &dst, v1, v2),
             mir::Instruction::Ge(v1, v2) => self.emit_binop2(VmInstruction::Ge, &dst, v1, v2),
             mir::Instruction::Lt(v1, v2) => self.emit_binop2(VmInstruction::Lt, &dst, v1, v2),
@@ -700,23 +1034,105 @@ impl ByteCodeGenerator {
             state_size,
             ..Default::default()
         };
+        self.current_fn = Some(mirfunc.label);
+        self.current_span = mirfunc.span.clone();
+        self.dead_values = dead_values(mirfunc, &self.captured);
+        self.spillable = spillable_variables(mirfunc, &self.captured);
+        self.spillable_temporaries = spillable_temporaries(mirfunc, &self.captured);
         self.vregister.0.push(VRegister::default());
         for (a, t) in mirfunc.args.iter().zip(mirfunc.argtypes.iter()) {
             let size = Self::word_size_for_type(*t);
-            self.vregister.push_stack(a, size as _);
+            let _ = self.get_destination_top(a.clone(), size);
         }
 
         // succeeding block will be compiled recursively
-        let block = &mirfunc.body[0];
-        block.0.iter().for_each(|(dst, inst)| {
-            let newinst = self.emit_instruction(&mut func, None, fidx, mirfunc, dst.clone(), inst);
-            if let Some(i) = newinst {
-                func.bytecodes.push(i);
-            }
-        });
+        self.emit_block(&mut func, None, fidx, mirfunc, 0, 0);
+        func.spill_size = self.vregister.get_top().spill_size;
         (mirfunc.label, func)
     }
+    // emits the instructions of the block from `skip`, and frees the values after their last use.
+    fn emit_block(
+        &mut self,
+        funcproto: &mut vm::FuncProto,
+        mut bytecodes_dst: Option<&mut Vec<VmInstruction>>,
+        fidx: usize,
+        mirfunc: &mir::Function,
+        bidx: usize,
+        skip: usize,
+    ) {
+        let block = &mirfunc.body[bidx].0;
+        for (i, (dst, inst)) in block.iter().enumerate().skip(skip) {
+            self.reload_operands(funcproto, bytecodes_dst.as_deref_mut(), inst);
+            let bytecodes = bytecodes_dst.as_deref_mut();
+            let d = dst.clone();
+            if let Some(inst) = self.emit_instruction(funcproto, bytecodes, fidx, mirfunc, d, inst)
+            {
+                match &mut bytecodes_dst {
+                    Some(dst) => dst.push(inst),
+                    None => funcproto.bytecodes.push(inst),
+                }
+            }
+            self.spill_if_high(funcproto, bytecodes_dst.as_deref_mut(), dst);
+            self.release_dead_values(bidx, i);
+        }
+    }
+    // moves the spilled temporary values back to the registers before they are read.
+    fn reload_operands(
+        &mut self,
+        funcproto: &mut vm::FuncProto,
+        bytecodes_dst: Option<&mut Vec<VmInstruction>>,
+        inst: &mir::Instruction,
+    ) {
+        let bytecodes_dst = bytecodes_dst.unwrap_or_else(|| funcproto.bytecodes.as_mut());
+        for v in optimize::operands(inst) {
+            if !self.spillable_temporaries.contains(v) {
+                continue;
+            }
+            let Some(SpillSlot(slot, size)) = self.vregister.get_top().spilled.remove(v) else {
+                continue;
+            };
+            let r = self.get_destination(v.clone(), size);
+            bytecodes_dst
+                .extend((0..size).map(|i| VmInstruction::Reload(r + i, slot + i as SpillPos)));
+        }
+    }
+    // spills the temporary value just defined if it occupies the registers left for the others.
+    fn spill_if_high(
+        &mut self,
+        funcproto: &mut vm::FuncProto,
+        bytecodes_dst: Option<&mut Vec<VmInstruction>>,
+        v: &Arc<mir::Value>,
+    ) {
+        if !self.spillable_temporaries.contains(v) {
+            return;
+        }
+        let vregister = self.vregister.get_top();
+        let Some(&MemoryRegion(r, size)) = vregister.regions.get(v) else {
+            return;
+        };
+        if r as u64 + size as u64 <= VARIABLE_REGISTERS {
+            return;
+        }
+        vregister.regions.remove(v);
+        let Some(slot) = vregister.push_spill(v, size as _) else {
+            self.report(ErrorKind::TooManySpills(self.current_fn.unwrap()));
+            return;
+        };
+        let bytecodes_dst = bytecodes_dst.unwrap_or_else(|| funcproto.bytecodes.as_mut());
+        bytecodes_dst.extend((0..size).map(|i| VmInstruction::Spill(slot + i as SpillPos, r + i)));
+    }
+    fn release_dead_values(&mut self, bidx: usize, i: usize) {
+        let vregister = self.vregister.get_top();
+        for v in self.dead_values.get(&(bidx, i)).into_iter().flatten() {
+            vregister.release(v);
+        }
+    }
     pub fn generate(&mut self, mir: Mir) -> vm::Program {
+        self.captured = mir
+            .functions
+            .iter()
+            .flat_map(|f| f.upindexes.iter().cloned())
+            .collect();
         self.program.global_fn_table = mir
             .functions
             .iter()
@@ -785,6 +1201,13 @@ fn optimize(program: vm::Program) -> vm::Program {
 pub fn gen_bytecode(mir: mir::Mir) -> Result<vm::Program, Vec<Box<dyn ReportableError>>> {
     let mut generator = ByteCodeGenerator::default();
     let program = generator.generate(mir);
+    if !generator.errors.is_empty() {
+        return Err(generator
+            .errors
+            .into_iter()
+            .map(|e| Box::new(e) as Box<dyn ReportableError>)
+            .collect());
+    }
     Ok(optimize(program))
 }
 
@@ -810,8 +1233,14 @@ mod test {
             0,
             Arc::new(mir::Argument("hoge".to_symbol(), numeric!())),
         ));
-        let mut func =
-            mir::Function::new(0, "test".to_symbol(), &[arg.clone()], &[numeric!()], None);
+        let mut func = mir::Function::new(
+            0,
+            "test".to_symbol(),
+            &[arg.clone()],
+            &[numeric!()],
+            None,
+            Span::default(),
+        );
         func.return_type.get_or_init(|| numeric!());
         let mut block = mir::Block::default();
         let resint = Arc::new(mir::Value::Register(1));
@@ -0,0 +1,169 @@
+//! Reference pages generated from the `///` doc comments on the toplevel definitions.
+//!
+//! The entries are made from the doc comments collected by the parser, and the signatures are
+//! built from the types inferred for the definitions.
+use super::parser::DocComment;
+use crate::interner::{Symbol, TypeNodeId};
+use crate::types::{Type, TypeVarNames};
+
+#[derive(Clone, Debug)]
+pub struct DocEntry {
+    pub name: Symbol,
+    pub params: Option<Vec<Symbol>>,
+    pub text: String,
+    /// `None` if the type of the definition could not be found.
+    pub ty: Option<TypeNodeId>,
+}
+
+impl DocEntry {
+    /// Attaches the types to the doc comments by the names of the definitions.
+    pub fn from_docs(docs: Vec<DocComment>, types: &[(Symbol, TypeNodeId)]) -> Vec<Self> {
+        docs.into_iter()
+            .map(|doc| {
+                let ty = types.iter().find(|(n, _)| *n == doc.name).map(|(_, t)| *t);
+                Self {
+                    name: doc.name,
+                    params: doc.params,
+                    text: doc.text,
+                    ty,
+                }
+            })
+            .collect()
+    }
+
+    /// The signature like `fn name(a: number) -> number` for functions, or `let name: number`.
+    /// The type variables are named as `'a`, `'b`, ... as in the error messages.
+    pub fn signature(&self) -> String {
+        let mut names = TypeVarNames::default();
+        let ty = self.ty.map(|t| t.to_type());
+        match (&self.params, ty) {
+            (Some(params), Some(Type::Function(ptypes, ret, _)))
+                if params.len() == ptypes.len() =>
+            {
+                let args = params
+                    .iter()
+                    .zip(ptypes.iter())
+                    .map(|(p, t)| format!("{p}: {}", t.to_type().to_string_for_error(&mut names)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let ret = ret.to_type().to_string_for_error(&mut names);
+                format!("fn {}({args}) -> {ret}", self.name)
+            }
+            (Some(params), _) => {
+                let args = params.iter().map(|p| p.as_str()).collect::<Vec<_>>();
+                format!("fn {}({})", self.name, args.join(", "))
+            }
+            (None, Some(t)) => {
+                format!("let {}: {}", self.name, t.to_string_for_error(&mut names))
+            }
+            (None, None) => format!("let {}", self.name),
+        }
+    }
+}
+
+/// Renders the entries into a Markdown page, with a section for each definition.
+pub fn render_markdown(title: &str, entries: &[DocEntry]) -> String {
+    let mut res = format!("# {title}\n");
+    for entry in entries {
+        res.push_str(&format!(
+            "\n## `{}`\n\n```\n{}\n```\n\n{}\n",
+            entry.name,
+            entry.signature(),
+            entry.text
+        ));
+    }
+    res
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Renders the entries into a standalone HTML page. The paragraphs of the texts are separated
+/// by blank lines.
+pub fn render_html(title: &str, entries: &[DocEntry]) -> String {
+    let title = escape_html(title);
+    let mut res = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n"
+    );
+    for entry in entries {
+        let name = escape_html(entry.name.as_str());
+        res.push_str(&format!(
+            "<section id=\"{name}\">\n<h2><code>{name}</code></h2>\n<pre><code>{}</code></pre>\n",
+            escape_html(&entry.signature())
+        ));
+        for para in entry.text.split("\n\n").filter(|p| !p.trim().is_empty()) {
+            res.push_str(&format!("<p>{}</p>\n", escape_html(para.trim())));
+        }
+        res.push_str("</section>\n");
+    }
+    res.push_str("</body>\n</html>\n");
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interner::ToSymbol;
+    use crate::types::PType;
+    use crate::utils::metadata::Span;
+
+    fn entries() -> Vec<DocEntry> {
+        let number = Type::Primitive(PType::Numeric).into_id();
+        let docs = vec![
+            DocComment {
+                name: "gain".to_symbol(),
+                params: Some(vec!["x".to_symbol(), "g".to_symbol()]),
+                text: "Multiplies the input by `g`.".to_string(),
+                span: Span::default(),
+            },
+            DocComment {
+                name: "freq".to_symbol(),
+                params: None,
+                text: "The frequency <Hz>.".to_string(),
+                span: Span::default(),
+            },
+        ];
+        let types = [
+            (
+                "gain".to_symbol(),
+                Type::Function(vec![number, number], number, None).into_id(),
+            ),
+            ("freq".to_symbol(), number),
+        ];
+        DocEntry::from_docs(docs, &types)
+    }
+
+    #[test]
+    fn markdown() {
+        let ans = "# lib
+
+## `gain`
+
+```
+fn gain(x: number, g: number) -> number
+```
+
+Multiplies the input by `g`.
+
+## `freq`
+
+```
+let freq: number
+```
+
+The frequency <Hz>.
+";
+        assert_eq!(render_markdown("lib", &entries()), ans);
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        let res = render_html("lib", &entries());
+        assert!(res.contains("<p>The frequency &lt;Hz&gt;.</p>"));
+        assert!(res.contains("<pre><code>fn gain(x: number, g: number) -&gt; number</code></pre>"));
+    }
+}
@@ -5,6 +5,7 @@ use crate::interner::{Symbol, ToSymbol};
 // unary
 pub(crate) const NEG: &str = "neg";
 pub(crate) const TOFLOAT: &str = "tofloat";
+pub(crate) const TOINT: &str = "toint";
 
 // binary
 pub(crate) const ADD: &str = "add";
@@ -40,9 +41,9 @@ pub(crate) const ROUND: &str = "round";
 // other operations
 pub(crate) const DELAY: &str = "delay";
 pub(crate) const MEM: &str = "mem";
-const BUILTIN_SYMS_UNSORTED: [&str; 31] = [
-    NEG, TOFLOAT, ADD, SUB, MULT, DIV, EQ, NE, LE, LT, GE, GT, MODULO, POW, AND, OR, SIN, COS, TAN,
-    ATAN, ATAN2, SQRT, ABS, LOG, MIN, MAX, CEIL, FLOOR, ROUND, DELAY, MEM,
+const BUILTIN_SYMS_UNSORTED: [&str; 32] = [
+    NEG, TOFLOAT, TOINT, ADD, SUB, MULT, DIV, EQ, NE, LE, LT, GE, GT, MODULO, POW, AND, OR, SIN,
+    COS, TAN, ATAN, ATAN2, SQRT, ABS, LOG, MIN, MAX, CEIL, FLOOR, ROUND, DELAY, MEM,
 ];
 thread_local!(pub (crate) static BUILTIN_SYMS: LazyCell<Vec<Symbol>> = LazyCell::new(|| {
     let mut v = BUILTIN_SYMS_UNSORTED
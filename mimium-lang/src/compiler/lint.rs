@@ -0,0 +1,363 @@
+//! Warnings for the code which compiles but is likely to be a mistake.
+//!
+//! The checks run on the parsed AST before the type inference, and only the code in the file
+//! being compiled is warned. The definitions loaded by `include` are checked for their uses, but
+//! the code inside the included files is not.
+use crate::ast::{sub_exprs, Expr};
+use crate::interner::{with_session_globals, ExprNodeId, Symbol};
+use crate::pattern::{pattern_names, TypedPattern};
+use crate::utils::error::ReportableError;
+use crate::utils::metadata::{FileId, Span};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WarningKind {
+    UnusedVariable(Symbol),
+    UnusedParameter(Symbol),
+    ShadowedGlobal(Symbol),
+    UnreachableCode,
+    // the name of the included file.
+    UnusedInclude(String),
+}
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning(pub WarningKind, pub Span);
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WarningKind::UnusedVariable(name) => write!(f, "Variable {name} is never used"),
+            WarningKind::UnusedParameter(name) => write!(f, "Parameter {name} is never used"),
+            WarningKind::ShadowedGlobal(name) => {
+                write!(f, "{name} shadows the global definition with the same name")
+            }
+            WarningKind::UnreachableCode => {
+                write!(
+                    f,
+                    "Unreachable code, the expression before it always returns"
+                )
+            }
+            WarningKind::UnusedInclude(file) => {
+                write!(f, "None of the definitions included from {file} is used")
+            }
+        }
+    }
+}
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+impl std::error::Error for Warning {}
+impl ReportableError for Warning {
+    fn get_span(&self) -> Span {
+        self.1.clone()
+    }
+    fn get_notes(&self) -> Vec<String> {
+        match self.0 {
+            WarningKind::UnusedVariable(_) | WarningKind::UnusedParameter(_) => {
+                vec!["Prefix the name with an underscore to silence this warning.".to_string()]
+            }
+            _ => vec![],
+        }
+    }
+    fn is_warning(&self) -> bool {
+        true
+    }
+}
+
+struct Binding {
+    name: Symbol,
+    span: Span,
+    is_param: bool,
+    // the bindings in the arms of `match` are not warned, because a variant without fields
+    // cannot be distinguished from a variable by the parser.
+    checked: bool,
+    used: bool,
+}
+
+struct Linter {
+    main_file: FileId,
+    // the toplevel definitions visible at the current point.
+    globals: Vec<Symbol>,
+    // the scopes of the local bindings, the innermost at the last.
+    scopes: Vec<Vec<Binding>>,
+    // the names referred and the files where they are referred.
+    uses: Vec<(Symbol, FileId)>,
+    warnings: Vec<Warning>,
+}
+
+// the definitions at the toplevel, with the expressions bound to them.
+fn toplevel_definitions(root: ExprNodeId) -> Vec<(Symbol, ExprNodeId)> {
+    let mut res = vec![];
+    let mut next = Some(root);
+    while let Some(e) = next {
+        next = match e.to_expr() {
+            Expr::Let(TypedPattern { pat, .. }, body, then) => {
+                let mut names = vec![];
+                pattern_names(&pat, &mut names);
+                res.extend(names.into_iter().map(|n| (n, body)));
+                then
+            }
+            Expr::LetRec(id, body, then) => {
+                res.push((id.id, body));
+                then
+            }
+            Expr::TypeDecl(_, then) | Expr::Then(_, then) => then,
+            _ => None,
+        };
+    }
+    res
+}
+
+// whether the evaluation of the expression always ends with `return`.
+fn always_returns(e: ExprNodeId) -> bool {
+    match e.to_expr() {
+        Expr::Return(_) => true,
+        Expr::Block(b) => b.is_some_and(always_returns),
+        Expr::Then(e, then) | Expr::Let(_, e, then) | Expr::LetRec(_, e, then) => {
+            always_returns(e) || then.is_some_and(always_returns)
+        }
+        Expr::If(_, then, Some(opt_else)) => always_returns(then) && always_returns(opt_else),
+        _ => false,
+    }
+}
+
+impl Linter {
+    fn is_local(&self) -> bool {
+        !self.scopes.is_empty()
+    }
+    fn warn(&mut self, kind: WarningKind, span: Span) {
+        if span.file == self.main_file {
+            self.warnings.push(Warning(kind, span))
+        }
+    }
+    fn push_scope(&mut self, bindings: Vec<Binding>) {
+        for b in bindings.iter() {
+            if b.checked && self.globals.contains(&b.name) && !b.name.as_str().starts_with('_') {
+                self.warn(WarningKind::ShadowedGlobal(b.name), b.span.clone());
+            }
+        }
+        self.scopes.push(bindings);
+    }
+    fn pop_scope(&mut self) {
+        let bindings = self.scopes.pop().unwrap_or_default();
+        for b in bindings {
+            if b.used || !b.checked || b.name.as_str().starts_with('_') {
+                continue;
+            }
+            let kind = if b.is_param {
+                WarningKind::UnusedParameter(b.name)
+            } else {
+                WarningKind::UnusedVariable(b.name)
+            };
+            self.warn(kind, b.span);
+        }
+    }
+    fn use_name(&mut self, name: Symbol, file: FileId) {
+        self.uses.push((name, file));
+        let binding = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.iter_mut().rev().find(|b| b.name == name));
+        if let Some(b) = binding {
+            b.used = true;
+        }
+    }
+    fn pattern_bindings(pat: &TypedPattern, checked: bool) -> Vec<Binding> {
+        let mut names = vec![];
+        pattern_names(&pat.pat, &mut names);
+        names
+            .into_iter()
+            .map(|name| Binding {
+                name,
+                span: pat.to_span(),
+                is_param: false,
+                checked,
+                used: false,
+            })
+            .collect()
+    }
+    fn walk(&mut self, e: ExprNodeId) {
+        match e.to_expr() {
+            Expr::Var(name) => self.use_name(name, e.to_span().file),
+            Expr::Lambda(params, _, body) => {
+                let bindings = params
+                    .iter()
+                    .map(|p| Binding {
+                        name: p.id,
+                        span: p.to_span(),
+                        is_param: true,
+                        checked: true,
+                        used: false,
+                    })
+                    .collect();
+                self.push_scope(bindings);
+                self.walk(body);
+                self.pop_scope();
+            }
+            Expr::Let(pat, body, then) if self.is_local() => {
+                self.walk(body);
+                self.push_scope(Self::pattern_bindings(&pat, true));
+                then.into_iter().for_each(|t| self.walk(t));
+                self.pop_scope();
+            }
+            Expr::Let(pat, body, then) => {
+                self.walk(body);
+                pattern_names(&pat.pat, &mut self.globals);
+                then.into_iter().for_each(|t| self.walk(t));
+            }
+            Expr::LetRec(id, body, then) if self.is_local() => {
+                let binding = Binding {
+                    name: id.id,
+                    span: id.to_span(),
+                    is_param: false,
+                    checked: true,
+                    used: false,
+                };
+                self.push_scope(vec![binding]);
+                self.walk(body);
+                then.into_iter().for_each(|t| self.walk(t));
+                self.pop_scope();
+            }
+            Expr::LetRec(id, body, then) => {
+                self.globals.push(id.id);
+                self.walk(body);
+                then.into_iter().for_each(|t| self.walk(t));
+            }
+            Expr::Then(first, Some(then)) => {
+                self.walk(first);
+                if self.is_local() && always_returns(first) {
+                    self.warn(WarningKind::UnreachableCode, then.to_span());
+                }
+                self.walk(then);
+            }
+            Expr::Match(scrutinee, arms) => {
+                self.walk(scrutinee);
+                for (pat, arm) in arms {
+                    self.push_scope(Self::pattern_bindings(&pat, false));
+                    self.walk(arm);
+                    self.pop_scope();
+                }
+            }
+            e => sub_exprs(&e).into_iter().for_each(|e| self.walk(e)),
+        }
+    }
+    // warns the `include`s in the main file whose definitions are used nowhere else.
+    fn check_includes(&mut self, definitions: &[(Symbol, ExprNodeId)]) {
+        let files = with_session_globals(|session_globals| {
+            session_globals
+                .source_map
+                .iter()
+                .filter_map(|(id, f)| Some((id, f.included_from.clone()?, f.name())))
+                .collect::<Vec<_>>()
+        });
+        for (file, span, name) in files.iter() {
+            if span.file != self.main_file {
+                continue;
+            }
+            // the files included from the included file are also a part of it.
+            let mut group = vec![*file];
+            let mut len = 0;
+            while len != group.len() {
+                len = group.len();
+                for (f, s, _) in files.iter() {
+                    if group.contains(&s.file) && !group.contains(f) {
+                        group.push(*f);
+                    }
+                }
+            }
+            let defined = definitions
+                .iter()
+                .filter(|(_, body)| group.contains(&body.to_span().file))
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>();
+            let used = self
+                .uses
+                .iter()
+                .any(|(n, f)| defined.contains(n) && !group.contains(f));
+            if !defined.is_empty() && !used {
+                let file_name = std::path::Path::new(name)
+                    .file_name()
+                    .map_or(name.clone(), |n| n.to_string_lossy().to_string());
+                self.warn(WarningKind::UnusedInclude(file_name), span.clone());
+            }
+        }
+    }
+}
+
+/// Checks the parsed program and returns the warnings for the file it was parsed from.
+pub fn lint(root: ExprNodeId) -> Vec<Warning> {
+    let definitions = toplevel_definitions(root);
+    let mut linter = Linter {
+        main_file: root.to_span().file,
+        globals: vec![],
+        scopes: vec![],
+        uses: vec![],
+        warnings: vec![],
+    };
+    linter.walk(root);
+    linter.check_includes(&definitions);
+    linter.warnings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler::parser::parse;
+    use crate::interner::ToSymbol;
+
+    fn kinds(src: &str) -> Vec<WarningKind> {
+        let ast = parse(src, None).unwrap();
+        lint(ast).into_iter().map(|w| w.0).collect()
+    }
+
+    #[test]
+    fn unused() {
+        let src = "fn f(x, y, _z){
+    let a = 1.0
+    let _b = 2.0
+    x
+}";
+        let ans = vec![
+            WarningKind::UnusedVariable("a".to_symbol()),
+            WarningKind::UnusedParameter("y".to_symbol()),
+        ];
+        assert_eq!(kinds(src), ans);
+    }
+
+    #[test]
+    fn shadowed_global() {
+        // only the globals defined before the binding are shadowed.
+        let src = "let gain = 0.5
+fn f(gain, rate){
+    let rate = gain * rate
+    let later = rate
+    later
+}
+let later = 1.0";
+        let ans = vec![WarningKind::ShadowedGlobal("gain".to_symbol())];
+        assert_eq!(kinds(src), ans);
+    }
+
+    #[test]
+    fn unreachable() {
+        let src = "fn f(x){
+    if (x > 0.0) {
+        return 1.0
+    } else {
+        return 0.0
+    }
+    x
+}
+fn g(x){
+    if (x > 0.0) {
+        return 1.0
+    } else {
+        0.0
+    }
+    x
+}";
+        assert_eq!(kinds(src), vec![WarningKind::UnreachableCode]);
+    }
+}
@@ -2,7 +2,7 @@ use super::intrinsics;
 use super::typing::{self, infer_root, InferContext};
 use crate::interner::{ExprNodeId, Symbol, ToSymbol, TypeNodeId};
 use crate::pattern::{Pattern, TypedId, TypedPattern};
-use crate::{function, numeric, unit};
+use crate::{function, integer, numeric, unit};
 pub mod convert_pronoun;
 pub(crate) mod recursecheck;
 use crate::mir::{self, Argument, Instruction, Mir, StateSize, VPtr, VReg, Value};
@@ -15,6 +15,7 @@ use crate::utils::error::ReportableError;
 use crate::utils::metadata::Span;
 
 use crate::ast::{Expr, Literal};
+use crate::ast_interpreter;
 use itertools::Itertools;
 
 // pub mod closure_convert;
@@ -30,6 +31,8 @@ struct ContextData {
     pub next_state_offset: Option<Vec<StateSize>>,
     pub cur_state_pos: Vec<StateSize>,
     pub push_sum: Vec<StateSize>,
+    // true if the body of the function is wrapped by the feed, so that `return` updates the state.
+    pub in_feed: bool,
 }
 
 #[derive(Debug)]
@@ -116,13 +119,28 @@ impl Context {
         }
     }
     fn make_binop_intrinsic(
-        &self,
+        &mut self,
         label: Symbol,
         args: &[(VPtr, TypeNodeId)],
     ) -> Option<Instruction> {
         debug_assert_eq!(args.len(), 2);
-        let a0 = args[0].0.clone();
-        let a1 = args[1].0.clone();
+        let mut a0 = args[0].0.clone();
+        let mut a1 = args[1].0.clone();
+        if args[0].1.to_type() == Type::Primitive(PType::Int) {
+            match label.as_str() {
+                intrinsics::ADD => return Some(Instruction::AddI(a0, a1)),
+                intrinsics::SUB => return Some(Instruction::SubI(a0, a1)),
+                intrinsics::MULT => return Some(Instruction::MulI(a0, a1)),
+                intrinsics::DIV => return Some(Instruction::DivI(a0, a1)),
+                intrinsics::POW => return Some(Instruction::PowI(a0, a1)),
+                intrinsics::MODULO => return Some(Instruction::ModI(a0, a1)),
+                // comparison operators are only for float in VM.
+                _ => {
+                    a0 = self.push_inst(Instruction::CastItoF(a0));
+                    a1 = self.push_inst(Instruction::CastItoF(a1));
+                }
+            }
+        }
         match label.as_str() {
             intrinsics::ADD => Some(Instruction::AddF(a0, a1)),
             intrinsics::SUB => Some(Instruction::SubF(a0, a1)),
@@ -150,12 +168,17 @@ impl Context {
         debug_assert_eq!(args.len(), 1);
         let a0 = args[0].0.clone();
         let a0_ty = args[0].1;
+        let is_int = a0_ty.to_type() == Type::Primitive(PType::Int);
         match label.as_str() {
+            intrinsics::NEG if is_int => Some(Instruction::NegI(a0)),
             intrinsics::NEG => Some(Instruction::NegF(a0)),
             intrinsics::SQRT => Some(Instruction::SqrtF(a0)),
+            intrinsics::ABS if is_int => Some(Instruction::AbsI(a0)),
             intrinsics::ABS => Some(Instruction::AbsF(a0)),
             intrinsics::SIN => Some(Instruction::SinF(a0)),
             intrinsics::COS => Some(Instruction::CosF(a0)),
+            intrinsics::TOFLOAT => Some(Instruction::CastItoF(a0)),
+            intrinsics::TOINT => Some(Instruction::CastFtoI(a0)),
             intrinsics::MEM => {
                 self.get_current_fn()
                     .state_sizes
@@ -199,6 +222,45 @@ impl Context {
         self.get_current_basicblock().0.push((res.clone(), inst));
         res
     }
+    // emits the return from the current function. The state offset shifted by the stateful
+    // function calls so far is restored before returning.
+    fn emit_return(&mut self, res: VPtr, rt: TypeNodeId) {
+        let push_sum = self.get_ctxdata().push_sum.clone();
+        if !push_sum.is_empty() {
+            self.get_current_basicblock().0.push((
+                Arc::new(mir::Value::None),
+                Instruction::PopStateOffset(push_sum),
+            )); //todo:offset size
+        }
+        match (res.as_ref(), rt.to_type()) {
+            (_, Type::Primitive(PType::Unit)) => {
+                let _ = self.push_inst(Instruction::Return(Arc::new(Value::None), rt));
+            }
+            (Value::State(v), _) => {
+                let _ = self.push_inst(Instruction::ReturnFeed(v.clone(), rt));
+            }
+            (Value::Function(i), _) => {
+                let idx = self.push_inst(Instruction::Uinteger(*i as u64));
+                let cls = self.push_inst(Instruction::Closure(idx));
+                let _ = self.push_inst(Instruction::CloseUpValues(cls.clone(), rt));
+                let _ = self.push_inst(Instruction::Return(cls, rt));
+            }
+            (_, _) => {
+                if rt.to_type().contains_function() {
+                    let _ = self.push_inst(Instruction::CloseUpValues(res.clone(), rt));
+                    let _ = self.push_inst(Instruction::Return(res.clone(), rt));
+                } else {
+                    let _ = self.push_inst(Instruction::Return(res.clone(), rt));
+                }
+            }
+        };
+    }
+    fn is_returned(&mut self) -> bool {
+        matches!(
+            self.get_current_basicblock().0.last(),
+            Some((_, Instruction::Return(..) | Instruction::ReturnFeed(..)))
+        )
+    }
     fn add_bind(&mut self, bind: (Symbol, VPtr)) {
         self.valenv.add_bind(&[bind]);
     }
@@ -241,6 +303,26 @@ impl Context {
                     self.add_bind_pattern(&tpat, elem_v, *cty, is_global)?;
                 }
             }
+            (Pattern::Record(fields), Type::Struct(tfields)) => {
+                for (name, pat) in fields.iter() {
+                    let idx = tfields
+                        .iter()
+                        .position(|(n, _)| n == name)
+                        .expect("typing error in the previous stage");
+                    let elem_v = self.push_inst(Instruction::GetElement {
+                        value: v.clone(),
+                        ty,
+                        array_idx: 0,
+                        tuple_offset: idx as u64,
+                    });
+                    let tid = Type::Unknown.into_id_with_span(span.clone());
+                    let tpat = TypedPattern {
+                        pat: pat.clone(),
+                        ty: tid,
+                    };
+                    self.add_bind_pattern(&tpat, elem_v, tfields[idx].1, is_global)?;
+                }
+            }
             _ => {
                 panic!("typing error in the previous stage")
             }
@@ -253,9 +335,10 @@ impl Context {
         args: &[VPtr],
         argtypes: &[TypeNodeId],
         parent_i: Option<usize>,
+        span: Span,
     ) -> usize {
         let index = self.program.functions.len();
-        let newf = mir::Function::new(index, name, args, argtypes, parent_i);
+        let newf = mir::Function::new(index, name, args, argtypes, parent_i, span);
         self.program.functions.push(newf);
         index
     }
@@ -264,13 +347,14 @@ impl Context {
         fname: Symbol,
         abinds: &[(Symbol, VPtr)],
         types: &[TypeNodeId],
+        span: Span,
         mut action: F,
     ) -> Result<(usize, VPtr), CompileError> {
         self.valenv.extend();
         self.valenv.add_bind(abinds);
         let args = abinds.iter().map(|(_, a)| a.clone()).collect::<Vec<_>>();
         let label = self.get_ctxdata().func_i;
-        let c_idx = self.make_new_function(fname, &args, types, Some(label));
+        let c_idx = self.make_new_function(fname, &args, types, Some(label), span);
 
         self.data.push(ContextData {
             func_i: c_idx,
@@ -458,15 +542,13 @@ impl Context {
             })
             .try_collect()
     }
-    fn eval_block(
-        &mut self,
-        block: Option<ExprNodeId>,
-    ) -> Result<(VPtr, TypeNodeId), CompileError> {
+    // evaluates the action in the new basic block.
+    fn eval_block<F>(&mut self, action: F) -> Result<(VPtr, TypeNodeId), CompileError>
+    where
+        F: FnOnce(&mut Self) -> Result<(VPtr, TypeNodeId), CompileError>,
+    {
         self.add_new_basicblock();
-        let (e, rt) = match block {
-            Some(e) => self.eval_expr(e),
-            None => Ok((Arc::new(Value::None), unit!())),
-        }?;
+        let (e, rt) = action(self)?;
         //if returning non-closure function, make closure
         let e = match e.as_ref() {
             Value::Function(idx) => {
@@ -477,6 +559,185 @@ impl Context {
         };
         Ok((e, rt))
     }
+    // evaluates `then_f` or `else_f` in the new blocks depending on the condition `c`.
+    fn eval_if_with<F1, F2>(
+        &mut self,
+        c: VPtr,
+        then_f: F1,
+        else_f: F2,
+    ) -> Result<VPtr, CompileError>
+    where
+        F1: FnOnce(&mut Self) -> Result<(VPtr, TypeNodeId), CompileError>,
+        F2: FnOnce(&mut Self) -> Result<(VPtr, TypeNodeId), CompileError>,
+    {
+        let cond_bidx = self.get_ctxdata().current_bb;
+
+        // This is just a placeholder. At this point, the locations of
+        // the block are not determined yet. These 0s will be
+        // overwritten later.
+        let _ = self.push_inst(Instruction::JmpIf(c, 0, 0, 0));
+
+        //insert then block
+        let then_bidx = cond_bidx + 1;
+        let (t, _) = self.eval_block(then_f)?;
+        //jmp to ret is inserted in bytecodegen
+        //insert else block
+        let else_bidx = self.get_ctxdata().current_bb + 1;
+        let (e, _) = self.eval_block(else_f)?;
+        //insert return block
+        self.add_new_basicblock();
+        let res = self.push_inst(Instruction::Phi(t, e));
+        let phi_bidx = self.get_ctxdata().current_bb;
+
+        // overwrite JmpIf
+        let jmp_if = self
+            .get_current_fn()
+            .body
+            .get_mut(cond_bidx)
+            .expect("no basic block found")
+            .0
+            .last_mut()
+            .expect("the block contains no inst?");
+        match &mut jmp_if.1 {
+            Instruction::JmpIf(_, then_dst, else_dst, phi_dst) => {
+                *then_dst = then_bidx as _;
+                *else_dst = else_bidx as _;
+                *phi_dst = phi_bidx as _;
+            }
+            _ => panic!("the last block should be Jmp"),
+        }
+        Ok(res)
+    }
+    // The value of the sum type is laid out as the tag (index of the variant) followed by
+    // the fields of the variant. The size of the whole value is decided by the largest variant.
+    fn variant_view_type(fields: &[TypeNodeId]) -> TypeNodeId {
+        let elems = std::iter::once(integer!())
+            .chain(fields.iter().copied())
+            .collect();
+        Type::Tuple(elems).into_id()
+    }
+    fn eval_variant(
+        &mut self,
+        name: Symbol,
+        args: &[ExprNodeId],
+        ty: TypeNodeId,
+    ) -> Result<VPtr, CompileError> {
+        let t = ty.to_type();
+        let (tag, fields) = t
+            .get_variant(name)
+            .expect("typing error in the previous stage");
+        let view_t = Self::variant_view_type(fields);
+        let alloc_insert_point = self.get_current_basicblock().0.len();
+        let dst = self.gen_new_register();
+        let tag_ptr = self.push_inst(Instruction::GetElement {
+            value: dst.clone(),
+            ty: view_t,
+            array_idx: 0,
+            tuple_offset: 0,
+        });
+        let tag_v = self.push_inst(Instruction::Integer(tag as i64));
+        self.push_inst(Instruction::Store(tag_ptr, tag_v, integer!()));
+        for (i, e) in args.iter().enumerate() {
+            let (v, elem_ty) = self.eval_expr(*e)?;
+            // a function stored in a variant must be a closure
+            let v = match v.as_ref() {
+                Value::Function(idx) => {
+                    let f = self.push_inst(Instruction::Uinteger(*idx as u64));
+                    self.push_inst(Instruction::Closure(f))
+                }
+                _ => v,
+            };
+            let ptr = self.push_inst(Instruction::GetElement {
+                value: dst.clone(),
+                ty: view_t,
+                array_idx: 0,
+                tuple_offset: i as u64 + 1,
+            });
+            self.push_inst(Instruction::Store(ptr, v, elem_ty));
+        }
+        self.get_current_basicblock()
+            .0
+            .insert(alloc_insert_point, (dst.clone(), Instruction::Alloc(ty)));
+        Ok(dst)
+    }
+    // The arms are tested from the top. The last arm is taken without testing
+    // because the exhaustiveness is already checked in the type inference.
+    fn eval_match_arms(
+        &mut self,
+        v: &VPtr,
+        st: TypeNodeId,
+        arms: &[(TypedPattern, ExprNodeId)],
+        ty: TypeNodeId,
+    ) -> Result<VPtr, CompileError> {
+        let ((tpat, body), rest) = arms.split_first().expect("match without arms");
+        let t = st.to_type();
+        let variant = match &tpat.pat {
+            Pattern::Variant(name, pats) => Some((*name, pats.clone())),
+            Pattern::Single(name) if t.get_variant(*name).is_some() => Some((*name, vec![])),
+            _ => None,
+        };
+        match variant {
+            Some((name, pats)) if !rest.is_empty() => {
+                let (tag, fields) = t
+                    .get_variant(name)
+                    .expect("typing error in the previous stage");
+                let tag_ptr = self.push_inst(Instruction::GetElement {
+                    value: v.clone(),
+                    ty: Self::variant_view_type(fields),
+                    array_idx: 0,
+                    tuple_offset: 0,
+                });
+                let tag_f = self.push_inst(Instruction::CastItoF(tag_ptr));
+                let expected = self.push_inst(Instruction::Float(tag as f64));
+                let c = self.push_inst(Instruction::Eq(tag_f, expected));
+                self.eval_if_with(
+                    c,
+                    |this| this.eval_match_arm(v, st, name, &pats, *body),
+                    |this| Ok((this.eval_match_arms(v, st, rest, ty)?, ty)),
+                )
+            }
+            Some((name, pats)) => Ok(self.eval_match_arm(v, st, name, &pats, *body)?.0),
+            None => {
+                // bindings in the arm are not visible from the outside.
+                let len = self.valenv.current_len();
+                self.add_bind_pattern(tpat, v.clone(), st, false)?;
+                let (res, _) = self.eval_expr(*body)?;
+                self.valenv.truncate_current(len);
+                Ok(res)
+            }
+        }
+    }
+    fn eval_match_arm(
+        &mut self,
+        v: &VPtr,
+        st: TypeNodeId,
+        name: Symbol,
+        pats: &[Pattern],
+        body: ExprNodeId,
+    ) -> Result<(VPtr, TypeNodeId), CompileError> {
+        let t = st.to_type();
+        let (_, fields) = t
+            .get_variant(name)
+            .expect("typing error in the previous stage");
+        let view_t = Self::variant_view_type(fields);
+        let len = self.valenv.current_len();
+        for (i, (pat, fty)) in pats.iter().zip(fields.iter()).enumerate() {
+            let elem_v = self.push_inst(Instruction::GetElement {
+                value: v.clone(),
+                ty: view_t,
+                array_idx: 0,
+                tuple_offset: i as u64 + 1,
+            });
+            let tpat = TypedPattern {
+                pat: pat.clone(),
+                ty: Type::Unknown.into_id_with_span(body.to_span()),
+            };
+            self.add_bind_pattern(&tpat, elem_v, *fty, false)?;
+        }
+        let res = self.eval_expr(body)?;
+        self.valenv.truncate_current(len);
+        Ok(res)
+    }
     pub fn eval_expr(&mut self, e: ExprNodeId) -> Result<(VPtr, TypeNodeId), CompileError> {
         let span = e.to_span();
         let ty = self.typeenv.lookup_res(e);
@@ -486,6 +747,9 @@ impl Context {
                 let t = InferContext::infer_type_literal(lit).map_err(CompileError::from)?;
                 Ok((v, t))
             }
+            Expr::Var(name) if self.typeenv.is_variant_constructor(e) => {
+                Ok((self.eval_variant(*name, &[], ty)?, ty))
+            }
             Expr::Var(name) => Ok((self.eval_rvar(*name, ty, &span)?, ty)),
             Expr::Block(b) => {
                 if let Some(block) = b {
@@ -520,9 +784,97 @@ impl Context {
                 // from the type information.
                 Ok((dst, ty))
             }
+            Expr::RecordLiteral(fields) => {
+                let alloc_insert_point = self.get_current_basicblock().0.len();
+                let dst = self.gen_new_register();
+                let t = ty.to_type();
+                // fields are evaluated in the order written in the source, and
+                // stored into the position determined by the struct type.
+                for (name, e) in fields.iter() {
+                    let (v, elem_ty) = self.eval_expr(*e)?;
+                    // a function stored in a record must be a closure
+                    let v = match v.as_ref() {
+                        Value::Function(idx) => {
+                            let f = self.push_inst(Instruction::Uinteger(*idx as u64));
+                            self.push_inst(Instruction::Closure(f))
+                        }
+                        _ => v,
+                    };
+                    let idx = t
+                        .get_field_index(*name)
+                        .expect("typing error in the previous stage");
+                    let ptr = self.push_inst(Instruction::GetElement {
+                        value: dst.clone(),
+                        ty,
+                        array_idx: 0,
+                        tuple_offset: idx as u64,
+                    });
+                    self.push_inst(Instruction::Store(ptr, v, elem_ty));
+                }
+                self.get_current_basicblock()
+                    .0
+                    .insert(alloc_insert_point, (dst.clone(), Instruction::Alloc(ty)));
+                Ok((dst, ty))
+            }
+            Expr::FieldAccess(record, name) => {
+                let (v, record_ty) = self.eval_expr(*record)?;
+                let idx = record_ty
+                    .to_type()
+                    .get_field_index(*name)
+                    .expect("typing error in the previous stage");
+                let res = self.push_inst(Instruction::GetElement {
+                    value: v,
+                    ty: record_ty,
+                    array_idx: 0,
+                    tuple_offset: idx as u64,
+                });
+                Ok((res, ty))
+            }
+            Expr::ArrayLiteral(items) => {
+                let elem_ty = match ty.to_type() {
+                    Type::Array(elem_ty) => elem_ty,
+                    _ => unreachable!("typing error in the previous stage"),
+                };
+                let len = self.push_inst(Instruction::Uinteger(items.len() as u64));
+                let dst = self.push_inst(Instruction::AllocArray(len, elem_ty));
+                for (i, e) in items.iter().enumerate() {
+                    let (v, _) = self.eval_expr(*e)?;
+                    // a function stored in an array must be a closure
+                    let v = match v.as_ref() {
+                        Value::Function(idx) => {
+                            let f = self.push_inst(Instruction::Uinteger(*idx as u64));
+                            self.push_inst(Instruction::Closure(f))
+                        }
+                        _ => v,
+                    };
+                    if elem_ty.to_type().contains_function() {
+                        //closures stored in the heap need to be closed immidiately
+                        self.push_inst(Instruction::CloseUpValues(v.clone(), elem_ty));
+                    }
+                    let idx = self.push_inst(Instruction::Integer(i as i64));
+                    self.push_inst(Instruction::SetArrayElem(dst.clone(), idx, v, elem_ty));
+                }
+                Ok((dst, ty))
+            }
+            Expr::ArrayAccess(arr, idx) => {
+                let (arr, _) = self.eval_expr(*arr)?;
+                let (idx, _) = self.eval_expr(*idx)?;
+                let res = self.push_inst(Instruction::GetArrayElem(arr, idx, ty));
+                Ok((res, ty))
+            }
             Expr::Proj(_, _) => todo!(),
-            Expr::ArrayAccess(_, _) => todo!(),
 
+            Expr::Apply(f, args) if self.typeenv.is_variant_constructor(*f) => {
+                let Expr::Var(name) = f.to_expr() else {
+                    unreachable!()
+                };
+                Ok((self.eval_variant(name, args, ty)?, ty))
+            }
+            Expr::Apply(f, args) if self.typeenv.is_type_constructor(*f) => {
+                // the constructor of a nominal type has no runtime representation.
+                let (v, _t) = self.eval_expr(args[0])?;
+                Ok((v, ty))
+            }
             Expr::Apply(f, args) => {
                 let (f, ft) = self.eval_expr(*f)?;
                 let del = self.make_delay(&f, args)?;
@@ -585,42 +937,17 @@ impl Context {
                     .collect::<Vec<_>>();
 
                 let name = self.consume_fnlabel();
-                let (c_idx, f) = self.do_in_child_ctx(name, &binds, &atypes, |ctx, c_idx| {
-                    let (res, _) = ctx.eval_expr(*body)?;
-
-                    let push_sum = ctx.get_ctxdata().push_sum.clone();
-                    if !push_sum.is_empty() {
-                        ctx.get_current_basicblock().0.push((
-                            Arc::new(mir::Value::None),
-                            Instruction::PopStateOffset(push_sum),
-                        )); //todo:offset size
-                    }
-                    match (res.as_ref(), rt.to_type()) {
-                        (_, Type::Primitive(PType::Unit)) => {
-                            let _ = ctx.push_inst(Instruction::Return(Arc::new(Value::None), rt));
-                        }
-                        (Value::State(v), _) => {
-                            let _ = ctx.push_inst(Instruction::ReturnFeed(v.clone(), rt));
+                let (c_idx, f) =
+                    self.do_in_child_ctx(name, &binds, &atypes, span.clone(), |ctx, c_idx| {
+                        let (res, _) = ctx.eval_expr(*body)?;
+                        // the body may end with `return`, which has already emitted the return.
+                        if !ctx.is_returned() {
+                            ctx.emit_return(res, rt);
                         }
-                        (Value::Function(i), _) => {
-                            let idx = ctx.push_inst(Instruction::Uinteger(*i as u64));
-                            let cls = ctx.push_inst(Instruction::Closure(idx));
-                            let _ = ctx.push_inst(Instruction::CloseUpValues(cls.clone(), rt));
-                            let _ = ctx.push_inst(Instruction::Return(cls, rt));
-                        }
-                        (_, _) => {
-                            if rt.to_type().contains_function() {
-                                let _ = ctx.push_inst(Instruction::CloseUpValues(res.clone(), rt));
-                                let _ = ctx.push_inst(Instruction::Return(res.clone(), rt));
-                            } else {
-                                let _ = ctx.push_inst(Instruction::Return(res.clone(), rt));
-                            }
-                        }
-                    };
 
-                    let f = Arc::new(Value::Function(c_idx));
-                    Ok((f, rt))
-                })?;
+                        let f = Arc::new(Value::Function(c_idx));
+                        Ok((f, rt))
+                    })?;
                 let child = self.program.functions.get_mut(c_idx).unwrap();
                 let res = if child.upindexes.is_empty() {
                     //todo:make Closure
@@ -637,6 +964,7 @@ impl Context {
                 self.get_ctxdata()
                     .cur_state_pos
                     .push(StateSize { size: 1, ty });
+                self.get_ctxdata().in_feed = true;
                 self.add_bind((*id, res.clone()));
                 let (retv, _t) = self.eval_expr(*expr)?;
                 self.get_current_fn()
@@ -706,6 +1034,10 @@ impl Context {
                     Ok((Arc::new(Value::None), unit!()))
                 }
             }
+            Expr::TypeDecl(_decl, then) => match then {
+                Some(then_e) => self.eval_expr(*then_e),
+                None => Ok((Arc::new(Value::None), unit!())),
+            },
             Expr::Assign(assignee, body) => {
                 let (src, ty) = self.eval_expr(*body)?;
                 self.eval_assign(*assignee, src, ty, &span)?;
@@ -720,48 +1052,35 @@ impl Context {
             }
             Expr::If(cond, then, else_) => {
                 let (c, _) = self.eval_expr(*cond)?;
-                let cond_bidx = self.get_ctxdata().current_bb;
-
-                // This is just a placeholder. At this point, the locations of
-                // the block are not determined yet. These 0s will be
-                // overwritten later.
-                let _ = self.push_inst(Instruction::JmpIf(c, 0, 0, 0));
-
-                //insert then block
-                let then_bidx = cond_bidx + 1;
-                let (t, _) = self.eval_block(Some(*then))?;
-                //jmp to ret is inserted in bytecodegen
-                //insert else block
-                let else_bidx = self.get_ctxdata().current_bb + 1;
-                let (e, _) = self.eval_block(*else_)?;
-                //insert return block
-                self.add_new_basicblock();
-                let res = self.push_inst(Instruction::Phi(t, e));
-                let phi_bidx = self.get_ctxdata().current_bb;
-
-                // overwrite JmpIf
-                let jmp_if = self
-                    .get_current_fn()
-                    .body
-                    .get_mut(cond_bidx)
-                    .expect("no basic block found")
-                    .0
-                    .last_mut()
-                    .expect("the block contains no inst?");
-                match &mut jmp_if.1 {
-                    Instruction::JmpIf(_, then_dst, else_dst, phi_dst) => {
-                        *then_dst = then_bidx as _;
-                        *else_dst = else_bidx as _;
-                        *phi_dst = phi_bidx as _;
-                    }
-                    _ => panic!("the last block should be Jmp"),
-                }
-
+                let res = self.eval_if_with(
+                    c,
+                    |this| this.eval_expr(*then),
+                    |this| match else_ {
+                        Some(e) => this.eval_expr(*e),
+                        None => Ok((Arc::new(Value::None), unit!())),
+                    },
+                )?;
+                Ok((res, ty))
+            }
+            Expr::Match(scrutinee, arms) => {
+                let (v, st) = self.eval_expr(*scrutinee)?;
+                let res = self.eval_match_arms(&v, st, arms, ty)?;
                 Ok((res, ty))
             }
-            Expr::Bracket(_) => todo!(),
-            Expr::Escape(_) => todo!(),
-            Expr::Error => todo!(),
+            Expr::Return(v) => {
+                let (v, t) = self.eval_expr(*v)?;
+                let v = if self.get_ctxdata().in_feed {
+                    Arc::new(Value::State(v))
+                } else {
+                    v
+                };
+                self.emit_return(v.clone(), t);
+                Ok((v, ty))
+            }
+            Expr::Bracket(_) | Expr::Escape(_) => {
+                unreachable!("macros should be expanded before generating MIR")
+            }
+            Expr::Error => unreachable!("the source with syntax errors should not be compiled"),
         }
     }
 }
@@ -799,23 +1118,127 @@ impl From<typing::Error> for CompileError {
     }
 }
 impl ReportableError for CompileError {
-    fn get_span(&self) -> std::ops::Range<usize> {
+    fn get_span(&self) -> Span {
         self.1.clone()
     }
+    fn get_secondary_labels(&self) -> Vec<(Span, String)> {
+        match &self.0 {
+            CompileErrorKind::TypingFailure(k) => k.secondary_labels(),
+            _ => vec![],
+        }
+    }
+    fn get_notes(&self) -> Vec<String> {
+        match &self.0 {
+            CompileErrorKind::TypingFailure(k) => k.notes(),
+            _ => vec![],
+        }
+    }
 }
 
-pub fn compile(
+/// Checks the types of the expression without generating the MIR. This is used for the source
+/// recovered from the syntax errors, whose `Expr::Error` parts can be any type.
+pub fn typecheck(
     root_expr_id: ExprNodeId,
     builtin_types: &[(Symbol, TypeNodeId)],
-    file_path: Option<Symbol>,
-) -> Result<Mir, Box<dyn ReportableError>> {
-    let ast2 = recursecheck::convert_recurse(root_expr_id);
-    let expr2 = convert_pronoun::convert_pronoun(ast2).map_err(|e| {
+    unloaded_fns: &[(Symbol, Symbol)],
+) -> Result<(), Box<dyn ReportableError>> {
+    let ast = recursecheck::convert_recurse(root_expr_id);
+    typecheck_converted(ast, builtin_types, unloaded_fns).map(|_| ())
+}
+// `unloaded_fns` are the functions given by the plugins not loaded, with the names of the
+// plugins, which are suggested when they are not found.
+fn typecheck_converted(
+    ast: ExprNodeId,
+    builtin_types: &[(Symbol, TypeNodeId)],
+    unloaded_fns: &[(Symbol, Symbol)],
+) -> Result<(ExprNodeId, InferContext), Box<dyn ReportableError>> {
+    let checked = convert_pronoun::convert_pronoun(ast).map_err(|e| {
         let eb: Box<dyn ReportableError> = Box::new(e);
         eb
     })?;
-    let infer_ctx = infer_root(expr2, builtin_types)
+    let infer_ctx = infer_root(checked, builtin_types, unloaded_fns)
         .map_err(|err| Box::new(CompileError::from(err)) as Box<dyn ReportableError>)?;
+    Ok((checked, infer_ctx))
+}
+/// Infers the types of the definitions at the toplevel of the source wrapped in the global
+/// context, in the order of the definitions. The types are the ones before the macros are
+/// expanded, and generalized if they are polymorphic.
+pub fn infer_toplevel_types(
+    root_expr_id: ExprNodeId,
+    builtin_types: &[(Symbol, TypeNodeId)],
+    unloaded_fns: &[(Symbol, Symbol)],
+) -> Result<Vec<(Symbol, TypeNodeId)>, Box<dyn ReportableError>> {
+    let ast = recursecheck::convert_recurse(root_expr_id);
+    let (checked, infer_ctx) = typecheck_converted(ast, builtin_types, unloaded_fns)?;
+    let toplevel = match checked.to_expr() {
+        Expr::Let(_, global, None) => match global.to_expr() {
+            Expr::Lambda(_, _, body) => body,
+            _ => checked,
+        },
+        _ => checked,
+    };
+    let mut res = vec![];
+    let mut next = Some(toplevel);
+    while let Some(e) = next {
+        next = match e.to_expr() {
+            Expr::Let(
+                TypedPattern {
+                    pat: Pattern::Single(name),
+                    ..
+                },
+                body,
+                then,
+            ) => {
+                res.push((name, infer_ctx.lookup_scheme(body)));
+                then
+            }
+            Expr::LetRec(id, body, then) => {
+                res.push((id.id, infer_ctx.lookup_res(body)));
+                then
+            }
+            Expr::Let(_, _, then) | Expr::TypeDecl(_, then) => then,
+            Expr::Then(_, then) => then,
+            _ => None,
+        };
+    }
+    Ok(res)
+}
+/// Infers the types of all the expressions in the source wrapped in the global context, for the
+/// tools like the language server. The expressions are the ones converted for the type
+/// inference, which keep the spans of the original ones. The types of the names at their
+/// binding sites are returned as well, located by the spans of the patterns.
+#[allow(clippy::type_complexity)]
+pub fn infer_expr_types(
+    root_expr_id: ExprNodeId,
+    builtin_types: &[(Symbol, TypeNodeId)],
+    unloaded_fns: &[(Symbol, Symbol)],
+) -> Result<(Vec<(ExprNodeId, TypeNodeId)>, Vec<(Span, TypeNodeId)>), Box<dyn ReportableError>> {
+    let ast = recursecheck::convert_recurse(root_expr_id);
+    let (_, infer_ctx) = typecheck_converted(ast, builtin_types, unloaded_fns)?;
+    Ok((
+        infer_ctx.expr_types().collect(),
+        infer_ctx.binding_types().collect(),
+    ))
+}
+pub fn compile(
+    root_expr_id: ExprNodeId,
+    builtin_types: &[(Symbol, TypeNodeId)],
+    unloaded_fns: &[(Symbol, Symbol)],
+    file_path: Option<Symbol>,
+) -> Result<Mir, Box<dyn ReportableError>> {
+    let ast2 = recursecheck::convert_recurse(root_expr_id);
+    let ast2 = if ast_interpreter::contains_macro(ast2) {
+        // the types of the code are checked before the expansion, so that the errors are
+        // reported at the macro definitions rather than the generated code.
+        typecheck_converted(ast2, builtin_types, unloaded_fns)?;
+        ast_interpreter::expand_macros(ast2).map_err(|e| {
+            let eb: Box<dyn ReportableError> = Box::new(e);
+            eb
+        })?
+    } else {
+        ast2
+    };
+    let (expr2, infer_ctx) = typecheck_converted(ast2, builtin_types, unloaded_fns)?;
     let mut ctx = Context::new(infer_ctx);
     let _res = ctx.eval_expr(expr2).map_err(|e| {
         let eb: Box<dyn ReportableError> = Box::new(e);
@@ -27,7 +27,7 @@ impl fmt::Display for Error {
 }
 impl std::error::Error for Error {}
 impl ReportableError for Error {
-    fn get_span(&self) -> std::ops::Range<usize> {
+    fn get_span(&self) -> Span {
         match self {
             Self::NoParentSelf(s) => s.clone(),
         }
@@ -77,6 +77,51 @@ where
             let elem = conversion(e)?;
             Ok(elem.map(|e| Expr::Proj(e, idx).into_id(span)))
         }
+        Expr::RecordLiteral(fields) => {
+            let elems: Vec<ConvertResult> =
+                fields.iter().map(|(_, e)| conversion(*e)).try_collect()?;
+            let fields_mapped = fields
+                .iter()
+                .zip(elems.iter())
+                .map(|((name, _), e)| (*name, get_content(*e)))
+                .collect();
+            if elems.iter().any(|e| e.is_err()) {
+                Ok(ConvertResult::Err(
+                    Expr::RecordLiteral(fields_mapped).into_id(span),
+                ))
+            } else {
+                Ok(ConvertResult::Ok(
+                    Expr::RecordLiteral(fields_mapped).into_id(span),
+                ))
+            }
+        }
+        Expr::FieldAccess(e, name) => {
+            let elem = conversion(e)?;
+            Ok(elem.map(|e| Expr::FieldAccess(e, name).into_id(span)))
+        }
+        Expr::ArrayLiteral(v) => {
+            let elems: Vec<ConvertResult> = v.into_iter().map(&conversion).try_collect()?;
+            let elems_mapped: Vec<ExprNodeId> = elems.iter().map(|e| get_content(*e)).collect();
+            if elems.iter().any(|e| e.is_err()) {
+                Ok(ConvertResult::Err(
+                    Expr::ArrayLiteral(elems_mapped).into_id(span),
+                ))
+            } else {
+                Ok(ConvertResult::Ok(
+                    Expr::ArrayLiteral(elems_mapped).into_id(span),
+                ))
+            }
+        }
+        Expr::ArrayAccess(arr, idx) => {
+            let arr = conversion(arr)?;
+            let idx = conversion(idx)?;
+            let content = Expr::ArrayAccess(get_content(arr), get_content(idx)).into_id(span);
+            if arr.is_ok() && idx.is_ok() {
+                Ok(ConvertResult::Ok(content))
+            } else {
+                Ok(ConvertResult::Err(content))
+            }
+        }
         Expr::Let(id, body, then) => {
             let body = conversion(body)?;
             let then = opt_conversion(then)?;
@@ -97,6 +142,16 @@ where
                 Ok(ConvertResult::Err(e_id))
             }
         }
+        Expr::TypeDecl(decl, then) => {
+            let then = opt_conversion(then)?;
+            if let Ok(t) = then.transpose() {
+                Ok(ConvertResult::Ok(Expr::TypeDecl(decl, t).into_id(span)))
+            } else {
+                Ok(ConvertResult::Err(
+                    Expr::TypeDecl(decl, then.map(get_content)).into_id(span),
+                ))
+            }
+        }
         Expr::Lambda(params, r_type, body) => {
             // Note: params and r_type cannot be handled by conversion() because
             //       these are Type, not Expr.
@@ -146,6 +201,22 @@ where
                 }
             }
         }
+        Expr::Match(scrutinee, arms) => {
+            let scrutinee = conversion(scrutinee)?;
+            let bodies: Vec<ConvertResult> =
+                arms.iter().map(|(_, e)| conversion(*e)).try_collect()?;
+            let arms_mapped = arms
+                .iter()
+                .zip(bodies.iter())
+                .map(|((pat, _), e)| (pat.clone(), get_content(*e)))
+                .collect();
+            let content = Expr::Match(get_content(scrutinee), arms_mapped).into_id(span);
+            if scrutinee.is_ok() && bodies.iter().all(|e| e.is_ok()) {
+                Ok(ConvertResult::Ok(content))
+            } else {
+                Ok(ConvertResult::Err(content))
+            }
+        }
         Expr::Block(body) => {
             if let Some(body) = body {
                 Ok(conversion(body)?.map(|e| Expr::Block(Some(e)).into_id(span)))
@@ -153,6 +224,24 @@ where
                 Ok(ConvertResult::Ok(Expr::Block(None).into_id(span)))
             }
         }
+        Expr::Then(body, then) => {
+            let body = conversion(body)?;
+            let then = opt_conversion(then)?;
+            if let (Ok(b), Ok(t)) = (body, then.transpose()) {
+                Ok(ConvertResult::Ok(Expr::Then(b, t).into_id(span)))
+            } else {
+                Ok(ConvertResult::Err(
+                    Expr::Then(get_content(body), then.map(get_content)).into_id(span),
+                ))
+            }
+        }
+        Expr::Assign(assignee, body) => {
+            let body = conversion(body)?;
+            Ok(body.map(|e| Expr::Assign(assignee, e).into_id(span)))
+        }
+        Expr::Return(body) => Ok(conversion(body)?.map(|e| Expr::Return(e).into_id(span))),
+        Expr::Bracket(body) => Ok(conversion(body)?.map(|e| Expr::Bracket(e).into_id(span))),
+        Expr::Escape(body) => Ok(conversion(body)?.map(|e| Expr::Escape(e).into_id(span))),
 
         _ => Ok(ConvertResult::Ok(e_id)),
     }
@@ -183,7 +272,8 @@ fn convert_self(e_id: ExprNodeId, feedctx: FeedId) -> Result<ConvertResult, Erro
         }
         Expr::Feed(_, _) => panic!(
             "Feed should not be shown before conversion at {}..{}",
-            span.start, span.end
+            span.start(),
+            span.end()
         ),
         _ => convert_recursively(e_id, conversion),
     }
@@ -260,52 +350,54 @@ mod test {
     use crate::{
         pattern::{Pattern, TypedId, TypedPattern},
         types::Type,
+        utils::metadata::FileId,
     };
 
     use super::*;
 
     #[test]
     pub fn test_selfconvert() {
+        let span = Span::new(FileId(0), 0..1);
         let src = Expr::Let(
             TypedPattern {
                 pat: Pattern::Single("lowpass".to_symbol()),
-                ty: Type::Unknown.into_id_with_span(0..1),
+                ty: Type::Unknown.into_id_with_span(span.clone()),
             },
             Expr::Lambda(
                 vec![TypedId {
                     id: "input".to_symbol(),
-                    ty: Type::Unknown.into_id_with_span(0..1),
+                    ty: Type::Unknown.into_id_with_span(span.clone()),
                 }],
                 None,
-                Expr::Literal(Literal::SelfLit).into_id(0..1),
+                Expr::Literal(Literal::SelfLit).into_id(span.clone()),
             )
-            .into_id(0..1),
+            .into_id(span.clone()),
             None,
         )
-        .into_id(0..1);
+        .into_id(span.clone());
         let res = convert_pronoun(src).unwrap();
 
         let ans = Expr::Let(
             TypedPattern {
                 pat: Pattern::Single("lowpass".to_symbol()),
-                ty: Type::Unknown.into_id_with_span(0..1),
+                ty: Type::Unknown.into_id_with_span(span.clone()),
             },
             Expr::Lambda(
                 vec![TypedId {
                     id: "input".to_symbol(),
-                    ty: Type::Unknown.into_id_with_span(0..1),
+                    ty: Type::Unknown.into_id_with_span(span.clone()),
                 }],
                 None,
                 Expr::Feed(
                     "feed_id0".to_symbol(),
-                    Expr::Var("feed_id0".to_symbol()).into_id(0..1),
+                    Expr::Var("feed_id0".to_symbol()).into_id(span.clone()),
                 )
-                .into_id(0..1),
+                .into_id(span.clone()),
             )
-            .into_id(0..1),
+            .into_id(span.clone()),
             None,
         )
-        .into_id(0..1);
+        .into_id(span.clone());
         assert_eq!(res, ans);
     }
 }
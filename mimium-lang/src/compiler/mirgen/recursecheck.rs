@@ -15,6 +15,7 @@ fn try_find_recurse(e_s: ExprNodeId, name: &Symbol) -> bool {
             //todo: start new search so we return false here
             false
         }
+        Expr::TypeDecl(_decl, then) => then.is_some_and(|e| try_find_recurse(e, name)),
         Expr::Assign(_v, e) => try_find_recurse(*e, name),
         Expr::Then(body, then_opt) => {
             try_find_recurse(*body, name)
@@ -25,16 +26,27 @@ fn try_find_recurse(e_s: ExprNodeId, name: &Symbol) -> bool {
             try_find_recurse(*body, name)
         }
         Expr::Proj(body, _idx) => try_find_recurse(*body, name),
+        Expr::RecordLiteral(fields) => fields.iter().any(|(_, v)| try_find_recurse(*v, name)),
+        Expr::FieldAccess(body, _field) => try_find_recurse(*body, name),
         Expr::Block(body) => body.map_or(false, |b| try_find_recurse(b, name)),
         Expr::Apply(fun, callee) => {
             try_find_recurse(*fun, name) || callee.iter().any(|v| try_find_recurse(*v, name))
         }
         Expr::Tuple(vec) => vec.iter().any(|v| try_find_recurse(*v, name)),
+        Expr::ArrayLiteral(vec) => vec.iter().any(|v| try_find_recurse(*v, name)),
+        Expr::ArrayAccess(arr, idx) => try_find_recurse(*arr, name) || try_find_recurse(*idx, name),
         Expr::If(cond, then, opt_else) => {
             try_find_recurse(*cond, name)
                 || try_find_recurse(*then, name)
                 || opt_else.map_or(false, |e| try_find_recurse(e, name))
         }
+        Expr::Match(scrutinee, arms) => {
+            try_find_recurse(*scrutinee, name)
+                || arms.iter().any(|(_, e)| try_find_recurse(*e, name))
+        }
+        Expr::Return(body) | Expr::Bracket(body) | Expr::Escape(body) => {
+            try_find_recurse(*body, name)
+        }
         Expr::Feed(_x, _body) => panic!("feed should not be shown in recurse removal process"),
         _ => false,
     }
@@ -64,12 +76,24 @@ pub fn convert_recurse(e_s: ExprNodeId) -> ExprNodeId {
             convert_recurse(*body),
             then.map(convert_recurse),
         ),
+        Expr::TypeDecl(decl, then) => Expr::TypeDecl(decl.clone(), then.map(convert_recurse)),
         Expr::Assign(v, e) => Expr::Assign(*v, convert_recurse(*e)),
         Expr::Then(body, then_opt) => {
             Expr::Then(convert_recurse(*body), then_opt.map(convert_recurse))
         }
         Expr::Tuple(es) => Expr::Tuple(convert_vec(es)),
         Expr::Proj(t, idx) => Expr::Proj(convert_recurse(*t), *idx),
+        Expr::RecordLiteral(fields) => Expr::RecordLiteral(
+            fields
+                .iter()
+                .map(|(name, e)| (*name, convert_recurse(*e)))
+                .collect(),
+        ),
+        Expr::FieldAccess(e, field) => Expr::FieldAccess(convert_recurse(*e), *field),
+        Expr::ArrayLiteral(es) => Expr::ArrayLiteral(convert_vec(es)),
+        Expr::ArrayAccess(arr, idx) => {
+            Expr::ArrayAccess(convert_recurse(*arr), convert_recurse(*idx))
+        }
         Expr::Block(body) => Expr::Block(body.map(convert_recurse)),
         Expr::Apply(fun, callee) => Expr::Apply(convert_recurse(*fun), convert_vec(callee)),
         Expr::If(cond, then, opt_else) => Expr::If(
@@ -77,9 +101,18 @@ pub fn convert_recurse(e_s: ExprNodeId) -> ExprNodeId {
             convert_recurse(*then),
             opt_else.map(convert_recurse),
         ),
+        Expr::Match(scrutinee, arms) => Expr::Match(
+            convert_recurse(*scrutinee),
+            arms.iter()
+                .map(|(pat, e)| (pat.clone(), convert_recurse(*e)))
+                .collect(),
+        ),
         Expr::Lambda(ids, opt_type, body) => {
             Expr::Lambda(ids.clone(), *opt_type, convert_recurse(*body))
         }
+        Expr::Return(body) => Expr::Return(convert_recurse(*body)),
+        Expr::Bracket(body) => Expr::Bracket(convert_recurse(*body)),
+        Expr::Escape(body) => Expr::Escape(convert_recurse(*body)),
         Expr::Feed(_x, _body) => panic!("feed should not be shown in recurse removal process"),
         e => e.clone(),
     };
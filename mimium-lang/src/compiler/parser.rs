@@ -1,9 +1,9 @@
 use std::path::PathBuf;
 
 use crate::ast::*;
-use crate::interner::{ExprNodeId, Symbol, ToSymbol, TypeNodeId};
+use crate::interner::{with_session_globals, ExprNodeId, Symbol, ToSymbol, TypeNodeId};
 use crate::pattern::{Pattern, TypedId, TypedPattern};
-use crate::types::{PType, Type};
+use crate::types::{sort_fields, PType, Type};
 use crate::utils::error::ReportableError;
 use crate::utils::metadata::*;
 use chumsky::{prelude::*, Parser};
@@ -12,17 +12,20 @@ mod token;
 use resolve_include::resolve_include;
 use token::{Comment, Op, Token};
 mod error;
+mod format;
 mod lexer;
 mod resolve_include;
+mod resolve_module;
 mod statement;
-use statement::{into_then_expr, stmt_from_expr_top, Statement};
+pub use format::format_source;
+use statement::{into_then_expr, stmt_from_expr_top, Statement, Statements};
 
 use super::intrinsics;
 
 #[cfg(test)]
 mod test;
 
-fn type_parser() -> impl Parser<Token, TypeNodeId, Error = Simple<Token>> + Clone {
+fn type_parser() -> impl Parser<Token, TypeNodeId, Error = Simple<Token, Span>> + Clone {
     recursive(|ty| {
         let primitive = select! {
            Token::FloatType => Type::Primitive(PType::Numeric),
@@ -40,8 +43,27 @@ fn type_parser() -> impl Parser<Token, TypeNodeId, Error = Simple<Token>> + Clon
             .boxed()
             .labelled("Tuple");
 
-        // let _struct_t = todo!();
-        let atom = primitive.or(tuple);
+        let struct_t = record_fields_parser(
+            ident_parser()
+                .then_ignore(just(Token::Colon))
+                .then(ty.clone()),
+        )
+        .map_with_span(|mut fields: Vec<(Symbol, TypeNodeId)>, s: Span| {
+            sort_fields(&mut fields);
+            Type::Struct(fields).into_id_with_span(s)
+        })
+        .boxed()
+        .labelled("Struct");
+        let array_t = ty
+            .clone()
+            .delimited_by(just(Token::ArrayBegin), just(Token::ArrayEnd))
+            .map_with_span(|elem, s: Span| Type::Array(elem).into_id_with_span(s))
+            .boxed()
+            .labelled("Array");
+        let name = qualified_ident_parser()
+            .map_with_span(|name, s| Type::TypeName(name).into_id_with_span(s))
+            .labelled("type name");
+        let atom = primitive.or(tuple).or(struct_t).or(array_t).or(name);
         let func = atom
             .clone()
             .separated_by(just(Token::Comma))
@@ -54,14 +76,45 @@ fn type_parser() -> impl Parser<Token, TypeNodeId, Error = Simple<Token>> + Clon
         func.or(atom).boxed().labelled("Type")
     })
 }
-fn ident_parser() -> impl Parser<Token, Symbol, Error = Simple<Token>> + Clone {
+/// Parses the variants of the sum type like `Sine | Saw(float) | Pulse(float, float)`.
+/// A single variant without fields is not a sum type but a type name unless it starts with `|`.
+fn union_type_parser() -> impl Parser<Token, TypeNodeId, Error = Simple<Token, Span>> + Clone {
+    let fields = type_parser()
+        .separated_by(just(Token::Comma))
+        .allow_trailing()
+        .at_least(1)
+        .delimited_by(just(Token::ParenBegin), just(Token::ParenEnd));
+    let variant = ident_parser().then(fields.clone().or_not().map(Option::unwrap_or_default));
+    let bar = just(Token::LambdaArgBeginEnd);
+    let leading_bar = bar
+        .clone()
+        .ignore_then(variant.clone().separated_by(bar.clone()).at_least(1));
+    let variants = variant
+        .clone()
+        .then(bar.ignore_then(variant).repeated().at_least(1))
+        .map(|(head, mut tail)| {
+            tail.insert(0, head);
+            tail
+        });
+    let single = ident_parser().then(fields).map(|v| vec![v]);
+    leading_bar
+        .or(variants)
+        .or(single)
+        .validate(|variants, span, emit| {
+            if let Err(e) = validate_unique_fields(&variants, span) {
+                emit(e)
+            }
+            variants
+        })
+        .map_with_span(|variants, s| Type::Union(variants).into_id_with_span(s))
+        .labelled("sum type")
+}
+fn ident_parser() -> impl Parser<Token, Symbol, Error = Simple<Token, Span>> + Clone {
     select! { Token::Ident(s) => s }.labelled("ident")
 }
-fn literals_parser() -> impl Parser<Token, ExprNodeId, Error = Simple<Token>> + Clone {
+fn literals_parser() -> impl Parser<Token, ExprNodeId, Error = Simple<Token, Span>> + Clone {
     select! {
-        //Currently Integer literals are treated as float until the integer type is introduced in type system.
-        // Token::Int(x) => Literal::Int(x),
-        Token::Int(x)=>Literal::Float(x.to_string().to_symbol()),
+        Token::Int(x) => Literal::Int(x),
         Token::Float(x) =>Literal::Float(x.to_symbol()),
         Token::Str(s) => Literal::String(s.to_symbol()),
         Token::SelfLit => Literal::SelfLit,
@@ -71,21 +124,32 @@ fn literals_parser() -> impl Parser<Token, ExprNodeId, Error = Simple<Token>> +
     .map_with_span(|e, s| Expr::Literal(e).into_id(s))
     .labelled("literal")
 }
-fn var_parser() -> impl Parser<Token, ExprNodeId, Error = Simple<Token>> + Clone {
-    ident_parser().map_with_span(|e, s| Expr::Var(e).into_id(s))
+fn path_parser() -> impl Parser<Token, Vec<Symbol>, Error = Simple<Token, Span>> + Clone {
+    ident_parser()
+        .separated_by(just(Token::DoubleColon))
+        .at_least(1)
+        .labelled("path")
+}
+/// Parses a name which may be qualified with the module path like `filter::lowpass`.
+/// The qualified name is kept as a single symbol joined with `::` until the modules are resolved.
+fn qualified_ident_parser() -> impl Parser<Token, Symbol, Error = Simple<Token, Span>> + Clone {
+    path_parser().map(|path| resolve_module::join_path(&path))
+}
+fn var_parser() -> impl Parser<Token, ExprNodeId, Error = Simple<Token, Span>> + Clone {
+    qualified_ident_parser().map_with_span(|e, s| Expr::Var(e).into_id(s))
 }
 fn with_type_annotation<P, O>(
     parser: P,
-) -> impl Parser<Token, (O, Option<TypeNodeId>), Error = Simple<Token>> + Clone
+) -> impl Parser<Token, (O, Option<TypeNodeId>), Error = Simple<Token, Span>> + Clone
 where
-    P: Parser<Token, O, Error = Simple<Token>> + Clone,
+    P: Parser<Token, O, Error = Simple<Token, Span>> + Clone,
 {
     parser
         .then(just(Token::Colon).ignore_then(type_parser()).or_not())
         .map(|(id, t)| (id, t))
 }
 
-fn lvar_parser_typed() -> impl Parser<Token, TypedId, Error = Simple<Token>> + Clone {
+fn lvar_parser_typed() -> impl Parser<Token, TypedId, Error = Simple<Token, Span>> + Clone {
     with_type_annotation(ident_parser())
         .map_with_span(|(sym, t), span| match t {
             Some(ty) => TypedId { id: sym, ty },
@@ -96,13 +160,53 @@ fn lvar_parser_typed() -> impl Parser<Token, TypedId, Error = Simple<Token>> + C
         })
         .labelled("lvar_typed")
 }
-fn pattern_parser() -> impl Parser<Token, TypedPattern, Error = Simple<Token>> + Clone {
-    let pat = recursive(|pat| {
+/// Parses `{field1 ..., field2 ...}` shared by struct types, record literals and record patterns.
+/// Line breaks are allowed around the fields.
+fn record_fields_parser<P, O>(
+    field: P,
+) -> impl Parser<Token, Vec<(Symbol, O)>, Error = Simple<Token, Span>> + Clone
+where
+    P: Parser<Token, (Symbol, O), Error = Simple<Token, Span>> + Clone,
+{
+    record_fields_trailing_parser(field).map(|(fields, _)| fields)
+}
+/// Same as [`record_fields_parser`], also returning whether the fields end with a comma.
+fn record_fields_trailing_parser<P, O>(
+    field: P,
+) -> impl Parser<Token, (Vec<(Symbol, O)>, bool), Error = Simple<Token, Span>> + Clone
+where
+    P: Parser<Token, (Symbol, O), Error = Simple<Token, Span>> + Clone,
+{
+    let linebreaks = just(Token::LineBreak).repeated();
+    field
+        .padded_by(linebreaks.clone())
+        .separated_by(just(Token::Comma))
+        .at_least(1)
+        .then(just(Token::Comma).or_not().map(|c| c.is_some()))
+        .then_ignore(linebreaks)
+        .delimited_by(just(Token::BlockBegin), just(Token::BlockEnd))
+        .validate(|(fields, trailing), span, emit| {
+            if let Err(e) = validate_unique_fields(&fields, span) {
+                emit(e)
+            }
+            (fields, trailing)
+        })
+}
+fn untyped_pattern_parser() -> impl Parser<Token, Pattern, Error = Simple<Token, Span>> + Clone {
+    recursive(|pat| {
+        // `{freq, amp = a}` is a shorthand of `{freq = freq, amp = a}`
+        let record = record_fields_parser(
+            ident_parser()
+                .then(just(Token::Assign).ignore_then(pat.clone()).or_not())
+                .map(|(name, p)| (name, p.unwrap_or(Pattern::Single(name)))),
+        )
+        .map(Pattern::Record);
         pat.clone()
             .separated_by(just(Token::Comma))
             .allow_trailing()
             .delimited_by(just(Token::ParenBegin), just(Token::ParenEnd))
             .map(Pattern::Tuple)
+            .or(record)
             .or(select! {
                 Token::Ident(s) => Pattern::Single(s),
                 // Note: _ represents an unused variable, but it is treated as
@@ -110,8 +214,10 @@ fn pattern_parser() -> impl Parser<Token, TypedPattern, Error = Simple<Token>> +
                 Token::PlaceHolder => Pattern::Single("_".to_symbol()),
             })
             .labelled("Pattern")
-    });
-    with_type_annotation(pat).map_with_span(|(pat, ty), s| match ty {
+    })
+}
+fn pattern_parser() -> impl Parser<Token, TypedPattern, Error = Simple<Token, Span>> + Clone {
+    with_type_annotation(untyped_pattern_parser()).map_with_span(|(pat, ty), s| match ty {
         Some(ty) => TypedPattern { pat, ty },
         None => TypedPattern {
             pat,
@@ -119,10 +225,13 @@ fn pattern_parser() -> impl Parser<Token, TypedPattern, Error = Simple<Token>> +
         },
     })
 }
-fn binop_folder<'a, I, OP>(prec: I, op: OP) -> BoxedParser<'a, Token, ExprNodeId, Simple<Token>>
+fn binop_folder<'a, I, OP>(
+    prec: I,
+    op: OP,
+) -> BoxedParser<'a, Token, ExprNodeId, Simple<Token, Span>>
 where
-    I: Parser<Token, ExprNodeId, Error = Simple<Token>> + Clone + 'a,
-    OP: Parser<Token, (Op, Span), Error = Simple<Token>> + Clone + 'a,
+    I: Parser<Token, ExprNodeId, Error = Simple<Token, Span>> + Clone + 'a,
+    OP: Parser<Token, (Op, Span), Error = Simple<Token, Span>> + Clone + 'a,
 {
     prec.clone()
         .then(
@@ -131,7 +240,7 @@ where
                 .repeated(),
         )
         .foldl(move |x, ((op, opspan), y)| {
-            let apply_span = x.to_span().start..y.to_span().end;
+            let apply_span = x.to_span().to(&y.to_span());
             let arg = match op {
                 Op::Pipe => return Expr::PipeApply(x, y).into_id(apply_span),
                 // A@B is a syntactic sugar of _mimium_schedule_at(B, A)
@@ -144,19 +253,21 @@ where
         .boxed()
 }
 
-type ExprParser<'a> = Recursive<'a, Token, ExprNodeId, Simple<Token>>;
+type ExprParser<'a> = Recursive<'a, Token, ExprNodeId, Simple<Token, Span>>;
 
 fn items_parser(
     expr: ExprParser<'_>,
-) -> impl Parser<Token, Vec<ExprNodeId>, Error = Simple<Token>> + Clone + '_ {
+) -> impl Parser<Token, Vec<ExprNodeId>, Error = Simple<Token, Span>> + Clone + '_ {
     expr.separated_by(just(Token::Comma))
         .allow_trailing()
         .collect::<Vec<_>>()
 }
 
-fn op_parser<'a, I>(apply: I) -> impl Parser<Token, ExprNodeId, Error = Simple<Token>> + Clone + 'a
+fn op_parser<'a, I>(
+    apply: I,
+) -> impl Parser<Token, ExprNodeId, Error = Simple<Token, Span>> + Clone + 'a
 where
-    I: Parser<Token, ExprNodeId, Error = Simple<Token>> + Clone + 'a,
+    I: Parser<Token, ExprNodeId, Error = Simple<Token, Span>> + Clone + 'a,
 {
     let unary = select! { Token::Op(Op::Minus) => {} }
         .map_with_span(|e, s| (e, s))
@@ -164,8 +275,9 @@ where
         .then(apply.clone())
         .foldr(|(_op, op_span), rhs| {
             let rhs_span = rhs.to_span();
-            let neg_op = Expr::Var("neg".to_symbol()).into_id(op_span.start..rhs_span.start);
-            Expr::Apply(neg_op, vec![rhs]).into_id(op_span.start..rhs_span.end)
+            let neg_span = Span::new(op_span.file, op_span.start()..rhs_span.start());
+            let neg_op = Expr::Var("neg".to_symbol()).into_id(neg_span);
+            Expr::Apply(neg_op, vec![rhs]).into_id(op_span.to(&rhs_span))
         })
         .labelled("unary");
 
@@ -212,7 +324,7 @@ where
 fn atom_parser<'a>(
     expr: ExprParser<'a>,
     expr_group: ExprParser<'a>,
-) -> impl Parser<Token, ExprNodeId, Error = Simple<Token>> + Clone + 'a {
+) -> impl Parser<Token, ExprNodeId, Error = Simple<Token, Span>> + Clone + 'a {
     let lambda = lvar_parser_typed()
         .separated_by(just(Token::Comma))
         .delimited_by(
@@ -225,11 +337,15 @@ fn atom_parser<'a>(
         .labelled("lambda");
     let macro_expand = select! { Token::MacroExpand(s) => Expr::Var(s) }
         .map_with_span(|e, s| e.into_id(s))
-        .then_ignore(just(Token::ParenBegin))
-        .then(expr_group.clone())
-        .then_ignore(just(Token::ParenEnd))
-        .map_with_span(|(id, then), s| {
-            Expr::Escape(Expr::Apply(id, vec![then]).into_id(s.clone())).into_id(s)
+        .then(
+            expr_group
+                .clone()
+                .separated_by(just(Token::Comma))
+                .allow_trailing()
+                .delimited_by(just(Token::ParenBegin), just(Token::ParenEnd)),
+        )
+        .map_with_span(|(id, args), s| {
+            Expr::Escape(Expr::Apply(id, args).into_id(s.clone())).into_id(s)
         })
         .labelled("macroexpand");
 
@@ -241,6 +357,35 @@ fn atom_parser<'a>(
         .clone()
         .delimited_by(just(Token::ParenBegin), just(Token::ParenEnd))
         .labelled("paren_expr");
+    // trailing comma is mandatory for a single-field record, as `{v = 3.0}` is a block which
+    // assigns to `v`.
+    let record = record_fields_trailing_parser(
+        ident_parser()
+            .then_ignore(just(Token::Assign))
+            .then(expr_group.clone()),
+    )
+    .try_map(|(fields, trailing), s| {
+        if fields.len() == 1 && !trailing {
+            Err(Simple::custom(
+                s,
+                "a single-field record needs a trailing comma",
+            ))
+        } else {
+            Ok(fields)
+        }
+    })
+    .map_with_span(|fields, s| Expr::RecordLiteral(fields).into_id(s))
+    .labelled("record");
+    let linebreaks = just(Token::LineBreak).repeated();
+    let array = expr
+        .clone()
+        .padded_by(linebreaks.clone())
+        .separated_by(just(Token::Comma))
+        .allow_trailing()
+        .then_ignore(linebreaks)
+        .delimited_by(just(Token::ArrayBegin), just(Token::ArrayEnd))
+        .map_with_span(|e, s| Expr::ArrayLiteral(e).into_id(s))
+        .labelled("array");
     //tuple must  lower precedence than parenexpr, not to parse single element tuple without trailing comma
     choice((
         literals_parser(),
@@ -249,33 +394,78 @@ fn atom_parser<'a>(
         macro_expand,
         parenexpr,
         tuple,
+        record,
+        array,
+    ))
+    .recover_with(nested_delimiters(
+        Token::ParenBegin,
+        Token::ParenEnd,
+        [
+            (Token::ArrayBegin, Token::ArrayEnd),
+            (Token::BlockBegin, Token::BlockEnd),
+        ],
+        |s| Expr::Error.into_id(s),
+    ))
+    .recover_with(nested_delimiters(
+        Token::ArrayBegin,
+        Token::ArrayEnd,
+        [
+            (Token::ParenBegin, Token::ParenEnd),
+            (Token::BlockBegin, Token::BlockEnd),
+        ],
+        |s| Expr::Error.into_id(s),
     ))
 }
 fn expr_parser(expr_group: ExprParser<'_>) -> ExprParser<'_> {
-    recursive(|expr: Recursive<Token, ExprNodeId, Simple<Token>>| {
+    recursive(|expr: Recursive<Token, ExprNodeId, Simple<Token, Span>>| {
         enum FoldItem {
             Args(Vec<ExprNodeId>),
             ArrayIndex(ExprNodeId),
+            Field(Symbol),
+            // arguments or an index failed to parse.
+            Error,
         }
         let parenitems = items_parser(expr.clone())
             .delimited_by(just(Token::ParenBegin), just(Token::ParenEnd))
-            .map_with_span(|args, args_span| (FoldItem::Args(args), args_span));
+            .map_with_span(|args, args_span| (FoldItem::Args(args), args_span))
+            .recover_with(nested_delimiters(
+                Token::ParenBegin,
+                Token::ParenEnd,
+                [
+                    (Token::ArrayBegin, Token::ArrayEnd),
+                    (Token::BlockBegin, Token::BlockEnd),
+                ],
+                |s| (FoldItem::Error, s),
+            ));
         let angle_paren_expr = expr
             .clone()
             .delimited_by(just(Token::ArrayBegin), just(Token::ArrayEnd))
-            .map_with_span(|e, s| (FoldItem::ArrayIndex(e), s));
+            .map_with_span(|e, s| (FoldItem::ArrayIndex(e), s))
+            .recover_with(nested_delimiters(
+                Token::ArrayBegin,
+                Token::ArrayEnd,
+                [
+                    (Token::ParenBegin, Token::ParenEnd),
+                    (Token::BlockBegin, Token::BlockEnd),
+                ],
+                |s| (FoldItem::Error, s),
+            ));
+        let field = just(Token::Dot)
+            .ignore_then(ident_parser())
+            .map_with_span(|name, s| (FoldItem::Field(name), s));
 
         let folder = |f: ExprNodeId, (item, args_span): (FoldItem, Span)| {
-            let f_span = f.to_span();
-            let span = f_span.start..args_span.end;
+            let span = f.to_span().to(&args_span);
             match item {
                 FoldItem::Args(args) => Expr::Apply(f, args).into_id(span),
                 FoldItem::ArrayIndex(index) => Expr::ArrayAccess(f, index).into_id(span),
+                FoldItem::Field(name) => Expr::FieldAccess(f, name).into_id(span),
+                FoldItem::Error => Expr::Error.into_id(span),
             }
         };
 
         let apply = atom_parser(expr.clone(), expr_group)
-            .then(angle_paren_expr.or(parenitems).repeated())
+            .then(angle_paren_expr.or(parenitems).or(field).repeated())
             .foldl(folder)
             .labelled("apply");
 
@@ -285,7 +475,7 @@ fn expr_parser(expr_group: ExprParser<'_>) -> ExprParser<'_> {
 // fn expr_statement_parser<'a>(
 //     expr_group: ExprParser<'a>,
 //     then: ExprParser<'a>,
-// ) -> impl Parser<Token, ExprNodeId, Error = Simple<Token>> + Clone + 'a {
+// ) -> impl Parser<Token, ExprNodeId, Error = Simple<Token, Span>> + Clone + 'a {
 //     let let_stmt = just(Token::Let)
 //         .ignore_then(pattern_parser().clone())
 //         .then_ignore(just(Token::Assign))
@@ -305,14 +495,31 @@ fn expr_parser(expr_group: ExprParser<'_>) -> ExprParser<'_> {
 //         .labelled("assign");
 //     let_stmt.or(assign)
 // }
-fn validate_reserved_pat(id: &TypedPattern, span: Span) -> Result<(), Simple<Token>> {
+fn validate_reserved_pat(id: &TypedPattern, span: Span) -> Result<(), Simple<Token, Span>> {
     match &id.pat {
         Pattern::Single(symbol) => validate_reserved_ident(*symbol, span),
         _ => Ok(()),
     }
 }
 
-fn validate_reserved_ident(id: Symbol, span: Span) -> Result<(), Simple<Token>> {
+fn validate_unique_fields<T>(
+    fields: &[(Symbol, T)],
+    span: Span,
+) -> Result<(), Simple<Token, Span>> {
+    match fields
+        .iter()
+        .enumerate()
+        .find(|(i, (name, _))| fields[..*i].iter().any(|(n, _)| n == name))
+    {
+        Some((_, (name, _))) => Err(Simple::custom(
+            span,
+            format!("Field \"{name}\" is defined more than once."),
+        )),
+        None => Ok(()),
+    }
+}
+
+fn validate_reserved_ident(id: Symbol, span: Span) -> Result<(), Simple<Token, Span>> {
     if intrinsics::BUILTIN_SYMS.with(|syms| syms.binary_search(&id).is_ok()) {
         Err(Simple::custom(
             span,
@@ -323,9 +530,19 @@ fn validate_reserved_ident(id: Symbol, span: Span) -> Result<(), Simple<Token>>
     }
 }
 
+// the body of a binding recovers by itself so that the name is still bound to `Expr::Error`,
+// and the uses of it do not cause the other errors.
+fn binding_body_parser(
+    expr: ExprParser<'_>,
+) -> impl Parser<Token, ExprNodeId, Error = Simple<Token, Span>> + Clone + '_ {
+    expr.then_ignore(statement_end()).recover_with(skip_until(
+        [Token::LineBreak, Token::SemiColon, Token::BlockEnd],
+        |s| Expr::Error.into_id(s),
+    ))
+}
 fn statement_parser(
     expr: ExprParser<'_>,
-) -> impl Parser<Token, (Statement, Span), Error = Simple<Token>> + Clone + '_ {
+) -> impl Parser<Token, (Statement, Span), Error = Simple<Token, Span>> + Clone + '_ {
     let let_ = just(Token::Let)
         .ignore_then(pattern_parser().clone().validate(|pat, span, emit| {
             if let Err(e) = validate_reserved_pat(&pat, span.clone()) {
@@ -334,7 +551,7 @@ fn statement_parser(
             pat
         }))
         .then_ignore(just(Token::Assign))
-        .then(expr.clone())
+        .then(binding_body_parser(expr.clone()))
         .map_with_span(|(ident, body), span| (Statement::Let(ident, body), span))
         .labelled("let");
     let letrec = just(Token::LetRec)
@@ -345,7 +562,7 @@ fn statement_parser(
             ident
         }))
         .then_ignore(just(Token::Assign))
-        .then(expr.clone())
+        .then(binding_body_parser(expr.clone()))
         .map_with_span(|(ident, body), span| (Statement::LetRec(ident, body), span))
         .labelled("letrec");
     let assign = var_parser()
@@ -356,10 +573,52 @@ fn statement_parser(
     let single = expr.map_with_span(|e, span| (Statement::Single(e), span));
     let_.or(letrec).or(assign).or(single)
 }
+// a statement must be followed by a separator, the end of the block or the end of the file.
+fn statement_end() -> impl Parser<Token, (), Error = Simple<Token, Span>> + Clone {
+    one_of([Token::LineBreak, Token::SemiColon, Token::BlockEnd])
+        .ignored()
+        .or(end())
+        .rewind()
+}
+// when the statement fails to parse, the tokens until the next separator are skipped and
+// the `fallback` is left in its place, so that the following statements are still parsed.
+fn recover_statement<'a, O: 'a>(
+    stmt: impl Parser<Token, O, Error = Simple<Token, Span>> + Clone + 'a,
+    fallback: impl Fn(Span) -> O + Clone + 'a,
+) -> impl Parser<Token, O, Error = Simple<Token, Span>> + Clone + 'a {
+    none_of([Token::BlockEnd])
+        .rewind()
+        .ignore_then(stmt.then_ignore(statement_end()).recover_with(skip_until(
+            [Token::LineBreak, Token::SemiColon, Token::BlockEnd],
+            fallback,
+        )))
+}
+fn error_statement(span: Span) -> (Statement, Span) {
+    (Statement::Single(Expr::Error.into_id(span.clone())), span)
+}
+// consecutive lines of doc comments, joined into a text.
+fn doc_parser() -> impl Parser<Token, String, Error = Simple<Token, Span>> + Clone {
+    select! { Token::Comment(Comment::Doc(s)) => s }
+        .map(|s| s.strip_prefix(' ').map_or(s.clone(), str::to_string))
+        .repeated()
+        .at_least(1)
+        .then_ignore(just(Token::LineBreak).repeated())
+        .map(|lines| lines.join("\n"))
+        .labelled("doc comment")
+}
+// `#[name]` before a definition. The names are checked when the modules are resolved.
+fn attribute_parser() -> impl Parser<Token, (Symbol, Span), Error = Simple<Token, Span>> + Clone {
+    select! { Token::Attribute(name) => name }
+        .map_with_span(|name, s| (name, s))
+        .then_ignore(just(Token::LineBreak).repeated())
+        .labelled("attribute")
+}
 fn statements_parser(
     expr: ExprParser<'_>,
-) -> impl Parser<Token, Option<ExprNodeId>, Error = Simple<Token>> + Clone + '_ {
-    statement_parser(expr)
+) -> impl Parser<Token, Option<ExprNodeId>, Error = Simple<Token, Span>> + Clone + '_ {
+    // doc comments on the local definitions are not used.
+    let stmt = doc_parser().or_not().ignore_then(statement_parser(expr));
+    recover_statement(stmt, error_statement)
         .separated_by(just(Token::LineBreak).or(just(Token::SemiColon)).repeated())
         .allow_leading()
         .allow_trailing()
@@ -368,11 +627,20 @@ fn statements_parser(
 
 fn block_parser(
     expr: ExprParser<'_>,
-) -> impl Parser<Token, ExprNodeId, Error = Simple<Token>> + Clone + '_ {
+) -> impl Parser<Token, ExprNodeId, Error = Simple<Token, Span>> + Clone + '_ {
     let stmts = statements_parser(expr);
     stmts
         .delimited_by(just(Token::BlockBegin), just(Token::BlockEnd))
         .map_with_span(|stmts, span| Expr::Block(stmts).into_id(span))
+        .recover_with(nested_delimiters(
+            Token::BlockBegin,
+            Token::BlockEnd,
+            [
+                (Token::ParenBegin, Token::ParenEnd),
+                (Token::ArrayBegin, Token::ArrayEnd),
+            ],
+            |s| Expr::Error.into_id(s),
+        ))
 }
 // expr_group contains let statement, assignment statement, function definiton,... they cannot be placed as an argument for apply directly.
 fn exprgroup_parser<'a>() -> ExprParser<'a> {
@@ -392,16 +660,63 @@ fn exprgroup_parser<'a>() -> ExprParser<'a> {
             .map_with_span(|((cond, then), opt_else), s| Expr::If(cond, then, opt_else).into_id(s))
             .labelled("if");
 
-        block
-            .or(if_)
-            // .or(expr_statement_parser(expr_group.clone(), expr_group))
-            .or(expr.clone())
+        // `Pulse(freq, width)` matches to the variant with its fields, while the other patterns
+        // (including a single name which is not a variant) match to any value.
+        let arm_pattern = qualified_ident_parser()
+            .then(
+                untyped_pattern_parser()
+                    .separated_by(just(Token::Comma))
+                    .allow_trailing()
+                    .delimited_by(just(Token::ParenBegin), just(Token::ParenEnd)),
+            )
+            .map(|(name, fields)| Pattern::Variant(name, fields))
+            // a qualified name like `osc::Sine` is always a variant.
+            .or(ident_parser()
+                .then(
+                    just(Token::DoubleColon)
+                        .ignore_then(ident_parser())
+                        .repeated()
+                        .at_least(1),
+                )
+                .map(|(head, mut tail)| {
+                    tail.insert(0, head);
+                    Pattern::Variant(resolve_module::join_path(&tail), vec![])
+                }))
+            .or(untyped_pattern_parser())
+            .map_with_span(|pat, s| TypedPattern {
+                pat,
+                ty: Type::Unknown.into_id_with_span(s),
+            });
+        let arm_sep = just(Token::Comma)
+            .or(just(Token::LineBreak))
+            .repeated()
+            .at_least(1);
+        let match_ = just(Token::Match)
+            .ignore_then(expr.clone())
+            .then(
+                arm_pattern
+                    .then_ignore(just(Token::FatArrow))
+                    .then(expr_group.clone())
+                    .separated_by(arm_sep.clone())
+                    .at_least(1)
+                    .allow_leading()
+                    .allow_trailing()
+                    .delimited_by(just(Token::BlockBegin), just(Token::BlockEnd)),
+            )
+            .map_with_span(|(scrutinee, arms), s| Expr::Match(scrutinee, arms).into_id(s))
+            .labelled("match");
+
+        let return_ = just(Token::Return)
+            .ignore_then(expr_group.clone())
+            .map_with_span(|e, s| Expr::Return(e).into_id(s))
+            .labelled("return");
+
+        // expr must be tried before block so that a record literal like
+        // `{freq = 440.0, amp = 0.5}` is not parsed as a block with an error.
+        expr.clone().or(block).or(if_).or(match_).or(return_)
+        // .or(expr_statement_parser(expr_group.clone(), expr_group))
     })
 }
-fn comment_parser() -> impl Parser<Token, (), Error = Simple<Token>> + Clone {
-    select! {Token::Comment(Comment::SingleLine(_t))=>(),
-    Token::Comment(Comment::MultiLine(_t))=>()}
-}
 fn gen_unknown_function_type(
     ids: &[TypedId],
     r_type: Option<TypeNodeId>,
@@ -424,9 +739,9 @@ fn gen_unknown_function_type(
     )
     .into_id_with_span(span.clone())
 }
-fn func_parser(
+fn toplevel_parser(
     current_file: Option<PathBuf>,
-) -> impl Parser<Token, ExprNodeId, Error = Simple<Token>> + Clone {
+) -> impl Parser<Token, Statements, Error = Simple<Token, Span>> + Clone {
     let exprgroup = exprgroup_parser();
     let lvar = lvar_parser_typed();
     let blockstart = just(Token::BlockBegin)
@@ -485,28 +800,82 @@ fn func_parser(
             )
         })
         .labelled("macro definition");
+    let type_decl = just(Token::Type)
+        .ignore_then(just(Token::Alias).or_not().map(|a| a.is_some()))
+        .then(ident_parser())
+        .then_ignore(just(Token::Assign))
+        .then(union_type_parser().or(type_parser()))
+        .validate(|((is_alias, name), ty), span, emit| {
+            if is_alias && matches!(ty.to_type(), Type::Union(_)) {
+                emit(Simple::custom(
+                    span,
+                    "Sum type cannot be declared as an alias.",
+                ))
+            }
+            ((is_alias, name), ty)
+        })
+        .map_with_span(|((is_alias, name), ty), s| {
+            (Statement::TypeDecl(TypeDecl { name, ty, is_alias }), s)
+        })
+        .labelled("type declaration");
     let global_stmt = statement_parser(exprgroup.clone());
-    let stmt = function_s.or(macro_s).or(global_stmt);
-    let stmts = stmt
-        .map(|s: (Statement, Span)| vec![s])
-        .or(
-            preprocess_parser(current_file.unwrap_or_default()).map_with_span(|e, s| {
-                stmt_from_expr_top(e)
-                    .into_iter()
-                    .map(|st| (st, s.clone()))
-                    .collect()
-            }),
-        )
-        .separated_by(just(Token::LineBreak).or(just(Token::SemiColon)).repeated())
-        .allow_leading()
-        .allow_trailing()
-        .flatten()
-        .map(|stmt| into_then_expr(&stmt));
-    stmts.try_map(|e: Option<ExprNodeId>, span| e.ok_or(Simple::custom(span, "empty expressions")))
+    let use_s = just(Token::Use)
+        .ignore_then(path_parser())
+        .map_with_span(|path, s| (Statement::Use(path), s))
+        .labelled("use");
+    let current_file = current_file.unwrap_or_default();
+    recursive(move |stmts| {
+        let module_s = just(Token::Mod)
+            .ignore_then(ident_parser())
+            .then(
+                stmts
+                    .delimited_by(just(Token::BlockBegin), just(Token::BlockEnd))
+                    .or_not(),
+            )
+            .map_with_span(|(name, body), s| (Statement::Module(name, body), s))
+            .labelled("module");
+        let item = function_s
+            .clone()
+            .or(macro_s.clone())
+            .or(type_decl.clone())
+            .or(module_s)
+            .or(use_s.clone());
+        let pub_s = just(Token::Pub)
+            .ignore_then(item.clone().or(global_stmt.clone()))
+            .map_with_span(|(stmt, _), s| (Statement::Pub(Box::new(stmt)), s));
+        let stmt = doc_parser()
+            .or_not()
+            .then(attribute_parser().repeated())
+            .then(pub_s.or(item).or(global_stmt.clone()))
+            .map(|((doc, attrs), (stmt, s))| {
+                let stmt = attrs.into_iter().rev().fold(stmt, |stmt, (name, span)| {
+                    Statement::Attribute(name, span, Box::new(stmt))
+                });
+                match doc {
+                    Some(doc) => (Statement::Doc(doc, Box::new(stmt)), s),
+                    None => (stmt, s),
+                }
+            });
+        let stmt = stmt
+            .map(|s: (Statement, Span)| vec![s])
+            .or(
+                preprocess_parser(current_file.clone()).map_with_span(|e, s| {
+                    stmt_from_expr_top(e)
+                        .into_iter()
+                        .map(|st| (st, s.clone()))
+                        .collect()
+                }),
+            );
+        recover_statement(stmt, |s| vec![error_statement(s)])
+            .separated_by(just(Token::LineBreak).or(just(Token::SemiColon)).repeated())
+            .allow_leading()
+            .allow_trailing()
+            .flatten()
+    })
 }
 fn preprocess_parser(
     current_file: PathBuf,
-) -> impl Parser<Token, ExprNodeId, Error = Simple<Token>> + Clone {
+) -> impl Parser<Token, ExprNodeId, Error = Simple<Token, Span>> + Clone {
     just(Token::Include)
         .ignore_then(
             select! {Token::Str(s) => s}
@@ -515,22 +884,23 @@ fn preprocess_parser(
         .try_map(move |filename, span: Span| {
             let cfile = current_file.to_str().unwrap();
             resolve_include(cfile, &filename, span.clone()).map_err(|_e| {
-                Simple::<Token>::custom(span, format!("failed to resolve include for {filename}"))
+                Simple::<Token, Span>::custom(
+                    span,
+                    format!("failed to resolve include for {filename}"),
+                )
             })
         })
 }
 fn parser(
     current_file: Option<PathBuf>,
-) -> impl Parser<Token, ExprNodeId, Error = Simple<Token>> + Clone {
-    let ignored = comment_parser()
-        .or(just(Token::LineBreak).ignored())
-        .or(just(Token::SemiColon).ignored());
-    func_parser(current_file)
+) -> impl Parser<Token, Statements, Error = Simple<Token, Span>> + Clone {
+    let ignored = just(Token::LineBreak).or(just(Token::SemiColon));
+    toplevel_parser(current_file)
         .padded_by(ignored.repeated())
         .then_ignore(end())
 }
 
-pub(crate) fn add_global_context(ast: ExprNodeId) -> ExprNodeId {
+pub fn add_global_context(ast: ExprNodeId) -> ExprNodeId {
     let span = ast.to_span();
     let res = Expr::Let(
         TypedPattern {
@@ -542,31 +912,191 @@ pub(crate) fn add_global_context(ast: ExprNodeId) -> ExprNodeId {
     );
     res.into_id(span.clone())
 }
+/// The documentation written with `///` comments before a top-level definition.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DocComment {
+    /// The name of the definition, qualified with the path of the module it belongs to.
+    pub name: Symbol,
+    /// The names of the parameters when the definition is a function.
+    pub params: Option<Vec<Symbol>>,
+    pub text: String,
+    pub span: Span,
+}
+/// An attribute like `#[inline]` written before a top-level function definition.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attribute {
+    /// The name of the attribute, like `inline` for `#[inline]`.
+    pub name: Symbol,
+    /// The name of the function, qualified with the path of the module it belongs to.
+    pub target: Symbol,
+    pub span: Span,
+}
+/// The doc comments and the attributes of the top-level definitions found in the source.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Annotations {
+    pub docs: Vec<DocComment>,
+    pub attributes: Vec<Attribute>,
+}
+/// The result of parsing with the doc comments found in the source.
+pub type Documented<T> = (T, Vec<DocComment>);
 pub fn parse(
     src: &str,
     current_file: Option<PathBuf>,
 ) -> Result<ExprNodeId, Vec<Box<dyn ReportableError>>> {
+    parse_with_docs(src, current_file).map(|(ast, _docs)| ast)
+}
+/// Parses the source, also returning the doc comments of the definitions in it.
+pub fn parse_with_docs(
+    src: &str,
+    current_file: Option<PathBuf>,
+) -> Result<Documented<ExprNodeId>, Vec<Box<dyn ReportableError>>> {
+    let file = with_session_globals(|session_globals| {
+        session_globals.add_source_file(current_file.clone(), src)
+    });
+    match parse_file_recovery(src, file, current_file) {
+        (Some(ast), annotations, errs) if errs.is_empty() => Ok((ast, annotations.docs)),
+        (_, _, errs) => Err(errs),
+    }
+}
+/// Parses the file loaded by the `include` at `span`, which is remembered in the source map.
+pub(crate) fn parse_included(
+    src: &str,
+    path: PathBuf,
+    span: Span,
+) -> Result<ExprNodeId, Vec<Box<dyn ReportableError>>> {
+    let file = with_session_globals(|session_globals| {
+        let file = session_globals.add_source_file(Some(path.clone()), src);
+        session_globals.source_map.set_included_from(file, span);
+        file
+    });
+    match parse_file_recovery(src, file, Some(path)) {
+        (Some(ast), _annotations, errs) if errs.is_empty() => Ok(ast),
+        (_, _, errs) => Err(errs),
+    }
+}
+/// Parses the source while recovering from the syntax errors. The parts failed to parse are
+/// left as `Expr::Error` in the returned expression, and all the errors are returned together.
+/// The expression is `None` only when the recovery was not possible.
+pub fn parse_recovery(
+    src: &str,
+    current_file: Option<PathBuf>,
+) -> (Option<ExprNodeId>, Vec<Box<dyn ReportableError>>) {
+    let (ast, _attributes, errs) = parse_recovery_with_attributes(src, current_file);
+    (ast, errs)
+}
+/// Parses the source like [`parse_recovery`], also returning the attributes of the definitions.
+pub fn parse_recovery_with_attributes(
+    src: &str,
+    current_file: Option<PathBuf>,
+) -> (
+    Option<ExprNodeId>,
+    Vec<Attribute>,
+    Vec<Box<dyn ReportableError>>,
+) {
+    let file = with_session_globals(|session_globals| {
+        session_globals.add_source_file(current_file.clone(), src)
+    });
+    let (ast, annotations, errs) = parse_file_recovery(src, file, current_file);
+    (ast, annotations.attributes, errs)
+}
+// parses the source registered in the source map as `file`.
+#[cfg(test)]
+fn parse_file(
+    src: &str,
+    file: FileId,
+    current_file: Option<PathBuf>,
+) -> Result<ExprNodeId, Vec<Box<dyn ReportableError>>> {
+    match parse_file_recovery(src, file, current_file) {
+        (Some(ast), _annotations, errs) if errs.is_empty() => Ok(ast),
+        (_, _, errs) => Err(errs),
+    }
+}
+fn parse_file_recovery(
+    src: &str,
+    file: FileId,
+    current_file: Option<PathBuf>,
+) -> (
+    Option<ExprNodeId>,
+    Annotations,
+    Vec<Box<dyn ReportableError>>,
+) {
+    let len = src.chars().count();
+    let (stmts, mut errs) = parse_statements(src, file, current_file.clone());
+    let (stmts, annotations) = match resolve_module::resolve_modules(stmts, current_file) {
+        Ok(res) => res,
+        Err(resolve_errs) => {
+            errs.extend(resolve_errs);
+            return (None, Annotations::default(), errs);
+        }
+    };
+    let res = into_then_expr(&stmts);
+    if res.is_none() && errs.is_empty() {
+        let e = Simple::custom(Span::new(file, 0..len), "empty expressions");
+        errs.push(Box::new(error::ParseError::<Token>(e)));
+    }
+    (res, annotations, errs)
+}
+// parses the source into the statements, whose modules are not resolved yet.
+// a doc comment is kept only at the beginning of a line followed by a statement. The other ones,
+// like the ones after an expression or at the end of a block, are single line comments.
+fn demote_misplaced_docs(tokens: Vec<(Token, Span)>) -> Vec<(Token, Span)> {
+    let is_doc = |t: &Token| matches!(t, Token::Comment(Comment::Doc(_)));
+    let mut res: Vec<(Token, Span)> = Vec::with_capacity(tokens.len());
+    for (i, (tok, span)) in tokens.iter().enumerate() {
+        let is_placed = is_doc(tok)
+            && res.last().is_none_or(|(prev, _)| {
+                is_doc(prev)
+                    || matches!(
+                        prev,
+                        Token::LineBreak | Token::SemiColon | Token::BlockBegin
+                    )
+            })
+            && tokens[i + 1..]
+                .iter()
+                .find(|(next, _)| !is_doc(next) && *next != Token::LineBreak)
+                .is_some_and(|(next, _)| *next != Token::BlockEnd);
+        match tok {
+            Token::Comment(Comment::Doc(_)) if !is_placed => {
+                res.push((Token::LineBreak, span.clone()))
+            }
+            _ => res.push((tok.clone(), span.clone())),
+        }
+    }
+    res
+}
+// `file` is the id of the source registered in the source map.
+// the statements failed to parse are left as `Expr::Error` along with the errors.
+fn parse_statements(
+    src: &str,
+    file: FileId,
+    current_file: Option<PathBuf>,
+) -> (Statements, Vec<Box<dyn ReportableError>>) {
     let len = src.chars().count();
     let mut errs = Vec::<Box<dyn ReportableError>>::new();
 
-    let (tokens, lex_errs) = lexer::lexer().parse_recovery(src);
+    let (tokens, lex_errs) = lexer::lexer().parse_recovery(lexer::char_stream(src, file));
     lex_errs
         .iter()
         .for_each(|e| errs.push(Box::new(error::ParseError::<char>(e.clone()))));
 
-    if let Some(t) = tokens {
-        let (ast, parse_errs) = parser(current_file)
-            .parse_recovery(chumsky::Stream::from_iter(len..len + 1, t.into_iter()));
-        match ast {
-            Some(ast) if parse_errs.is_empty() => Ok(ast),
-            _ => {
-                parse_errs
-                    .iter()
-                    .for_each(|e| errs.push(Box::new(error::ParseError::<Token>(e.clone()))));
-                Err(errs)
-            }
-        }
-    } else {
-        Err(errs)
-    }
+    let Some(t) = tokens else {
+        return (vec![], errs);
+    };
+    // comments can be placed anywhere. a single line comment also ends the line.
+    // doc comments are kept to be attached to the definitions.
+    let t = t
+        .into_iter()
+        .filter_map(|(tok, span)| match tok {
+            Token::Comment(Comment::SingleLine(_)) => Some((Token::LineBreak, span)),
+            Token::Comment(Comment::MultiLine(_)) => None,
+            _ => Some((tok, span)),
+        })
+        .collect::<Vec<_>>();
+    let t = demote_misplaced_docs(t).into_iter();
+    let (ast, parse_errs) = parser(current_file)
+        .parse_recovery(chumsky::Stream::from_iter(Span::new(file, len..len + 1), t));
+    parse_errs
+        .iter()
+        .for_each(|e| errs.push(Box::new(error::ParseError::<Token>(e.clone()))));
+    (ast.unwrap_or_default(), errs)
 }
@@ -5,15 +5,15 @@ use std::fmt;
 use std::hash::Hash;
 // pub struct LexError(chumsky::error::Simple<char>);
 #[derive(Debug)]
-pub struct ParseError<T>(pub chumsky::error::Simple<T>)
+pub struct ParseError<T>(pub chumsky::error::Simple<T, Span>)
 where
     T: Hash + std::cmp::Eq + fmt::Debug + fmt::Display;
 
-impl<T> Into<chumsky::error::Simple<T>> for ParseError<T>
+impl<T> Into<chumsky::error::Simple<T, Span>> for ParseError<T>
 where
     T: Hash + std::cmp::Eq + fmt::Debug + fmt::Display,
 {
-    fn into(self) -> chumsky::error::Simple<T> {
+    fn into(self) -> chumsky::error::Simple<T, Span> {
         self.0
     }
 }
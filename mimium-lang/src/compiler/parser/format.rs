@@ -0,0 +1,340 @@
+//! Formatter which re-emits the source in the canonical style.
+//!
+//! It works on the token stream rather than the AST, so that the comments and the blank lines
+//! between statements stay where they were written. Blocks and the other delimiters opened on a
+//! line indent the following lines by one level, and the lines continuing an expression, like
+//! the ones starting or ending with a pipe, are indented by one more level.
+use std::path::PathBuf;
+
+use chumsky::Parser;
+
+use super::token::{Comment, Op, Token};
+use super::{error, lexer, parse_statements};
+use crate::interner::with_session_globals;
+use crate::utils::error::ReportableError;
+
+const INDENT: &str = "    ";
+// the characters which the lexer joins into a single operator.
+const OP_CHARS: [char; 13] = ['+', '-', '*', '/', '!', '=', '&', '|', '%', '>', '<', '^', '@'];
+
+/// Formats the source into the canonical style. The comments are kept and consecutive blank
+/// lines are collapsed into one. The source which fails to parse is not formatted, and its
+/// errors are returned instead.
+pub fn format_source(
+    src: &str,
+    current_file: Option<PathBuf>,
+) -> Result<String, Vec<Box<dyn ReportableError>>> {
+    let file = with_session_globals(|session_globals| {
+        session_globals.add_source_file(current_file.clone(), src)
+    });
+    let (_stmts, errs) = parse_statements(src, file, current_file);
+    if !errs.is_empty() {
+        return Err(errs);
+    }
+    let tokens = lexer::lexer()
+        .parse(lexer::char_stream(src, file))
+        .map_err(|errs| {
+            errs.into_iter()
+                .map(|e| Box::new(error::ParseError::<char>(e)) as Box<dyn ReportableError>)
+                .collect::<Vec<_>>()
+        })?;
+    let chars = src.chars().collect::<Vec<_>>();
+    let mut formatter = Formatter::default();
+    for (tok, span) in tokens {
+        let breaks = chars[span.range].iter().filter(|c| **c == '\n').count();
+        formatter.push(tok, breaks);
+    }
+    Ok(formatter.finish())
+}
+
+// `|` is used for the parameters of lambdas and for the variants of sum types.
+#[derive(Clone, Copy, PartialEq)]
+enum Role {
+    Plain,
+    Unary,
+    LambdaOpen,
+    LambdaClose,
+    Bar,
+}
+
+#[derive(Default)]
+struct Formatter {
+    out: String,
+    // the last token emitted except for comments, with its role.
+    prev: Option<(Token, Role)>,
+    // whether a comment was emitted last on the current line.
+    after_comment: bool,
+    // the number of line breaks seen since the last emitted token.
+    pending_breaks: usize,
+    // the indentation of the current line.
+    line_indent: usize,
+    // the indentation of the lines where the unclosed delimiters were opened.
+    delimiters: Vec<usize>,
+    in_lambda_params: bool,
+    in_type_decl: bool,
+}
+
+impl Formatter {
+    fn push(&mut self, tok: Token, breaks: usize) {
+        match tok {
+            Token::LineBreak => self.pending_breaks += breaks,
+            // a single line comment contains the line break at its end.
+            Token::Comment(Comment::SingleLine(c)) => {
+                self.write_comment(None, &format!("//{}", c.trim_end()));
+                self.pending_breaks += breaks.max(1);
+            }
+            Token::Comment(Comment::Doc(c)) => {
+                self.write_comment(None, &format!("///{}", c.trim_end()));
+                self.pending_breaks += breaks.max(1);
+            }
+            Token::Comment(Comment::MultiLine(c)) => {
+                self.write_comment(None, &format!("/*{c}*/"));
+            }
+            tok => self.write_token(tok),
+        }
+    }
+
+    fn write_comment(&mut self, next: Option<&Token>, text: &str) {
+        if !self.begin_line(next, Role::Plain) && !self.out.ends_with(['(', '[']) {
+            self.out.push(' ');
+        }
+        self.out.push_str(text);
+        self.after_comment = true;
+    }
+
+    fn write_token(&mut self, tok: Token) {
+        let role = self.role_of(&tok);
+        let line_start = self.begin_line(Some(&tok), role);
+        if tok == Token::Type {
+            self.in_type_decl = true;
+        }
+        let text = tok.to_string();
+        // adjacent operators like `| |` or `* -` must not be joined into another one.
+        let joined = self.out.ends_with(OP_CHARS) && text.starts_with(OP_CHARS);
+        if !line_start && (self.after_comment || joined || self.needs_space(&tok, role)) {
+            self.out.push(' ');
+        }
+        match (&tok, role) {
+            (Token::LambdaArgBeginEnd, Role::LambdaOpen) => self.in_lambda_params = true,
+            (Token::LambdaArgBeginEnd, Role::LambdaClose) => self.in_lambda_params = false,
+            (Token::ParenBegin | Token::ArrayBegin | Token::BlockBegin, _) => {
+                self.delimiters.push(self.line_indent)
+            }
+            (Token::ParenEnd | Token::ArrayEnd | Token::BlockEnd, _) => {
+                self.delimiters.pop();
+            }
+            _ => {}
+        }
+        self.out.push_str(&text);
+        self.after_comment = false;
+        self.prev = Some((tok, role));
+    }
+
+    // starts a new line if there were line breaks before the next token, and returns if the
+    // token is placed at the beginning of the line.
+    fn begin_line(&mut self, next: Option<&Token>, role: Role) -> bool {
+        if self.out.is_empty() {
+            self.pending_breaks = 0;
+            return true;
+        }
+        if self.pending_breaks == 0 {
+            return false;
+        }
+        let closing = matches!(
+            next,
+            Some(Token::ParenEnd | Token::ArrayEnd | Token::BlockEnd)
+        );
+        let opened = matches!(
+            self.prev,
+            Some((Token::ParenBegin | Token::ArrayBegin | Token::BlockBegin, _))
+        ) && !self.after_comment;
+        // no blank lines just inside the delimiters.
+        let blank = self.pending_breaks > 1 && !closing && !opened;
+        self.out.push_str(if blank { "\n\n" } else { "\n" });
+        self.pending_breaks = 0;
+        let base = self.delimiters.last().map_or(0, |i| i + 1);
+        let continued = self.is_continuation(next, role);
+        if !continued && self.delimiters.is_empty() && role != Role::Bar {
+            self.in_type_decl = false;
+        }
+        self.line_indent = match (closing, self.delimiters.last()) {
+            (true, Some(i)) => *i,
+            _ if continued => base + 1,
+            _ => base,
+        };
+        self.out.push_str(&INDENT.repeat(self.line_indent));
+        true
+    }
+
+    // the line continues the expression on the previous line.
+    fn is_continuation(&self, next: Option<&Token>, role: Role) -> bool {
+        let prev_continues = matches!(
+            &self.prev,
+            Some((Token::Op(_), Role::Plain | Role::Unary))
+                | Some((Token::Assign | Token::Arrow | Token::FatArrow, _))
+                | Some((Token::LambdaArgBeginEnd, Role::Bar))
+        );
+        let next_continues = match next {
+            Some(Token::Op(Op::Minus)) => false,
+            Some(Token::Op(_)) => true,
+            Some(Token::LambdaArgBeginEnd) => role == Role::Bar,
+            _ => false,
+        };
+        prev_continues || next_continues
+    }
+
+    fn role_of(&self, tok: &Token) -> Role {
+        let prev_is_value = self.prev.as_ref().is_some_and(|(t, r)| {
+            *r == Role::Plain
+                && matches!(
+                    t,
+                    Token::Ident(_)
+                        | Token::Int(_)
+                        | Token::Float(_)
+                        | Token::Str(_)
+                        | Token::SelfLit
+                        | Token::Now
+                        | Token::PlaceHolder
+                        | Token::FloatType
+                        | Token::IntegerType
+                        | Token::StringType
+                        | Token::ParenEnd
+                        | Token::ArrayEnd
+                        | Token::BlockEnd
+                )
+        });
+        let at_line_start = self.pending_breaks > 0;
+        match tok {
+            Token::LambdaArgBeginEnd if self.in_lambda_params => Role::LambdaClose,
+            // sum types are the only place where `|` is not a part of lambdas.
+            Token::LambdaArgBeginEnd if self.in_type_decl => Role::Bar,
+            Token::LambdaArgBeginEnd => Role::LambdaOpen,
+            Token::Op(Op::Minus) if at_line_start || !prev_is_value => Role::Unary,
+            _ => Role::Plain,
+        }
+    }
+
+    // whether a space is put between the previous token and the next one on the same line.
+    fn needs_space(&self, next: &Token, role: Role) -> bool {
+        let Some((prev, prev_role)) = &self.prev else {
+            return false;
+        };
+        match (prev_role, role) {
+            (_, Role::LambdaClose) | (Role::LambdaOpen | Role::Unary, _) => return false,
+            (Role::LambdaClose | Role::Bar, _) | (_, Role::Bar) => return true,
+            _ => {}
+        }
+        match (prev, next) {
+            (
+                _,
+                Token::ParenEnd
+                | Token::ArrayEnd
+                | Token::BlockEnd
+                | Token::Comma
+                | Token::SemiColon
+                | Token::Colon
+                | Token::Dot
+                | Token::DoubleColon,
+            ) => false,
+            (
+                Token::ParenBegin
+                | Token::ArrayBegin
+                | Token::BlockBegin
+                | Token::Dot
+                | Token::DoubleColon,
+                _,
+            ) => false,
+            (Token::Op(Op::At), _) | (_, Token::Op(Op::At)) => false,
+            // application and indexing.
+            (
+                Token::Ident(_)
+                | Token::MacroExpand(_)
+                | Token::Include
+                | Token::SelfLit
+                | Token::ParenEnd
+                | Token::ArrayEnd,
+                Token::ParenBegin,
+            ) => false,
+            (Token::Ident(_) | Token::ParenEnd | Token::ArrayEnd, Token::ArrayBegin) => false,
+            _ => true,
+        }
+    }
+
+    fn finish(mut self) -> String {
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn format(src: &str) -> String {
+        match format_source(src, None) {
+            Ok(res) => res,
+            Err(errs) => panic!("{}", crate::utils::error::dump_to_string(&errs)),
+        }
+    }
+
+    #[test]
+    fn spaces_and_indentation() {
+        let src = "fn addmul(a,b)->float{\n  a+b* -1.0\n}\nfn dsp(){\nlet r={freq=440.0,}\n  addmul(r.freq,[1.0,2.0][0])\n}";
+        let ans = "fn addmul(a, b) -> float {
+    a + b * -1.0
+}
+fn dsp() {
+    let r = {freq = 440.0,}
+    addmul(r.freq, [1.0, 2.0][0])
+}
+";
+        assert_eq!(format(src), ans);
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        let src = "// gain\nfn gain(x){\n\n    x*2.0 // doubled\n\n\n}\n\n\n\n/* output */ fn dsp(){ gain(1.0) }";
+        let ans = "// gain
+fn gain(x) {
+    x * 2.0 // doubled
+}
+
+/* output */ fn dsp() {gain(1.0)}
+";
+        assert_eq!(format(src), ans);
+    }
+
+    #[test]
+    fn lambdas_and_pipes() {
+        let src = "fn dsp(){\nlet f = | |{1.0}\nlet x = 3.0\n|> |a|{\na*2.0\n}\n  |> f\nx\n}";
+        let ans = "fn dsp() {
+    let f = | | {1.0}
+    let x = 3.0
+        |> |a| {
+            a * 2.0
+        }
+        |> f
+    x
+}
+";
+        assert_eq!(format(src), ans);
+    }
+
+    #[test]
+    fn sum_type() {
+        let src = "type Wave= |Sine|Saw(float)\nfn dsp(){ |x|x }";
+        let ans = "type Wave = | Sine | Saw(float)
+fn dsp() {|x| x}
+";
+        assert_eq!(format(src), ans);
+    }
+
+    #[test]
+    fn idempotent() {
+        let src = "fn dsp(){\nlet x = 3.0 |>\n1.0 + _ |>\n  |a| a\nx\n}";
+        let once = format(src);
+        assert_eq!(format(&once), once);
+    }
+}
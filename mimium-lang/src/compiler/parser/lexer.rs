@@ -4,7 +4,7 @@ use crate::utils::metadata::*;
 use chumsky::prelude::*;
 use chumsky::Parser;
 
-fn comment_parser() -> impl Parser<char, Comment, Error = Simple<char>> + Clone {
+fn comment_parser() -> impl Parser<char, Comment, Error = Simple<char, Span>> + Clone {
     // comment parser that keep its contents length, not to break line number for debugging.
     // replaces all characters except for newline.
     let single_line = (just("//"))
@@ -15,10 +15,16 @@ fn comment_parser() -> impl Parser<char, Comment, Error = Simple<char>> + Clone
         .ignore_then(take_until(just("*/").ignored()))
         .map(|(c, _)| Comment::MultiLine(String::from_iter(c.iter())));
 
-    single_line.or(multi_line)
+    // `///` starts a doc comment, while `////...` is an ordinary one.
+    let doc = just("///")
+        .ignore_then(filter(|c: &char| *c != '/').rewind())
+        .ignore_then(take_until(text::newline().ignored()))
+        .map(|(c, _)| Comment::Doc(String::from_iter(c.iter())));
+
+    doc.or(single_line).or(multi_line)
 }
 
-pub fn lexer() -> impl Parser<char, Vec<(Token, Span)>, Error = Simple<char>> {
+pub fn lexer() -> impl Parser<char, Vec<(Token, Span)>, Error = Simple<char, Span>> {
     // A parser for numbers
     let int = text::int(10).map(|s: String| Token::Int(s.parse().unwrap()));
 
@@ -41,6 +47,7 @@ pub fn lexer() -> impl Parser<char, Vec<(Token, Span)>, Error = Simple<char>> {
         .collect::<String>()
         .map(|s: String| match s.as_str() {
             "->" => Token::Arrow,
+            "=>" => Token::FatArrow,
             "|" => Token::LambdaArgBeginEnd,
             "+" => Token::Op(Op::Sum),
             "-" => Token::Op(Op::Minus),
@@ -61,13 +68,15 @@ pub fn lexer() -> impl Parser<char, Vec<(Token, Span)>, Error = Simple<char>> {
             "|>" => Token::Op(Op::Pipe),
             _ => Token::Op(Op::Unknown(s)),
         });
-    let separator = one_of(",.:;").map(|c| match c {
-        ',' => Token::Comma,
-        '.' => Token::Dot,
-        ':' => Token::Colon,
-        ';' => Token::SemiColon,
-        _ => Token::Ident(c.to_string().to_symbol()),
-    });
+    let separator = just("::")
+        .to(Token::DoubleColon)
+        .or(one_of(",.:;").map(|c| match c {
+            ',' => Token::Comma,
+            '.' => Token::Dot,
+            ':' => Token::Colon,
+            ';' => Token::SemiColon,
+            _ => Token::Ident(c.to_string().to_symbol()),
+        }));
     // A parser for identifiers and keywords
     let ident = text::ident().map(|ident: String| match ident.as_str() {
         "fn" => Token::Function,
@@ -78,6 +87,8 @@ pub fn lexer() -> impl Parser<char, Vec<(Token, Span)>, Error = Simple<char>> {
         "letrec" => Token::LetRec,
         "if" => Token::If,
         "else" => Token::Else,
+        "match" => Token::Match,
+        "return" => Token::Return,
         // "true" => Token::Bool(true),
         // "false" => Token::Bool(false),
         // "null" => Token::Null,
@@ -85,7 +96,12 @@ pub fn lexer() -> impl Parser<char, Vec<(Token, Span)>, Error = Simple<char>> {
         "int" => Token::IntegerType,
         "string" => Token::StringType,
         "struct" => Token::StructType,
+        "type" => Token::Type,
+        "alias" => Token::Alias,
         "include" => Token::Include,
+        "mod" => Token::Mod,
+        "use" => Token::Use,
+        "pub" => Token::Pub,
         "_" => Token::PlaceHolder,
         _ => Token::Ident(ident.to_symbol()),
     });
@@ -93,7 +109,7 @@ pub fn lexer() -> impl Parser<char, Vec<(Token, Span)>, Error = Simple<char>> {
         .then_ignore(just('!'))
         .map(|ident: String| Token::MacroExpand(ident.to_symbol()));
 
-    let parens = one_of::<_, _, Simple<char>>("(){}[]").map(|c| match c {
+    let parens = one_of::<_, _, Simple<char, Span>>("(){}[]").map(|c| match c {
         '(' => Token::ParenBegin,
         ')' => Token::ParenEnd,
         '{' => Token::BlockBegin,
@@ -102,6 +118,10 @@ pub fn lexer() -> impl Parser<char, Vec<(Token, Span)>, Error = Simple<char>> {
         ']' => Token::ArrayEnd,
         _ => Token::Ident(c.to_string().to_symbol()),
     });
+    let attribute = just("#[")
+        .ignore_then(text::ident().padded())
+        .then_ignore(just(']'))
+        .map(|name: String| Token::Attribute(name.to_symbol()));
     let linebreak = text::newline()
         .map(|_| '\n')
         // .or(just::<_, _, Simple<char>>(';'))
@@ -116,6 +136,7 @@ pub fn lexer() -> impl Parser<char, Vec<(Token, Span)>, Error = Simple<char>> {
         .or(str_)
         // .or(ctrl)
         .or(macro_expand)
+        .or(attribute)
         .or(separator)
         .or(ident)
         .or(op)
@@ -133,13 +154,34 @@ pub fn lexer() -> impl Parser<char, Vec<(Token, Span)>, Error = Simple<char>> {
         .then_ignore(end())
 }
 
+/// Makes a character stream whose spans point to the given source file.
+pub fn char_stream(
+    src: &str,
+    file: FileId,
+) -> chumsky::Stream<'_, char, Span, impl Iterator<Item = (char, Span)> + '_> {
+    let len = src.chars().count();
+    chumsky::Stream::from_iter(
+        Span::new(file, len..len),
+        src.chars()
+            .enumerate()
+            .map(move |(i, c)| (c, Span::new(file, i..i + 1))),
+    )
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::ops::Range;
+
+    fn lex(src: &str) -> (Option<Vec<(Token, Range<usize>)>>, Vec<Simple<char, Span>>) {
+        let (res, errs) = lexer().parse_recovery(char_stream(src, FileId(0)));
+        let res = res.map(|toks| toks.into_iter().map(|(t, s)| (t, s.range)).collect());
+        (res, errs)
+    }
     #[test]
     fn test_let() {
         let src = "let hoge = 36\nfuga";
-        let (res, _errs) = lexer().parse_recovery(src);
+        let (res, _errs) = lex(src);
         let ans = [
             (Token::Let, 0..3),
             (Token::Ident("hoge".to_symbol()), 4..8),
@@ -184,13 +226,42 @@ another line
             (Token::Ident("line".to_symbol()), 70..74),
             (Token::LineBreak, 74..75),
         ];
-        let (res, errs) = lexer().parse_recovery(src);
+        let (res, errs) = lex(src);
         assert!(errs.is_empty());
         assert!(res.is_some());
         assert_eq!(ans, res.unwrap());
         assert_eq!(src.len(), 75);
     }
 
+    #[test]
+    fn doc_comment() {
+        let src = "/// doc\n//// not doc\nfn";
+        let ans = vec![
+            (Token::Comment(Comment::Doc(" doc".into())), 0..8),
+            (
+                Token::Comment(Comment::SingleLine("// not doc".into())),
+                8..21,
+            ),
+            (Token::Function, 21..23),
+        ];
+        let (res, errs) = lex(src);
+        assert!(errs.is_empty());
+        assert_eq!(ans, res.unwrap());
+    }
+
+    #[test]
+    fn attribute() {
+        let src = "#[inline]\nfn";
+        let ans = vec![
+            (Token::Attribute("inline".to_symbol()), 0..9),
+            (Token::LineBreak, 9..10),
+            (Token::Function, 10..12),
+        ];
+        let (res, errs) = lex(src);
+        assert!(errs.is_empty());
+        assert_eq!(ans, res.unwrap());
+    }
+
     #[test]
     fn test_whitespaces() {
         let cases = [
@@ -199,7 +270,7 @@ another line
             "foo  \n", // linebreak at end
         ];
         for c in cases {
-            let (res, errs) = lexer().parse_recovery(c);
+            let (res, errs) = lex(c);
             assert!(errs.is_empty(), "failed to parse");
             assert_eq!(res.unwrap()[0], (Token::Ident("foo".to_symbol()), 0..3))
         }
@@ -1,4 +1,4 @@
-use super::{parse, Span};
+use super::{parse_included, Span};
 use crate::interner::ExprNodeId;
 use crate::utils::error::{ReportableError, ReportableErrorDyn};
 use crate::utils::fileloader;
@@ -19,5 +19,5 @@ pub(super) fn resolve_include(
         .map_err(|e| make_vec_error(e, span.clone()))?;
     let content =
         fileloader::load(abspath.to_str().unwrap()).map_err(|e| make_vec_error(e, span.clone()))?;
-    parse(&content, Some(abspath))
+    parse_included(&content, abspath, span)
 }
@@ -0,0 +1,734 @@
+//! Resolution of the modules declared with `mod` and imported with `use`.
+//!
+//! Modules exist only in the parser. The definitions in a module are flattened into the
+//! global context with the names qualified by the module path (e.g. `lowpass` in
+//! `mod filter` becomes `filter::lowpass`), so the later stages do not know about modules.
+//! `mod name` without a body loads `name.mmm` next to the current file. Each file is loaded
+//! only once even if it is declared as a module several times.
+use std::collections::{btree_map::Entry, BTreeMap};
+use std::path::{Path, PathBuf};
+
+use super::statement::{Statement, Statements};
+use super::{parse_statements, Annotations, Attribute, DocComment, Span};
+use crate::ast::{Expr, TypeDecl};
+use crate::interner::{with_session_globals, ExprNodeId, Symbol, ToSymbol, TypeNodeId};
+use crate::mir::InlineHint;
+use crate::pattern::{Pattern, TypedId, TypedPattern};
+use crate::types::Type;
+use crate::utils::error::{ReportableError, ReportableErrorDyn};
+use crate::utils::fileloader;
+
+const SEPARATOR: &str = "::";
+
+pub(super) fn join_path(path: &[Symbol]) -> Symbol {
+    path.iter()
+        .map(|s| s.as_str())
+        .collect::<Vec<_>>()
+        .join(SEPARATOR)
+        .to_symbol()
+}
+fn split_path(name: Symbol) -> Vec<Symbol> {
+    name.as_str()
+        .split(SEPARATOR)
+        .map(|s| s.to_symbol())
+        .collect()
+}
+// the name of the definition in the global context.
+fn mangle(path: &[Symbol], name: Symbol) -> Symbol {
+    let mut res = path.to_vec();
+    res.push(name);
+    join_path(&res)
+}
+fn make_error(message: String, span: Span) -> Box<dyn ReportableError> {
+    Box::new(ReportableErrorDyn { message, span })
+}
+
+// statements in the modules, with the path of the module they belong to.
+type Flattened = Vec<(Vec<Symbol>, Statement, Span)>;
+
+#[derive(Clone, Debug)]
+struct Item {
+    mangled: Symbol,
+    is_pub: bool,
+    is_variant: bool,
+}
+#[derive(Clone, Debug)]
+struct ModuleRef {
+    path: Vec<Symbol>,
+    is_pub: bool,
+}
+#[derive(Clone, Debug)]
+enum Resolved {
+    Item(Item),
+    Module(Vec<Symbol>),
+}
+#[derive(Clone, Debug, Default)]
+struct Scope {
+    items: BTreeMap<Symbol, Item>,
+    modules: BTreeMap<Symbol, ModuleRef>,
+}
+
+struct PendingUse {
+    module: Vec<Symbol>,
+    target: Vec<Symbol>,
+    is_pub: bool,
+    span: Span,
+}
+
+#[derive(Default)]
+struct ModuleResolver {
+    scopes: BTreeMap<Vec<Symbol>, Scope>,
+    // canonical path of the loaded files and the module path they are loaded as.
+    loaded_files: BTreeMap<PathBuf, Vec<Symbol>>,
+    loading_files: Vec<PathBuf>,
+    uses: Vec<PendingUse>,
+    annotations: Annotations,
+    errs: Vec<Box<dyn ReportableError>>,
+}
+
+impl ModuleResolver {
+    fn scope_mut(&mut self, path: &[Symbol]) -> &mut Scope {
+        self.scopes.entry(path.to_vec()).or_default()
+    }
+    fn define(&mut self, path: &[Symbol], name: Symbol, is_pub: bool, is_variant: bool) {
+        let item = Item {
+            mangled: mangle(path, name),
+            is_pub,
+            is_variant,
+        };
+        self.scope_mut(path).items.insert(name, item);
+    }
+    fn define_pattern(&mut self, path: &[Symbol], pat: &Pattern, is_pub: bool) {
+        match pat {
+            Pattern::Single(name) if name.as_str() != "_" => {
+                self.define(path, *name, is_pub, false)
+            }
+            Pattern::Single(_) => {}
+            Pattern::Tuple(pats) | Pattern::Variant(_, pats) => pats
+                .iter()
+                .for_each(|p| self.define_pattern(path, p, is_pub)),
+            Pattern::Record(fields) => fields
+                .iter()
+                .for_each(|(_, p)| self.define_pattern(path, p, is_pub)),
+        }
+    }
+    fn define_module(&mut self, path: &[Symbol], name: Symbol, module: ModuleRef, span: &Span) {
+        match self.scope_mut(path).modules.entry(name) {
+            Entry::Occupied(_) => {
+                let msg = format!("Module {name} is declared more than once");
+                self.errs.push(make_error(msg, span.clone()));
+            }
+            Entry::Vacant(e) => {
+                e.insert(module);
+            }
+        }
+    }
+    // registers the definitions and flattens the statements in the modules into `out`,
+    // with the path of the module they belong to.
+    fn collect(
+        &mut self,
+        path: &[Symbol],
+        stmts: Statements,
+        current_file: &Path,
+        out: &mut Flattened,
+    ) {
+        self.scope_mut(path);
+        for (stmt, span) in stmts {
+            let (mut stmt, doc) = match stmt {
+                Statement::Doc(doc, stmt) => (*stmt, Some(doc)),
+                stmt => (stmt, None),
+            };
+            let mut attrs = vec![];
+            while let Statement::Attribute(name, attr_span, inner) = stmt {
+                attrs.push((name, attr_span));
+                stmt = *inner;
+            }
+            let (stmt, is_pub) = match stmt {
+                Statement::Pub(stmt) => (*stmt, true),
+                stmt => (stmt, false),
+            };
+            if let Some(text) = doc {
+                self.collect_doc(path, &stmt, text, &span);
+            }
+            for (name, attr_span) in attrs {
+                self.collect_attribute(path, &stmt, name, attr_span);
+            }
+            match stmt {
+                Statement::Module(name, body) => {
+                    self.collect_module(path, name, body, is_pub, current_file, &span, out)
+                }
+                Statement::Use(target) => self.uses.push(PendingUse {
+                    module: path.to_vec(),
+                    target,
+                    is_pub,
+                    span,
+                }),
+                Statement::Let(ref pat, _) => {
+                    self.define_pattern(path, &pat.pat, is_pub);
+                    out.push((path.to_vec(), stmt, span));
+                }
+                Statement::LetRec(ref id, _) | Statement::MacroExpand(ref id, _) => {
+                    self.define(path, id.id, is_pub, false);
+                    out.push((path.to_vec(), stmt, span));
+                }
+                Statement::TypeDecl(ref decl) => {
+                    self.define(path, decl.name, is_pub, false);
+                    if let Type::Union(variants) = decl.ty.to_type() {
+                        variants
+                            .iter()
+                            .for_each(|(name, _)| self.define(path, *name, is_pub, true));
+                    }
+                    out.push((path.to_vec(), stmt, span));
+                }
+                Statement::Assign(_, _) | Statement::Single(_) if is_pub => {
+                    let msg = "Only definitions can be exported with pub".to_string();
+                    self.errs.push(make_error(msg, span));
+                }
+                Statement::Assign(_, _) | Statement::Single(_) => {
+                    out.push((path.to_vec(), stmt, span))
+                }
+                Statement::Pub(_) => unreachable!("pub cannot be nested"),
+                Statement::Doc(..) | Statement::Attribute(..) => {
+                    unreachable!("doc comments and attributes are placed before pub")
+                }
+            }
+        }
+    }
+    // the doc comments are kept only for the definitions of a single name.
+    fn collect_doc(&mut self, path: &[Symbol], stmt: &Statement, text: String, span: &Span) {
+        let (name, body) = match stmt {
+            Statement::Let(
+                TypedPattern {
+                    pat: Pattern::Single(name),
+                    ..
+                },
+                body,
+            ) => (*name, *body),
+            Statement::LetRec(id, body) => (id.id, *body),
+            _ => return,
+        };
+        let params = match body.to_expr() {
+            Expr::Lambda(ids, _, _) => Some(ids.iter().map(|id| id.id).collect()),
+            _ => None,
+        };
+        self.annotations.docs.push(DocComment {
+            name: mangle(path, name),
+            params,
+            text,
+            span: span.clone(),
+        });
+    }
+    // the attributes are only for the inliner, so they can be put only on the functions.
+    fn collect_attribute(&mut self, path: &[Symbol], stmt: &Statement, name: Symbol, span: Span) {
+        if InlineHint::from_attribute(name).is_none() {
+            let msg = format!("Unknown attribute #[{name}]");
+            self.errs.push(make_error(msg, span));
+            return;
+        }
+        let target = match stmt {
+            Statement::Let(
+                TypedPattern {
+                    pat: Pattern::Single(target),
+                    ..
+                },
+                body,
+            )
+            | Statement::LetRec(TypedId { id: target, .. }, body)
+                if matches!(body.to_expr(), Expr::Lambda(..)) =>
+            {
+                *target
+            }
+            _ => {
+                let msg = format!("Attribute #[{name}] can be put only on functions");
+                self.errs.push(make_error(msg, span));
+                return;
+            }
+        };
+        self.annotations.attributes.push(Attribute {
+            name,
+            target: mangle(path, target),
+            span,
+        });
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn collect_module(
+        &mut self,
+        path: &[Symbol],
+        name: Symbol,
+        body: Option<Statements>,
+        is_pub: bool,
+        current_file: &Path,
+        span: &Span,
+        out: &mut Flattened,
+    ) {
+        let child = [path, &[name]].concat();
+        let Some(body) = body else {
+            return self.collect_file_module(path, name, is_pub, current_file, span, out);
+        };
+        let module = ModuleRef {
+            path: child.clone(),
+            is_pub,
+        };
+        self.define_module(path, name, module, span);
+        self.collect(&child, body, current_file, out);
+    }
+    fn collect_file_module(
+        &mut self,
+        path: &[Symbol],
+        name: Symbol,
+        is_pub: bool,
+        current_file: &Path,
+        span: &Span,
+        out: &mut Flattened,
+    ) {
+        let filename = format!("{name}.mmm");
+        let cfile = current_file.to_string_lossy();
+        let file = match fileloader::get_canonical_path(&cfile, &filename) {
+            Ok(file) => file,
+            Err(e) => {
+                let msg = format!("failed to find module file {filename}: {e}");
+                return self.errs.push(make_error(msg, span.clone()));
+            }
+        };
+        if self.loading_files.contains(&file) {
+            let msg = format!("Module {name} is circularly declared in {}", file.display());
+            return self.errs.push(make_error(msg, span.clone()));
+        }
+        // the file already loaded is shared, not to define the same things twice.
+        if let Some(loaded) = self.loaded_files.get(&file) {
+            let module = ModuleRef {
+                path: loaded.clone(),
+                is_pub,
+            };
+            return self.define_module(path, name, module, span);
+        }
+        let content = match fileloader::load(&file.to_string_lossy()) {
+            Ok(content) => content,
+            Err(e) => return self.errs.push(make_error(e.to_string(), span.clone())),
+        };
+        let file_id = with_session_globals(|session_globals| {
+            session_globals.add_source_file(Some(file.clone()), &content)
+        });
+        let (stmts, errs) = parse_statements(&content, file_id, Some(file.clone()));
+        self.errs.extend(errs);
+        let child = [path, &[name]].concat();
+        self.loaded_files.insert(file.clone(), child.clone());
+        let module = ModuleRef {
+            path: child.clone(),
+            is_pub,
+        };
+        self.define_module(path, name, module, span);
+        self.loading_files.push(file.clone());
+        self.collect(&child, stmts, &file, out);
+        self.loading_files.pop();
+    }
+
+    // the scopes of the module and its ancestors, from the innermost.
+    fn lexical_scopes<'a>(&'a self, from: &'a [Symbol]) -> impl Iterator<Item = &'a Scope> + 'a {
+        (0..=from.len())
+            .rev()
+            .filter_map(move |i| self.scopes.get(&from[..i]))
+    }
+    fn lookup_item<'a>(&'a self, from: &'a [Symbol], name: Symbol) -> Option<&'a Item> {
+        self.lexical_scopes(from)
+            .find_map(|scope| scope.items.get(&name))
+    }
+    fn lookup_module<'a>(&'a self, from: &'a [Symbol], name: Symbol) -> Option<&'a ModuleRef> {
+        self.lexical_scopes(from)
+            .find_map(|scope| scope.modules.get(&name))
+    }
+    // private definitions are visible from the module itself and its descendants.
+    fn is_accessible(is_pub: bool, owner: &[Symbol], from: &[Symbol]) -> bool {
+        is_pub || from.starts_with(owner)
+    }
+    fn resolve_path(&self, from: &[Symbol], path: &[Symbol]) -> Result<Resolved, String> {
+        let (last, modules) = path.split_last().expect("empty path");
+        let Some((first, rest)) = modules.split_first() else {
+            return self
+                .lookup_item(from, *last)
+                .map(|item| Resolved::Item(item.clone()))
+                .or_else(|| {
+                    self.lookup_module(from, *last)
+                        .map(|m| Resolved::Module(m.path.clone()))
+                })
+                .ok_or_else(|| format!("{last} is not found"));
+        };
+        let mut module = self
+            .lookup_module(from, *first)
+            .ok_or_else(|| format!("Module {first} is not found"))?
+            .path
+            .clone();
+        for name in rest.iter() {
+            let child = self.scopes[&module]
+                .modules
+                .get(name)
+                .ok_or_else(|| format!("Module {name} is not found in {}", join_path(&module)))?;
+            if !Self::is_accessible(child.is_pub, &module, from) {
+                return Err(format!(
+                    "Module {name} is private in {}",
+                    join_path(&module)
+                ));
+            }
+            module = child.path.clone();
+        }
+        let scope = &self.scopes[&module];
+        let (is_pub, resolved) = match (scope.items.get(last), scope.modules.get(last)) {
+            (Some(item), _) => (item.is_pub, Resolved::Item(item.clone())),
+            (None, Some(m)) => (m.is_pub, Resolved::Module(m.path.clone())),
+            (None, None) => return Err(format!("{last} is not found in {}", join_path(&module))),
+        };
+        if Self::is_accessible(is_pub, &module, from) {
+            Ok(resolved)
+        } else {
+            Err(format!("{last} is private in {}", join_path(&module)))
+        }
+    }
+    // `use` may refer to the name imported with another `pub use`, so they are resolved
+    // repeatedly until no more progress.
+    fn resolve_uses(&mut self) {
+        let mut pending = std::mem::take(&mut self.uses);
+        loop {
+            let len = pending.len();
+            pending.retain(|u| match self.resolve_path(&u.module, &u.target) {
+                Ok(resolved) => {
+                    self.import(u, resolved);
+                    false
+                }
+                Err(_) => true,
+            });
+            if pending.is_empty() || pending.len() == len {
+                break;
+            }
+        }
+        for u in pending {
+            let msg = self.resolve_path(&u.module, &u.target).unwrap_err();
+            self.errs.push(make_error(msg, u.span));
+        }
+    }
+    fn import(&mut self, u: &PendingUse, resolved: Resolved) {
+        let name = *u.target.last().unwrap();
+        let scope = self.scope_mut(&u.module);
+        match resolved {
+            Resolved::Item(item) => {
+                let item = Item {
+                    is_pub: u.is_pub,
+                    ..item
+                };
+                scope.items.insert(name, item);
+            }
+            Resolved::Module(path) => {
+                let module = ModuleRef {
+                    path,
+                    is_pub: u.is_pub,
+                };
+                scope.modules.insert(name, module);
+            }
+        }
+    }
+}
+
+// replaces the names in a statement with the qualified names of the definitions.
+struct Renamer<'a> {
+    resolver: &'a ModuleResolver,
+    module: &'a [Symbol],
+    // names bound locally, which hide the definitions in modules.
+    locals: Vec<Symbol>,
+    errs: Vec<Box<dyn ReportableError>>,
+}
+
+impl Renamer<'_> {
+    fn resolve(&mut self, name: Symbol, span: &Span) -> Option<Item> {
+        if name.as_str().contains(SEPARATOR) {
+            match self.resolver.resolve_path(self.module, &split_path(name)) {
+                Ok(Resolved::Item(item)) => Some(item),
+                Ok(Resolved::Module(_)) => {
+                    let msg = format!("Module {name} cannot be used as a value");
+                    self.errs.push(make_error(msg, span.clone()));
+                    None
+                }
+                Err(msg) => {
+                    self.errs.push(make_error(msg, span.clone()));
+                    None
+                }
+            }
+        } else if self.locals.contains(&name) {
+            None
+        } else {
+            self.resolver.lookup_item(self.module, name).cloned()
+        }
+    }
+    fn rename(&mut self, name: Symbol, span: &Span) -> Symbol {
+        self.resolve(name, span).map_or(name, |item| item.mangled)
+    }
+    fn rename_type(&mut self, t: TypeNodeId) -> TypeNodeId {
+        let span = t.to_span();
+        match t.to_type() {
+            Type::TypeName(name) => {
+                let locals = std::mem::take(&mut self.locals);
+                let name = self.rename(name, &span);
+                self.locals = locals;
+                Type::TypeName(name).into_id_with_span(span)
+            }
+            _ => t
+                .apply_fn(|t| self.rename_type(t))
+                .to_type()
+                .into_id_with_span(span),
+        }
+    }
+    fn rename_typed_pattern(&mut self, tpat: &TypedPattern) -> TypedPattern {
+        TypedPattern {
+            pat: tpat.pat.clone(),
+            ty: self.rename_type(tpat.ty),
+        }
+    }
+    fn rename_typed_id(&mut self, id: &TypedId) -> TypedId {
+        TypedId {
+            id: id.id,
+            ty: self.rename_type(id.ty),
+        }
+    }
+    fn bind_pattern(&mut self, pat: &Pattern) {
+        match pat {
+            Pattern::Single(name) => self.locals.push(*name),
+            Pattern::Tuple(pats) | Pattern::Variant(_, pats) => {
+                pats.iter().for_each(|p| self.bind_pattern(p))
+            }
+            Pattern::Record(fields) => fields.iter().for_each(|(_, p)| self.bind_pattern(p)),
+        }
+    }
+    // the pattern of `match` arm, whose names may refer to the variants.
+    fn rename_arm_pattern(&mut self, pat: &Pattern, span: &Span) -> Pattern {
+        match pat {
+            Pattern::Variant(name, pats) => {
+                pats.iter().for_each(|p| self.bind_pattern(p));
+                Pattern::Variant(self.rename(*name, span), pats.clone())
+            }
+            Pattern::Single(name) => match self.resolve(*name, span) {
+                Some(item) if item.is_variant => Pattern::Single(item.mangled),
+                _ => {
+                    self.locals.push(*name);
+                    pat.clone()
+                }
+            },
+            _ => {
+                self.bind_pattern(pat);
+                pat.clone()
+            }
+        }
+    }
+    fn rename_decl(&mut self, decl: &TypeDecl, name: Symbol) -> TypeDecl {
+        let ty = match decl.ty.to_type() {
+            Type::Union(variants) => Type::Union(
+                variants
+                    .iter()
+                    .map(|(v, fields)| {
+                        let fields = fields.iter().map(|t| self.rename_type(*t)).collect();
+                        (mangle(self.module, *v), fields)
+                    })
+                    .collect(),
+            )
+            .into_id_with_span(decl.ty.to_span()),
+            _ => self.rename_type(decl.ty),
+        };
+        TypeDecl {
+            name,
+            ty,
+            is_alias: decl.is_alias,
+        }
+    }
+    fn rename_vec(&mut self, v: &[ExprNodeId]) -> Vec<ExprNodeId> {
+        v.iter().map(|e| self.rename_expr(*e)).collect()
+    }
+    fn rename_opt(&mut self, e: Option<ExprNodeId>) -> Option<ExprNodeId> {
+        e.map(|e| self.rename_expr(e))
+    }
+    // the names bound locally in the action are removed afterwards.
+    fn scoped<T>(&mut self, action: impl FnOnce(&mut Self) -> T) -> T {
+        let len = self.locals.len();
+        let res = action(self);
+        self.locals.truncate(len);
+        res
+    }
+    fn rename_expr(&mut self, e: ExprNodeId) -> ExprNodeId {
+        let span = e.to_span();
+        let res = match e.to_expr() {
+            Expr::Var(name) => Expr::Var(self.rename(name, &span)),
+            Expr::Block(b) => Expr::Block(self.rename_opt(b)),
+            Expr::Tuple(v) => Expr::Tuple(self.rename_vec(&v)),
+            Expr::Proj(e, idx) => Expr::Proj(self.rename_expr(e), idx),
+            Expr::RecordLiteral(fields) => Expr::RecordLiteral(
+                fields
+                    .iter()
+                    .map(|(name, e)| (*name, self.rename_expr(*e)))
+                    .collect(),
+            ),
+            Expr::FieldAccess(e, name) => Expr::FieldAccess(self.rename_expr(e), name),
+            Expr::ArrayLiteral(v) => Expr::ArrayLiteral(self.rename_vec(&v)),
+            Expr::ArrayAccess(arr, idx) => {
+                Expr::ArrayAccess(self.rename_expr(arr), self.rename_expr(idx))
+            }
+            Expr::Apply(f, args) => Expr::Apply(self.rename_expr(f), self.rename_vec(&args)),
+            Expr::PipeApply(a, f) => Expr::PipeApply(self.rename_expr(a), self.rename_expr(f)),
+            Expr::Lambda(params, rtype, body) => {
+                let params = params
+                    .iter()
+                    .map(|id| self.rename_typed_id(id))
+                    .collect::<Vec<_>>();
+                let rtype = rtype.map(|t| self.rename_type(t));
+                let body = self.scoped(|this| {
+                    this.locals.extend(params.iter().map(|id| id.id));
+                    this.rename_expr(body)
+                });
+                Expr::Lambda(params, rtype, body)
+            }
+            Expr::Assign(v, e) => Expr::Assign(self.rename_expr(v), self.rename_expr(e)),
+            Expr::Then(e, then) => Expr::Then(self.rename_expr(e), self.rename_opt(then)),
+            Expr::Feed(id, body) => {
+                let body = self.scoped(|this| {
+                    this.locals.push(id);
+                    this.rename_expr(body)
+                });
+                Expr::Feed(id, body)
+            }
+            Expr::Let(tpat, body, then) => {
+                let body = self.rename_expr(body);
+                let tpat = self.rename_typed_pattern(&tpat);
+                let then = self.scoped(|this| {
+                    this.bind_pattern(&tpat.pat);
+                    this.rename_opt(then)
+                });
+                Expr::Let(tpat, body, then)
+            }
+            Expr::LetRec(id, body, then) => {
+                let id = self.rename_typed_id(&id);
+                let (body, then) = self.scoped(|this| {
+                    this.locals.push(id.id);
+                    (this.rename_expr(body), this.rename_opt(then))
+                });
+                Expr::LetRec(id, body, then)
+            }
+            Expr::TypeDecl(decl, then) => {
+                Expr::TypeDecl(self.rename_decl(&decl, decl.name), self.rename_opt(then))
+            }
+            Expr::If(cond, then, else_) => Expr::If(
+                self.rename_expr(cond),
+                self.rename_expr(then),
+                self.rename_opt(else_),
+            ),
+            Expr::Match(scrutinee, arms) => {
+                let scrutinee = self.rename_expr(scrutinee);
+                let arms = arms
+                    .iter()
+                    .map(|(tpat, body)| {
+                        let span = tpat.to_span();
+                        self.scoped(|this| {
+                            let pat = this.rename_arm_pattern(&tpat.pat, &span);
+                            let tpat = TypedPattern { pat, ty: tpat.ty };
+                            (tpat, this.rename_expr(*body))
+                        })
+                    })
+                    .collect();
+                Expr::Match(scrutinee, arms)
+            }
+            Expr::Return(e) => Expr::Return(self.rename_expr(e)),
+            Expr::Bracket(e) => Expr::Bracket(self.rename_expr(e)),
+            Expr::Escape(e) => Expr::Escape(self.rename_expr(e)),
+            Expr::Literal(_) | Expr::Error => return e,
+        };
+        res.into_id(span)
+    }
+    // the name bound at the top level of the module is qualified with the module path.
+    fn rename_def_pattern(&self, pat: &Pattern) -> Pattern {
+        match pat {
+            Pattern::Single(name) if name.as_str() != "_" => {
+                Pattern::Single(mangle(self.module, *name))
+            }
+            Pattern::Single(_) => pat.clone(),
+            Pattern::Tuple(pats) => {
+                Pattern::Tuple(pats.iter().map(|p| self.rename_def_pattern(p)).collect())
+            }
+            Pattern::Record(fields) => Pattern::Record(
+                fields
+                    .iter()
+                    .map(|(name, p)| (*name, self.rename_def_pattern(p)))
+                    .collect(),
+            ),
+            Pattern::Variant(_, _) => unreachable!("variant pattern cannot be used in let"),
+        }
+    }
+    fn rename_stmt(&mut self, stmt: Statement) -> Statement {
+        match stmt {
+            Statement::Let(tpat, body) => {
+                let body = self.rename_expr(body);
+                let tpat = TypedPattern {
+                    pat: self.rename_def_pattern(&tpat.pat),
+                    ty: self.rename_type(tpat.ty),
+                };
+                Statement::Let(tpat, body)
+            }
+            Statement::LetRec(id, body) => {
+                let body = self.rename_expr(body);
+                let id = TypedId {
+                    id: mangle(self.module, id.id),
+                    ty: self.rename_type(id.ty),
+                };
+                Statement::LetRec(id, body)
+            }
+            Statement::MacroExpand(id, body) => {
+                let body = self.rename_expr(body);
+                let id = TypedId {
+                    id: mangle(self.module, id.id),
+                    ty: self.rename_type(id.ty),
+                };
+                Statement::MacroExpand(id, body)
+            }
+            Statement::TypeDecl(decl) => {
+                let name = mangle(self.module, decl.name);
+                Statement::TypeDecl(self.rename_decl(&decl, name))
+            }
+            Statement::Assign(v, e) => Statement::Assign(self.rename_expr(v), self.rename_expr(e)),
+            Statement::Single(e) => Statement::Single(self.rename_expr(e)),
+            Statement::Module(..)
+            | Statement::Use(_)
+            | Statement::Pub(_)
+            | Statement::Doc(..)
+            | Statement::Attribute(..) => {
+                unreachable!("modules are flattened beforehand")
+            }
+        }
+    }
+}
+
+/// Flattens the modules into the global context, checking the visibility of the definitions.
+/// The doc comments and the attributes of the definitions are returned with their qualified
+/// names.
+pub(super) fn resolve_modules(
+    stmts: Statements,
+    current_file: Option<PathBuf>,
+) -> Result<(Statements, Annotations), Vec<Box<dyn ReportableError>>> {
+    let mut resolver = ModuleResolver::default();
+    let current_file = current_file.unwrap_or_default();
+    let mut flattened = vec![];
+    resolver.collect(&[], stmts, &current_file, &mut flattened);
+    resolver.resolve_uses();
+    let mut errs = std::mem::take(&mut resolver.errs);
+    let res = flattened
+        .into_iter()
+        .map(|(module, stmt, span)| {
+            let mut renamer = Renamer {
+                resolver: &resolver,
+                module: &module,
+                locals: vec![],
+                errs: vec![],
+            };
+            let stmt = renamer.rename_stmt(stmt);
+            errs.extend(renamer.errs);
+            (stmt, span)
+        })
+        .collect();
+    if errs.is_empty() {
+        Ok((res, resolver.annotations))
+    } else {
+        Err(errs)
+    }
+}
@@ -1,10 +1,12 @@
 use crate::{
-    ast::Expr,
-    interner::ExprNodeId,
+    ast::{Expr, TypeDecl},
+    interner::{ExprNodeId, Symbol},
     pattern::{TypedId, TypedPattern},
 };
 
 use super::Span;
+pub(super) type Statements = Vec<(Statement, Span)>;
+
 // an intermediate representation used in parser.
 // Note that this struct do not distinct between a global statement(allows `fn(){}`) and a local statement.
 // The distinction is done in the actual parser logic.
@@ -14,7 +16,18 @@ pub(super) enum Statement {
     MacroExpand(TypedId, ExprNodeId),
     LetRec(TypedId, ExprNodeId),
     Assign(ExprNodeId, ExprNodeId),
+    TypeDecl(TypeDecl),
     Single(ExprNodeId),
+    // `mod name {...}`, or `mod name` which loads `name.mmm` when its body is None.
+    Module(Symbol, Option<Statements>),
+    // `use path::to::name`
+    Use(Vec<Symbol>),
+    // exported definition, only valid in the global context.
+    Pub(Box<Statement>),
+    // definition with the doc comments written before it.
+    Doc(String, Box<Statement>),
+    // definition with an attribute like `#[inline]` written before it.
+    Attribute(Symbol, Span, Box<Statement>),
 }
 
 pub fn stmt_from_expr_top(expr:ExprNodeId)->Vec<Statement>{
@@ -36,6 +49,12 @@ pub fn stmt_from_expr_top(expr:ExprNodeId)->Vec<Statement>{
                 stmt_from_expr(then, target);
             }
         }
+        Expr::TypeDecl(decl, then_opt) => {
+            target.push(Statement::TypeDecl(decl));
+            if let Some(then) = then_opt {
+                stmt_from_expr(then, target);
+            }
+        }
         _ => target.push(Statement::Single(expr)),
     }
 }
@@ -43,10 +62,7 @@ pub fn stmt_from_expr_top(expr:ExprNodeId)->Vec<Statement>{
 // A helper function to convert vector of statements to nested expression
 pub(super) fn into_then_expr(stmts: &[(Statement, Span)]) -> Option<ExprNodeId> {
     let get_span = |spana: Span, spanb: Option<ExprNodeId>| match spanb {
-        Some(b) => {
-            let start = spana.start;
-            start..b.to_span().end
-        }
+        Some(b) => spana.to(&b.to_span()),
         None => spana,
     };
     let e_pre = stmts.iter().rev().fold(None, |then, (stmt, span)| {
@@ -60,12 +76,23 @@ pub(super) fn into_then_expr(stmts: &[(Statement, Span)]) -> Option<ExprNodeId>
             (_, Statement::Assign(name, body)) => {
                 Some(Expr::Then(Expr::Assign(*name, *body).into_id(span.clone()), then).into_id(s))
             }
+            (_, Statement::TypeDecl(decl)) => Some(Expr::TypeDecl(decl.clone(), then).into_id(s)),
             (_, Statement::MacroExpand(fname, body)) => {
                 //todo!
                 Some(Expr::LetRec(fname.clone(), *body, then).into_id(s))
             }
             (None, Statement::Single(e)) => Some(*e),
             (t, Statement::Single(e)) => Some(Expr::Then(*e, t).into_id(s)),
+            (
+                _,
+                Statement::Module(..)
+                | Statement::Use(_)
+                | Statement::Pub(_)
+                | Statement::Doc(..)
+                | Statement::Attribute(..),
+            ) => {
+                unreachable!("modules should be resolved before converting into expression")
+            }
         }
     });
     // log::debug!("stmts {:?}, e_pre: {:?}", stmts, e_pre);
@@ -1,12 +1,17 @@
 use super::*;
 use crate::pattern::TypedId;
 use crate::utils;
-use std::path::PathBuf;
+use std::ops::Range;
+
+// The sources in the tests are parsed as the file with id 0.
+fn loc(range: Range<usize>) -> Span {
+    Span::new(FileId(0), range)
+}
 
 macro_rules! test_string {
     ($src:literal, $ans:expr) => {
         let srcstr = $src.to_string();
-        match parse(&srcstr, None) {
+        match parse_file(&srcstr, FileId(0), None) {
             Ok(ast) => {
                 assert!(
                     ast.to_expr() == $ans.to_expr(),
@@ -16,7 +21,7 @@ macro_rules! test_string {
                 );
             }
             Err(errs) => {
-                utils::error::report(&srcstr, PathBuf::new(), &errs);
+                utils::error::report(&errs);
                 panic!();
             }
         }
@@ -28,12 +33,12 @@ fn test_let() {
     let ans = Expr::Let(
         TypedPattern {
             pat: Pattern::Single("goge".to_symbol()),
-            ty: Type::Unknown.into_id_with_span(4..8),
+            ty: Type::Unknown.into_id_with_span(loc(4..8)),
         },
-        Expr::Literal(Literal::Float("36".to_symbol())).into_id(11..13),
-        Some(Expr::Var("goge".to_symbol()).into_id(15..19)),
+        Expr::Literal(Literal::Int(36)).into_id(loc(11..13)),
+        Some(Expr::Var("goge".to_symbol()).into_id(loc(15..19))),
     )
-    .into_id(0..19);
+    .into_id(loc(0..19));
     test_string!("let goge = 36\n goge", ans);
 }
 #[test]
@@ -44,47 +49,106 @@ fn test_lettuple() {
                 Pattern::Single("a".to_symbol()),
                 Pattern::Single("b".to_symbol()),
             ]),
-            ty: Type::Unknown.into_id_with_span(4..9),
+            ty: Type::Unknown.into_id_with_span(loc(4..9)),
         },
         Expr::Tuple(vec![
-            Expr::Literal(Literal::Float("36".to_symbol())).into_id(13..15),
-            Expr::Literal(Literal::Float("89".to_symbol())).into_id(16..18),
+            Expr::Literal(Literal::Int(36)).into_id(loc(13..15)),
+            Expr::Literal(Literal::Int(89)).into_id(loc(16..18)),
         ])
-        .into_id(12..19),
-        Some(Expr::Var("hoge".to_symbol()).into_id(21..25)),
+        .into_id(loc(12..19)),
+        Some(Expr::Var("hoge".to_symbol()).into_id(loc(21..25))),
     )
-    .into_id(0..25);
+    .into_id(loc(0..25));
     test_string!("let (a,b) = (36,89)\n hoge", ans);
 }
 #[test]
 fn test_if() {
     let ans = Expr::If(
-        Expr::Literal(Literal::Float("100".to_symbol())).into_id(4..7),
-        Expr::Var("hoge".to_symbol()).into_id(9..13),
-        Some(Expr::Var("fuga".to_symbol()).into_id(19..23)),
+        Expr::Literal(Literal::Int(100)).into_id(loc(4..7)),
+        Expr::Var("hoge".to_symbol()).into_id(loc(9..13)),
+        Some(Expr::Var("fuga".to_symbol()).into_id(loc(19..23))),
     )
-    .into_id(0..23);
+    .into_id(loc(0..23));
     test_string!("if (100) hoge else fuga", ans);
 }
 #[test]
 fn test_if_noelse() {
     let ans = Expr::If(
-        Expr::Literal(Literal::Float("100".to_symbol())).into_id(4..7),
-        Expr::Var("hoge".to_symbol()).into_id(9..13),
+        Expr::Literal(Literal::Int(100)).into_id(loc(4..7)),
+        Expr::Var("hoge".to_symbol()).into_id(loc(9..13)),
         None,
     )
-    .into_id(0..13);
+    .into_id(loc(0..13));
     test_string!("if (100) hoge ", ans);
 }
 
+#[test]
+fn test_return() {
+    let ans = Expr::If(
+        Expr::Var("hoge".to_symbol()).into_id(loc(4..8)),
+        Expr::Return(Expr::Literal(Literal::Int(1)).into_id(loc(17..18))).into_id(loc(10..18)),
+        None,
+    )
+    .into_id(loc(0..18));
+    test_string!("if (hoge) return 1", ans);
+}
+#[test]
+fn test_recover_multiple_errors() {
+    let src = "let a = (1 +)\nlet b = f(2 3)\nb";
+    let (ast, _annotations, errs) = parse_file_recovery(src, FileId(0), None);
+    assert_eq!(errs.len(), 2, "{}", utils::error::dump_to_string(&errs));
+    let ans = Expr::Let(
+        TypedPattern {
+            pat: Pattern::Single("a".to_symbol()),
+            ty: Type::Unknown.into_id_with_span(loc(4..5)),
+        },
+        Expr::Error.into_id(loc(8..13)),
+        Some(
+            Expr::Let(
+                TypedPattern {
+                    pat: Pattern::Single("b".to_symbol()),
+                    ty: Type::Unknown.into_id_with_span(loc(18..19)),
+                },
+                Expr::Error.into_id(loc(22..28)),
+                Some(Expr::Var("b".to_symbol()).into_id(loc(29..30))),
+            )
+            .into_id(loc(14..30)),
+        ),
+    )
+    .into_id(loc(0..30));
+    assert_eq!(ast, Some(ans));
+}
+#[test]
+fn test_doc_comment() {
+    let src = "/// Doubles\n///  the input.\nfn twice(x){\n    /// not collected\n    let y = x\n    y * 2.0\n}\n// not a doc\nlet z = 1.0\ntwice(z)";
+    let (ast, annotations, errs) = parse_file_recovery(src, FileId(0), None);
+    assert!(errs.is_empty(), "{}", utils::error::dump_to_string(&errs));
+    assert!(ast.is_some());
+    let ans = vec![DocComment {
+        name: "twice".to_symbol(),
+        params: Some(vec!["x".to_symbol()]),
+        text: "Doubles\n the input.".to_string(),
+        span: loc(28..90),
+    }];
+    assert_eq!(annotations.docs, ans);
+}
+#[test]
+fn test_misplaced_doc_comment() {
+    // the doc comments not followed by a statement are single line comments.
+    let src = "fn dsp(){\n    let x = 1.0 /// after\n    x\n    /// note\n}\n/// at the end\n";
+    let (ast, annotations, errs) = parse_file_recovery(src, FileId(0), None);
+    assert!(errs.is_empty(), "{}", utils::error::dump_to_string(&errs));
+    assert!(ast.is_some());
+    assert!(annotations.docs.is_empty());
+}
 #[test]
 fn test_int() {
-    let ans = Expr::Literal(Literal::Float("3466".to_symbol())).into_id(0..4);
+    let ans = Expr::Literal(Literal::Int(3466)).into_id(loc(0..4));
     test_string!("3466", ans);
 }
 #[test]
 fn test_string() {
-    let ans = Expr::Literal(Literal::String("teststr".to_symbol())).into_id(0..9);
+    let ans = Expr::Literal(Literal::String("teststr".to_symbol())).into_id(loc(0..9));
     test_string!("\"teststr\"", ans);
 }
 #[test]
@@ -93,14 +157,14 @@ fn test_block() {
         Expr::Let(
             TypedPattern {
                 pat: Pattern::Single("hoge".to_symbol()),
-                ty: Type::Unknown.into_id_with_span(5..9),
+                ty: Type::Unknown.into_id_with_span(loc(5..9)),
             },
-            Expr::Literal(Literal::Float("100".to_symbol())).into_id(12..15),
-            Some(Expr::Var("hoge".to_symbol()).into_id(16..20)),
+            Expr::Literal(Literal::Int(100)).into_id(loc(12..15)),
+            Some(Expr::Var("hoge".to_symbol()).into_id(loc(16..20))),
         )
-        .into_id(1..20),
+        .into_id(loc(1..20)),
     ))
-    .into_id(0..21);
+    .into_id(loc(0..21));
     test_string!(
         "{let hoge = 100
 hoge}",
@@ -110,54 +174,54 @@ hoge}",
 #[test]
 fn test_add() {
     let ans = Expr::Apply(
-        Expr::Var("add".to_symbol()).into_id(6..7),
+        Expr::Var("add".to_symbol()).into_id(loc(6..7)),
         vec![
-            Expr::Literal(Literal::Float("3466.0".to_symbol())).into_id(0..6),
-            Expr::Literal(Literal::Float("2000.0".to_symbol())).into_id(7..13),
+            Expr::Literal(Literal::Float("3466.0".to_symbol())).into_id(loc(0..6)),
+            Expr::Literal(Literal::Float("2000.0".to_symbol())).into_id(loc(7..13)),
         ],
     )
-    .into_id(0..13);
+    .into_id(loc(0..13));
     test_string!("3466.0+2000.0", ans);
 }
 #[test]
 fn test_at() {
     let ans1 = Expr::Apply(
-        Expr::Var("_mimium_schedule_at".to_symbol()).into_id(3..4),
+        Expr::Var("_mimium_schedule_at".to_symbol()).into_id(loc(3..4)),
         vec![
-            Expr::Literal(Literal::Float("1.0".to_symbol())).into_id(4..7),
-            Expr::Var("foo".to_symbol()).into_id(0..3),
+            Expr::Literal(Literal::Float("1.0".to_symbol())).into_id(loc(4..7)),
+            Expr::Var("foo".to_symbol()).into_id(loc(0..3)),
         ],
     )
-    .into_id(0..7);
+    .into_id(loc(0..7));
     test_string!("foo@1.0", ans1);
 
     let time = Expr::Apply(
-        Expr::Var("pow".to_symbol()).into_id(7..8),
+        Expr::Var("pow".to_symbol()).into_id(loc(7..8)),
         vec![
-            Expr::Literal(Literal::Float("1.0".to_symbol())).into_id(4..7),
-            Expr::Literal(Literal::Float("2.0".to_symbol())).into_id(8..11),
+            Expr::Literal(Literal::Float("1.0".to_symbol())).into_id(loc(4..7)),
+            Expr::Literal(Literal::Float("2.0".to_symbol())).into_id(loc(8..11)),
         ],
     )
-    .into_id(4..11);
+    .into_id(loc(4..11));
     let ans2 = Expr::Apply(
-        Expr::Var("_mimium_schedule_at".to_symbol()).into_id(3..4),
-        vec![time, Expr::Var("foo".to_symbol()).into_id(0..3)],
+        Expr::Var("_mimium_schedule_at".to_symbol()).into_id(loc(3..4)),
+        vec![time, Expr::Var("foo".to_symbol()).into_id(loc(0..3))],
     )
-    .into_id(0..11);
+    .into_id(loc(0..11));
     test_string!("foo@1.0^2.0", ans2);
 }
 #[test]
 fn test_var() {
-    let ans = Expr::Var("hoge".to_symbol()).into_id(0..4);
+    let ans = Expr::Var("hoge".to_symbol()).into_id(loc(0..4));
     test_string!("hoge", ans);
 }
 #[test]
 fn test_apply() {
     let ans = Expr::Apply(
-        Expr::Var("myfun".to_symbol()).into_id(0..5),
-        vec![Expr::Var("callee".to_symbol()).into_id(6..12)],
+        Expr::Var("myfun".to_symbol()).into_id(loc(0..5)),
+        vec![Expr::Var("callee".to_symbol()).into_id(loc(6..12))],
     )
-    .into_id(0..13);
+    .into_id(loc(0..13));
     test_string!("myfun(callee)", ans);
 }
 
@@ -165,53 +229,68 @@ fn test_apply() {
 fn test_assign1() {
     let ans = Expr::Then(
         Expr::Assign(
-            Expr::Var("hoge".to_symbol()).into_id(0..4),
-            Expr::Var("fuga".to_symbol()).into_id(7..11),
+            Expr::Var("hoge".to_symbol()).into_id(loc(0..4)),
+            Expr::Var("fuga".to_symbol()).into_id(loc(7..11)),
         )
-        .into_id(0..11),
+        .into_id(loc(0..11)),
         None,
     )
-    .into_id(0..11);
+    .into_id(loc(0..11));
     test_string!("hoge = fuga", ans);
 }
 #[test]
 fn test_assign2() {
     let ans = Expr::Then(
         Expr::Assign(
-            Expr::Var("hoge".to_symbol()).into_id(0..4),
-            Expr::Var("fuga".to_symbol()).into_id(7..11),
+            Expr::Var("hoge".to_symbol()).into_id(loc(0..4)),
+            Expr::Var("fuga".to_symbol()).into_id(loc(7..11)),
         )
-        .into_id(0..11),
-        Some(Expr::Literal(Literal::Float("100.0".to_symbol())).into_id(13..18)),
+        .into_id(loc(0..11)),
+        Some(Expr::Literal(Literal::Float("100.0".to_symbol())).into_id(loc(13..18))),
     )
-    .into_id(0..18);
+    .into_id(loc(0..18));
     test_string!("hoge = fuga\n 100.0", ans);
 }
 #[test]
 fn test_applynested() {
     let ans = Expr::Apply(
-        Expr::Var("myfun".to_symbol()).into_id(0..5),
+        Expr::Var("myfun".to_symbol()).into_id(loc(0..5)),
         vec![Expr::Apply(
-            Expr::Var("myfun2".to_symbol()).into_id(6..12),
-            vec![Expr::Var("callee".to_symbol()).into_id(13..19)],
+            Expr::Var("myfun2".to_symbol()).into_id(loc(6..12)),
+            vec![Expr::Var("callee".to_symbol()).into_id(loc(13..19))],
         )
-        .into_id(6..20)],
+        .into_id(loc(6..20))],
     )
-    .into_id(0..20);
+    .into_id(loc(0..20));
     test_string!("myfun(myfun2(callee))", ans);
 }
 #[test]
 fn test_macroexpand() {
     let ans = Expr::Escape(
         Expr::Apply(
-            Expr::Var("myfun".to_symbol()).into_id(0..6),
-            vec![Expr::Var("callee".to_symbol()).into_id(7..13)],
+            Expr::Var("myfun".to_symbol()).into_id(loc(0..6)),
+            vec![Expr::Var("callee".to_symbol()).into_id(loc(7..13))],
         )
-        .into_id(0..14),
+        .into_id(loc(0..14)),
     )
-    .into_id(0..14);
+    .into_id(loc(0..14));
     test_string!("myfun!(callee)", ans);
 }
+#[test]
+fn test_macroexpand_multiple_args() {
+    let ans = Expr::Escape(
+        Expr::Apply(
+            Expr::Var("myfun".to_symbol()).into_id(loc(0..6)),
+            vec![
+                Expr::Literal(Literal::Int(1)).into_id(loc(7..8)),
+                Expr::Var("callee".to_symbol()).into_id(loc(10..16)),
+            ],
+        )
+        .into_id(loc(0..17)),
+    )
+    .into_id(loc(0..17));
+    test_string!("myfun!(1, callee)", ans);
+}
 
 #[test]
 fn test_fndef() {
@@ -219,13 +298,13 @@ fn test_fndef() {
         TypedId {
             ty: Type::Function(
                 vec![
-                    Type::Unknown.into_id_with_span(0..28),
-                    Type::Unknown.into_id_with_span(0..28),
+                    Type::Unknown.into_id_with_span(loc(0..28)),
+                    Type::Unknown.into_id_with_span(loc(0..28)),
                 ],
-                Type::Unknown.into_id_with_span(0..28),
+                Type::Unknown.into_id_with_span(loc(0..28)),
                 None,
             )
-            .into_id_with_span(0..28),
+            .into_id_with_span(loc(0..28)),
 
             id: "hoge".to_symbol(),
         },
@@ -233,20 +312,20 @@ fn test_fndef() {
             vec![
                 TypedId {
                     id: "input".to_symbol(),
-                    ty: Type::Unknown.into_id_with_span(8..13),
+                    ty: Type::Unknown.into_id_with_span(loc(8..13)),
                 },
                 TypedId {
                     id: "gue".to_symbol(),
-                    ty: Type::Unknown.into_id_with_span(14..17),
+                    ty: Type::Unknown.into_id_with_span(loc(14..17)),
                 },
             ],
             None,
-            Expr::Var("input".to_symbol()).into_id(21..26),
+            Expr::Var("input".to_symbol()).into_id(loc(21..26)),
         )
-        .into_id(0..28),
+        .into_id(loc(0..28)),
         None,
     )
-    .into_id(0..28);
+    .into_id(loc(0..28));
     test_string!("fn hoge(input,gue){\n input\n}", ans);
 }
 #[test]
@@ -256,64 +335,64 @@ fn global_fnmultiple() {
             id: "hoge".to_symbol(),
             ty: Type::Function(
                 vec![
-                    Type::Unknown.into_id_with_span(0..28),
-                    Type::Unknown.into_id_with_span(0..28),
+                    Type::Unknown.into_id_with_span(loc(0..28)),
+                    Type::Unknown.into_id_with_span(loc(0..28)),
                 ],
-                Type::Unknown.into_id_with_span(0..28),
+                Type::Unknown.into_id_with_span(loc(0..28)),
                 None,
             )
-            .into_id_with_span(0..28),
+            .into_id_with_span(loc(0..28)),
         },
         Expr::Lambda(
             vec![
                 TypedId {
                     id: "input".to_symbol(),
-                    ty: Type::Unknown.into_id_with_span(8..13),
+                    ty: Type::Unknown.into_id_with_span(loc(8..13)),
                 },
                 TypedId {
                     id: "gue".to_symbol(),
-                    ty: Type::Unknown.into_id_with_span(14..17),
+                    ty: Type::Unknown.into_id_with_span(loc(14..17)),
                 },
             ],
             None,
-            Expr::Var("input".to_symbol()).into_id(21..26),
+            Expr::Var("input".to_symbol()).into_id(loc(21..26)),
         )
-        .into_id(0..28),
+        .into_id(loc(0..28)),
         Some(
             Expr::LetRec(
                 TypedId {
                     id: "hoge".to_symbol(),
                     ty: Type::Function(
                         vec![
-                            Type::Unknown.into_id_with_span(29..57),
-                            Type::Unknown.into_id_with_span(29..57),
+                            Type::Unknown.into_id_with_span(loc(29..57)),
+                            Type::Unknown.into_id_with_span(loc(29..57)),
                         ],
-                        Type::Unknown.into_id_with_span(29..57),
+                        Type::Unknown.into_id_with_span(loc(29..57)),
                         None,
                     )
-                    .into_id_with_span(29..57),
+                    .into_id_with_span(loc(29..57)),
                 },
                 Expr::Lambda(
                     vec![
                         TypedId {
                             id: "input".to_symbol(),
-                            ty: Type::Unknown.into_id_with_span(37..42),
+                            ty: Type::Unknown.into_id_with_span(loc(37..42)),
                         },
                         TypedId {
                             id: "gue".to_symbol(),
-                            ty: Type::Unknown.into_id_with_span(43..46),
+                            ty: Type::Unknown.into_id_with_span(loc(43..46)),
                         },
                     ],
                     None,
-                    Expr::Var("input".to_symbol()).into_id(50..55),
+                    Expr::Var("input".to_symbol()).into_id(loc(50..55)),
                 )
-                .into_id(29..57),
+                .into_id(loc(29..57)),
                 None,
             )
-            .into_id(29..57),
+            .into_id(loc(29..57)),
         ),
     )
-    .into_id(0..57);
+    .into_id(loc(0..57));
     test_string!(
         "fn hoge(input,gue){\n input\n}\nfn hoge(input,gue){\n input\n}",
         ans
@@ -325,45 +404,45 @@ fn test_macrodef() {
     let ans = Expr::LetRec(
         TypedId {
             id: "hoge".to_symbol(),
-            ty: Type::Unknown.into_id_with_span(6..10),
+            ty: Type::Unknown.into_id_with_span(loc(6..10)),
         },
         Expr::Lambda(
             vec![
                 TypedId {
                     id: "input".to_symbol(),
-                    ty: Type::Unknown.into_id_with_span(11..16),
+                    ty: Type::Unknown.into_id_with_span(loc(11..16)),
                 },
                 TypedId {
                     id: "gue".to_symbol(),
-                    ty: Type::Unknown.into_id_with_span(17..20),
+                    ty: Type::Unknown.into_id_with_span(loc(17..20)),
                 },
             ],
             None,
-            Expr::Bracket(Expr::Var("input".to_symbol()).into_id(24..29)).into_id(0..31),
+            Expr::Bracket(Expr::Var("input".to_symbol()).into_id(loc(24..29))).into_id(loc(0..31)),
         )
-        .into_id(0..31),
+        .into_id(loc(0..31)),
         None,
     )
-    .into_id(0..31);
+    .into_id(loc(0..31));
     test_string!("macro hoge(input,gue){\n input\n}", ans);
 }
 
 #[test]
 fn test_tuple() {
     let tuple_items = vec![
-        Expr::Literal(Literal::Float("1.0".to_symbol())).into_id(1..4),
-        Expr::Literal(Literal::Float("2.0".to_symbol())).into_id(6..9),
+        Expr::Literal(Literal::Float("1.0".to_symbol())).into_id(loc(1..4)),
+        Expr::Literal(Literal::Float("2.0".to_symbol())).into_id(loc(6..9)),
     ];
 
-    let ans = Expr::Tuple(tuple_items.clone()).into_id(0..10);
+    let ans = Expr::Tuple(tuple_items.clone()).into_id(loc(0..10));
     test_string!("(1.0, 2.0)", ans);
 
     // with trailing comma
-    let ans = Expr::Tuple(tuple_items.clone()).into_id(0..12);
+    let ans = Expr::Tuple(tuple_items.clone()).into_id(loc(0..12));
     test_string!("(1.0, 2.0, )", ans);
 
     // trailing comma is mandatory for a single-element tuple
-    let ans = Expr::Tuple(vec![tuple_items[0]]).into_id(0..7);
+    let ans = Expr::Tuple(vec![tuple_items[0]]).into_id(loc(0..7));
     test_string!("(1.0, )", ans);
 
     // This is not a tuple
@@ -371,55 +450,188 @@ fn test_tuple() {
     test_string!("(1.0)", ans);
 }
 
+#[test]
+fn test_record() {
+    let ans = Expr::RecordLiteral(vec![
+        (
+            "freq".to_symbol(),
+            Expr::Literal(Literal::Float("440.0".to_symbol())).into_id(loc(8..13)),
+        ),
+        (
+            "amp".to_symbol(),
+            Expr::Literal(Literal::Float("0.5".to_symbol())).into_id(loc(21..24)),
+        ),
+    ])
+    .into_id(loc(0..25));
+    test_string!("{freq = 440.0, amp = 0.5}", ans);
+
+    // trailing comma is mandatory for a single-field record
+    let ans = Expr::RecordLiteral(vec![(
+        "freq".to_symbol(),
+        Expr::Literal(Literal::Float("440.0".to_symbol())).into_id(loc(8..13)),
+    )])
+    .into_id(loc(0..15));
+    test_string!("{freq = 440.0,}", ans);
+}
+#[test]
+fn test_block_assign() {
+    // This is not a record but a block with an assignment
+    let ans = Expr::Block(Some(
+        Expr::Then(
+            Expr::Assign(
+                Expr::Var("v".to_symbol()).into_id(loc(1..2)),
+                Expr::Literal(Literal::Float("3.0".to_symbol())).into_id(loc(5..8)),
+            )
+            .into_id(loc(1..8)),
+            None,
+        )
+        .into_id(loc(1..8)),
+    ))
+    .into_id(loc(0..9));
+    test_string!("{v = 3.0}", ans);
+}
+#[test]
+fn test_field_access() {
+    let ans = Expr::FieldAccess(
+        Expr::FieldAccess(
+            Expr::Var("synth".to_symbol()).into_id(loc(0..5)),
+            "filter".to_symbol(),
+        )
+        .into_id(loc(0..12)),
+        "cutoff".to_symbol(),
+    )
+    .into_id(loc(0..19));
+    test_string!("synth.filter.cutoff", ans);
+}
+#[test]
+fn test_array() {
+    let ans = Expr::ArrayAccess(
+        Expr::ArrayLiteral(vec![
+            Expr::Literal(Literal::Float("1.0".to_symbol())).into_id(loc(1..4)),
+            Expr::Literal(Literal::Float("2.0".to_symbol())).into_id(loc(6..9)),
+        ])
+        .into_id(loc(0..10)),
+        Expr::Var("i".to_symbol()).into_id(loc(11..12)),
+    )
+    .into_id(loc(0..13));
+    test_string!("[1.0, 2.0][i]", ans);
+}
+#[test]
+fn test_type_alias() {
+    let ans = Expr::TypeDecl(
+        TypeDecl {
+            name: "Stereo".to_symbol(),
+            ty: Type::Tuple(vec![
+                Type::Primitive(PType::Numeric).into_id_with_span(loc(21..26)),
+                Type::Primitive(PType::Numeric).into_id_with_span(loc(28..33)),
+            ])
+            .into_id_with_span(loc(20..34)),
+            is_alias: true,
+        },
+        Some(Expr::Var("x".to_symbol()).into_id(loc(35..36))),
+    )
+    .into_id(loc(0..36));
+    test_string!("type alias Stereo = (float, float)\nx", ans);
+}
+#[test]
+fn test_sum_type() {
+    let ans = Expr::TypeDecl(
+        TypeDecl {
+            name: "Wave".to_symbol(),
+            ty: Type::Union(vec![
+                ("Sine".to_symbol(), vec![]),
+                (
+                    "Saw".to_symbol(),
+                    vec![Type::Primitive(PType::Numeric).into_id_with_span(loc(23..28))],
+                ),
+            ])
+            .into_id_with_span(loc(12..29)),
+            is_alias: false,
+        },
+        Some(Expr::Var("x".to_symbol()).into_id(loc(30..31))),
+    )
+    .into_id(loc(0..31));
+    test_string!("type Wave = Sine | Saw(float)\nx", ans);
+}
+#[test]
+fn test_letrecord() {
+    let ans = Expr::Let(
+        TypedPattern {
+            pat: Pattern::Record(vec![
+                ("freq".to_symbol(), Pattern::Single("freq".to_symbol())),
+                ("amp".to_symbol(), Pattern::Single("a".to_symbol())),
+            ]),
+            ty: Type::Struct(vec![
+                (
+                    "amp".to_symbol(),
+                    Type::Primitive(PType::Numeric).into_id_with_span(loc(40..45)),
+                ),
+                (
+                    "freq".to_symbol(),
+                    Type::Primitive(PType::Numeric).into_id_with_span(loc(28..33)),
+                ),
+            ])
+            .into_id_with_span(loc(21..46)),
+        },
+        Expr::Var("synth".to_symbol()).into_id(loc(49..54)),
+        Some(Expr::Var("a".to_symbol()).into_id(loc(55..56))),
+    )
+    .into_id(loc(0..56));
+    test_string!(
+        "let {freq, amp = a}: {freq: float, amp: float} = synth\na",
+        ans
+    );
+}
+
 #[test]
 fn test_stmt_without_return() {
     let ans = Expr::LetRec(
         TypedId {
             id: "test".to_symbol(),
             ty: Type::Function(
-                vec![Type::Unknown.into_id_with_span(0..56)],
-                Type::Unknown.into_id_with_span(0..56),
+                vec![Type::Unknown.into_id_with_span(loc(0..56))],
+                Type::Unknown.into_id_with_span(loc(0..56)),
                 None,
             )
-            .into_id_with_span(0..56),
+            .into_id_with_span(loc(0..56)),
         },
         Expr::Lambda(
             vec![TypedId {
                 id: "input".to_symbol(),
-                ty: Type::Unknown.into_id_with_span(8..13),
+                ty: Type::Unknown.into_id_with_span(loc(8..13)),
             }],
             None,
             Expr::Let(
                 TypedPattern {
                     pat: Pattern::Single("v".to_symbol()),
-                    ty: Type::Unknown.into_id_with_span(24..25),
+                    ty: Type::Unknown.into_id_with_span(loc(24..25)),
                 },
                 Expr::Apply(
-                    Expr::Var("add".to_symbol()).into_id(33..34),
+                    Expr::Var("add".to_symbol()).into_id(loc(33..34)),
                     vec![
-                        Expr::Var("input".to_symbol()).into_id(28..33),
-                        Expr::Literal(Literal::Float("1".to_symbol())).into_id(34..35),
+                        Expr::Var("input".to_symbol()).into_id(loc(28..33)),
+                        Expr::Literal(Literal::Int(1)).into_id(loc(34..35)),
                     ],
                 )
-                .into_id(28..35),
+                .into_id(loc(28..35)),
                 Some(
                     Expr::Then(
                         Expr::Apply(
-                            Expr::Var("print".to_symbol()).into_id(40..45),
-                            vec![Expr::Var("v".to_symbol()).into_id(46..47)],
+                            Expr::Var("print".to_symbol()).into_id(loc(40..45)),
+                            vec![Expr::Var("v".to_symbol()).into_id(loc(46..47))],
                         )
-                        .into_id(40..48),
-                        Some(Expr::Var("v".to_symbol()).into_id(53..54)),
+                        .into_id(loc(40..48)),
+                        Some(Expr::Var("v".to_symbol()).into_id(loc(53..54))),
                     )
-                    .into_id(40..54),
+                    .into_id(loc(40..54)),
                 ),
             )
-            .into_id(20..54),
+            .into_id(loc(20..54)),
         )
-        .into_id(0..56),
+        .into_id(loc(0..56)),
         None,
     )
-    .into_id(0..56);
+    .into_id(loc(0..56));
     test_string!(
         r"fn test(input){
     let v = input+1
@@ -452,7 +664,24 @@ fn test_err_builtin_redefine() {
     assert_eq!(res.len(), 1);
 
     let err_ans: Box<dyn ReportableError> = Box::new(error::ParseError::<Token>(
-        Simple::custom(3..6, "Builtin functions cannot be re-defined.").with_label("function decl"),
+        Simple::custom(loc(3..6), "Builtin functions cannot be re-defined.")
+            .with_label("function decl"),
     ));
     assert_eq!(res[0].to_string(), err_ans.to_string())
 }
+
+#[test]
+fn test_err_attribute() {
+    let src = "#[unroll]
+fn hoge(){
+    0.0
+}
+#[inline]
+let fuga = 1.0
+hoge";
+    let res = &parse(&src.to_string(), None).expect_err("should be error");
+    let msgs = res.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+    assert_eq!(msgs.len(), 2, "{msgs:?}");
+    assert!(msgs[0].contains("Unknown attribute #[unroll]"), "{msgs:?}");
+    assert!(msgs[1].contains("can be put only on functions"), "{msgs:?}");
+}
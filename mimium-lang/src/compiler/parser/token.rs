@@ -7,6 +7,8 @@ use crate::interner::{Symbol, ToSymbol};
 pub enum Comment {
     SingleLine(String),
     MultiLine(String),
+    // `///`, documentation of the definition that follows.
+    Doc(String),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -57,6 +59,7 @@ pub enum Token {
     Dot,
 
     Colon,
+    DoubleColon, // ::
     SemiColon,
 
     Let,
@@ -80,16 +83,23 @@ pub enum Token {
 
     If,
     Else,
+    Match,
+    FatArrow, // =>
 
     Return,
     Type,
     Alias,
 
     Include,
+    Mod,
+    Use,
+    Pub,
 
     LineBreak,
 
     Comment(Comment),
+    // `#[name]` before a definition, like `#[inline]`.
+    Attribute(Symbol),
 
     EndOfInput,
 }
@@ -160,6 +170,7 @@ impl fmt::Display for Token {
             Token::Comma => write!(f, ","),
             Token::Dot => write!(f, "."),
             Token::Colon => write!(f, ":"),
+            Token::DoubleColon => write!(f, "::"),
             Token::SemiColon => write!(f, ";"),
             Token::Let => write!(f, "let"),
             Token::LetRec => write!(f, "letrec"),
@@ -180,13 +191,19 @@ impl fmt::Display for Token {
 
             Token::If => write!(f, "if"),
             Token::Else => write!(f, "else"),
+            Token::Match => write!(f, "match"),
+            Token::FatArrow => write!(f, "=>"),
 
             Token::Return => write!(f, "return"),
             Token::Type => write!(f, "type"),
-            Token::Alias => write!(f, "newtype"),
+            Token::Alias => write!(f, "alias"),
             Token::Include => write!(f, "include"),
+            Token::Mod => write!(f, "mod"),
+            Token::Use => write!(f, "use"),
+            Token::Pub => write!(f, "pub"),
             Token::LineBreak => write!(f, "linebreak"),
             Token::Comment(_) => write!(f, "comment"),
+            Token::Attribute(x) => write!(f, "#[{}]", x),
             Token::EndOfInput => write!(f, "endofinput"),
         }
     }
@@ -0,0 +1,1644 @@
+//! Ahead-of-time export of the program to a self-contained Rust module, for the devices which run
+//! a fixed patch without the compiler and the VM.
+//!
+//! The module defines `Patch`, which holds the state storage, the global values and the arrays of
+//! the program, and whose `process` method computes a sample as `dsp` does on the VM. The external
+//! functions called by the program are the methods of the trait `Externals` implemented by the
+//! host. The builtins `probe`, `probeln`, `min` and `max` have the default implementations same as
+//! the VM, while `len` and `now` are computed in the module, where `now` counts the processed
+//! samples.
+//!
+//! The values are kept as the raw words of the VM so that the module computes the same results
+//! bit by bit: each MIR function becomes a method, each register a local variable of a word or of
+//! an array of words, and the states are laid out in the same way, shifted by `pushstateidx`.
+//! The closures and the strings are not supported.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+
+use super::bytecodegen::ByteCodeGenerator;
+use crate::interner::{Symbol, TypeNodeId};
+use crate::mir::{self, optimize, Instruction, Mir, VPtr, Value};
+use crate::runtime::vm::ArrayStorage;
+use crate::types::{PType, Type};
+use crate::utils::error::ReportableError;
+use crate::utils::metadata::Span;
+
+#[derive(Debug, Clone)]
+pub enum ErrorKind {
+    /// The program has no `dsp` function.
+    NoDsp,
+    /// `dsp` takes the input channels, which `process` does not give.
+    DspWithInputs,
+    /// The function uses the feature which the module cannot express.
+    Unsupported(Symbol, &'static str),
+}
+#[derive(Debug, Clone)]
+pub struct Error(pub ErrorKind, pub Span);
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::NoDsp => write!(f, "No dsp function found to export."),
+            ErrorKind::DspWithInputs => {
+                write!(
+                    f,
+                    "dsp function with the input channels cannot be exported."
+                )
+            }
+            ErrorKind::Unsupported(name, what) => write!(
+                f,
+                "Function {name} uses {what}, which cannot be exported to Rust."
+            ),
+        }
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ReportableError for Error {
+    fn get_span(&self) -> Span {
+        self.1.clone()
+    }
+}
+
+/// How a value of the type is passed to the external functions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Repr {
+    Unit,
+    Float,
+    Int,
+    Word,
+    Words(usize),
+}
+
+impl Repr {
+    fn of(ty: TypeNodeId) -> Self {
+        match (ty.to_type(), word_size(ty)) {
+            (Type::Primitive(PType::Numeric), _) => Repr::Float,
+            (Type::Primitive(PType::Int), _) => Repr::Int,
+            (_, 0) => Repr::Unit,
+            (_, 1) => Repr::Word,
+            (_, n) => Repr::Words(n),
+        }
+    }
+    fn rust_type(self) -> String {
+        match self {
+            Repr::Unit => "()".to_string(),
+            Repr::Float => "f64".to_string(),
+            Repr::Int => "i64".to_string(),
+            Repr::Word => "u64".to_string(),
+            Repr::Words(n) => format!("[u64; {n}]"),
+        }
+    }
+}
+
+/// The representation of a MIR value in the generated code.
+#[derive(Debug, Clone)]
+enum Slot {
+    /// The value of no words, like the unit.
+    Unit,
+    Float(f64),
+    Int(i64),
+    Uint(u64),
+    /// A word held in the local variable.
+    Word(String),
+    /// `len` words from `offset` in the local variable of an array of `size` words.
+    Words {
+        var: String,
+        offset: usize,
+        len: usize,
+        size: usize,
+    },
+}
+
+/// The functions emitted after the patch only when they are used. The ones from `AllocArray` are
+/// the methods of the patch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Helper {
+    Float,
+    Int,
+    FromFloat,
+    FromInt,
+    FromBool,
+    IsTrue,
+    Words,
+    IntPow,
+    Divisor,
+    Delay,
+    AllocArray,
+    ArrayLen,
+    ArrayIndex,
+    ArrayElem,
+    ArrayElemMut,
+}
+
+impl Helper {
+    fn is_method(self) -> bool {
+        self >= Helper::AllocArray
+    }
+    fn code(self) -> &'static str {
+        match self {
+            Helper::Float => "fn float(v: u64) -> f64 {\n    f64::from_bits(v)\n}\n",
+            Helper::Int => "fn int(v: u64) -> i64 {\n    v as i64\n}\n",
+            Helper::FromFloat => "fn from_float(v: f64) -> u64 {\n    v.to_bits()\n}\n",
+            Helper::FromInt => "fn from_int(v: i64) -> u64 {\n    v as u64\n}\n",
+            Helper::FromBool => {
+                "fn from_bool(v: bool) -> u64 {
+    if v {
+        1.0f64.to_bits()
+    } else {
+        0.0f64.to_bits()
+    }
+}
+"
+            }
+            Helper::IsTrue => {
+                "/// The condition of `if`, which is false for 0 and the negative numbers.
+fn is_true(v: u64) -> bool {
+    let v = f64::from_bits(v);
+    v > 0.0 || v.is_nan()
+}
+"
+            }
+            Helper::Words => {
+                "fn words<const N: usize>(v: &[u64]) -> [u64; N] {
+    v[..N].try_into().unwrap()
+}
+"
+            }
+            Helper::IntPow => {
+                "/// A negative exponent results in 0 unless the base is 1 or -1.
+fn int_pow(base: i64, exp: i64) -> i64 {
+    match (base, exp) {
+        (_, 0..) => base.wrapping_pow(exp.min(u32::MAX as i64) as u32),
+        (1, _) => 1,
+        (-1, _) if exp % 2 == 0 => 1,
+        (-1, _) => -1,
+        _ => 0,
+    }
+}
+"
+            }
+            Helper::Divisor => {
+                "fn divisor(v: u64) -> Result<i64, RuntimeError> {
+    match v as i64 {
+        0 => Err(RuntimeError::DivisionByZero),
+        v => Ok(v),
+    }
+}
+"
+            }
+            Helper::Delay => {
+                "/// The delay line in the state storage: the read and the write position followed by `len`
+/// words of the samples.
+fn delay(state: &mut [u64], len: u64, input: u64, time: u64) -> u64 {
+    let time = f64::from_bits(time) as u64;
+    let read = state[0];
+    let write = (read + time) % len;
+    state[1] = write;
+    let res = state[2 + read as usize];
+    state[2 + write as usize] = input;
+    state[0] = (read + 1) % len;
+    res
+}
+"
+            }
+            Helper::AllocArray => {
+                "    fn alloc_array(&mut self, len: u64, elem_size: usize) -> u64 {
+        let len = len as usize;
+        let data = vec![0; len * elem_size];
+        self.array_gc.allocated += data.len().max(1);
+        let arr = Some(Array {
+            len,
+            elem_size,
+            data,
+        });
+        match self.arrays.iter().position(Option::is_none) {
+            Some(i) => {
+                self.arrays[i] = arr;
+                i as u64
+            }
+            None => {
+                self.arrays.push(arr);
+                (self.arrays.len() - 1) as u64
+            }
+        }
+    }
+    fn array(&self, arr: u64) -> &Array {
+        self.arrays[arr as usize].as_ref().expect(\"Invalid Array Id referred\")
+    }
+"
+            }
+            Helper::ArrayLen => {
+                "    fn array_len(&self, arr: u64) -> i64 {
+        self.array(arr).len as i64
+    }
+"
+            }
+            Helper::ArrayIndex => {
+                "    fn array_range(&self, arr: u64, i: u64) -> Result<std::ops::Range<usize>, RuntimeError> {
+        let Array { len, elem_size, .. } = *self.array(arr);
+        match usize::try_from(i as i64) {
+            Ok(i) if i < len => Ok(i * elem_size..(i + 1) * elem_size),
+            _ => Err(RuntimeError::ArrayIndexOutOfRange),
+        }
+    }
+"
+            }
+            Helper::ArrayElem => {
+                "    fn array_elem(&self, arr: u64, i: u64) -> Result<&[u64], RuntimeError> {
+        let range = self.array_range(arr, i)?;
+        Ok(&self.array(arr).data[range])
+    }
+"
+            }
+            Helper::ArrayElemMut => {
+                "    fn array_elem_mut(&mut self, arr: u64, i: u64) -> Result<&mut [u64], RuntimeError> {
+        let range = self.array_range(arr, i)?;
+        let arr = self.arrays[arr as usize].as_mut();
+        Ok(&mut arr.expect(\"Invalid Array Id referred\").data[range])
+    }
+"
+            }
+        }
+    }
+}
+
+/// An external function called by the program, with the types at its first call.
+struct External {
+    name: Symbol,
+    args: Vec<Repr>,
+    ret: Repr,
+}
+
+impl External {
+    // the builtins of the VM, which the host does not have to implement.
+    fn default_body(&self) -> Option<&'static str> {
+        let body = match self.name.as_str() {
+            "probe" => "print!(\"{a0}\");\n        a0",
+            "probeln" => "println!(\"{a0} \");\n        a0",
+            // as the builtins of the VM, the numbers are compared by their bits.
+            "min" => "f64::from_bits(a0.to_bits().min(a1.to_bits()))",
+            "max" => "f64::from_bits(a0.to_bits().max(a1.to_bits()))",
+            _ => return None,
+        };
+        let takes_numbers = match self.name.as_str() {
+            "probe" | "probeln" => self.args == [Repr::Float],
+            _ => self.args == [Repr::Float, Repr::Float],
+        };
+        (takes_numbers && self.ret == Repr::Float).then_some(body)
+    }
+}
+
+fn word_size(ty: TypeNodeId) -> usize {
+    ByteCodeGenerator::word_size_for_type(ty) as usize
+}
+
+// an identifier in snake case from the name in the source.
+fn ident(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '_' => c,
+            'A'..='Z' => c.to_ascii_lowercase(),
+            _ => '_',
+        })
+        .collect()
+}
+
+fn method_name(idx: usize, func: &mir::Function) -> String {
+    let name = ident(func.label.as_str());
+    let words = name.split('_').filter(|w| !w.is_empty());
+    format!("f{idx}_{}", words.collect::<Vec<_>>().join("_"))
+}
+
+fn float_literal(v: f64) -> String {
+    if v.is_nan() {
+        format!("f64::from_bits({:#x})", v.to_bits())
+    } else if v.is_infinite() {
+        let sign = if v > 0.0 { "" } else { "NEG_" };
+        format!("f64::{sign}INFINITY")
+    } else {
+        format!("{v:?}")
+    }
+}
+
+// parenthesizes the negative literal to be an operand of the unary or the cast operator.
+fn atom(expr: String) -> String {
+    if expr.starts_with('-') {
+        format!("({expr})")
+    } else {
+        expr
+    }
+}
+
+fn has_effect(inst: &Instruction) -> bool {
+    !matches!(
+        inst,
+        Instruction::Uinteger(_)
+            | Instruction::Integer(_)
+            | Instruction::Float(_)
+            | Instruction::String(_)
+            | Instruction::Alloc(_)
+            | Instruction::Load(..)
+            | Instruction::GetElement { .. }
+            | Instruction::GetGlobal(..)
+            | Instruction::Closure(_)
+            | Instruction::GetUpValue(..)
+            | Instruction::GetState(_)
+            | Instruction::Phi(..)
+            | Instruction::AddF(..)
+            | Instruction::SubF(..)
+            | Instruction::MulF(..)
+            | Instruction::DivF(..)
+            | Instruction::ModF(..)
+            | Instruction::NegF(_)
+            | Instruction::AbsF(_)
+            | Instruction::SinF(_)
+            | Instruction::CosF(_)
+            | Instruction::PowF(..)
+            | Instruction::LogF(..)
+            | Instruction::SqrtF(_)
+            | Instruction::AddI(..)
+            | Instruction::SubI(..)
+            | Instruction::MulI(..)
+            | Instruction::NegI(_)
+            | Instruction::AbsI(_)
+            | Instruction::PowI(..)
+            | Instruction::LogI(..)
+            | Instruction::Not(_)
+            | Instruction::Eq(..)
+            | Instruction::Ne(..)
+            | Instruction::Gt(..)
+            | Instruction::Ge(..)
+            | Instruction::Lt(..)
+            | Instruction::Le(..)
+            | Instruction::And(..)
+            | Instruction::Or(..)
+            | Instruction::CastFtoI(_)
+            | Instruction::CastItoF(_)
+            | Instruction::CastItoB(_)
+    )
+}
+
+fn instructions(func: &mir::Function) -> impl Iterator<Item = &(VPtr, Instruction)> {
+    func.body.iter().flat_map(|b| b.0.iter())
+}
+
+// the values read by the instructions which are emitted: the ones with the side effects, and the
+// ones whose results are read.
+fn live_values(func: &mir::Function) -> HashSet<VPtr> {
+    let mut live = HashSet::new();
+    loop {
+        let n = live.len();
+        for (dst, inst) in instructions(func) {
+            if has_effect(inst) || live.contains(dst) {
+                live.extend(optimize::operands(inst).into_iter().cloned());
+            }
+        }
+        if live.len() == n {
+            return live;
+        }
+    }
+}
+
+// the functions called directly, whose index is given by `uint`.
+fn callees(func: &mir::Function) -> Vec<usize> {
+    let consts = instructions(func)
+        .filter_map(|(dst, inst)| match inst {
+            Instruction::Uinteger(u) => Some((dst.clone(), *u as usize)),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+    instructions(func)
+        .filter_map(|(_, inst)| match inst {
+            Instruction::Call(f, ..) => consts.get(f).copied(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The function being generated.
+struct FunctionCtx<'a> {
+    func: &'a mir::Function,
+    slots: HashMap<VPtr, Slot>,
+    live: HashSet<VPtr>,
+    // the values whose memory is written by `store`, which are declared as mutable.
+    mutated: HashSet<VPtr>,
+    lines: Vec<String>,
+    depth: usize,
+}
+
+impl FunctionCtx<'_> {
+    fn line(&mut self, line: impl AsRef<str>) {
+        let indent = "    ".repeat(self.depth + 2);
+        self.lines.push(format!("{indent}{}", line.as_ref()));
+    }
+    fn slot(&self, v: &VPtr) -> Slot {
+        match v.as_ref() {
+            Value::Register(_) | Value::Argument(..) => self
+                .slots
+                .get(v)
+                .cloned()
+                .unwrap_or_else(|| panic!("value {v} not found")),
+            // the functions are called by their names, and the others have no value.
+            _ => Slot::Unit,
+        }
+    }
+    // declares the local variable of `size` words for the value.
+    fn define(&mut self, dst: &VPtr, size: usize, expr: String) {
+        let var = var_name(dst);
+        let mutable = if self.mutated.contains(dst) {
+            "mut "
+        } else {
+            ""
+        };
+        let slot = if size == 1 {
+            self.line(format!("let {mutable}{var} = {expr};"));
+            Slot::Word(var)
+        } else {
+            self.line(format!("let {mutable}{var}: [u64; {size}] = {expr};"));
+            Slot::Words {
+                var,
+                offset: 0,
+                len: size,
+                size,
+            }
+        };
+        self.slots.insert(dst.clone(), slot);
+    }
+    // takes the expression of the variable defined just before, to use it directly as the value
+    // of the block instead.
+    fn take_definition(&mut self, var: &str) -> Option<String> {
+        let indent = "    ".repeat(self.depth + 2);
+        let value = self.lines.last().and_then(|l| {
+            let l = l.strip_prefix(&indent)?.strip_prefix("let ")?;
+            let (v, rest) = l.split_once([' ', ':'])?;
+            let value = rest.split_once("= ")?.1.strip_suffix(';')?;
+            (v == var).then(|| value.to_string())
+        })?;
+        self.lines.pop();
+        Some(value)
+    }
+    // turns the `if` expression defining the variable just before into the value of the block.
+    fn take_if_definition(&mut self, var: &str) -> bool {
+        let indent = "    ".repeat(self.depth + 2);
+        if self.lines.last() != Some(&format!("{indent}}};")) {
+            return false;
+        }
+        let head = format!("{indent}let {var} = if ");
+        let Some(i) = self.lines.iter().rposition(|l| l.starts_with(&head)) else {
+            return false;
+        };
+        self.lines[i] = format!("{indent}if {}", &self.lines[i][head.len()..]);
+        *self.lines.last_mut().unwrap() = format!("{indent}}}");
+        true
+    }
+    // the value returned at the end of the function.
+    fn tail(&mut self, expr: String) {
+        match self.take_definition(&expr) {
+            Some(value) => match value.strip_suffix('?') {
+                Some(res) => self.line(res),
+                None => self.line(format!("Ok({value})")),
+            },
+            None => self.line(format!("Ok({expr})")),
+        }
+    }
+}
+
+fn var_name(v: &VPtr) -> String {
+    match v.as_ref() {
+        Value::Register(r) => format!("v{r}"),
+        _ => unreachable!("{v} is not a register"),
+    }
+}
+
+struct RustGenerator<'a> {
+    mir: &'a Mir,
+    globals: Vec<(VPtr, usize)>,
+    globals_size: usize,
+    externals: Vec<External>,
+    helpers: BTreeSet<Helper>,
+    uses_state: bool,
+    uses_now: bool,
+    errors: Vec<Error>,
+}
+
+impl<'a> RustGenerator<'a> {
+    fn new(mir: &'a Mir) -> Self {
+        Self {
+            mir,
+            globals: vec![],
+            globals_size: 0,
+            externals: vec![],
+            helpers: BTreeSet::new(),
+            uses_state: false,
+            uses_now: false,
+            errors: vec![],
+        }
+    }
+    fn report(&mut self, kind: ErrorKind) {
+        self.errors.push(Error(kind, Span::default()));
+    }
+    fn unsupported(&mut self, f: &FunctionCtx, what: &'static str) {
+        let reported = self.errors.iter().any(|Error(k, _)| {
+            matches!(k, ErrorKind::Unsupported(name, w) if *name == f.func.label && *w == what)
+        });
+        if !reported {
+            self.report(ErrorKind::Unsupported(f.func.label, what));
+        }
+    }
+    fn helper(&mut self, h: Helper) -> &'static str {
+        self.helpers.insert(h);
+        match h {
+            Helper::ArrayElem | Helper::ArrayElemMut => {
+                self.helpers.insert(Helper::ArrayIndex);
+            }
+            Helper::Delay => self.uses_state = true,
+            _ => {}
+        }
+        match h {
+            Helper::Float => "float",
+            Helper::Int => "int",
+            Helper::FromFloat => "from_float",
+            Helper::FromInt => "from_int",
+            Helper::FromBool => "from_bool",
+            Helper::IsTrue => "is_true",
+            Helper::Words => "words",
+            Helper::IntPow => "int_pow",
+            Helper::Divisor => "divisor",
+            Helper::Delay => "delay",
+            Helper::AllocArray => "self.alloc_array",
+            Helper::ArrayLen => "self.array_len",
+            Helper::ArrayIndex => "self.array_range",
+            Helper::ArrayElem => "self.array_elem",
+            Helper::ArrayElemMut => "self.array_elem_mut",
+        }
+    }
+    fn global_pos(&mut self, gv: &VPtr, size: usize) -> usize {
+        match self.globals.iter().find(|(v, _)| v == gv) {
+            Some((_, pos)) => *pos,
+            None => {
+                let pos = self.globals_size;
+                self.globals.push((gv.clone(), pos));
+                self.globals_size += size;
+                pos
+            }
+        }
+    }
+    fn state(&mut self) -> &'static str {
+        self.uses_state = true;
+        "self.state[self.state_pos]"
+    }
+    fn state_range(&mut self, size: usize) -> String {
+        self.uses_state = true;
+        format!("self.state[self.state_pos..self.state_pos + {size}]")
+    }
+
+    // the expressions of the value as a word, a float, an integer, an array of `size` words and
+    // a slice of them.
+    fn word(&mut self, s: &Slot) -> String {
+        match s {
+            Slot::Unit => "0".to_string(),
+            Slot::Float(v) => format!("{}({})", self.helper(Helper::FromFloat), float_literal(*v)),
+            Slot::Int(v) => format!("{}({v})", self.helper(Helper::FromInt)),
+            Slot::Uint(v) => v.to_string(),
+            Slot::Word(var) => var.clone(),
+            Slot::Words { var, offset, .. } => format!("{var}[{offset}]"),
+        }
+    }
+    fn float(&mut self, s: &Slot) -> String {
+        match s {
+            Slot::Float(v) => float_literal(*v),
+            _ => format!("{}({})", self.helper(Helper::Float), self.word(s)),
+        }
+    }
+    fn int(&mut self, s: &Slot) -> String {
+        match s {
+            Slot::Int(v) => v.to_string(),
+            _ => format!("{}({})", self.helper(Helper::Int), self.word(s)),
+        }
+    }
+    fn array(&mut self, s: &Slot, size: usize) -> String {
+        match s {
+            Slot::Words {
+                var,
+                offset: 0,
+                len,
+                size: total,
+            } if len == total => var.clone(),
+            Slot::Words {
+                var, offset, len, ..
+            } => format!(
+                "{}(&{var}[{offset}..{}])",
+                self.helper(Helper::Words),
+                offset + len
+            ),
+            _ if size == 1 => format!("[{}]", self.word(s)),
+            _ => format!("[0; {size}]"),
+        }
+    }
+    fn slice(&mut self, s: &Slot, size: usize) -> String {
+        match s {
+            Slot::Words {
+                var,
+                offset: 0,
+                len,
+                size: total,
+            } if len == total => format!("&{var}"),
+            Slot::Words {
+                var, offset, len, ..
+            } => format!("&{var}[{offset}..{}]", offset + len),
+            _ => format!("&{}", self.array(s, size)),
+        }
+    }
+    // the array of the words read from the head of the slice of the memory like the states.
+    fn read(&mut self, memory: &str) -> String {
+        format!("{}(&{memory})", self.helper(Helper::Words))
+    }
+    // writes the value of `size` words to the place in the memory.
+    fn write(&mut self, f: &mut FunctionCtx, place: &Slot, src: &Slot, size: usize) {
+        match (place, size) {
+            (_, 0) => {}
+            (Slot::Word(var), 1) => {
+                let src = self.word(src);
+                f.line(format!("{var} = {src};"));
+            }
+            (Slot::Words { var, offset, .. }, 1) => {
+                let src = self.word(src);
+                f.line(format!("{var}[{offset}] = {src};"));
+            }
+            (Slot::Words { .. }, _) => {
+                let dst = self.slice(place, size);
+                let src = self.slice(src, size);
+                f.line(format!("{}.copy_from_slice({src});", &dst[1..]));
+            }
+            _ => self.unsupported(f, "the store to a constant"),
+        }
+    }
+
+    // the arguments of the call, leaving out the ones of the unit type.
+    fn call_args(&mut self, f: &FunctionCtx, args: &[(VPtr, TypeNodeId)]) -> String {
+        args.iter()
+            .filter_map(|(a, ty)| {
+                let s = f.slot(a);
+                match word_size(*ty) {
+                    0 => None,
+                    1 => Some(self.word(&s)),
+                    n => Some(self.array(&s, n)),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+    fn ext_call(
+        &mut self,
+        f: &FunctionCtx,
+        label: Symbol,
+        args: &[(VPtr, TypeNodeId)],
+        rty: TypeNodeId,
+    ) -> String {
+        match label.as_str() {
+            "_mimium_getnow" => {
+                self.uses_now = true;
+                let from_float = self.helper(Helper::FromFloat);
+                return format!("{from_float}(self.now as f64)");
+            }
+            "len" if args.len() == 1 => {
+                let arr = self.word(&f.slot(&args[0].0));
+                let from_int = self.helper(Helper::FromInt);
+                let len = self.helper(Helper::ArrayLen);
+                return format!("{from_int}({len}({arr}))");
+            }
+            _ => {}
+        }
+        let reprs = args.iter().map(|(_, t)| Repr::of(*t)).collect::<Vec<_>>();
+        let ret = Repr::of(rty);
+        if !self.externals.iter().any(|e| e.name == label) {
+            self.externals.push(External {
+                name: label,
+                args: reprs.clone(),
+                ret,
+            });
+        }
+        let args = args
+            .iter()
+            .zip(reprs)
+            .filter_map(|((a, _), repr)| {
+                let s = f.slot(a);
+                match repr {
+                    Repr::Unit => None,
+                    Repr::Float => Some(self.float(&s)),
+                    Repr::Int => Some(self.int(&s)),
+                    Repr::Word => Some(self.word(&s)),
+                    Repr::Words(n) => Some(self.array(&s, n)),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let call = format!("self.ext.{}({args})", ident(label.as_str()));
+        match ret {
+            Repr::Float => format!("{}({call})", self.helper(Helper::FromFloat)),
+            Repr::Int => format!("{}({call})", self.helper(Helper::FromInt)),
+            _ => call,
+        }
+    }
+
+    fn binop_f(&mut self, f: &FunctionCtx, op: &str, a: &VPtr, b: &VPtr) -> String {
+        let (a, b) = (self.float(&f.slot(a)), self.float(&f.slot(b)));
+        let expr = match (op, a.as_str(), b.as_str()) {
+            // the multiplication by -1 folded by the optimizer, which is written as the negation.
+            ("*", "-1.0", x) | ("*", x, "-1.0") => format!("-{}", atom(x.to_string())),
+            _ => format!("{a} {op} {b}"),
+        };
+        format!("{}({expr})", self.helper(Helper::FromFloat))
+    }
+    fn method_f(&mut self, f: &FunctionCtx, method: &str, args: &[&VPtr]) -> String {
+        let args = args
+            .iter()
+            .map(|a| self.float(&f.slot(a)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{}(f64::{method}({args}))", self.helper(Helper::FromFloat))
+    }
+    fn method_i(&mut self, f: &FunctionCtx, method: &str, args: &[&VPtr]) -> String {
+        let args = args
+            .iter()
+            .map(|a| self.int(&f.slot(a)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{}(i64::{method}({args}))", self.helper(Helper::FromInt))
+    }
+    fn division_i(&mut self, f: &FunctionCtx, method: &str, a: &VPtr, b: &VPtr) -> String {
+        let a = self.int(&f.slot(a));
+        let b = self.word(&f.slot(b));
+        let divisor = self.helper(Helper::Divisor);
+        let from_int = self.helper(Helper::FromInt);
+        format!("{from_int}(i64::{method}({a}, {divisor}({b})?))")
+    }
+    fn compare(&mut self, f: &FunctionCtx, op: &str, a: &VPtr, b: &VPtr) -> String {
+        let (a, b) = (self.float(&f.slot(a)), self.float(&f.slot(b)));
+        format!("{}({a} {op} {b})", self.helper(Helper::FromBool))
+    }
+    fn logical(&mut self, f: &FunctionCtx, op: &str, a: &VPtr, b: &VPtr) -> String {
+        let (a, b) = (self.float(&f.slot(a)), self.float(&f.slot(b)));
+        format!(
+            "{}({a} > 0.0 {op} {b} > 0.0)",
+            self.helper(Helper::FromBool)
+        )
+    }
+
+    // the expression of the instruction which computes a value of a word from the operands.
+    fn pure_expr(&mut self, f: &FunctionCtx, inst: &Instruction) -> Option<String> {
+        let expr = match inst {
+            Instruction::AddF(a, b) => self.binop_f(f, "+", a, b),
+            Instruction::SubF(a, b) => self.binop_f(f, "-", a, b),
+            Instruction::MulF(a, b) => self.binop_f(f, "*", a, b),
+            Instruction::DivF(a, b) => self.binop_f(f, "/", a, b),
+            Instruction::ModF(a, b) => self.binop_f(f, "%", a, b),
+            Instruction::PowF(a, b) => self.method_f(f, "powf", &[a, b]),
+            Instruction::NegF(a) => {
+                let a = atom(self.float(&f.slot(a)));
+                format!("{}(-{a})", self.helper(Helper::FromFloat))
+            }
+            Instruction::AbsF(a) => self.method_f(f, "abs", &[a]),
+            Instruction::SinF(a) => self.method_f(f, "sin", &[a]),
+            Instruction::CosF(a) => self.method_f(f, "cos", &[a]),
+            Instruction::SqrtF(a) => self.method_f(f, "sqrt", &[a]),
+            Instruction::AddI(a, b) => self.method_i(f, "wrapping_add", &[a, b]),
+            Instruction::SubI(a, b) => self.method_i(f, "wrapping_sub", &[a, b]),
+            Instruction::MulI(a, b) => self.method_i(f, "wrapping_mul", &[a, b]),
+            Instruction::DivI(a, b) => self.division_i(f, "wrapping_div", a, b),
+            Instruction::ModI(a, b) => self.division_i(f, "wrapping_rem", a, b),
+            Instruction::NegI(a) => self.method_i(f, "wrapping_neg", &[a]),
+            Instruction::AbsI(a) => self.method_i(f, "wrapping_abs", &[a]),
+            Instruction::PowI(a, b) => {
+                let (a, b) = (self.int(&f.slot(a)), self.int(&f.slot(b)));
+                let pow = self.helper(Helper::IntPow);
+                format!("{}({pow}({a}, {b}))", self.helper(Helper::FromInt))
+            }
+            Instruction::Eq(a, b) => self.compare(f, "==", a, b),
+            Instruction::Ne(a, b) => self.compare(f, "!=", a, b),
+            Instruction::Gt(a, b) => self.compare(f, ">", a, b),
+            Instruction::Ge(a, b) => self.compare(f, ">=", a, b),
+            Instruction::Lt(a, b) => self.compare(f, "<", a, b),
+            Instruction::Le(a, b) => self.compare(f, "<=", a, b),
+            Instruction::And(a, b) => self.logical(f, "&&", a, b),
+            Instruction::Or(a, b) => self.logical(f, "||", a, b),
+            Instruction::CastFtoI(a) => {
+                let a = atom(self.float(&f.slot(a)));
+                format!("{}({a} as i64)", self.helper(Helper::FromInt))
+            }
+            Instruction::CastItoF(a) => {
+                let a = atom(self.int(&f.slot(a)));
+                format!("{}({a} as f64)", self.helper(Helper::FromFloat))
+            }
+            _ => return None,
+        };
+        Some(expr)
+    }
+
+    fn emit_return(&mut self, f: &mut FunctionCtx, v: &VPtr, ty: TypeNodeId) {
+        let expr = match word_size(ty) {
+            0 => "()".to_string(),
+            1 => self.word(&f.slot(v)),
+            n => self.array(&f.slot(v), n),
+        };
+        if f.depth == 0 {
+            f.tail(expr);
+        } else {
+            f.line(format!("return Ok({expr});"));
+        }
+    }
+
+    fn emit_instruction(&mut self, f: &mut FunctionCtx, dst: &VPtr, inst: &Instruction) {
+        let live = f.live.contains(dst);
+        if let Some(expr) = self.pure_expr(f, inst) {
+            // the division is kept for its error even if the result is not read.
+            if live {
+                f.define(dst, 1, expr);
+            } else if has_effect(inst) {
+                f.line(format!("{expr};"));
+            }
+            return;
+        }
+        match inst {
+            Instruction::Uinteger(u) => {
+                f.slots.insert(dst.clone(), Slot::Uint(*u));
+            }
+            Instruction::Integer(i) => {
+                f.slots.insert(dst.clone(), Slot::Int(*i));
+            }
+            Instruction::Float(v) => {
+                f.slots.insert(dst.clone(), Slot::Float(*v));
+            }
+            Instruction::Alloc(ty) => match word_size(*ty) {
+                _ if !live => {}
+                0 => {
+                    f.slots.insert(dst.clone(), Slot::Unit);
+                }
+                n => {
+                    // always an array, as it is the place which `store` writes to.
+                    let var = var_name(dst);
+                    let mutable = if f.mutated.contains(dst) { "mut " } else { "" };
+                    f.line(format!("let {mutable}{var} = [0u64; {n}];"));
+                    let slot = Slot::Words {
+                        var,
+                        offset: 0,
+                        len: n,
+                        size: n,
+                    };
+                    f.slots.insert(dst.clone(), slot);
+                }
+            },
+            Instruction::Load(ptr, ty) => {
+                let size = word_size(*ty);
+                if !live || size == 0 {
+                    return;
+                }
+                let src = f.slot(ptr);
+                let root = self.root(f, ptr);
+                if !f.mutated.contains(&root) && !f.mutated.contains(dst) {
+                    // the memory is never written, so that the value can be shared.
+                    f.slots.insert(dst.clone(), src);
+                } else {
+                    let expr = match size {
+                        1 => self.word(&src),
+                        n => self.array(&src, n),
+                    };
+                    f.define(dst, size, expr);
+                }
+            }
+            Instruction::Store(ptr, src, ty) => {
+                let place = f.slot(ptr);
+                let src = f.slot(src);
+                self.write(f, &place, &src, word_size(*ty));
+            }
+            Instruction::GetElement {
+                value,
+                ty,
+                array_idx,
+                tuple_offset,
+            } => {
+                let elems = ty.to_type().get_aggregate_elems().unwrap();
+                let len = word_size(elems[*tuple_offset as usize]);
+                let t_offset: usize = elems[0..*tuple_offset as usize]
+                    .iter()
+                    .map(|t| word_size(*t))
+                    .sum();
+                let offset = word_size(*ty) * *array_idx as usize + t_offset;
+                let slot = match f.slot(value) {
+                    _ if len == 0 => Slot::Unit,
+                    Slot::Words {
+                        var,
+                        offset: base,
+                        size,
+                        ..
+                    } => Slot::Words {
+                        var,
+                        offset: base + offset,
+                        len,
+                        size,
+                    },
+                    s => s,
+                };
+                f.slots.insert(dst.clone(), slot);
+            }
+            Instruction::GetGlobal(v, ty) => {
+                let size = word_size(*ty);
+                let pos = self.global_pos(v, size);
+                if live && size > 0 {
+                    let expr = match size {
+                        1 => format!("self.globals[{pos}]"),
+                        _ => self.read(&format!("self.globals[{pos}..]")),
+                    };
+                    f.define(dst, size, expr);
+                }
+            }
+            Instruction::SetGlobal(v, src, ty) => {
+                let size = word_size(*ty);
+                let pos = self.global_pos(v, size);
+                let src = f.slot(src);
+                match size {
+                    0 => {}
+                    1 => {
+                        let src = self.word(&src);
+                        f.line(format!("self.globals[{pos}] = {src};"));
+                    }
+                    n => {
+                        let src = self.slice(&src, n);
+                        f.line(format!(
+                            "self.globals[{pos}..{}].copy_from_slice({src});",
+                            pos + n
+                        ));
+                    }
+                }
+            }
+            Instruction::AllocArray(len, elem_ty) => {
+                let len = self.word(&f.slot(len));
+                let alloc = self.helper(Helper::AllocArray);
+                let expr = format!("{alloc}({len}, {})", word_size(*elem_ty));
+                if live {
+                    f.define(dst, 1, expr);
+                } else {
+                    f.line(format!("{expr};"));
+                }
+                // the fields of the arrays are read by the access to the elements.
+                self.helper(Helper::ArrayElem);
+            }
+            Instruction::GetArrayElem(arr, idx, elem_ty) => {
+                let (arr, idx) = (self.word(&f.slot(arr)), self.word(&f.slot(idx)));
+                let elem = format!("{}({arr}, {idx})?", self.helper(Helper::ArrayElem));
+                match word_size(*elem_ty) {
+                    n if !live || n == 0 => f.line(format!("{elem};")),
+                    1 => f.define(dst, 1, format!("{elem}[0]")),
+                    n => {
+                        let words = self.helper(Helper::Words);
+                        f.define(dst, n, format!("{words}({elem})"))
+                    }
+                }
+            }
+            Instruction::SetArrayElem(arr, idx, src, elem_ty) => {
+                let (arr, idx) = (self.word(&f.slot(arr)), self.word(&f.slot(idx)));
+                let elem = format!("{}({arr}, {idx})?", self.helper(Helper::ArrayElemMut));
+                let src = f.slot(src);
+                match word_size(*elem_ty) {
+                    0 => f.line(format!("{elem};")),
+                    1 => {
+                        let src = self.word(&src);
+                        f.line(format!("{elem}[0] = {src};"));
+                    }
+                    n => {
+                        let src = self.slice(&src, n);
+                        f.line(format!("{elem}.copy_from_slice({src});"));
+                    }
+                }
+            }
+            Instruction::Call(func, args, rty) | Instruction::CallCls(func, args, rty) => {
+                let is_pure = matches!(func.as_ref(), Value::ExtFunction(label, _)
+                    if matches!(label.as_str(), "_mimium_getnow" | "len"));
+                if is_pure && !live {
+                    return;
+                }
+                let call = match (func.as_ref(), f.slot(func)) {
+                    (Value::ExtFunction(label, _), _) => Some(self.ext_call(f, *label, args, *rty)),
+                    (Value::Register(_), Slot::Uint(idx))
+                        if matches!(inst, Instruction::Call(..)) =>
+                    {
+                        let callee = &self.mir.functions[idx as usize];
+                        let args = self.call_args(f, args);
+                        Some(format!("self.{}({args})?", method_name(idx as _, callee)))
+                    }
+                    _ => {
+                        self.unsupported(f, "the call of closures");
+                        None
+                    }
+                };
+                let size = word_size(*rty);
+                match call {
+                    Some(call) if live && size > 0 => f.define(dst, size, call),
+                    Some(call) => f.line(format!("{call};")),
+                    None => {}
+                }
+            }
+            Instruction::PushStateOffset(v) | Instruction::PopStateOffset(v) => {
+                let size = ByteCodeGenerator::calc_state_size(v);
+                let op = match inst {
+                    Instruction::PushStateOffset(_) => "+=",
+                    _ => "-=",
+                };
+                if size > 0 {
+                    self.uses_state = true;
+                    f.line(format!("self.state_pos {op} {size};"));
+                }
+            }
+            Instruction::GetState(ty) => {
+                let size = word_size(*ty);
+                if live && size > 0 {
+                    let expr = match size {
+                        1 => self.state().to_string(),
+                        _ => self.read("self.state[self.state_pos..]"),
+                    };
+                    self.uses_state = true;
+                    f.define(dst, size, expr);
+                }
+            }
+            Instruction::SetState(src, ty) => {
+                let src = f.slot(src);
+                match word_size(*ty) {
+                    0 => {}
+                    1 => {
+                        let (place, src) = (self.state(), self.word(&src));
+                        f.line(format!("{place} = {src};"));
+                    }
+                    n => {
+                        let (place, src) = (self.state_range(n), self.slice(&src, n));
+                        f.line(format!("{place}.copy_from_slice({src});"));
+                    }
+                }
+            }
+            Instruction::Delay(len, src, time) => {
+                let (src, time) = (self.word(&f.slot(src)), self.word(&f.slot(time)));
+                let delay = self.helper(Helper::Delay);
+                let expr =
+                    format!("{delay}(&mut self.state[self.state_pos..], {len}, {src}, {time})");
+                if live {
+                    f.define(dst, 1, expr);
+                } else {
+                    f.line(format!("{expr};"));
+                }
+            }
+            Instruction::Mem(src) => {
+                let (place, src) = (self.state(), self.word(&f.slot(src)));
+                if live {
+                    f.define(dst, 1, format!("std::mem::replace(&mut {place}, {src})"));
+                } else {
+                    f.line(format!("{place} = {src};"));
+                }
+            }
+            Instruction::Return(v, ty) => self.emit_return(f, v, *ty),
+            Instruction::ReturnFeed(new, ty) => {
+                let new = f.slot(new);
+                let expr = match word_size(*ty) {
+                    0 => "()".to_string(),
+                    1 => {
+                        let (place, new) = (self.state(), self.word(&new));
+                        format!("std::mem::replace(&mut {place}, {new})")
+                    }
+                    n => {
+                        // the old value is returned after the state is updated.
+                        let old = self.read("self.state[self.state_pos..]");
+                        f.define(dst, n, old);
+                        let (place, new) = (self.state_range(n), self.slice(&new, n));
+                        f.line(format!("{place}.copy_from_slice({new});"));
+                        self.array(&f.slot(dst), n)
+                    }
+                };
+                if f.depth == 0 {
+                    f.line(format!("Ok({expr})"));
+                } else {
+                    f.line(format!("return Ok({expr});"));
+                }
+            }
+            Instruction::String(_) => self.unsupported(f, "strings"),
+            Instruction::Closure(_) | Instruction::CloseUpValues(..) => {
+                self.unsupported(f, "closures")
+            }
+            Instruction::GetUpValue(..) | Instruction::SetUpValue(..) => {
+                self.unsupported(f, "upvalues")
+            }
+            Instruction::JmpIf(..) | Instruction::Phi(..) => unreachable!(),
+            _ => self.unsupported(f, "an instruction not supported by the VM"),
+        }
+        if !self.errors.is_empty() {
+            // keeps generating to report all the errors, with a dummy value of the failed one.
+            f.slots.entry(dst.clone()).or_insert(Slot::Unit);
+        }
+    }
+
+    // the value which has the memory that `v` refers to.
+    fn root(&self, f: &FunctionCtx, v: &VPtr) -> VPtr {
+        instructions(f.func)
+            .find_map(|(dst, inst)| match inst {
+                Instruction::GetElement { value, .. } if dst == v => Some(self.root(f, value)),
+                Instruction::Load(ptr, _) if dst == v => {
+                    // the value shared with the memory is written through it.
+                    matches!(f.slots.get(v), Some(s) if self.shares(f, s, ptr))
+                        .then(|| self.root(f, ptr))
+                }
+                _ => None,
+            })
+            .unwrap_or_else(|| v.clone())
+    }
+    fn shares(&self, f: &FunctionCtx, s: &Slot, ptr: &VPtr) -> bool {
+        match (s, f.slots.get(ptr)) {
+            (Slot::Word(a), Some(Slot::Word(b))) => a == b,
+            (Slot::Words { var: a, .. }, Some(Slot::Words { var: b, .. })) => a == b,
+            _ => false,
+        }
+    }
+
+    // emits the instructions of the block from `skip`. Returns true if the block returns from
+    // the function.
+    fn emit_block(&mut self, f: &mut FunctionCtx, bidx: usize, skip: usize) -> bool {
+        let block = &f.func.body[bidx].0;
+        for (dst, inst) in block.iter().skip(skip) {
+            match inst {
+                Instruction::JmpIf(cond, tbb, ebb, pbb) => {
+                    self.emit_branch(f, cond, *tbb as usize, *ebb as usize, *pbb as usize);
+                    return self.emit_block(f, *pbb as usize, 1);
+                }
+                Instruction::Return(..) | Instruction::ReturnFeed(..) => {
+                    self.emit_instruction(f, dst, inst);
+                    return true;
+                }
+                _ => self.emit_instruction(f, dst, inst),
+            }
+        }
+        false
+    }
+    fn emit_branch(
+        &mut self,
+        f: &mut FunctionCtx,
+        cond: &VPtr,
+        tbb: usize,
+        ebb: usize,
+        pbb: usize,
+    ) {
+        let (phidst, phi) = f.func.body[pbb].0.first().unwrap();
+        let Instruction::Phi(t, e) = phi else {
+            unreachable!("Unexpected inst: {phi:?}")
+        };
+        let is_none = |v: &VPtr| matches!(v.as_ref(), Value::None);
+        let cond = self.word(&f.slot(cond));
+        let cond = format!("if {}({cond}) {{", self.helper(Helper::IsTrue));
+        // the phi becomes the value of `if` when both the branches have the value, otherwise the
+        // variable assigned in the branch.
+        let phivar = f.live.contains(phidst).then(|| var_name(phidst));
+        let is_expr = phivar.is_some() && !is_none(t) && !is_none(e);
+        match &phivar {
+            Some(var) if is_expr => f.line(format!("let {var} = {cond}")),
+            Some(var) => {
+                let mutable = if is_none(t) && is_none(e) { "" } else { "mut " };
+                f.line(format!("let {mutable}{var} = 0u64;"));
+                f.line(cond);
+            }
+            None => f.line(cond),
+        }
+        for (bidx, v, head) in [(tbb, t, None), (ebb, e, Some("} else {"))] {
+            let start = f.lines.len();
+            if let Some(head) = head {
+                f.line(head);
+            }
+            f.depth += 1;
+            let returned = self.emit_block(f, bidx, 0);
+            if let (Some(var), false, false) = (&phivar, returned, is_none(v)) {
+                let v = self.word(&f.slot(v));
+                if is_expr {
+                    if !f.take_if_definition(&v) {
+                        let v = f.take_definition(&v).unwrap_or(v);
+                        f.line(v);
+                    }
+                } else {
+                    f.line(format!("{var} = {v};"));
+                }
+            }
+            f.depth -= 1;
+            if head.is_some() && f.lines.len() == start + 1 {
+                // no else block for `if` without `else`.
+                f.lines.pop();
+            }
+        }
+        f.line(if is_expr { "};" } else { "}" });
+        if let Some(var) = phivar {
+            f.slots.insert(phidst.clone(), Slot::Word(var));
+        }
+    }
+
+    fn gen_function(&mut self, idx: usize) -> String {
+        let func = &self.mir.functions[idx];
+        let live = live_values(func);
+        let mut f = FunctionCtx {
+            func,
+            slots: HashMap::new(),
+            live,
+            mutated: HashSet::new(),
+            lines: vec![],
+            depth: 0,
+        };
+        // the values declared as mutable are the roots of the places written by `store`.
+        let mut bases = HashMap::new();
+        for (dst, inst) in instructions(func) {
+            if let Instruction::GetElement { value, .. } = inst {
+                bases.insert(dst.clone(), value.clone());
+            }
+        }
+        for (_, inst) in instructions(func) {
+            if let Instruction::Store(ptr, ..) = inst {
+                let mut root = ptr;
+                while let Some(base) = bases.get(root) {
+                    root = base;
+                }
+                f.mutated.insert(root.clone());
+            }
+        }
+        let mut params = vec!["&mut self".to_string()];
+        for (a, ty) in func.args.iter().zip(func.argtypes.iter()) {
+            let Value::Argument(i, _) = a.as_ref() else {
+                unreachable!()
+            };
+            let size = word_size(*ty);
+            let var = match (f.live.contains(a), f.mutated.contains(a)) {
+                (false, _) => format!("_a{i}"),
+                (true, false) => format!("a{i}"),
+                (true, true) => format!("mut a{i}"),
+            };
+            let name = var.trim_start_matches("mut ").to_string();
+            let slot = match size {
+                0 => Slot::Unit,
+                1 => Slot::Word(name),
+                n => Slot::Words {
+                    var: name,
+                    offset: 0,
+                    len: n,
+                    size: n,
+                },
+            };
+            f.slots.insert(a.clone(), slot);
+            match size {
+                0 => {}
+                1 => params.push(format!("{var}: u64")),
+                n => params.push(format!("{var}: [u64; {n}]")),
+            }
+        }
+        let rty = *func
+            .return_type
+            .get()
+            .expect("return type not inferred correctly");
+        let ret = match word_size(rty) {
+            0 => "()".to_string(),
+            1 => "u64".to_string(),
+            n => format!("[u64; {n}]"),
+        };
+        self.emit_block(&mut f, 0, 0);
+
+        let mut res = String::new();
+        if params.len() > 7 {
+            res += "    #[allow(clippy::too_many_arguments)]\n";
+        }
+        let name = method_name(idx, func);
+        let params = params.join(", ");
+        let _ = writeln!(
+            res,
+            "    fn {name}({params}) -> Result<{ret}, RuntimeError> {{"
+        );
+        for line in f.lines {
+            res += &line;
+            res.push('\n');
+        }
+        res += "    }\n";
+        res
+    }
+
+    // the functions called from the global context and `dsp`, which are exported.
+    fn reachable_functions(&self, dsp: usize) -> Vec<usize> {
+        let mut reached = BTreeSet::new();
+        let mut stack = vec![0, dsp];
+        while let Some(idx) = stack.pop() {
+            if reached.insert(idx) {
+                stack.extend(callees(&self.mir.functions[idx]));
+            }
+        }
+        reached.into_iter().collect()
+    }
+
+    /// The method of the patch releasing the arrays unreachable from the states and the global
+    /// values, which are the only words left after a call. As in the VM, a word equal to the id of
+    /// an array is regarded as a reference to it, and the collection runs only after enough words
+    /// are allocated.
+    fn collect_arrays(&self) -> String {
+        let roots = match (self.uses_state, self.globals_size > 0) {
+            (true, true) => "self.state.iter().chain(&self.globals)",
+            (true, false) => "self.state.iter()",
+            (false, true) => "self.globals.iter()",
+            (false, false) => "std::iter::empty::<&u64>()",
+        };
+        let min_words = ArrayStorage::MIN_COLLECTION_WORDS;
+        format!(
+            "    fn collect_arrays(&mut self) {{
+        let ArrayGc {{
+            allocated,
+            live,
+            reachable,
+            pending,
+        }} = &mut self.array_gc;
+        if *allocated < (*live).max({min_words}) {{
+            return;
+        }}
+        reachable.clear();
+        reachable.resize(self.arrays.len(), false);
+        pending.clear();
+        pending.extend({roots});
+        while let Some(w) = pending.pop() {{
+            let Some(Some(arr)) = self.arrays.get(w as usize) else {{
+                continue;
+            }};
+            if !reachable[w as usize] {{
+                reachable[w as usize] = true;
+                pending.extend_from_slice(&arr.data);
+            }}
+        }}
+        *allocated = 0;
+        *live = 0;
+        for (arr, reachable) in self.arrays.iter_mut().zip(reachable.iter()) {{
+            match arr {{
+                Some(a) if *reachable => *live += a.data.len().max(1),
+                _ => *arr = None,
+            }}
+        }}
+    }}
+"
+        )
+    }
+    fn generate(&mut self) -> String {
+        let Some(dsp) = self
+            .mir
+            .functions
+            .iter()
+            .position(|f| f.label.as_str() == "dsp")
+        else {
+            self.report(ErrorKind::NoDsp);
+            return String::new();
+        };
+        let dspfn = &self.mir.functions[dsp];
+        if dspfn.argtypes.iter().any(|t| word_size(*t) > 0) {
+            self.report(ErrorKind::DspWithInputs);
+        }
+        let methods = self
+            .reachable_functions(dsp)
+            .into_iter()
+            .map(|idx| self.gen_function(idx))
+            .collect::<Vec<_>>();
+        let nchannels = word_size(*dspfn.return_type.get().unwrap());
+        let state_size = [0, dsp]
+            .iter()
+            .map(|i| ByteCodeGenerator::calc_state_size(&self.mir.functions[*i].state_sizes))
+            .max()
+            .unwrap();
+        let uses_arrays = self.helpers.contains(&Helper::AllocArray);
+
+        let mut res = String::new();
+        let file = self
+            .mir
+            .file_path
+            .map_or("the source".to_string(), |p| p.to_string());
+        let _ = writeln!(res, "// Generated by mimium from {file}.\n");
+
+        res += "/// The functions called by the patch, which the host implements. The builtin functions of\n";
+        res += "/// mimium have the default implementations same as the VM.\n";
+        res += "pub trait Externals {";
+        if !self.externals.is_empty() {
+            res.push('\n');
+        }
+        for ext in self.externals.iter() {
+            let params = std::iter::once("&mut self".to_string())
+                .chain(
+                    ext.args
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, r)| **r != Repr::Unit)
+                        .map(|(i, r)| format!("a{i}: {}", r.rust_type())),
+                )
+                .collect::<Vec<_>>()
+                .join(", ");
+            let ret = match ext.ret {
+                Repr::Unit => String::new(),
+                r => format!(" -> {}", r.rust_type()),
+            };
+            let name = ident(ext.name.as_str());
+            match ext.default_body() {
+                Some(body) => {
+                    let _ = writeln!(
+                        res,
+                        "    fn {name}({params}){ret} {{\n        {body}\n    }}"
+                    );
+                }
+                None => {
+                    let _ = writeln!(res, "    fn {name}({params}){ret};");
+                }
+            }
+        }
+        res += "}\n\n";
+        if self.externals.iter().all(|e| e.default_body().is_some()) {
+            res += "impl Externals for () {}\n\n";
+        }
+
+        res +=
+            "/// The error which aborts the processing of a sample, as the runtime error of the VM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeError {
+    ArrayIndexOutOfRange,
+    DivisionByZero,
+}
+
+";
+        if uses_arrays {
+            res += "struct Array {\n    len: usize,\n    elem_size: usize,\n    data: Vec<u64>,\n}\n\n";
+            res += "/// The words of the arrays allocated after the last collection and left by it, with the\n";
+            res += "/// buffers reused by the collections.\n";
+            res += "#[derive(Default)]\nstruct ArrayGc {\n    allocated: usize,\n    live: usize,\n    reachable: Vec<bool>,\n    pending: Vec<u64>,\n}\n\n";
+        }
+
+        res += "/// The program compiled ahead of time, which holds the states of the signal processing.\n";
+        res += "pub struct Patch<E: Externals> {\n    pub ext: E,\n";
+        if self.uses_state {
+            res += "    state: Vec<u64>,\n    state_pos: usize,\n";
+        }
+        if self.globals_size > 0 {
+            let _ = writeln!(res, "    globals: [u64; {}],", self.globals_size);
+        }
+        if uses_arrays {
+            res += "    arrays: Vec<Option<Array>>,\n    array_gc: ArrayGc,\n";
+        }
+        if self.uses_now {
+            res += "    now: u64,\n";
+        }
+        res += "}\n\n";
+
+        res += "impl<E: Externals> Patch<E> {\n";
+        res += "    /// The number of the channels returned by [`Self::process`].\n";
+        let _ = writeln!(res, "    pub const OUTPUT_CHANNELS: usize = {nchannels};");
+        res += "    /// Creates the patch, evaluating the global context of the program.\n";
+        res += "    pub fn new(ext: E) -> Result<Self, RuntimeError> {\n";
+        let mut fields = String::new();
+        if self.uses_state {
+            let _ = writeln!(
+                fields,
+                "            state: vec![0; {state_size}],\n            state_pos: 0,"
+            );
+        }
+        if self.globals_size > 0 {
+            let _ = writeln!(fields, "            globals: [0; {}],", self.globals_size);
+        }
+        if uses_arrays {
+            fields += "            arrays: vec![],\n            array_gc: ArrayGc::default(),\n";
+        }
+        if self.uses_now {
+            fields += "            now: 0,\n";
+        }
+        if fields.is_empty() {
+            res += "        let mut patch = Self { ext };\n";
+        } else {
+            let _ = write!(
+                res,
+                "        let mut patch = Self {{\n            ext,\n{fields}        }};\n"
+            );
+        }
+        let _ = writeln!(
+            res,
+            "        patch.{}()?;",
+            method_name(0, &self.mir.functions[0])
+        );
+        if uses_arrays {
+            res += "        patch.collect_arrays();\n";
+        }
+        res += "        Ok(patch)\n    }\n";
+
+        res += "    /// Computes the next sample as `dsp` does.\n";
+        let _ = writeln!(
+            res,
+            "    pub fn process(&mut self) -> Result<[f64; {nchannels}], RuntimeError> {{"
+        );
+        let call = format!("self.{}()", method_name(dsp, dspfn));
+        let output = match nchannels {
+            0 => "[]",
+            1 => "[f64::from_bits(res)]",
+            _ => "res.map(f64::from_bits)",
+        };
+        let res_var = if nchannels == 0 { "_res" } else { "res" };
+        if uses_arrays || self.uses_now {
+            let _ = writeln!(res, "        let res = {call};");
+            if uses_arrays {
+                res += "        self.collect_arrays();\n";
+            }
+            if self.uses_now {
+                res += "        self.now += 1;\n";
+            }
+            let _ = writeln!(res, "        let {res_var} = res?;");
+        } else if nchannels == 0 {
+            let _ = writeln!(res, "        {call}?;");
+        } else {
+            let _ = writeln!(res, "        let res = {call}?;");
+        }
+        let _ = writeln!(res, "        Ok({output})\n    }}");
+        for m in methods {
+            res += &m;
+        }
+        for h in self.helpers.iter().filter(|h| h.is_method()) {
+            res += h.code();
+        }
+        if uses_arrays {
+            res += &self.collect_arrays();
+        }
+        res += "}\n";
+        for h in self.helpers.iter().filter(|h| !h.is_method()) {
+            res.push('\n');
+            res += h.code();
+        }
+        res
+    }
+}
+
+/// Generates the Rust module equivalent to the program. Fails if the program has no `dsp`, or
+/// uses the features not supported by the module like closures.
+pub fn gen_rust(mir: &Mir) -> Result<String, Vec<Box<dyn ReportableError>>> {
+    let mut generator = RustGenerator::new(mir);
+    let res = generator.generate();
+    if !generator.errors.is_empty() {
+        return Err(generator
+            .errors
+            .into_iter()
+            .map(|e| Box::new(e) as Box<dyn ReportableError>)
+            .collect());
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::{Context, ExtFunTypeInfo};
+    use crate::interner::ToSymbol;
+    use crate::runtime::vm::builtin::get_builtin_fn_types;
+    use crate::{function, numeric, types::PType, types::Type};
+
+    fn emit(src: &str) -> Result<String, Vec<String>> {
+        let accumulate = ExtFunTypeInfo {
+            name: "accumulate".to_symbol(),
+            ty: function!(vec![numeric!()], numeric!()),
+        };
+        let types = get_builtin_fn_types().into_iter().chain([accumulate]);
+        Context::new(types, None)
+            .emit_rust(src)
+            .map_err(|errs| errs.iter().map(|e| e.to_string()).collect())
+    }
+
+    #[test]
+    fn externals() {
+        let src = r#"
+fn counter() {
+    self + 1.0
+}
+fn dsp() {
+    let x = probe(counter())
+    (accumulate(x), now)
+}
+"#;
+        let code = emit(src).unwrap();
+        assert!(code.contains("    fn accumulate(&mut self, a0: f64) -> f64;\n"));
+        // `probe` has the default implementation, but `accumulate` has not.
+        assert!(code.contains("    fn probe(&mut self, a0: f64) -> f64 {\n"));
+        assert!(!code.contains("impl Externals for ()"));
+        assert!(code.contains("pub const OUTPUT_CHANNELS: usize = 2;"));
+        assert!(code.contains("from_float(self.now as f64)"));
+    }
+
+    #[test]
+    fn unsupported() {
+        let src = r#"
+fn dsp() {
+    let x = 1.0
+    let f = |y| x + y
+    f(2.0)
+}
+"#;
+        let errs = emit(src).unwrap_err();
+        assert!(errs.iter().any(|e| e.contains("closures")), "{errs:?}");
+        let errs = emit("fn gain(x) { x }").unwrap_err();
+        assert_eq!(errs, ["No dsp function found to export."]);
+    }
+}
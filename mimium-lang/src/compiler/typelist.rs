@@ -0,0 +1,70 @@
+//! The listing of the inferred types printed by `--emit-types`.
+//!
+//! The toplevel definitions are printed with their generalized types, whose type variables are
+//! quantified like `forall 'a. ('a)->'a`. The type variables left unresolved without
+//! generalization, as in the recursive functions, are printed without `forall`. The function
+//! types are printed with their state slots.
+use crate::interner::{with_session_globals, Symbol, TypeNodeId};
+use crate::types::TypeVarNames;
+use crate::utils::metadata::Span;
+use std::fmt;
+
+pub struct TypeList {
+    /// The toplevel definitions in the order of the definitions.
+    pub definitions: Vec<(Symbol, TypeNodeId)>,
+    /// The expressions in the source ordered by their locations, if they were requested.
+    pub exprs: Option<Vec<(Span, TypeNodeId)>>,
+}
+
+fn type_scheme(t: TypeNodeId) -> String {
+    let mut names = TypeVarNames::default();
+    let ty = t.to_type().to_string_with_state(&mut names);
+    match names.scheme_names() {
+        vars if vars.is_empty() => ty,
+        vars => format!("forall {}. {ty}", vars.join(" ")),
+    }
+}
+
+// the line and column of the expression with the first line of its code, shortened if too long.
+fn describe(span: &Span) -> (String, String) {
+    const MAX_LEN: usize = 32;
+    let file = with_session_globals(|session_globals| {
+        session_globals.source_map.get_file(span.file).cloned()
+    });
+    let Some(file) = file else {
+        return ("?".to_string(), String::new());
+    };
+    let (line, col) = file.line_column(span.start());
+    let code = file
+        .content
+        .chars()
+        .skip(span.start())
+        .take(span.end() - span.start())
+        .take_while(|c| *c != '\n')
+        .collect::<String>();
+    let code = if code.chars().count() > MAX_LEN {
+        format!("{}...", code.chars().take(MAX_LEN).collect::<String>())
+    } else {
+        code
+    };
+    (format!("{line}:{col}"), code)
+}
+
+impl fmt::Display for TypeList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, t) in self.definitions.iter() {
+            writeln!(f, "{name}: {}", type_scheme(*t))?;
+        }
+        if let Some(exprs) = &self.exprs {
+            writeln!(f)?;
+            for (span, t) in exprs.iter() {
+                let (pos, code) = describe(span);
+                let ty = t
+                    .to_type()
+                    .to_string_with_state(&mut TypeVarNames::default());
+                writeln!(f, "{pos}\t{code}\t: {ty}")?;
+            }
+        }
+        Ok(())
+    }
+}
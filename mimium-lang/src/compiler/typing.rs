@@ -1,9 +1,14 @@
-use crate::ast::{Expr, Literal};
+use crate::ast::{Expr, Literal, TypeDecl};
 use crate::compiler::intrinsics;
-use crate::interner::{ExprKey, ExprNodeId, Symbol, ToSymbol, TypeNodeId};
+use crate::interner::{with_session_globals, ExprKey, ExprNodeId, Symbol, ToSymbol, TypeNodeId};
 use crate::pattern::{Pattern, TypedPattern};
-use crate::types::{PType, Type, TypeVar};
-use crate::utils::{environment::Environment, error::ReportableError, metadata::Span};
+use crate::types::{sort_fields, PType, Type, TypeVar, TypeVarNames};
+use crate::utils::{
+    environment::Environment,
+    error::ReportableError,
+    metadata::{FileId, Span},
+    suggestion,
+};
 use crate::{function, integer, numeric, unit};
 use itertools::Itertools;
 use std::cell::RefCell;
@@ -11,36 +16,114 @@ use std::collections::BTreeMap;
 use std::fmt;
 use std::rc::Rc;
 
-//todo: span for 2 different locations
 #[derive(Clone, Debug, PartialEq)]
 pub enum ErrorKind {
-    TypeMismatch(Type, Type),
+    // the expected type, the actual one, and the type node located where the expected type came from.
+    TypeMismatch(Type, Type, Option<TypeNodeId>),
     PatternMismatch(Type, Pattern),
     NonFunctionForLetRec(Type),
     NonFunctionForApply(Type),
     CircularType,
     IndexOutOfRange(u16, u16),
     IndexForNonTuple,
-    VariableNotFound(String),
+    FieldNotExist(Symbol, Type),
+    FieldForNonStruct(Type),
+    NonNumericForOperator(Symbol, Type),
+    // the name, the similar names which can be suggested, and the plugin not loaded which gives
+    // the name.
+    VariableNotFound(String, Vec<String>, Option<Symbol>),
+    TypeNotFound(Symbol),
+    VariantNotFound(Symbol),
+    VariantArityMismatch(Symbol, usize, usize),
+    // the name of the type, and the location of its previous declaration.
+    DuplicateType(Symbol, Span),
+    // the name of the variant, the sum type which already declares it, and the location of it.
+    DuplicateVariant(Symbol, Symbol, Span),
+    NonExhaustiveMatch(Vec<Symbol>),
     NonPrimitiveInFeed,
+    AssignToArrayElem,
 }
 #[derive(Clone, Debug, PartialEq)]
 pub struct Error(pub ErrorKind, pub Span);
 
+impl ErrorKind {
+    // the types in the message, printed with the type variables named in the same order as
+    // the message so that the labels and the notes can refer to them.
+    fn types_for_error(&self) -> (Vec<String>, TypeVarNames) {
+        let mut names = TypeVarNames::default();
+        let types = match self {
+            ErrorKind::TypeMismatch(e, a, _) => vec![e, a],
+            ErrorKind::PatternMismatch(t, _)
+            | ErrorKind::NonFunctionForLetRec(t)
+            | ErrorKind::NonFunctionForApply(t)
+            | ErrorKind::FieldNotExist(_, t)
+            | ErrorKind::FieldForNonStruct(t)
+            | ErrorKind::NonNumericForOperator(_, t) => vec![t],
+            _ => vec![],
+        };
+        let types = types
+            .into_iter()
+            .map(|t| t.to_string_for_error(&mut names))
+            .collect();
+        (types, names)
+    }
+    /// The locations related to the error other than the one where it happened.
+    pub fn secondary_labels(&self) -> Vec<(Span, String)> {
+        match self {
+            ErrorKind::TypeMismatch(_, _, Some(origin)) => {
+                let (types, _) = self.types_for_error();
+                vec![(
+                    origin.to_span(),
+                    format!("{} is expected because of this", types[0]),
+                )]
+            }
+            ErrorKind::DuplicateType(name, prev) => {
+                vec![(prev.clone(), format!("{name} is first declared here"))]
+            }
+            ErrorKind::DuplicateVariant(name, ty, prev) => {
+                vec![(
+                    prev.clone(),
+                    format!("{name} is already declared by {ty} here"),
+                )]
+            }
+            _ => vec![],
+        }
+    }
+    pub fn notes(&self) -> Vec<String> {
+        if let ErrorKind::VariableNotFound(name, similar, plugin) = self {
+            let plugin_hint = plugin.map(|plugin| {
+                format!("{name} is provided by {plugin}, which is not loaded in this environment.")
+            });
+            let suggestion = (!similar.is_empty()).then(|| {
+                let names = similar.iter().map(|s| format!("`{s}`")).join(", ");
+                format!("did you mean {names}?")
+            });
+            return plugin_hint.into_iter().chain(suggestion).collect();
+        }
+        let (_, names) = self.types_for_error();
+        match names.names().as_slice() {
+            [] => vec![],
+            [var] => vec![format!(
+                "{var} is a type variable, which stands for a type not inferred yet."
+            )],
+            vars => vec![format!(
+                "{} are type variables, which stand for the types not inferred yet.",
+                vars.join(", ")
+            )],
+        }
+    }
+}
+
 impl fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (types, _) = self.types_for_error();
         match &self {
-            ErrorKind::TypeMismatch(e, a) => write!(
-                f,
-                "Type Mismatch, between {} and {}",
-                e.to_string_for_error(),
-                a.to_string_for_error()
-            ),
-            ErrorKind::PatternMismatch(e, p) => write!(
-                f,
-                "Pattern {p} cannot have {} type.",
-                e.to_string_for_error()
-            ),
+            ErrorKind::TypeMismatch(..) => {
+                write!(f, "Type Mismatch, between {} and {}", types[0], types[1])
+            }
+            ErrorKind::PatternMismatch(_, p) => {
+                write!(f, "Pattern {p} cannot have {} type.", types[0])
+            }
 
             ErrorKind::CircularType => write!(f, "Circular loop of type definition"),
             ErrorKind::IndexOutOfRange(len, idx) => write!(
@@ -49,21 +132,60 @@ impl fmt::Display for ErrorKind {
                 len, idx
             ),
             ErrorKind::IndexForNonTuple => write!(f, "Index access for non-tuple variable"),
-            ErrorKind::VariableNotFound(v) => {
+            ErrorKind::FieldNotExist(name, _) => {
+                write!(f, "Field \"{name}\" does not exist in {} type", types[0])
+            }
+            ErrorKind::FieldForNonStruct(_) => write!(
+                f,
+                "Field access for {} type, which is not a struct (or its type is not known yet)",
+                types[0]
+            ),
+            ErrorKind::NonNumericForOperator(name, _) => write!(
+                f,
+                "\"{name}\" can be applied only to int or float, but it was {} type.",
+                types[0]
+            ),
+            ErrorKind::VariableNotFound(v, _, _) => {
                 write!(f, "Variable {} not found in this scope", v)
             }
+            ErrorKind::TypeNotFound(name) => {
+                write!(f, "Type {name} is not declared")
+            }
+            ErrorKind::VariantNotFound(name) => {
+                write!(f, "Variant {name} is not declared")
+            }
+            ErrorKind::VariantArityMismatch(name, expected, actual) => write!(
+                f,
+                "Variant {name} has {expected} field(s) but {actual} were given"
+            ),
+            ErrorKind::DuplicateType(name, _) => {
+                write!(f, "Type {name} is declared more than once")
+            }
+            ErrorKind::DuplicateVariant(name, ty, _) => write!(
+                f,
+                "Variant {name} is already declared by the other sum type {ty}"
+            ),
+            ErrorKind::NonExhaustiveMatch(missing) => write!(
+                f,
+                "Match is not exhaustive, variant(s) {} are not covered",
+                missing.iter().join(", ")
+            ),
             ErrorKind::NonPrimitiveInFeed => {
                 write!(f, "Function that uses self cannot be return function type.")
             }
-            ErrorKind::NonFunctionForApply(t) => write!(
+            ErrorKind::AssignToArrayElem => write!(
+                f,
+                "Assignment to an array element is not supported, make a new array instead."
+            ),
+            ErrorKind::NonFunctionForApply(_) => write!(
                 f,
                 "{} is not applicable because it is not a function type.",
-                t.to_string_for_error()
+                types[0]
             ),
-            ErrorKind::NonFunctionForLetRec(t) => write!(
+            ErrorKind::NonFunctionForLetRec(_) => write!(
                 f,
                 "\"letrec\" requires the expression to be function type but it was {} type.",
-                t.to_string_for_error()
+                types[0]
             ),
         }
     }
@@ -75,9 +197,15 @@ impl fmt::Display for Error {
 }
 impl std::error::Error for Error {}
 impl ReportableError for Error {
-    fn get_span(&self) -> std::ops::Range<usize> {
+    fn get_span(&self) -> Span {
         self.1.clone()
     }
+    fn get_secondary_labels(&self) -> Vec<(Span, String)> {
+        self.0.secondary_labels()
+    }
+    fn get_notes(&self) -> Vec<String> {
+        self.0.notes()
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -90,10 +218,24 @@ pub struct InferContext {
     generalize_map: BTreeMap<u64, u64>,
     instantiate_map: BTreeMap<u64, u64>,
     result_map: BTreeMap<ExprKey, TypeNodeId>,
+    // the types of the names at their binding sites, i.e. the patterns of `let`, the names of
+    // the recursive functions and the parameters, which are not expressions.
+    binding_types: Vec<(Span, TypeNodeId)>,
+    // the generalized types bound to the names by `let`, keyed by their bodies.
+    scheme_map: BTreeMap<ExprKey, TypeNodeId>,
+    // types declared with `type` or `type alias`. They can be declared only at the top level.
+    type_decls: BTreeMap<Symbol, TypeNodeId>,
+    // return types of the functions being inferred, used for checking `return`.
+    return_types: Vec<TypeNodeId>,
+    // the functions given by the plugins which are not loaded, with the names of the plugins.
+    unloaded_fns: Vec<(Symbol, Symbol)>,
+    // the field accesses whose receivers are not known to be structs yet, with the types of the
+    // receivers and the fields. They are checked once the receivers are resolved.
+    field_constraints: Vec<(TypeNodeId, Symbol, TypeNodeId, Span, Span)>,
     pub env: Environment<TypeNodeId>, // interm_map:HashMap<i64,Type>
 }
 impl InferContext {
-    fn new(builtins: &[(Symbol, TypeNodeId)]) -> Self {
+    fn new(builtins: &[(Symbol, TypeNodeId)], unloaded_fns: &[(Symbol, Symbol)]) -> Self {
         let mut res = Self {
             interm_idx: 0,
             typescheme_idx: 0,
@@ -103,6 +245,12 @@ impl InferContext {
             generalize_map: Default::default(),
             instantiate_map: Default::default(),
             result_map: Default::default(),
+            binding_types: vec![],
+            scheme_map: Default::default(),
+            type_decls: Default::default(),
+            return_types: vec![],
+            unloaded_fns: unloaded_fns.to_vec(),
+            field_constraints: vec![],
             env: Environment::<TypeNodeId>::new(),
         };
         res.env.extend();
@@ -112,7 +260,8 @@ impl InferContext {
     }
 }
 impl InferContext {
-    fn intrinsic_types() -> Vec<(Symbol, TypeNodeId)> {
+    /// The types of the intrinsic functions, which are available without any plugins.
+    pub fn intrinsic_types() -> Vec<(Symbol, TypeNodeId)> {
         let binop_ty = function!(vec![numeric!(), numeric!()], numeric!());
         let binop_names = [
             intrinsics::ADD,
@@ -149,6 +298,10 @@ impl InferContext {
                 intrinsics::TOFLOAT.to_symbol(),
                 function!(vec![integer!()], numeric!()),
             ),
+            (
+                intrinsics::TOINT.to_symbol(),
+                function!(vec![numeric!()], integer!()),
+            ),
         ]
         .into_iter()
         .chain(binds)
@@ -192,21 +345,174 @@ impl InferContext {
     fn convert_unknown_to_intermediate(&mut self, t: TypeNodeId) -> TypeNodeId {
         match t.to_type() {
             Type::Unknown => self.gen_intermediate_type(),
-            _ => t,
+            _ => t.apply_fn(|t| self.convert_unknown_to_intermediate(t)),
+        }
+    }
+    // replace the type names in the annotation with the declared types.
+    fn resolve_type_names(&self, t: TypeNodeId) -> Result<TypeNodeId, Error> {
+        match t.to_type() {
+            Type::TypeName(name) => self
+                .type_decls
+                .get(&name)
+                .copied()
+                .ok_or_else(|| Error(ErrorKind::TypeNotFound(name), t.to_span())),
+            Type::Alias(_, _) | Type::Named(_, _) => Ok(t),
+            _ => {
+                let mut err = None;
+                let res = t.apply_fn(|t| {
+                    self.resolve_type_names(t).unwrap_or_else(|e| {
+                        err.get_or_insert(e);
+                        t
+                    })
+                });
+                err.map_or(Ok(res), Err)
+            }
         }
     }
+    fn convert_annotation(&mut self, t: TypeNodeId) -> Result<TypeNodeId, Error> {
+        let t = self.resolve_type_names(t)?;
+        Ok(self.convert_unknown_to_intermediate(t))
+    }
     fn convert_unknown_function(
         &mut self,
         atypes: &[TypeNodeId],
         rty: TypeNodeId,
         s: Option<TypeNodeId>,
-    ) -> TypeNodeId {
+    ) -> Result<TypeNodeId, Error> {
         let a = atypes
             .iter()
-            .map(|a| self.convert_unknown_to_intermediate(*a))
-            .collect();
-        let r = self.convert_unknown_to_intermediate(rty);
-        Type::Function(a, r, s).into_id()
+            .map(|a| self.convert_annotation(*a))
+            .try_collect()?;
+        let r = self.convert_annotation(rty)?;
+        Ok(Type::Function(a, r, s).into_id())
+    }
+    // returns the nominal type and its definition when `fun` is the name of a nominal type.
+    fn lookup_constructor(&self, fun: ExprNodeId) -> Option<(TypeNodeId, TypeNodeId)> {
+        let Expr::Var(name) = fun.to_expr() else {
+            return None;
+        };
+        let named = *self.type_decls.get(&name)?;
+        match named.to_type() {
+            Type::Named(_, def) => Some((named, def)),
+            _ => None,
+        }
+    }
+    /// The declared type name is used as a constructor of the nominal type, like `Meter(1.0)`.
+    pub fn is_type_constructor(&self, fun: ExprNodeId) -> bool {
+        self.lookup_constructor(fun).is_some()
+    }
+    // returns the sum type which declares the variant, with the types of its fields.
+    fn lookup_variant(&self, name: Symbol) -> Option<(TypeNodeId, Vec<TypeNodeId>)> {
+        self.type_decls
+            .values()
+            .find_map(|named| match named.to_type() {
+                Type::Named(_, def) => def
+                    .to_type()
+                    .get_variant(name)
+                    .map(|(_, fields)| (*named, fields.to_vec())),
+                _ => None,
+            })
+    }
+    fn is_variant(&self, name: Symbol) -> bool {
+        self.lookup_variant(name).is_some()
+    }
+    /// The variant name of the declared sum type is used as its constructor, like `Saw(440.0)`.
+    /// It has a priority over the variables with the same name.
+    pub fn is_variant_constructor(&self, fun: ExprNodeId) -> bool {
+        matches!(fun.to_expr(), Expr::Var(name) if self.is_variant(name))
+    }
+    fn infer_type_variant(
+        &mut self,
+        name: Symbol,
+        args: &[ExprNodeId],
+        span: Span,
+    ) -> Result<TypeNodeId, Error> {
+        let (named, fields) = self.lookup_variant(name).unwrap();
+        if fields.len() != args.len() {
+            return Err(Error(
+                ErrorKind::VariantArityMismatch(name, fields.len(), args.len()),
+                span,
+            ));
+        }
+        for (field, arg) in fields.iter().zip(args.iter()) {
+            let t = self.infer_type(*arg)?;
+            Self::unify_types(*field, t, arg.to_span())?;
+        }
+        Ok(named)
+    }
+    // A pattern of `match` arm is a variant pattern when it is `Name(..)` or a single name of
+    // the declared variant. Otherwise, it matches to any value.
+    fn get_variant_pattern(&self, pat: &Pattern) -> Option<(Symbol, Vec<Pattern>)> {
+        match pat {
+            Pattern::Variant(name, fields) => Some((*name, fields.clone())),
+            Pattern::Single(name) if self.is_variant(*name) => Some((*name, vec![])),
+            _ => None,
+        }
+    }
+    fn infer_type_match(
+        &mut self,
+        scrutinee: ExprNodeId,
+        arms: &[(TypedPattern, ExprNodeId)],
+        span: Span,
+    ) -> Result<TypeNodeId, Error> {
+        let st = self.infer_type(scrutinee)?;
+        let res_t = self.gen_intermediate_type();
+        let mut covered = vec![];
+        let mut has_catch_all = false;
+        for (tpat, body) in arms.iter() {
+            let pat_span = tpat.to_span();
+            self.env.extend();
+            match self.get_variant_pattern(&tpat.pat) {
+                Some((name, pats)) => {
+                    let (named, fields) = self
+                        .lookup_variant(name)
+                        .ok_or_else(|| Error(ErrorKind::VariantNotFound(name), pat_span.clone()))?;
+                    Self::unify_types(named, st, pat_span.clone())?;
+                    if fields.len() != pats.len() {
+                        return Err(Error(
+                            ErrorKind::VariantArityMismatch(name, fields.len(), pats.len()),
+                            pat_span,
+                        ));
+                    }
+                    for (pat, field) in pats.iter().zip(fields.iter()) {
+                        let p = TypedPattern {
+                            pat: pat.clone(),
+                            ty: Type::Unknown.into_id_with_span(pat_span.clone()),
+                        };
+                        self.bind_pattern(*field, &p, pat_span.clone())?;
+                    }
+                    covered.push(name);
+                }
+                None => {
+                    self.bind_pattern(st, tpat, pat_span)?;
+                    has_catch_all = true;
+                }
+            }
+            let bt = self.infer_type(*body)?;
+            Self::unify_types(res_t, bt, body.to_span())?;
+            self.env.to_outer();
+        }
+        if !has_catch_all {
+            if let Type::Union(variants) = Self::strip_type_name(st).to_type() {
+                let missing = variants
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .filter(|name| !covered.contains(name))
+                    .collect::<Vec<_>>();
+                if !missing.is_empty() {
+                    return Err(Error(ErrorKind::NonExhaustiveMatch(missing), span));
+                }
+            }
+        }
+        Ok(res_t)
+    }
+    // remove the names of the declared types at the outermost of the type.
+    fn strip_type_name(t: TypeNodeId) -> TypeNodeId {
+        let t = t.get_root();
+        match t.to_type() {
+            Type::Alias(_, def) | Type::Named(_, def) => Self::strip_type_name(def),
+            _ => t,
+        }
     }
     // return true when the circular loop of intermediate variable exists.
     fn occur_check(id1: u64, t2: TypeNodeId) -> bool {
@@ -229,7 +535,9 @@ impl InferContext {
                     && cls(*r)
                     && cls(s.map(|x| x).unwrap_or_else(|| Type::Unknown.into_id()))
             }
-            Type::Struct(_s) => todo!(),
+            Type::Struct(s) => s.iter().any(|(_, t)| cls(*t)),
+            Type::Union(v) => v.iter().any(|(_, fields)| vec_cls(fields)),
+            Type::Alias(_, t) | Type::Named(_, t) | Type::Code(t) => cls(*t),
             _ => false,
         }
     }
@@ -243,6 +551,8 @@ impl InferContext {
                     None => t,
                 }
             }
+            // the later stages do not distinguish the declared types from their definitions.
+            Type::Alias(_, def) | Type::Named(_, def) => Self::substitute_type(def),
             _ => t.apply_fn(Self::substitute_type),
         }
     }
@@ -256,14 +566,36 @@ impl InferContext {
         e_list.iter_mut().for_each(|(e, t)| {
             log::trace!("e: {:?} t: {}", e, t.to_type());
             let _old = self.result_map.insert(*e, *t);
-        })
+        });
+        self.scheme_map
+            .values_mut()
+            .for_each(|t| *t = Self::substitute_type(*t));
+        self.binding_types
+            .iter_mut()
+            .for_each(|(_, t)| *t = Self::substitute_type(*t));
     }
 
+    // `t1` is the expected type, and `t2` is the actual one.
     fn unify_types(t1: TypeNodeId, t2: TypeNodeId, span: Span) -> Result<TypeNodeId, Error> {
+        let res =
+            Self::unify_types_in(t1, t2, span).map_err(|e| Self::mismatch_with_origin(e, t1))?;
+        // the unified type inherits the location of the expected type, e.g. the definition of
+        // the function, so that the later mismatches can point to it. The existing type is
+        // returned as it is if it is the expected one or already located.
+        let has_span =
+            with_session_globals(|session_globals| session_globals.get_span(res).is_some());
+        let origin = t1.to_span();
+        if res == t1 || has_span || origin.file == FileId::DUMMY {
+            Ok(res)
+        } else {
+            Ok(res.to_type().into_id_with_span(origin))
+        }
+    }
+    fn unify_types_in(t1: TypeNodeId, t2: TypeNodeId, span: Span) -> Result<TypeNodeId, Error> {
         let unify_vec = |a1: &[TypeNodeId], a2: &[TypeNodeId]| -> Result<Vec<_>, Error> {
             if a1.len() != a2.len() {
                 return Err(Error(
-                    ErrorKind::TypeMismatch(t1.to_type(), t2.to_type()),
+                    ErrorKind::TypeMismatch(t1.to_type(), t2.to_type(), None),
                     span.clone(),
                 ));
             }
@@ -320,47 +652,185 @@ impl InferContext {
             }
             (t1, Type::Instantiated(_)) => Ok(t1.clone().into_id_with_span(span)),
             (Type::Instantiated(_), t2) => Ok(t2.clone().into_id_with_span(span)),
+            // report the mismatch with the alias name rather than its definition.
+            (Type::Alias(_, a1), _) => Self::unify_types(*a1, t2r, span.clone())
+                .map_err(|e| Self::mismatch_of_alias(e, t1r, t2r)),
+            (_, Type::Alias(_, a2)) => Self::unify_types(t1r, *a2, span.clone())
+                .map_err(|e| Self::mismatch_of_alias(e, t1r, t2r)),
+            (Type::Named(n1, _), Type::Named(n2, _)) if n1 == n2 => Ok(t1r),
+            // the expected type is reused as it is if unifying its elements did not change them.
             (Type::Array(a1), Type::Array(a2)) => {
-                Ok(Type::Array(Self::unify_types(*a1, *a2, span)?).into_id())
+                let a = Self::unify_types(*a1, *a2, span)?;
+                Ok(if a == *a1 {
+                    t1r
+                } else {
+                    Type::Array(a).into_id()
+                })
             }
             (Type::Ref(x1), Type::Ref(x2)) => {
-                Ok(Type::Ref(Self::unify_types(*x1, *x2, span)?).into_id())
+                let x = Self::unify_types(*x1, *x2, span)?;
+                Ok(if x == *x1 {
+                    t1r
+                } else {
+                    Type::Ref(x).into_id()
+                })
             }
             (Type::Tuple(a1), Type::Tuple(a2)) => {
-                Ok(Type::Tuple(unify_vec(a1, a2)?).into_id_with_span(span))
+                let v = unify_vec(a1, a2)?;
+                Ok(if v == *a1 {
+                    t1r
+                } else {
+                    Type::Tuple(v).into_id_with_span(span)
+                })
+            }
+            (Type::Struct(a1), Type::Struct(a2)) => {
+                // fields are sorted by name beforehand, so they can be compared one by one.
+                let names_matched = a1.len() == a2.len()
+                    && a1.iter().zip(a2.iter()).all(|((n1, _), (n2, _))| n1 == n2);
+                if !names_matched {
+                    return Err(Error(
+                        ErrorKind::TypeMismatch(t1.to_type(), t2.to_type(), None),
+                        span,
+                    ));
+                }
+                let types1 = a1.iter().map(|(_, t)| *t).collect::<Vec<_>>();
+                let types = unify_vec(&types1, &a2.iter().map(|(_, t)| *t).collect::<Vec<_>>())?;
+                if types == types1 {
+                    return Ok(t1r);
+                }
+                let fields = a1.iter().map(|(n, _)| *n).zip(types).collect();
+                Ok(Type::Struct(fields).into_id_with_span(span))
             }
-            (Type::Struct(_a1), Type::Struct(_a2)) => todo!(), //todo
-            (Type::Function(p1, r1, s1), Type::Function(p2, r2, s2)) => Ok(Type::Function(
-                unify_vec(p1, p2)?,
-                Self::unify_types(*r1, *r2, span.clone())?,
-                match (s1, s2) {
+            (Type::Function(p1, r1, s1), Type::Function(p2, r2, s2)) => {
+                let p = unify_vec(p1, p2)?;
+                let r = Self::unify_types(*r1, *r2, span.clone())?;
+                let s = match (s1, s2) {
                     (Some(e1), Some(e2)) => Some(Self::unify_types(*e1, *e2, span)?),
                     (None, None) => None,
                     (_, _) => todo!("error handling"),
-                },
-            )
-            .into_id()),
-            (Type::Primitive(p1), Type::Primitive(p2)) if p1 == p2 => {
-                Ok(Type::Primitive(p1.clone()).into_id())
+                };
+                Ok(if p == *p1 && r == *r1 && s == *s1 {
+                    t1r
+                } else {
+                    Type::Function(p, r, s).into_id()
+                })
             }
+            (Type::Primitive(p1), Type::Primitive(p2)) if p1 == p2 => Ok(t1r),
 
-            (Type::Code(_p1), Type::Code(_p2)) => {
-                todo!("type system for multi-stage computation has not implemented yet")
+            (Type::Code(p1), Type::Code(p2)) => {
+                let p = Self::unify_types(*p1, *p2, span)?;
+                Ok(if p == *p1 {
+                    t1r
+                } else {
+                    Type::Code(p).into_id()
+                })
             }
-            (p1, p2) => Err(Error(ErrorKind::TypeMismatch(p1.clone(), p2.clone()), span)),
+            (p1, p2) => Err(Error(
+                ErrorKind::TypeMismatch(p1.clone(), p2.clone(), None),
+                span,
+            )),
+        }
+    }
+    fn mismatch_of_alias(e: Error, t1: TypeNodeId, t2: TypeNodeId) -> Error {
+        match e {
+            Error(ErrorKind::TypeMismatch(_, _, origin), span) => Error(
+                ErrorKind::TypeMismatch(t1.to_type(), t2.to_type(), origin),
+                span,
+            ),
+            e => e,
+        }
+    }
+    // attaches the location of the expected type to the mismatch, unless the inner types
+    // already gave the more specific one.
+    fn mismatch_with_origin(e: Error, expected: TypeNodeId) -> Error {
+        match e {
+            Error(ErrorKind::TypeMismatch(t1, t2, None), span) => {
+                let origin = [expected.get_root(), expected].into_iter().find(|t| {
+                    let s = t.to_span();
+                    s.file != FileId::DUMMY && s != span
+                });
+                Error(ErrorKind::TypeMismatch(t1, t2, origin), span)
+            }
+            e => e,
         }
     }
     fn generalize(&mut self, t: TypeNodeId) -> TypeNodeId {
+        let mut v_g_map = BTreeMap::<u64, TypeNodeId>::default();
+        self.generalize_in(t, &mut v_g_map)
+    }
+    // the same type variable is generalized into the same scheme variable within a type.
+    fn generalize_in(
+        &mut self,
+        t: TypeNodeId,
+        v_g_map: &mut BTreeMap<u64, TypeNodeId>,
+    ) -> TypeNodeId {
         match t.to_type() {
             Type::Intermediate(tvar) => {
-                let &TypeVar { level, var, .. } = &tvar.borrow() as _;
-                if level > self.level {
-                    self.get_typescheme(var)
+                let &TypeVar {
+                    level, var, parent, ..
+                } = &tvar.borrow() as _;
+                if let Some(p) = parent {
+                    // already resolved type variable is not a subject of generalization.
+                    self.generalize_in(p, v_g_map)
+                } else if level > self.level && !self.is_field_constrained(var) {
+                    match v_g_map.get(&var) {
+                        Some(g) => *g,
+                        None => {
+                            let g = self.get_typescheme(var);
+                            v_g_map.insert(var, g);
+                            g
+                        }
+                    }
                 } else {
                     t
                 }
             }
-            _ => t.apply_fn(|t| self.generalize(t)),
+            _ => t.apply_fn(|t| self.generalize_in(t, v_g_map)),
+        }
+    }
+    // a type variable used in a pending field access is kept monomorphic, otherwise the
+    // instances would not be related to the field once the receiver is resolved.
+    fn is_field_constrained(&self, var: u64) -> bool {
+        self.field_constraints
+            .iter()
+            .any(|(recv, _, ft, ..)| Self::occur_check(var, *recv) || Self::occur_check(var, *ft))
+    }
+    // checks the pending field accesses whose receivers have been resolved. With `finish`, the
+    // receivers which are still unknown are reported as errors.
+    fn resolve_field_constraints(&mut self, finish: bool) -> Result<(), Error> {
+        loop {
+            let pending = std::mem::take(&mut self.field_constraints);
+            let len = pending.len();
+            for (recv, name, ft, recv_span, span) in pending {
+                match Self::substitute_type(recv).to_type() {
+                    Type::Struct(fields) => {
+                        let t = fields
+                            .iter()
+                            .find_map(|(n, t)| (*n == name).then_some(*t))
+                            .ok_or_else(|| {
+                                Error(
+                                    ErrorKind::FieldNotExist(name, Type::Struct(fields.clone())),
+                                    span.clone(),
+                                )
+                            })?;
+                        Self::unify_types(t, ft, span)?;
+                    }
+                    Type::Intermediate(_) => self
+                        .field_constraints
+                        .push((recv, name, ft, recv_span, span)),
+                    t => return Err(Error(ErrorKind::FieldForNonStruct(t), recv_span)),
+                }
+            }
+            if self.field_constraints.len() == len {
+                break;
+            }
+        }
+        match self.field_constraints.first() {
+            Some((recv, _, _, recv_span, _)) if finish => Err(Error(
+                ErrorKind::FieldForNonStruct(recv.to_type()),
+                recv_span.clone(),
+            )),
+            _ => Ok(()),
         }
     }
     fn instantiate(&mut self, t: TypeNodeId) -> TypeNodeId {
@@ -392,6 +862,11 @@ impl InferContext {
         span: Span,
     ) -> Result<TypeNodeId, Error> {
         let TypedPattern { pat, .. } = ty_pat;
+        // values of the declared types can be destructured as same as their definitions.
+        let t = match pat {
+            Pattern::Single(_) => t,
+            _ => Self::strip_type_name(t),
+        };
         let pat_t = match pat {
             Pattern::Single(id) => {
                 let gt = self.generalize(t);
@@ -412,6 +887,55 @@ impl InferContext {
                     .try_collect()?;
                 Ok(Type::Tuple(res).into_id())
             }
+            Pattern::Record(fields) => {
+                // When the type of the value is already known, the pattern may
+                // pick up only a part of the fields.
+                let known = Self::substitute_type(t);
+                let field_types = match known.to_type() {
+                    Type::Struct(tfields) => fields
+                        .iter()
+                        .map(|(name, _)| {
+                            tfields
+                                .iter()
+                                .find(|(n, _)| n == name)
+                                .map(|(_, t)| *t)
+                                .ok_or_else(|| {
+                                    Error(
+                                        ErrorKind::FieldNotExist(*name, known.to_type()),
+                                        span.clone(),
+                                    )
+                                })
+                        })
+                        .try_collect()?,
+                    _ => fields
+                        .iter()
+                        .map(|_| self.gen_intermediate_type_with_span(ty_pat.to_span()))
+                        .collect::<Vec<_>>(),
+                };
+                let mut res = fields
+                    .iter()
+                    .zip(field_types)
+                    .map(|((name, p), ity)| {
+                        let p = TypedPattern {
+                            pat: p.clone(),
+                            ty: ity,
+                        };
+                        self.bind_pattern(ity, &p, span.clone()).map(|t| (*name, t))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                match known.to_type() {
+                    Type::Struct(_) => Ok(known),
+                    _ => {
+                        sort_fields(&mut res);
+                        Ok(Type::Struct(res).into_id())
+                    }
+                }
+            }
+            // variant patterns are checked in `infer_type_match`.
+            Pattern::Variant(_, _) => Err(Error(
+                ErrorKind::PatternMismatch(t.to_type(), pat.clone()),
+                span.clone(),
+            )),
         }?;
         Self::unify_types(t, pat_t, span)
     }
@@ -419,14 +943,44 @@ impl InferContext {
     pub fn lookup(&self, name: &Symbol, span: &Span) -> Result<TypeNodeId, Error> {
         self.env.lookup(name).map_or_else(
             || {
+                let plugin = self
+                    .unloaded_fns
+                    .iter()
+                    .find_map(|(f, plugin)| (f == name).then_some(*plugin));
                 Err(Error(
-                    ErrorKind::VariableNotFound(name.to_string()),
+                    ErrorKind::VariableNotFound(
+                        name.to_string(),
+                        self.similar_names(*name),
+                        plugin,
+                    ),
                     span.clone(),
                 ))
             }, //todo:Span
             |v| Ok(*v),
         )
     }
+    // the names in the scope and the builtins which may be the one intended by `name`.
+    // The definitions in the modules are also suggested when their last part matches.
+    fn similar_names(&self, name: Symbol) -> Vec<String> {
+        let builtins = intrinsics::BUILTIN_SYMS.with(|syms| (*syms).clone());
+        let candidates = self
+            .env
+            .names()
+            .chain(builtins.iter())
+            .map(|s| s.as_str())
+            .unique()
+            .collect::<Vec<_>>();
+        let qualified = candidates
+            .iter()
+            .filter(|c| c.rsplit("::").next() == Some(name.as_str()) && **c != name.as_str());
+        let similar = suggestion::similar_names(name.as_str(), candidates.iter().copied());
+        qualified
+            .copied()
+            .chain(similar)
+            .unique()
+            .map(|s| s.to_string())
+            .collect()
+    }
     pub(crate) fn infer_type_literal(e: &Literal) -> Result<TypeNodeId, Error> {
         let pt = match e {
             Literal::Float(_s) => PType::Numeric,
@@ -438,6 +992,83 @@ impl InferContext {
         };
         Ok(Type::Primitive(pt).into_id())
     }
+    fn is_overloaded_operator(fun: ExprNodeId, nargs: usize) -> bool {
+        let name = match fun.to_expr() {
+            Expr::Var(name) => name,
+            _ => return false,
+        };
+        match name.as_str() {
+            intrinsics::NEG | intrinsics::ABS => nargs == 1,
+            intrinsics::ADD
+            | intrinsics::SUB
+            | intrinsics::MULT
+            | intrinsics::DIV
+            | intrinsics::MODULO
+            | intrinsics::POW
+            | intrinsics::GT
+            | intrinsics::LT
+            | intrinsics::GE
+            | intrinsics::LE
+            | intrinsics::EQ
+            | intrinsics::NE => nargs == 2,
+            _ => false,
+        }
+    }
+    // Arithmetic and comparison operators are shared between int and float.
+    // Because functions are not monomorphized, the type of operands must be decided
+    // at the point of the application: if it is not known yet, it defaults to float.
+    fn infer_type_operator(
+        &mut self,
+        fun: ExprNodeId,
+        args: &[ExprNodeId],
+        span: Span,
+    ) -> Result<TypeNodeId, Error> {
+        let name = match fun.to_expr() {
+            Expr::Var(name) => name,
+            _ => unreachable!(),
+        };
+        let arg_types = self.infer_vec(args)?;
+        let operand_t = arg_types.iter().skip(1).try_fold(arg_types[0], |acc, t| {
+            Self::unify_types(acc, *t, span.clone())
+        })?;
+        let operand_t = match Self::substitute_type(operand_t).to_type() {
+            Type::Primitive(PType::Int) => integer!(),
+            Type::Primitive(PType::Numeric) => numeric!(),
+            Type::Intermediate(_) => Self::unify_types(numeric!(), operand_t, span.clone())?,
+            t => {
+                return Err(Error(
+                    ErrorKind::NonNumericForOperator(name, t),
+                    args[0].to_span(),
+                ))
+            }
+        };
+        let ret_t = match name.as_str() {
+            intrinsics::GT
+            | intrinsics::LT
+            | intrinsics::GE
+            | intrinsics::LE
+            | intrinsics::EQ
+            | intrinsics::NE => numeric!(),
+            _ => operand_t,
+        };
+        let fn_t = Type::Function(vec![operand_t; args.len()], ret_t, None).into_id();
+        self.result_map.insert(fun.0, fn_t);
+        Ok(ret_t)
+    }
+    fn infer_type_constructor(
+        &mut self,
+        fun: ExprNodeId,
+        args: &[ExprNodeId],
+        span: Span,
+    ) -> Result<TypeNodeId, Error> {
+        let (named, def) = self.lookup_constructor(fun).unwrap();
+        let ctor_t = Type::Function(vec![def], named, None).into_id();
+        let args_t = self.infer_vec(args)?;
+        let fntype = Type::Function(args_t, self.gen_intermediate_type(), None).into_id();
+        Self::unify_types(ctor_t, fntype, span)?;
+        self.result_map.insert(fun.0, ctor_t);
+        Ok(named)
+    }
     fn infer_vec(&mut self, e: &[ExprNodeId]) -> Result<Vec<TypeNodeId>, Error> {
         e.iter().map(|e| self.infer_type(*e)).try_collect()
     }
@@ -450,7 +1081,10 @@ impl InferContext {
     fn infer_type(&mut self, e: ExprNodeId) -> Result<TypeNodeId, Error> {
         let span = e.to_span().clone();
         let res = match &e.to_expr() {
-            Expr::Literal(l) => Self::infer_type_literal(l),
+            // the type remembers the literal so that the mismatch can point to it.
+            Expr::Literal(l) => {
+                Self::infer_type_literal(l).map(|t| t.to_type().into_id_with_span(span.clone()))
+            }
             Expr::Tuple(e) => Ok(Type::Tuple(self.infer_vec(e.as_slice())?).into_id()),
             Expr::Proj(e, idx) => {
                 let tup = self.infer_type(*e)?;
@@ -468,6 +1102,50 @@ impl InferContext {
                     _ => Err(Error(ErrorKind::IndexForNonTuple, e.to_span().clone())),
                 }
             }
+            Expr::RecordLiteral(fields) => {
+                let mut res = fields
+                    .iter()
+                    .map(|(name, e)| self.infer_type(*e).map(|t| (*name, t)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                sort_fields(&mut res);
+                Ok(Type::Struct(res).into_id())
+            }
+            Expr::FieldAccess(e, name) => {
+                let et = self.infer_type(*e)?;
+                let recv = Self::substitute_type(et);
+                let et = recv.to_type();
+                match &et {
+                    Type::Struct(fields) => fields
+                        .iter()
+                        .find_map(|(n, t)| (n == name).then_some(*t))
+                        .ok_or_else(|| Error(ErrorKind::FieldNotExist(*name, et.clone()), span)),
+                    // the receiver may be a parameter without annotation, whose type is known
+                    // only after its uses.
+                    Type::Intermediate(_) => {
+                        let ft = self.gen_intermediate_type();
+                        self.field_constraints
+                            .push((recv, *name, ft, e.to_span(), span));
+                        Ok(ft)
+                    }
+                    _ => Err(Error(ErrorKind::FieldForNonStruct(et.clone()), e.to_span())),
+                }
+            }
+            Expr::ArrayLiteral(items) => {
+                let elem_t = self.gen_intermediate_type();
+                for item in items.iter() {
+                    let t = self.infer_type(*item)?;
+                    Self::unify_types(elem_t, t, item.to_span())?;
+                }
+                Ok(Type::Array(elem_t).into_id_with_span(span.clone()))
+            }
+            Expr::ArrayAccess(arr, idx) => {
+                let elem_t = self.gen_intermediate_type();
+                let arr_t = self.infer_type(*arr)?;
+                Self::unify_types(Type::Array(elem_t).into_id(), arr_t, arr.to_span())?;
+                let idx_t = self.infer_type(*idx)?;
+                Self::unify_types(integer!(), idx_t, idx.to_span())?;
+                Ok(elem_t)
+            }
             Expr::Feed(id, body) => {
                 let feedv = self.gen_intermediate_type();
                 self.env.add_bind(&[(*id, feedv)]);
@@ -485,27 +1163,42 @@ impl InferContext {
                     .iter()
                     .map(|id| {
                         let pt = if !id.is_unknown() {
-                            id.ty
+                            self.convert_annotation(id.ty)?
                         } else {
                             self.gen_intermediate_type()
                         };
                         self.env.add_bind(&[(id.id, pt)]);
-                        pt
+                        self.binding_types.push((id.to_span(), pt));
+                        Ok(pt)
                     })
-                    .collect();
-                let bty = if let Some(r) = rtype {
-                    let bty = self.infer_type(*body)?;
-                    Self::unify_types(*r, bty, body.to_span())?
-                } else {
-                    self.infer_type(*body)?
+                    .try_collect()?;
+                let rty = match rtype {
+                    Some(r) => self.convert_annotation(*r)?,
+                    None => self.gen_intermediate_type(),
                 };
+                self.return_types.push(rty);
+                let bty = self.infer_type(*body);
+                self.return_types.pop();
+                let bty = Self::unify_types(rty, bty?, body.to_span())?;
                 self.env.to_outer();
-                Ok(Type::Function(ptypes, bty, None).into_id())
+                Ok(Type::Function(ptypes, bty, None).into_id_with_span(span.clone()))
             }
             Expr::Let(tpat, body, then) => {
                 let bodyt = self.infer_type_levelup(*body)?;
-
+                let bodyt = if !tpat.is_unknown() {
+                    let annotated = self.convert_annotation(tpat.ty)?;
+                    Self::unify_types(annotated, bodyt, body.to_span())?
+                } else {
+                    bodyt
+                };
+                self.resolve_field_constraints(false)?;
                 let _ = self.bind_pattern(bodyt, tpat, body.to_span())?;
+                self.binding_types.push((tpat.to_span(), bodyt));
+                if let Pattern::Single(name) = &tpat.pat {
+                    if let Some(scheme) = self.env.lookup(name) {
+                        self.scheme_map.insert(body.0, *scheme);
+                    }
+                }
 
                 match then {
                     Some(e) => self.infer_type(*e),
@@ -516,7 +1209,7 @@ impl InferContext {
                 let t = id.ty.to_type();
                 let idt = match (id.is_unknown(), &t) {
                     (false, Type::Function(atypes, rty, s)) => {
-                        self.convert_unknown_function(atypes, *rty, *s)
+                        self.convert_unknown_function(atypes, *rty, *s)?
                     }
                     (false, _) => {
                         return Err(Error(ErrorKind::NonFunctionForLetRec(t.clone()), span))
@@ -524,6 +1217,7 @@ impl InferContext {
                     (true, _) => self.gen_intermediate_type(),
                 };
                 self.env.add_bind(&[(id.id, idt)]);
+                self.binding_types.push((id.to_span(), idt));
                 //polymorphic inference is not allowed in recursive function.
                 let bodyt = self.infer_type_levelup(*body)?;
                 let _ = Self::unify_types(idt, bodyt, body.to_span())?;
@@ -532,11 +1226,47 @@ impl InferContext {
                     None => Ok(Type::Primitive(PType::Unit).into_id()),
                 }
             }
+            Expr::TypeDecl(TypeDecl { name, ty, is_alias }, then) => {
+                if let Some(prev) = self.type_decls.get(name) {
+                    return Err(Error(
+                        ErrorKind::DuplicateType(*name, prev.to_span()),
+                        ty.to_span(),
+                    ));
+                }
+                let def = self.resolve_type_names(*ty)?;
+                // the variants are looked up by their names, so they must be unique among the
+                // sum types.
+                if let (false, Type::Union(variants)) = (*is_alias, def.to_type()) {
+                    let duplicate = variants
+                        .iter()
+                        .find_map(|(v, _)| self.lookup_variant(*v).map(|(named, _)| (*v, named)));
+                    if let Some((v, named)) = duplicate {
+                        let Type::Named(other, _) = named.to_type() else {
+                            unreachable!("variants are declared only by the named types")
+                        };
+                        return Err(Error(
+                            ErrorKind::DuplicateVariant(v, other, named.to_span()),
+                            ty.to_span(),
+                        ));
+                    }
+                }
+                let t = if *is_alias {
+                    Type::Alias(*name, def)
+                } else {
+                    Type::Named(*name, def)
+                };
+                self.type_decls
+                    .insert(*name, t.into_id_with_span(ty.to_span()));
+                match then {
+                    Some(e) => self.infer_type(*e),
+                    None => Ok(unit!()),
+                }
+            }
             Expr::Assign(assignee, expr) => {
                 let name = match assignee.to_expr() {
                     Expr::Var(v) => v,
                     Expr::ArrayAccess(_, _) => {
-                        unimplemented!("Assignment to array is not implemented yet.")
+                        return Err(Error(ErrorKind::AssignToArrayElem, assignee.to_span()))
                     }
                     _ => unreachable!(),
                 };
@@ -549,11 +1279,24 @@ impl InferContext {
                 let _ = self.infer_type(*e)?;
                 then.map_or(Ok(unit!()), |t| self.infer_type(t))
             }
+            Expr::Var(name) if self.is_variant(*name) => self.infer_type_variant(*name, &[], span),
             Expr::Var(name) => {
                 let res = self.lookup(name, &span)?;
                 // log::debug!("{} {} /level{}", name.as_str(), res, self.level);
                 Ok(self.instantiate(res))
             }
+            Expr::Apply(fun, callee) if self.is_variant_constructor(*fun) => {
+                let Expr::Var(name) = fun.to_expr() else {
+                    unreachable!()
+                };
+                self.infer_type_variant(name, callee, span)
+            }
+            Expr::Apply(fun, callee) if self.is_type_constructor(*fun) => {
+                self.infer_type_constructor(*fun, callee, span)
+            }
+            Expr::Apply(fun, callee) if Self::is_overloaded_operator(*fun, callee.len()) => {
+                self.infer_type_operator(*fun, callee, span)
+            }
             Expr::Apply(fun, callee) => {
                 let fnl = self.infer_type(*fun)?;
                 let callee_t = self.infer_vec(callee.as_slice())?;
@@ -581,13 +1324,41 @@ impl InferContext {
                 let elset = opt_else.map_or(Ok(Type::Primitive(PType::Unit).into_id()), |e| {
                     self.infer_type(e)
                 })?;
-                let else_span = opt_else.map_or(span.end..span.end, |e| e.to_span());
+                let else_span = opt_else
+                    .map_or(Span::new(span.file, span.end()..span.end()), |e| {
+                        e.to_span()
+                    });
                 log::trace!("then: {}, else: {}", thent.to_type(), elset.to_type());
                 Self::unify_types(thent, elset, else_span)
             }
+            Expr::Match(scrutinee, arms) => self.infer_type_match(*scrutinee, arms, span),
+            Expr::Return(v) => {
+                let vt = self.infer_type(*v)?;
+                let rty = *self
+                    .return_types
+                    .last()
+                    .expect("return should be inside of the function");
+                Self::unify_types(rty, vt, v.to_span())?;
+                // `return` does not produce a value, so it can be used in place of any type.
+                Ok(self.gen_intermediate_type())
+            }
             Expr::Block(expr) => expr.map_or(Ok(Type::Primitive(PType::Unit).into_id()), |e| {
                 self.infer_type(e)
             }),
+            Expr::Bracket(body) => Ok(Type::Code(self.infer_type(*body)?).into_id()),
+            Expr::Escape(code) => {
+                let code_t = self.infer_type(*code)?;
+                let res_t = self.gen_intermediate_type();
+                let code_t =
+                    Self::unify_types(Type::Code(res_t).into_id(), code_t, code.to_span())?;
+                match code_t.to_type() {
+                    Type::Code(t) => Ok(t),
+                    _ => unreachable!(),
+                }
+            }
+            // the part failed to parse can be any type, so that it does not cause
+            // the other errors around it.
+            Expr::Error => Ok(self.gen_intermediate_type()),
             _ => {
                 // todo!();
                 Ok(Type::Primitive(PType::Unit).into_id())
@@ -600,14 +1371,32 @@ impl InferContext {
     pub fn lookup_res(&self, e: ExprNodeId) -> TypeNodeId {
         *self.result_map.get(&e.0).expect("type inference failed")
     }
+    /// The type bound to the name by the `let` whose body is `e`, which is generalized if it is
+    /// polymorphic. The type of the body is returned for the other expressions.
+    pub fn lookup_scheme(&self, e: ExprNodeId) -> TypeNodeId {
+        self.scheme_map
+            .get(&e.0)
+            .copied()
+            .unwrap_or_else(|| self.lookup_res(e))
+    }
+    /// The types inferred for every expression visited.
+    pub fn expr_types(&self) -> impl Iterator<Item = (ExprNodeId, TypeNodeId)> + '_ {
+        self.result_map.iter().map(|(k, t)| (ExprNodeId(*k), *t))
+    }
+    /// The types of the names at their binding sites, located by the spans of the patterns.
+    pub fn binding_types(&self) -> impl Iterator<Item = (Span, TypeNodeId)> + '_ {
+        self.binding_types.iter().cloned()
+    }
 }
 
 pub fn infer_root(
     e: ExprNodeId,
     builtin_types: &[(Symbol, TypeNodeId)],
+    unloaded_fns: &[(Symbol, Symbol)],
 ) -> Result<InferContext, Error> {
-    let mut ctx = InferContext::new(builtin_types);
+    let mut ctx = InferContext::new(builtin_types, unloaded_fns);
     let _ = ctx.infer_type(e)?;
+    ctx.resolve_field_constraints(true)?;
     ctx.substitute_all_intermediates();
     Ok(ctx)
 }
@@ -3,12 +3,18 @@ use std::{
     collections::BTreeMap,
     fmt::{self, Display},
     hash::Hash,
+    path::PathBuf,
 };
 
 use slotmap::SlotMap;
 use string_interner::{backend::StringBackend, StringInterner};
 
-use crate::{ast::Expr, dummy_span, types::Type, utils::metadata::Span};
+use crate::{
+    ast::Expr,
+    dummy_span,
+    types::Type,
+    utils::metadata::{FileId, SourceMap, Span},
+};
 slotmap::new_key_type! {
     pub struct ExprKey;
     pub struct TypeKey;
@@ -19,6 +25,7 @@ pub struct SessionGlobals {
     pub expr_storage: SlotMap<ExprKey, Expr>,
     pub type_storage: SlotMap<TypeKey, Type>,
     pub span_storage: BTreeMap<NodeId, Span>,
+    pub source_map: SourceMap,
 }
 
 impl SessionGlobals {
@@ -64,6 +71,10 @@ impl SessionGlobals {
     pub fn get_span<T: ToNodeId>(&self, node_id: T) -> Option<&Span> {
         self.span_storage.get(&node_id.to_node_id())
     }
+
+    pub fn add_source_file(&mut self, path: Option<PathBuf>, content: &str) -> FileId {
+        self.source_map.add_file(path, content)
+    }
 }
 
 thread_local!(static SESSION_GLOBALS: RefCell<SessionGlobals> =  RefCell::new(
@@ -71,7 +82,8 @@ thread_local!(static SESSION_GLOBALS: RefCell<SessionGlobals> =  RefCell::new(
         symbol_interner: StringInterner::new(),
         expr_storage: SlotMap::with_key(),
         type_storage: SlotMap::with_key(),
-        span_storage: BTreeMap::new()
+        span_storage: BTreeMap::new(),
+        source_map: SourceMap::default(),
     }
 ));
 
@@ -208,26 +220,26 @@ impl ToNodeId for TypeNodeId {
 impl std::fmt::Display for ExprNodeId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let span = self.to_span();
-        write!(f, "{:?},{}..{}", self.to_expr(), span.start, span.end)
+        write!(f, "{:?},{}..{}", self.to_expr(), span.start(), span.end())
     }
 }
 impl std::fmt::Debug for ExprNodeId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let span = self.to_span();
-        write!(f, "{:#?},{}..{}", self.to_expr(), span.start, span.end)
+        write!(f, "{:#?},{}..{}", self.to_expr(), span.start(), span.end())
     }
 }
 impl std::fmt::Display for TypeNodeId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let span = self.to_span();
 
-        write!(f, "{:?},{}..{}", self.to_type(), span.start, span.end)
+        write!(f, "{:?},{}..{}", self.to_type(), span.start(), span.end())
     }
 }
 impl std::fmt::Debug for TypeNodeId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let span = self.to_span();
 
-        write!(f, "{:#?},{}..{}", self.to_type(), span.start, span.end)
+        write!(f, "{:#?},{}..{}", self.to_type(), span.start(), span.end())
     }
 }
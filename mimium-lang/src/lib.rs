@@ -3,7 +3,7 @@
 pub mod ast;
 pub mod interner;
 pub mod mir;
-pub(crate) mod pattern;
+pub mod pattern;
 pub mod types;
 pub mod utils;
 
@@ -33,6 +33,8 @@ pub struct ExecContext {
     path: Option<Symbol>,
     extclsinfos_reserve: Vec<ExtClsInfo>,
     extfuntypes: Vec<ExtFunTypeInfo>,
+    // the plugins known but not loaded, with the types of their functions.
+    unloaded_plugins: Vec<(Symbol, Vec<ExtFunTypeInfo>)>,
 }
 impl ExecContext {
     //The Argument will be changed to the plugins, when the plugin system is introduced
@@ -51,6 +53,7 @@ impl ExecContext {
             path,
             extclsinfos_reserve: vec![],
             extfuntypes,
+            unloaded_plugins: vec![],
         }
     }
     pub fn add_plugin<T: Plugin + 'static>(&mut self, plug: T) {
@@ -67,8 +70,39 @@ impl ExecContext {
         self.extclsinfos_reserve.extend(sysplug_info);
         self.sys_plugins.push(plugin_dyn)
     }
+    /// Registers the plugin which is not loaded in this environment. The errors for its
+    /// functions tell that they need the plugin.
+    pub fn add_unloaded_plugin<T: Plugin + 'static>(&mut self, plug: &T) {
+        let fns = plug
+            .get_ext_functions()
+            .into_iter()
+            .map(|(name, _, ty)| ExtFunTypeInfo { name, ty })
+            .chain(
+                plug.get_ext_closures()
+                    .into_iter()
+                    .map(|(name, _, ty)| ExtFunTypeInfo { name, ty }),
+            )
+            .collect();
+        self.unloaded_plugins
+            .push((plugin::get_plugin_name::<T>(), fns));
+    }
+    /// Registers the system plugin which is not loaded in this environment, in the same way as
+    /// [`Self::add_unloaded_plugin`].
+    pub fn add_unloaded_system_plugin<T: SystemPlugin + 'static>(&mut self, plug: &T) {
+        let fns = plugin::get_sysplugin_types(plug);
+        self.unloaded_plugins
+            .push((plugin::get_plugin_name::<T>(), fns));
+    }
+    /// The types of the functions given by the plugins and the builtins of the VM.
+    pub fn get_extfun_types(&self) -> &[ExtFunTypeInfo] {
+        &self.extfuntypes
+    }
     pub fn prepare_compiler(&mut self) {
-        self.compiler = Some(compiler::Context::new(self.extfuntypes.clone(), self.path));
+        let mut compiler = compiler::Context::new(self.extfuntypes.clone(), self.path);
+        for (name, fns) in self.unloaded_plugins.iter() {
+            compiler.add_unloaded_plugin(*name, fns.iter().copied());
+        }
+        self.compiler = Some(compiler);
     }
     pub fn prepare_machine(&mut self, src: &str) -> Result<(), Vec<Box<dyn ReportableError>>> {
         if self.compiler.is_none() {
@@ -76,20 +110,30 @@ impl ExecContext {
         }
 
         let prog = self.compiler.as_ref().unwrap().emit_bytecode(src)?;
-        self.prepare_machine_with_bytecode(prog);
-        Ok(())
+        self.prepare_machine_with_bytecode(prog)
     }
-    pub fn prepare_machine_with_bytecode(&mut self, prog: Program) {
+    /// Prepares the VM for the program compiled beforehand, e.g. loaded from a bytecode file.
+    /// Fails if the program uses the external functions which the plugins do not give.
+    pub fn prepare_machine_with_bytecode(
+        &mut self,
+        prog: Program,
+    ) -> Result<(), Vec<Box<dyn ReportableError>>> {
         self.extclsinfos_reserve
             .extend(plugin::get_extclsinfos(&self.plugins));
         let extfninfos =
             plugin::get_extfuninfos(&self.plugins).chain(get_builtin_fns().into_iter());
-        let vm = vm::Machine::new(
+        let vm = vm::Machine::try_new(
             prog,
             extfninfos,
             self.extclsinfos_reserve.clone().into_iter(),
-        );
+        )
+        .map_err(|errs| {
+            errs.into_iter()
+                .map(|e| Box::new(e) as Box<dyn ReportableError>)
+                .collect::<Vec<_>>()
+        })?;
         self.vm = Some(vm);
+        Ok(())
     }
     pub fn try_get_main_loop(&mut self) -> Option<Box<dyn FnOnce()>> {
         let mut mainloops = self.sys_plugins.iter_mut().filter_map(|p| {
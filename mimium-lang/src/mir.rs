@@ -2,9 +2,11 @@
 use crate::{
     interner::{Symbol, TypeNodeId},
     types::TypeSize,
+    utils::metadata::Span,
 };
 use std::{cell::OnceCell, sync::Arc};
 
+pub mod optimize;
 pub mod print;
 
 // #[derive(Debug, Clone, PartialEq)]
@@ -52,6 +54,12 @@ pub enum Instruction {
         array_idx: u64,
         tuple_offset: u64,
     },
+    // allocate array on the heap (length, type of the element)
+    AllocArray(VPtr, TypeNodeId),
+    // load an element of the array with bounds checking (array, index, type of the element)
+    GetArrayElem(VPtr, VPtr, TypeNodeId),
+    // store a value into an element of the array (array, index, source, type of the element)
+    SetArrayElem(VPtr, VPtr, VPtr, TypeNodeId),
     // call function, arguments, type of return value
     Call(VPtr, Vec<(VPtr, TypeNodeId)>, TypeNodeId),
     CallCls(VPtr, Vec<(VPtr, TypeNodeId)>, TypeNodeId),
@@ -69,6 +77,8 @@ pub enum Instruction {
     PopStateOffset(Vec<StateSize>),
     //load internal state to register(destination)
     GetState(TypeNodeId),
+    //store the value to internal state. Used for the feed of the inlined function.
+    SetState(VPtr, TypeNodeId),
 
     //condition,  basic block index for then statement, else statement, and merge block
     JmpIf(VPtr, u64, u64, u64),
@@ -107,7 +117,7 @@ pub enum Instruction {
     NegI(VPtr),
     AbsI(VPtr),
 
-    PowI(VPtr),
+    PowI(VPtr, VPtr),
     LogI(VPtr, VPtr),
     // primitive Operations for bool
     Not(VPtr),
@@ -141,6 +151,29 @@ pub struct OpenUpValue {
     pub is_closure: bool,
 }
 
+/// How the function is treated by the inliner, given by the attribute on its definition.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InlineHint {
+    /// Inlined when it is small or called only once.
+    #[default]
+    Auto,
+    /// `#[inline]`: inlined regardless of its size, as long as it is possible.
+    Always,
+    /// `#[noinline]`: never inlined.
+    Never,
+}
+
+impl InlineHint {
+    /// The hint given by the attribute of the name, like `inline` for `#[inline]`.
+    pub fn from_attribute(name: Symbol) -> Option<Self> {
+        match name.as_str() {
+            "inline" => Some(Self::Always),
+            "noinline" => Some(Self::Never),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Function {
     pub index: usize,
@@ -152,6 +185,9 @@ pub struct Function {
     pub upperfn_i: Option<usize>,
     pub body: Vec<Block>,
     pub state_sizes: Vec<StateSize>,
+    pub inline_hint: InlineHint,
+    /// The location of the lambda or the `fn` which defines the function.
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -167,6 +203,7 @@ impl Function {
         args: &[VPtr],
         argtypes: &[TypeNodeId],
         upperfn_i: Option<usize>,
+        span: Span,
     ) -> Self {
         Self {
             index,
@@ -178,6 +215,8 @@ impl Function {
             upperfn_i,
             body: vec![Block::default()],
             state_sizes: vec![],
+            inline_hint: InlineHint::Auto,
+            span,
         }
     }
     pub fn add_new_basicblock(&mut self) -> usize {
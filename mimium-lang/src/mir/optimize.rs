@@ -0,0 +1,808 @@
+//! Optimization passes over the MIR, run before generating the bytecode.
+//!
+//! The passes keep the form which the bytecode generator expects: a register is read only once
+//! unless it is a pointer (`alloc`, `getelement`), and the blocks of `if` are laid out as the
+//! condition, then, else and merge blocks. So a value is forwarded to the place of another only
+//! when the number of the reads of the register does not increase.
+//!
+//! The instructions which decide the layout of the generated code, such as the ones accessing the
+//! internal states and the upvalues, are never removed even if they are unreachable.
+
+use super::*;
+use crate::runtime::vm::Machine;
+use crate::types::{PType, Type};
+use std::collections::{HashMap, HashSet};
+
+mod inline;
+use inline::inline_functions;
+
+/// How much the MIR is optimized before generating the bytecode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// The MIR is used as generated.
+    None,
+    /// Inlining, constant folding, copy propagation and dead code elimination.
+    #[default]
+    Basic,
+}
+
+pub struct Pass {
+    pub name: &'static str,
+    /// Returns true if the MIR was changed.
+    pub run: fn(&mut Mir) -> bool,
+}
+
+pub const PASSES: [Pass; 3] = [
+    Pass {
+        name: "constant folding",
+        run: fold_constants,
+    },
+    Pass {
+        name: "copy propagation",
+        run: propagate_copies,
+    },
+    Pass {
+        name: "dead code elimination",
+        run: eliminate_dead_code,
+    },
+];
+
+// the passes may enable each other, so they are repeated until nothing changes.
+const MAX_ITERATION: usize = 16;
+
+/// Inlines the functions, then runs the passes for the level repeatedly until none of them
+/// changes the MIR.
+pub fn optimize(mut mir: Mir, level: OptLevel) -> Mir {
+    if level == OptLevel::None {
+        return mir;
+    }
+    // the inliner works on the arguments read by `load`, so it runs before the other passes.
+    if inline_functions(&mut mir) {
+        log::trace!("inlining changed the MIR");
+    }
+    for _ in 0..MAX_ITERATION {
+        let mut changed = false;
+        for pass in PASSES.iter() {
+            if (pass.run)(&mut mir) {
+                log::trace!("{} changed the MIR", pass.name);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    mir
+}
+
+// All the values read by the instruction. Works both for `&Instruction` and `&mut Instruction`.
+macro_rules! operands {
+    ($inst:expr) => {
+        match $inst {
+            Instruction::Load(a, _)
+            | Instruction::GetElement { value: a, .. }
+            | Instruction::AllocArray(a, _)
+            | Instruction::GetGlobal(a, _)
+            | Instruction::Closure(a)
+            | Instruction::CloseUpValues(a, _)
+            | Instruction::SetUpValue(_, a, _)
+            | Instruction::SetState(a, _)
+            | Instruction::JmpIf(a, ..)
+            | Instruction::Return(a, _)
+            | Instruction::ReturnFeed(a, _)
+            | Instruction::Mem(a)
+            | Instruction::NegF(a)
+            | Instruction::AbsF(a)
+            | Instruction::SinF(a)
+            | Instruction::CosF(a)
+            | Instruction::SqrtF(a)
+            | Instruction::NegI(a)
+            | Instruction::AbsI(a)
+            | Instruction::Not(a)
+            | Instruction::CastFtoI(a)
+            | Instruction::CastItoF(a)
+            | Instruction::CastItoB(a) => vec![a],
+            Instruction::Store(a, b, _)
+            | Instruction::GetArrayElem(a, b, _)
+            | Instruction::SetGlobal(a, b, _)
+            | Instruction::Phi(a, b)
+            | Instruction::Delay(_, a, b)
+            | Instruction::AddF(a, b)
+            | Instruction::SubF(a, b)
+            | Instruction::MulF(a, b)
+            | Instruction::DivF(a, b)
+            | Instruction::ModF(a, b)
+            | Instruction::PowF(a, b)
+            | Instruction::LogF(a, b)
+            | Instruction::AddI(a, b)
+            | Instruction::SubI(a, b)
+            | Instruction::MulI(a, b)
+            | Instruction::DivI(a, b)
+            | Instruction::ModI(a, b)
+            | Instruction::PowI(a, b)
+            | Instruction::LogI(a, b)
+            | Instruction::Eq(a, b)
+            | Instruction::Ne(a, b)
+            | Instruction::Gt(a, b)
+            | Instruction::Ge(a, b)
+            | Instruction::Lt(a, b)
+            | Instruction::Le(a, b)
+            | Instruction::And(a, b)
+            | Instruction::Or(a, b) => vec![a, b],
+            Instruction::SetArrayElem(a, b, c, _) => vec![a, b, c],
+            Instruction::Call(f, args, _) | Instruction::CallCls(f, args, _) => std::iter::once(f)
+                .chain(args.into_iter().map(|(a, _)| a))
+                .collect(),
+            Instruction::Uinteger(_)
+            | Instruction::Integer(_)
+            | Instruction::Float(_)
+            | Instruction::String(_)
+            | Instruction::Alloc(_)
+            | Instruction::GetUpValue(..)
+            | Instruction::PushStateOffset(_)
+            | Instruction::PopStateOffset(_)
+            | Instruction::GetState(_)
+            | Instruction::Jmp(_) => vec![],
+        }
+    };
+}
+
+pub(crate) fn operands(inst: &Instruction) -> Vec<&VPtr> {
+    operands!(inst)
+}
+fn operands_mut(inst: &mut Instruction) -> Vec<&mut VPtr> {
+    operands!(inst)
+}
+
+// the operand used as the address of the memory or the function, rather than read as a value.
+// The bytecode generator places the other values relative to it, so it cannot be replaced.
+pub(crate) fn address_operand(inst: &Instruction) -> Option<&VPtr> {
+    match inst {
+        Instruction::Load(a, _)
+        | Instruction::Store(a, _, _)
+        | Instruction::GetElement { value: a, .. }
+        | Instruction::SetArrayElem(a, _, _, _)
+        | Instruction::GetGlobal(a, _)
+        | Instruction::SetGlobal(a, _, _)
+        | Instruction::Closure(a)
+        | Instruction::CloseUpValues(a, _)
+        | Instruction::Call(a, _, _)
+        | Instruction::CallCls(a, _, _) => Some(a),
+        _ => None,
+    }
+}
+
+// the registers in the value, including the ones wrapped by the global or the state.
+fn registers(v: &Value, res: &mut Vec<VReg>) {
+    match v {
+        Value::Register(r) => res.push(*r),
+        Value::Global(v) | Value::State(v) => registers(v, res),
+        _ => {}
+    }
+}
+
+#[derive(Default)]
+struct Uses {
+    count: HashMap<VReg, usize>,
+    // the registers used as the addresses, or captured by the closures as the upvalues.
+    pinned: HashSet<VReg>,
+}
+
+impl Uses {
+    fn collect(mir: &Mir) -> Self {
+        let mut res = Self::default();
+        for f in mir.functions.iter() {
+            for (_, inst) in f.body.iter().flat_map(|b| b.0.iter()) {
+                let mut regs = vec![];
+                operands(inst)
+                    .into_iter()
+                    .for_each(|v| registers(v, &mut regs));
+                for r in regs {
+                    *res.count.entry(r).or_default() += 1;
+                }
+                let mut regs = vec![];
+                if let Some(a) = address_operand(inst) {
+                    registers(a, &mut regs);
+                }
+                res.pinned.extend(regs);
+            }
+            let mut regs = vec![];
+            f.upindexes.iter().for_each(|v| registers(v, &mut regs));
+            for r in regs {
+                *res.count.entry(r).or_default() += 1;
+                res.pinned.insert(r);
+            }
+        }
+        res
+    }
+    fn count(&self, v: &Value) -> usize {
+        match v {
+            Value::Register(r) => self.count.get(r).copied().unwrap_or(0),
+            _ => 0,
+        }
+    }
+    // whether the uses of the register can be replaced by another value.
+    fn is_replaceable(&self, v: &Value) -> bool {
+        matches!(v, Value::Register(r) if !self.pinned.contains(r))
+    }
+}
+
+fn is_scalar(ty: TypeNodeId) -> bool {
+    matches!(
+        ty.to_type(),
+        Type::Primitive(PType::Numeric | PType::Int | PType::String)
+    )
+}
+
+// whether the instruction can be dropped when it is unreachable. The other ones decide the layout
+// of the generated code, like the positions of the upvalues, the delays and the internal states.
+fn is_droppable(inst: &Instruction) -> bool {
+    !matches!(
+        inst,
+        Instruction::Call(..)
+            | Instruction::CallCls(..)
+            | Instruction::GetUpValue(..)
+            | Instruction::SetUpValue(..)
+            | Instruction::PushStateOffset(_)
+            | Instruction::PopStateOffset(_)
+            | Instruction::GetState(_)
+            | Instruction::SetState(..)
+            | Instruction::ReturnFeed(..)
+            | Instruction::Delay(..)
+            | Instruction::Mem(_)
+            | Instruction::JmpIf(..)
+            | Instruction::Jmp(_)
+    )
+}
+
+// whether the instruction has no effect other than defining its register.
+fn is_pure(inst: &Instruction) -> bool {
+    matches!(
+        inst,
+        Instruction::Uinteger(_)
+            | Instruction::Integer(_)
+            | Instruction::Float(_)
+            | Instruction::String(_)
+            | Instruction::Alloc(_)
+            | Instruction::Load(..)
+            | Instruction::GetElement { .. }
+            | Instruction::GetGlobal(..)
+            | Instruction::AddF(..)
+            | Instruction::SubF(..)
+            | Instruction::MulF(..)
+            | Instruction::DivF(..)
+            | Instruction::ModF(..)
+            | Instruction::NegF(_)
+            | Instruction::AbsF(_)
+            | Instruction::SinF(_)
+            | Instruction::CosF(_)
+            | Instruction::PowF(..)
+            | Instruction::LogF(..)
+            | Instruction::SqrtF(_)
+            | Instruction::AddI(..)
+            | Instruction::SubI(..)
+            | Instruction::MulI(..)
+            | Instruction::NegI(_)
+            | Instruction::AbsI(_)
+            | Instruction::PowI(..)
+            | Instruction::Not(_)
+            | Instruction::Eq(..)
+            | Instruction::Ne(..)
+            | Instruction::Gt(..)
+            | Instruction::Ge(..)
+            | Instruction::Lt(..)
+            | Instruction::Le(..)
+            | Instruction::And(..)
+            | Instruction::Or(..)
+            | Instruction::CastFtoI(_)
+            | Instruction::CastItoF(_)
+            | Instruction::CastItoB(_)
+    )
+}
+
+fn is_return(inst: &Instruction) -> bool {
+    matches!(inst, Instruction::Return(..) | Instruction::ReturnFeed(..))
+}
+
+// Replaces the reads of the values by `subst`, following the chains of the replacements.
+fn substitute(f: &mut Function, subst: &HashMap<VPtr, VPtr>) {
+    if subst.is_empty() {
+        return;
+    }
+    let resolve = |v: &VPtr| {
+        let mut v = v;
+        while let Some(next) = subst.get(v) {
+            v = next;
+        }
+        v.clone()
+    };
+    for (_, inst) in f.body.iter_mut().flat_map(|b| b.0.iter_mut()) {
+        for v in operands_mut(inst) {
+            *v = resolve(v);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Const {
+    Float(f64),
+    Int(i64),
+}
+
+impl Const {
+    fn of(inst: &Instruction) -> Option<Self> {
+        match inst {
+            Instruction::Float(n) => Some(Self::Float(*n)),
+            Instruction::Integer(i) => Some(Self::Int(*i)),
+            _ => None,
+        }
+    }
+    fn to_instruction(self) -> Instruction {
+        match self {
+            Self::Float(n) => Instruction::Float(n),
+            Self::Int(i) => Instruction::Integer(i),
+        }
+    }
+    fn from_bool(b: bool) -> Self {
+        Self::Float(if b { 1.0 } else { 0.0 })
+    }
+}
+
+// Computes the instruction whose operands are all constants, in the same way as the VM.
+fn fold(inst: &Instruction, consts: &HashMap<VPtr, Const>) -> Option<Const> {
+    let c = |v: &VPtr| consts.get(v).copied();
+    let float = |v: &VPtr| match c(v) {
+        Some(Const::Float(n)) => Some(n),
+        _ => None,
+    };
+    let int = |v: &VPtr| match c(v) {
+        Some(Const::Int(i)) => Some(i),
+        _ => None,
+    };
+    let res = match inst {
+        Instruction::AddF(a, b) => Const::Float(float(a)? + float(b)?),
+        Instruction::SubF(a, b) => Const::Float(float(a)? - float(b)?),
+        Instruction::MulF(a, b) => Const::Float(float(a)? * float(b)?),
+        Instruction::DivF(a, b) => Const::Float(float(a)? / float(b)?),
+        Instruction::ModF(a, b) => Const::Float(float(a)? % float(b)?),
+        Instruction::PowF(a, b) => Const::Float(float(a)?.powf(float(b)?)),
+        Instruction::NegF(a) => Const::Float(-float(a)?),
+        Instruction::AbsF(a) => Const::Float(float(a)?.abs()),
+        Instruction::SinF(a) => Const::Float(float(a)?.sin()),
+        Instruction::CosF(a) => Const::Float(float(a)?.cos()),
+        Instruction::SqrtF(a) => Const::Float(float(a)?.sqrt()),
+        Instruction::AddI(a, b) => Const::Int(int(a)?.wrapping_add(int(b)?)),
+        Instruction::SubI(a, b) => Const::Int(int(a)?.wrapping_sub(int(b)?)),
+        Instruction::MulI(a, b) => Const::Int(int(a)?.wrapping_mul(int(b)?)),
+        // the division by zero is left to be reported at runtime.
+        Instruction::DivI(a, b) if int(b) != Some(0) => Const::Int(int(a)?.wrapping_div(int(b)?)),
+        Instruction::ModI(a, b) if int(b) != Some(0) => Const::Int(int(a)?.wrapping_rem(int(b)?)),
+        Instruction::PowI(a, b) => Const::Int(Machine::int_pow(int(a)?, int(b)?)),
+        Instruction::NegI(a) => Const::Int(int(a)?.wrapping_neg()),
+        Instruction::AbsI(a) => Const::Int(int(a)?.wrapping_abs()),
+        Instruction::Eq(a, b) => Const::from_bool(float(a)? == float(b)?),
+        Instruction::Ne(a, b) => Const::from_bool(float(a)? != float(b)?),
+        Instruction::Gt(a, b) => Const::from_bool(float(a)? > float(b)?),
+        Instruction::Ge(a, b) => Const::from_bool(float(a)? >= float(b)?),
+        Instruction::Lt(a, b) => Const::from_bool(float(a)? < float(b)?),
+        Instruction::Le(a, b) => Const::from_bool(float(a)? <= float(b)?),
+        Instruction::And(a, b) => Const::from_bool(float(a)? > 0.0 && float(b)? > 0.0),
+        Instruction::Or(a, b) => Const::from_bool(float(a)? > 0.0 || float(b)? > 0.0),
+        Instruction::CastFtoI(a) => Const::Int(float(a)? as i64),
+        Instruction::CastItoF(a) => Const::Float(int(a)? as f64),
+        _ => return None,
+    };
+    Some(res)
+}
+
+// the globals set only once to the constants, like `let PI = 3.14159`.
+fn constant_globals(mir: &Mir) -> HashMap<VPtr, Const> {
+    let mut res = HashMap::<VPtr, Option<Const>>::new();
+    for f in mir.functions.iter() {
+        let insts = f.body.iter().flat_map(|b| b.0.iter());
+        let consts = insts
+            .clone()
+            .filter_map(|(dst, inst)| Some((dst.clone(), Const::of(inst)?)))
+            .collect::<HashMap<_, _>>();
+        for (_, inst) in insts {
+            if let Instruction::SetGlobal(gv, src, _) = inst {
+                let c = consts.get(src).copied();
+                res.entry(gv.clone()).and_modify(|c| *c = None).or_insert(c);
+            }
+        }
+    }
+    res.into_iter()
+        .filter_map(|(gv, c)| Some((gv, c?)))
+        .collect()
+}
+
+fn fold_constants(mir: &mut Mir) -> bool {
+    let globals = constant_globals(mir);
+    let uses = Uses::collect(mir);
+    let mut changed = false;
+    for f in mir.functions.iter_mut() {
+        let mut consts = HashMap::new();
+        // the blocks are ordered so that the registers are defined before they are read.
+        for (dst, inst) in f.body.iter_mut().flat_map(|b| b.0.iter_mut()) {
+            let folded = match inst {
+                Instruction::GetGlobal(gv, _) => globals.get(gv).copied(),
+                _ => fold(inst, &consts),
+            };
+            if let Some(c) = folded {
+                *inst = c.to_instruction();
+                changed = true;
+            }
+            if let Some(c) = Const::of(inst) {
+                consts.insert(dst.clone(), c);
+            }
+        }
+        for bidx in 0..f.body.len() {
+            while fold_branch(f, bidx, &consts, &uses) {
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+// Replaces `if` on a constant condition at the end of the block with the branch taken. Only the
+// branches consisting of a single block are folded, and the blocks left are removed later as
+// unreachable.
+fn fold_branch(f: &mut Function, bidx: usize, consts: &HashMap<VPtr, Const>, uses: &Uses) -> bool {
+    let Some((_, Instruction::JmpIf(c, then_i, else_i, phi_i))) = f.body[bidx].0.last() else {
+        return false;
+    };
+    let (then_i, else_i, phi_i) = (*then_i as usize, *else_i as usize, *phi_i as usize);
+    let taken_then = match consts.get(c) {
+        // the same condition as `JmpIfNeg` of the VM, which takes the else branch if <= 0.
+        Some(Const::Float(n)) => n.is_nan() || *n > 0.0,
+        _ => return false,
+    };
+    if else_i != then_i + 1 || phi_i != else_i + 1 {
+        return false;
+    }
+    let (taken, untaken) = if taken_then {
+        (then_i, else_i)
+    } else {
+        (else_i, then_i)
+    };
+    if !f.body[untaken].0.iter().all(|(_, inst)| is_droppable(inst)) {
+        return false;
+    }
+    let Some((phi, Instruction::Phi(t, e))) = f.body[phi_i].0.first() else {
+        return false;
+    };
+    let v = if taken_then { t } else { e };
+    if uses.count(phi) > 0 && !(uses.is_replaceable(phi) && is_plain_value(&definitions(f), v)) {
+        return false;
+    }
+    let subst = HashMap::from([(phi.clone(), v.clone())]);
+    let mut insts = std::mem::take(&mut f.body[taken].0);
+    insts.extend(f.body[phi_i].0.drain(1..));
+    f.body[untaken].0.clear();
+    f.body[phi_i].0.clear();
+    let block = &mut f.body[bidx].0;
+    block.pop();
+    block.extend(insts);
+    substitute(f, &subst);
+    true
+}
+
+// the instructions defining the registers in the function.
+fn definitions(f: &Function) -> HashMap<VPtr, Instruction> {
+    f.body
+        .iter()
+        .flat_map(|b| b.0.iter())
+        .filter(|(dst, _)| matches!(dst.as_ref(), Value::Register(_)))
+        .map(|(dst, inst)| (dst.clone(), inst.clone()))
+        .collect()
+}
+
+// whether the value can be read later in place of its copy. The pointers, like the elements of
+// the tuples, may be written after they are copied.
+fn is_plain_value(defs: &HashMap<VPtr, Instruction>, v: &VPtr) -> bool {
+    match defs.get(v) {
+        Some(Instruction::GetElement { .. } | Instruction::Alloc(_)) => false,
+        Some(_) => true,
+        None => matches!(v.as_ref(), Value::Argument(..)),
+    }
+}
+
+// Forwards the values copied by `load` to where they are read: the arguments, and the local
+// variables which are stored only once.
+fn propagate_copies(mir: &mut Mir) -> bool {
+    let uses = Uses::collect(mir);
+    let mut changed = false;
+    for f in mir.functions.iter_mut() {
+        let defs = definitions(f);
+        // the position of the instructions (block, index) to be removed or replaced.
+        let mut removed = HashSet::<(usize, usize)>::new();
+        let mut replaced = HashMap::<(usize, usize), Instruction>::new();
+        let mut subst = HashMap::<VPtr, VPtr>::new();
+        let mut stores = HashMap::<VPtr, Vec<(usize, usize, VPtr, TypeNodeId)>>::new();
+        let mut loads = HashMap::<VPtr, Vec<(usize, usize, VPtr)>>::new();
+        for (bi, block) in f.body.iter().enumerate() {
+            for (i, (dst, inst)) in block.0.iter().enumerate() {
+                match inst {
+                    // the arguments are never assigned, so they can be read directly.
+                    Instruction::Load(a, ty)
+                        if matches!(a.as_ref(), Value::Argument(..))
+                            && is_scalar(*ty)
+                            && uses.is_replaceable(dst) =>
+                    {
+                        subst.insert(dst.clone(), a.clone());
+                        removed.insert((bi, i));
+                    }
+                    Instruction::Load(a, _) => {
+                        loads
+                            .entry(a.clone())
+                            .or_default()
+                            .push((bi, i, dst.clone()))
+                    }
+                    Instruction::Store(a, v, ty) => {
+                        stores
+                            .entry(a.clone())
+                            .or_default()
+                            .push((bi, i, v.clone(), *ty))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for (bi, block) in f.body.iter().enumerate() {
+            for (i, (alloc, inst)) in block.0.iter().enumerate() {
+                let Instruction::Alloc(_) = inst else {
+                    continue;
+                };
+                let (sbi, si, v, ty) = match stores.get(alloc).map(|s| s.as_slice()) {
+                    Some([store]) => store.clone(),
+                    _ => continue,
+                };
+                let alloc_loads = loads.get(alloc).map_or(&[][..], |l| l.as_slice());
+                if !is_scalar(ty) || uses.count(alloc) != alloc_loads.len() + 1 {
+                    continue;
+                }
+                // the loads before the store read the value not initialized yet.
+                if alloc_loads
+                    .iter()
+                    .any(|(lbi, li, _)| (*lbi, *li) < (sbi, si))
+                {
+                    continue;
+                }
+                let forwarded = match (defs.get(&v), alloc_loads) {
+                    (_, []) => true,
+                    (Some(c @ (Instruction::Float(_) | Instruction::Integer(_))), _) => {
+                        for (lbi, li, _) in alloc_loads.iter() {
+                            replaced.insert((*lbi, *li), c.clone());
+                        }
+                        true
+                    }
+                    // the value is moved to the only place it is read.
+                    (_, [(lbi, li, dst)])
+                        if uses.is_replaceable(dst) && is_plain_value(&defs, &v) =>
+                    {
+                        subst.insert(dst.clone(), v.clone());
+                        removed.insert((*lbi, *li));
+                        true
+                    }
+                    _ => false,
+                };
+                if forwarded {
+                    removed.insert((bi, i));
+                    removed.insert((sbi, si));
+                }
+            }
+        }
+        if removed.is_empty() && replaced.is_empty() {
+            continue;
+        }
+        changed = true;
+        for (bi, block) in f.body.iter_mut().enumerate() {
+            let insts = std::mem::take(&mut block.0);
+            block.0 = insts
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| !removed.contains(&(bi, *i)))
+                .map(|(i, (dst, inst))| (dst, replaced.remove(&(bi, i)).unwrap_or(inst)))
+                .collect();
+        }
+        substitute(f, &subst);
+    }
+    changed
+}
+
+// Removes the blocks which are not reached from the first block, and renumbers the rest.
+fn remove_unreachable_blocks(f: &mut Function) -> bool {
+    let mut reachable = vec![false; f.body.len()];
+    let mut stack = vec![0];
+    while let Some(i) = stack.pop() {
+        if std::mem::replace(&mut reachable[i], true) {
+            continue;
+        }
+        if let Some((_, Instruction::JmpIf(_, t, e, p))) = f.body[i].0.last() {
+            stack.extend([*t as usize, *e as usize, *p as usize]);
+        }
+    }
+    if reachable.iter().all(|r| *r) {
+        return false;
+    }
+    let mut new_index = vec![0; f.body.len()];
+    let mut count = 0;
+    for (i, r) in reachable.iter().enumerate() {
+        new_index[i] = count;
+        count += *r as u64;
+    }
+    let blocks = std::mem::take(&mut f.body);
+    f.body = blocks
+        .into_iter()
+        .zip(reachable)
+        .filter_map(|(b, r)| r.then_some(b))
+        .collect();
+    for block in f.body.iter_mut() {
+        if let Some((_, Instruction::JmpIf(_, t, e, p))) = block.0.last_mut() {
+            *t = new_index[*t as usize];
+            *e = new_index[*e as usize];
+            *p = new_index[*p as usize];
+        }
+    }
+    true
+}
+
+// Removes the code after `return` in the block, the unreachable blocks and the instructions
+// whose results are never used.
+fn eliminate_dead_code(mir: &mut Mir) -> bool {
+    let mut changed = false;
+    for f in mir.functions.iter_mut() {
+        for block in f.body.iter_mut() {
+            let Some(ret) = block.0.iter().position(|(_, inst)| is_return(inst)) else {
+                continue;
+            };
+            let rest = &block.0[ret + 1..];
+            if !rest.is_empty() && rest.iter().all(|(_, inst)| is_droppable(inst)) {
+                block.0.truncate(ret + 1);
+                changed = true;
+            }
+        }
+        changed |= remove_unreachable_blocks(f);
+    }
+    loop {
+        let uses = Uses::collect(mir);
+        let mut removed = false;
+        for f in mir.functions.iter_mut() {
+            for block in f.body.iter_mut() {
+                let len = block.0.len();
+                block.0.retain(|(dst, inst)| {
+                    !(is_pure(inst)
+                        && matches!(dst.as_ref(), Value::Register(_))
+                        && uses.count(dst) == 0)
+                });
+                removed |= block.0.len() != len;
+            }
+        }
+        if !removed {
+            break;
+        }
+        changed = true;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler::Context;
+
+    fn compile(src: &str) -> Mir {
+        let mir = Context::new([], None).emit_mir(src).unwrap();
+        optimize(mir, OptLevel::Basic)
+    }
+    fn instructions<'a>(mir: &'a Mir, name: &str) -> Vec<&'a Instruction> {
+        let f = mir
+            .functions
+            .iter()
+            .find(|f| f.label.as_str() == name)
+            .unwrap();
+        f.body
+            .iter()
+            .flat_map(|b| b.0.iter().map(|(_, inst)| inst))
+            .collect()
+    }
+
+    #[test]
+    fn constant_folding() {
+        let src = "let PI = 3.14159265359
+fn dsp(){
+    let omega = 2.0*PI*440.0/48000.0
+    sin(omega)
+}";
+        let mir = compile(src);
+        let omega = 2.0 * 3.14159265359 * 440.0 / 48000.0_f64;
+        let insts = instructions(&mir, "dsp");
+        assert_eq!(insts[0], &Instruction::Float(omega.sin()), "{mir}");
+        assert!(matches!(insts[1], Instruction::Return(..)), "{mir}");
+        assert_eq!(insts.len(), 2, "{mir}");
+    }
+
+    #[test]
+    fn copy_propagation() {
+        let src = "fn mix(x, y){
+    let sum = x + y
+    sum
+}";
+        let mir = compile(src);
+        let insts = instructions(&mir, "mix");
+        assert_eq!(insts.len(), 2, "{mir}");
+        let Instruction::AddF(x, y) = insts[0] else {
+            panic!("{mir}")
+        };
+        assert!(matches!(x.as_ref(), Value::Argument(0, _)));
+        assert!(matches!(y.as_ref(), Value::Argument(1, _)));
+    }
+
+    #[test]
+    fn constant_branch() {
+        let src = "let DEBUG = 0.0
+fn dsp(){
+    if (DEBUG > 0.5) { 1.0 } else { 2.0 }
+}";
+        let mir = compile(src);
+        let insts = instructions(&mir, "dsp");
+        assert_eq!(insts[0], &Instruction::Float(2.0), "{mir}");
+        assert_eq!(insts.len(), 2, "{mir}");
+        let f = mir.functions.iter().find(|f| f.label.as_str() == "dsp");
+        assert_eq!(f.unwrap().body.len(), 1);
+    }
+
+    fn has_call(insts: &[&Instruction]) -> bool {
+        insts.iter().any(|i| matches!(i, Instruction::Call(..)))
+    }
+
+    #[test]
+    fn inline_small_function() {
+        let src = "fn mix(x, y){
+    (x + y) * 0.5
+}
+fn dsp(){
+    mix(1.0, 3.0)
+}";
+        let mir = compile(src);
+        let insts = instructions(&mir, "dsp");
+        assert!(!has_call(&insts), "{mir}");
+        assert_eq!(insts[0], &Instruction::Float(2.0), "{mir}");
+    }
+
+    #[test]
+    fn noinline_attribute() {
+        let src = "#[noinline]
+fn mix(x, y){
+    (x + y) * 0.5
+}
+fn dsp(){
+    mix(1.0, 3.0)
+}";
+        let mir = compile(src);
+        let f = mir.functions.iter().find(|f| f.label.as_str() == "mix");
+        assert_eq!(f.unwrap().inline_hint, InlineHint::Never);
+        assert!(has_call(&instructions(&mir, "dsp")), "{mir}");
+    }
+
+    #[test]
+    fn inline_stateful_function() {
+        let src = "fn counter(){
+    self + 1.0
+}
+fn dsp(){
+    counter() + counter()
+}";
+        let mir = compile(src);
+        let insts = instructions(&mir, "dsp");
+        assert!(!has_call(&insts), "{mir}");
+        let setstates = insts
+            .iter()
+            .filter(|i| matches!(i, Instruction::SetState(..)))
+            .count();
+        assert_eq!(setstates, 2, "{mir}");
+        let push = |i: &&Instruction| matches!(i, Instruction::PushStateOffset(_));
+        assert!(insts.iter().any(push), "{mir}");
+    }
+}
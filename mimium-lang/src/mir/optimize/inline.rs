@@ -0,0 +1,330 @@
+//! Inlining of the direct calls to the small functions and the ones called only once.
+//!
+//! The body of the callee is copied in place of the `call` with its registers renamed, and the
+//! arguments are passed through the local variables because the callee may read them several
+//! times. The blocks of the callee are inserted right after the block of the call, and the
+//! instructions following the call are moved to the end of the last block of the callee, so
+//! that the blocks of `if` keep their order.
+//!
+//! The internal states of a stateful callee stay where they were. The caller shifts the state
+//! position before the call and the callee restores it by `popstateidx` before returning, so
+//! the body works in the same way when it is inlined. Only `retfeed` is replaced, with
+//! `getstate` and `setstate` which do the same as it except for returning.
+
+use super::*;
+
+// the callees up to this number of instructions are inlined at every call.
+const SMALL_SIZE: usize = 16;
+// the callees called only once are inlined up to this size.
+const SINGLE_USE_SIZE: usize = 64;
+// the callers are not grown beyond this size, because the registers of a function are limited.
+const MAX_CALLER_SIZE: usize = 256;
+
+fn size(f: &Function) -> usize {
+    f.body.iter().map(|b| b.0.len()).sum()
+}
+
+// the index of the function called directly by the instruction.
+fn callee_of(defs: &HashMap<VPtr, Instruction>, inst: &Instruction) -> Option<usize> {
+    match inst {
+        Instruction::Call(f, ..) => match defs.get(f) {
+            Some(Instruction::Uinteger(i)) => Some(*i as usize),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn direct_calls(f: &Function) -> Vec<usize> {
+    let defs = definitions(f);
+    f.body
+        .iter()
+        .flat_map(|b| b.0.iter())
+        .filter_map(|(_, inst)| callee_of(&defs, inst))
+        .collect()
+}
+
+// the functions which may call themselves through the direct calls. Inlining does not change
+// this, as the caller only takes over the calls of the callee.
+fn recursive_functions(mir: &Mir) -> HashSet<usize> {
+    let graph = mir.functions.iter().map(direct_calls).collect::<Vec<_>>();
+    (0..graph.len())
+        .filter(|i| {
+            let mut visited = HashSet::new();
+            let mut stack = graph[*i].clone();
+            while let Some(j) = stack.pop() {
+                if j == *i {
+                    return true;
+                }
+                if visited.insert(j) {
+                    stack.extend(graph.get(j).into_iter().flatten());
+                }
+            }
+            false
+        })
+        .collect()
+}
+
+fn call_counts(mir: &Mir) -> HashMap<usize, usize> {
+    let mut res = HashMap::new();
+    for i in mir.functions.iter().flat_map(direct_calls) {
+        *res.entry(i).or_default() += 1;
+    }
+    res
+}
+
+fn delay_sizes(f: &Function) -> HashSet<u64> {
+    f.body
+        .iter()
+        .flat_map(|b| b.0.iter())
+        .filter_map(|(_, inst)| match inst {
+            Instruction::Delay(max, ..) => Some(*max),
+            _ => None,
+        })
+        .collect()
+}
+
+// whether the body of the function can be copied into another one. The function must return
+// only at the end of the block reached last from the first block, must not touch the upvalues,
+// and must read the arguments only by `load`.
+fn is_inlinable(f: &Function) -> bool {
+    if !f.upindexes.is_empty() {
+        return false;
+    }
+    let mut last = 0;
+    while let Some((_, Instruction::JmpIf(_, _, _, phi))) = f.body[last].0.last() {
+        last = *phi as usize;
+    }
+    if last != f.body.len() - 1 {
+        return false;
+    }
+    let returns_at_end = match f.body[last].0.last() {
+        Some((_, Instruction::Return(v, _))) => {
+            matches!(v.as_ref(), Value::Register(_) | Value::None)
+        }
+        Some((_, Instruction::ReturnFeed(v, _))) => matches!(v.as_ref(), Value::Register(_)),
+        _ => false,
+    };
+    let insts = f.body.iter().flat_map(|b| b.0.iter());
+    returns_at_end
+        && insts.clone().filter(|(_, inst)| is_return(inst)).count() == 1
+        && insts.clone().all(|(_, inst)| match inst {
+            Instruction::Closure(_)
+            | Instruction::CloseUpValues(..)
+            | Instruction::GetUpValue(..)
+            | Instruction::SetUpValue(..)
+            | Instruction::Jmp(_) => false,
+            Instruction::Load(..) => true,
+            inst => operands(inst)
+                .into_iter()
+                .all(|v| !matches!(v.as_ref(), Value::Argument(..))),
+        })
+}
+
+// the position (block, index) of the first call in the caller which can be inlined, with the
+// index of the callee.
+fn find_inlinable_call(
+    mir: &Mir,
+    caller_i: usize,
+    recursive: &HashSet<usize>,
+) -> Option<(usize, usize, usize)> {
+    let caller = &mir.functions[caller_i];
+    let defs = definitions(caller);
+    let counts = call_counts(mir);
+    // the result of the call cannot be replaced if a closure captures it.
+    let captured = mir
+        .functions
+        .iter()
+        .flat_map(|f| f.upindexes.iter())
+        .collect::<HashSet<_>>();
+    let caller_delays = delay_sizes(caller);
+    caller.body.iter().enumerate().find_map(|(bi, block)| {
+        block.0.iter().enumerate().find_map(|(i, (dst, inst))| {
+            let callee_i = callee_of(&defs, inst)?;
+            let callee = mir.functions.get(callee_i)?;
+            let is_small = size(callee) <= SMALL_SIZE
+                || (counts.get(&callee_i) == Some(&1) && size(callee) <= SINGLE_USE_SIZE);
+            let by_hint = match callee.inline_hint {
+                InlineHint::Auto => is_small && size(caller) + size(callee) <= MAX_CALLER_SIZE,
+                InlineHint::Always => true,
+                InlineHint::Never => false,
+            };
+            // every `delay` in a function uses the size of the first one in the VM.
+            let delays = caller_delays.union(&delay_sizes(callee)).count();
+            let inlinable = by_hint
+                && callee_i != caller_i
+                && !recursive.contains(&callee_i)
+                && delays <= 1
+                && !captured.contains(dst)
+                && is_inlinable(callee);
+            inlinable.then_some((bi, i, callee_i))
+        })
+    })
+}
+
+fn new_register(next_reg: &mut VReg) -> VPtr {
+    let res = Arc::new(Value::Register(*next_reg));
+    *next_reg += 1;
+    res
+}
+
+// Replaces the call at (block, index) in the caller with the body of the callee.
+fn inline_call(
+    caller: &mut Function,
+    bidx: usize,
+    idx: usize,
+    callee: &Function,
+    next_reg: &mut VReg,
+) {
+    let (dst, Instruction::Call(_, args, _)) = caller.body[bidx].0[idx].clone() else {
+        unreachable!("not a call")
+    };
+    let insts = callee.body.iter().flat_map(|b| b.0.iter());
+    let used_args = insts
+        .clone()
+        .filter_map(|(_, inst)| match inst {
+            Instruction::Load(a, _) => match a.as_ref() {
+                Value::Argument(k, _) => Some(*k),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    let mut insts_before = vec![];
+    let mut arg_ptrs = HashMap::new();
+    for (k, (v, ty)) in args.iter().enumerate() {
+        if !used_args.contains(&k) {
+            continue;
+        }
+        let ptr = new_register(next_reg);
+        insts_before.push((ptr.clone(), Instruction::Alloc(*ty)));
+        insts_before.push((
+            Arc::new(Value::None),
+            Instruction::Store(ptr.clone(), v.clone(), *ty),
+        ));
+        arg_ptrs.insert(k, ptr);
+    }
+    let renamed = insts
+        .filter(|(dst, _)| matches!(dst.as_ref(), Value::Register(_)))
+        .map(|(dst, _)| (dst.clone(), new_register(next_reg)))
+        .collect::<HashMap<_, _>>();
+    let mut blocks = callee
+        .body
+        .iter()
+        .map(|block| {
+            let insts = block.0.iter().map(|(dst, inst)| {
+                let dst = renamed.get(dst).unwrap_or(dst).clone();
+                let mut inst = inst.clone();
+                match &mut inst {
+                    Instruction::Load(a, _) => {
+                        if let Value::Argument(k, _) = a.as_ref() {
+                            *a = arg_ptrs[k].clone();
+                        }
+                    }
+                    // the blocks except for the first one are placed after the block of the call.
+                    Instruction::JmpIf(_, t, e, p) => {
+                        for b in [t, e, p] {
+                            *b += bidx as u64;
+                        }
+                    }
+                    _ => {}
+                }
+                for v in operands_mut(&mut inst) {
+                    if let Some(r) = renamed.get(v) {
+                        *v = r.clone();
+                    }
+                }
+                (dst, inst)
+            });
+            Block(insts.collect())
+        })
+        .collect::<Vec<_>>();
+
+    let mut subst = HashMap::new();
+    let last = blocks.last_mut().unwrap();
+    match last.0.pop() {
+        Some((_, Instruction::Return(v, ty))) => match v.as_ref() {
+            Value::None => last.0.push((dst.clone(), Instruction::Alloc(ty))),
+            _ => {
+                subst.insert(dst.clone(), v);
+            }
+        },
+        Some((_, Instruction::ReturnFeed(v, ty))) => {
+            let old = new_register(next_reg);
+            last.0.push((old.clone(), Instruction::GetState(ty)));
+            last.0
+                .push((Arc::new(Value::None), Instruction::SetState(v, ty)));
+            subst.insert(dst.clone(), old);
+        }
+        _ => unreachable!("the callee must return at the end"),
+    }
+
+    let mut rest = caller.body[bidx].0.split_off(idx + 1);
+    caller.body[bidx].0.pop();
+    // the blocks of the caller after the call are moved back by the blocks of the callee.
+    let nblocks = blocks.len() as u64 - 1;
+    let shift = |inst: &mut Instruction| {
+        if let Instruction::JmpIf(_, t, e, p) = inst {
+            for b in [t, e, p] {
+                if *b > bidx as u64 {
+                    *b += nblocks;
+                }
+            }
+        }
+    };
+    for block in caller.body.iter_mut() {
+        if let Some((_, inst)) = block.0.last_mut() {
+            shift(inst);
+        }
+    }
+    if let Some((_, inst)) = rest.last_mut() {
+        shift(inst);
+    }
+    blocks.last_mut().unwrap().0.extend(rest);
+    let mut blocks = blocks.into_iter();
+    let first = blocks.next().unwrap();
+    let block = &mut caller.body[bidx].0;
+    block.extend(insts_before);
+    block.extend(first.0);
+    caller.body.splice(bidx + 1..bidx + 1, blocks);
+    substitute(caller, &subst);
+}
+
+fn max_register(mir: &Mir) -> VReg {
+    let mut regs = vec![];
+    for f in mir.functions.iter() {
+        f.body
+            .iter()
+            .flat_map(|b| b.0.iter())
+            .for_each(|(dst, _)| registers(dst, &mut regs));
+        f.upindexes.iter().for_each(|v| registers(v, &mut regs));
+    }
+    regs.into_iter().max().unwrap_or(0)
+}
+
+/// Inlines the direct calls to the functions which are small or called only once, or which
+/// have `#[inline]`. Returns true if any call was inlined.
+pub fn inline_functions(mir: &mut Mir) -> bool {
+    let recursive = recursive_functions(mir);
+    let mut next_reg = max_register(mir) + 1;
+    let mut changed = false;
+    for caller_i in 0..mir.functions.len() {
+        while let Some((bidx, idx, callee_i)) = find_inlinable_call(mir, caller_i, &recursive) {
+            let callee = mir.functions[callee_i].clone();
+            log::trace!(
+                "inlining {} into {}",
+                callee.label,
+                mir.functions[caller_i].label
+            );
+            inline_call(
+                &mut mir.functions[caller_i],
+                bidx,
+                idx,
+                &callee,
+                &mut next_reg,
+            );
+            changed = true;
+        }
+    }
+    changed
+}
@@ -40,6 +40,15 @@ impl std::fmt::Display for Mir {
                 let _ = write!(f, "upper:{upper_i}");
             }
             let _ = write!(f, " state_size: {}", display_state_sizes(&fun.state_sizes));
+            match fun.inline_hint {
+                InlineHint::Auto => {}
+                InlineHint::Always => {
+                    let _ = write!(f, " #[inline]");
+                }
+                InlineHint::Never => {
+                    let _ = write!(f, " #[noinline]");
+                }
+            }
             for (i, block) in fun.body.iter().enumerate() {
                 let _ = write!(f, "\n  block {i}\n");
                 for (v, insts) in block.0.iter() {
@@ -96,6 +105,13 @@ impl std::fmt::Display for Instruction {
                 let ty = ty.to_type();
                 write!(f, "getelement {value}, {ty}, {tuple_offset}[{array_idx}]")
             }
+            Instruction::AllocArray(len, ty) => write!(f, "allocarray {len}, {}", ty.to_type()),
+            Instruction::GetArrayElem(arr, idx, ty) => {
+                write!(f, "getarrayelem {arr}, {idx}, {}", ty.to_type())
+            }
+            Instruction::SetArrayElem(arr, idx, src, ty) => {
+                write!(f, "setarrayelem {arr}, {idx}, {src}, {}", ty.to_type())
+            }
             Instruction::Call(fptr, args, rty) => {
                 write!(
                     f,
@@ -147,6 +163,7 @@ impl std::fmt::Display for Instruction {
             }
 
             Instruction::GetState(ty) => write!(f, "getstate {}", ty.to_type()),
+            Instruction::SetState(v, ty) => write!(f, "setstate {} {}", *v, ty.to_type()),
             Instruction::JmpIf(cond, tbb, ebb, pbb) => write!(f, "jmpif {cond} {tbb} {ebb} {pbb}"),
             Instruction::Jmp(bb) => write!(f, "jmp {bb}"),
             Instruction::Phi(t, e) => write!(f, "phi {t} {e}"),
@@ -173,7 +190,7 @@ impl std::fmt::Display for Instruction {
             Instruction::ModI(a, b) => write!(f, "modi {} {}", *a, *b),
             Instruction::NegI(a) => write!(f, "negi {}", *a),
             Instruction::AbsI(a) => write!(f, "absi {}", *a),
-            Instruction::PowI(a) => write!(f, "powi {}", *a),
+            Instruction::PowI(a, b) => write!(f, "powi {} {}", *a, *b),
             Instruction::LogI(_, _) => todo!(),
             Instruction::Not(_) => todo!(),
             Instruction::Eq(a, b) => write!(f, "eq {} {}", *a, *b),
@@ -184,8 +201,8 @@ impl std::fmt::Display for Instruction {
             Instruction::Le(a, b) => write!(f, "le {} {}", *a, *b),
             Instruction::And(a, b) => write!(f, "and {} {}", *a, *b),
             Instruction::Or(a, b) => write!(f, "or {} {}", *a, *b),
-            Instruction::CastFtoI(_) => todo!(),
-            Instruction::CastItoF(_) => todo!(),
+            Instruction::CastFtoI(a) => write!(f, "casttoint {}", *a),
+            Instruction::CastItoF(a) => write!(f, "casttofloat {}", *a),
             Instruction::CastItoB(_) => todo!(),
         }
     }
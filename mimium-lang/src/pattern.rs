@@ -8,6 +8,10 @@ use crate::utils::miniprint::MiniPrint;
 pub enum Pattern {
     Single(Symbol),
     Tuple(Vec<Self>),
+    Record(Vec<(Symbol, Self)>),
+    // a variant of the sum type with the patterns for its fields, like `Pulse(freq, width)`.
+    // This can be used only in the arms of `match`.
+    Variant(Symbol, Vec<Self>),
 }
 
 impl std::fmt::Display for Pattern {
@@ -22,6 +26,22 @@ impl std::fmt::Display for Pattern {
                     .concat();
                 write!(f, "({s})")
             }
+            Pattern::Record(fields) => {
+                let s = fields
+                    .iter()
+                    .map(|(name, p)| format!("{name} = {p}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "{{{s}}}")
+            }
+            Pattern::Variant(name, fields) => {
+                let s = fields
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "{name}({s})")
+            }
         }
     }
 }
@@ -64,6 +84,17 @@ impl MiniPrint for TypedId {
     }
 }
 
+/// Collects the names bound by the pattern.
+pub fn pattern_names(pat: &Pattern, names: &mut Vec<Symbol>) {
+    match pat {
+        Pattern::Single(id) => names.push(*id),
+        Pattern::Tuple(pats) | Pattern::Variant(_, pats) => {
+            pats.iter().for_each(|p| pattern_names(p, names))
+        }
+        Pattern::Record(fields) => fields.iter().for_each(|(_, p)| pattern_names(p, names)),
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct TypedPattern {
     pub pat: Pattern,
@@ -106,7 +137,7 @@ impl TryFrom<TypedPattern> for TypedId {
     fn try_from(value: TypedPattern) -> Result<Self, Self::Error> {
         match value.pat {
             Pattern::Single(id) => Ok(TypedId { id, ty: value.ty }),
-            Pattern::Tuple(_) => Err(ConversionError),
+            Pattern::Tuple(_) | Pattern::Record(_) | Pattern::Variant(_, _) => Err(ConversionError),
         }
     }
 }
@@ -8,10 +8,14 @@
 //! 3. **System Plugin**. If your plugin needs to mutate states of system-wide instance (1 plugin instance per 1 vm), you need to implement `SystemPlugin` traits. System plugin can have callbacks invoked at the important timings of the system like `on_init`, `before_on_sample` & so on. Internal synchronous event scheduler is implemented through this plugins system. `mimium-rand` is also an example of this type of module.
 
 mod system_plugin;
-pub use system_plugin::{to_ext_cls_info, DynSystemPlugin, SysPluginSignature, SystemPlugin,SystemPluginFnType};
+pub use system_plugin::{
+    get_signature_types, get_sysplugin_types, to_ext_cls_info, DynSystemPlugin, SysPluginSignature,
+    SystemPlugin, SystemPluginFnType,
+};
 
 use crate::{
     compiler::ExtFunTypeInfo,
+    interner::{Symbol, ToSymbol},
     runtime::vm::{ExtClsInfo, ExtFnInfo},
 };
 
@@ -62,6 +66,14 @@ pub trait UGenPlugin {
 // pub type UGenPluginCollection(Vec<DynUGenPlugin>);
 // impl Plugin for UGenPluginCollection{}
 
+/// The name of the plugin shown in the messages, which is the name of its type without the
+/// module path and the type parameters.
+pub fn get_plugin_name<T: ?Sized>() -> Symbol {
+    let name = std::any::type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name).to_symbol()
+}
+
 pub fn get_extfun_types(plugins: &[Box<dyn Plugin>]) -> impl Iterator<Item = ExtFunTypeInfo> + '_ {
     plugins.iter().flat_map(|plugin| {
         plugin
@@ -1,4 +1,5 @@
 use crate::{
+    compiler::ExtFunTypeInfo,
     interner::{ToSymbol, TypeNodeId},
     runtime::{
         vm::{ExtClsInfo, Machine, ReturnCode},
@@ -50,6 +51,21 @@ pub trait SystemPlugin {
 #[derive(Clone)]
 pub struct DynSystemPlugin(pub Rc<UnsafeCell<dyn SystemPlugin>>);
 
+/// The types of the functions given by the system plugin, without loading it.
+pub fn get_sysplugin_types<T: SystemPlugin>(sysplugin: &T) -> Vec<ExtFunTypeInfo> {
+    get_signature_types(sysplugin.gen_interfaces())
+}
+/// The types of the functions in the signatures, for the plugins which give their signatures
+/// without being instantiated.
+pub fn get_signature_types(signatures: Vec<SysPluginSignature>) -> Vec<ExtFunTypeInfo> {
+    signatures
+        .into_iter()
+        .map(|SysPluginSignature { name, ty, .. }| ExtFunTypeInfo {
+            name: name.to_symbol(),
+            ty,
+        })
+        .collect()
+}
 
 pub fn to_ext_cls_info<T: SystemPlugin + 'static>(
     sysplugin: T,
@@ -4,10 +4,7 @@ use crate::{
     utils::error,
     utils::miniprint::MiniPrint,
 };
-use std::{
-    io::{stdin, stdout, Write},
-    path::PathBuf,
-};
+use std::io::{stdin, stdout, Write};
 
 pub enum ReplMode {
     Eval,
@@ -67,7 +64,7 @@ fn repl(data: &mut ReplAppData) -> ! {
                         Ok(v) => {
                             println!("{:?}", v);
                         }
-                        Err(e) => error::report(&src, PathBuf::new(), &e),
+                        Err(e) => error::report(&e),
                     },
                     ReplMode::EvalMulti(n) => {
                         let mut res = Ok(Value::Primitive(PValue::Numeric(0.0)));
@@ -79,14 +76,14 @@ fn repl(data: &mut ReplAppData) -> ! {
                             Ok(v) => {
                                 println!("{:?}", v);
                             }
-                            Err(e) => error::report(&src, PathBuf::new(), &e),
+                            Err(e) => error::report(&e),
                         }
                     }
                     ReplMode::ShowAST => match compiler::emit_ast(&src, None) {
                         Ok(ast) => {
                             println!("{}", ast.pretty_print());
                         }
-                        Err(e) => error::report(&src, PathBuf::new(), &e),
+                        Err(e) => error::report(&e),
                     },
                 }
                 src.clear();
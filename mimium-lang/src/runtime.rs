@@ -1,3 +1,4 @@
+use crate::interner::{Symbol, TypeNodeId};
 use crate::utils::{error::ReportableError, metadata::Span};
 
 // pub mod scheduler;
@@ -11,12 +12,33 @@ pub struct Time(pub u64);
 #[derive(Debug)]
 pub enum ErrorKind {
     Unknown,
+    /// The external function used in the program is not given by the plugins.
+    ExtFunNotFound(Symbol),
+    /// The type of the external function in the program, and the one given by the plugins.
+    ExtFunTypeMismatch(Symbol, TypeNodeId, TypeNodeId),
+    DivisionByZero,
+    /// The length of the array, and the index.
+    IndexOutOfRange(usize, i64),
 }
 
 impl std::fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ErrorKind::Unknown => write!(f, "Unknown Error"),
+            ErrorKind::ExtFunNotFound(name) => {
+                write!(f, "external function {name} cannot be found")
+            }
+            ErrorKind::ExtFunTypeMismatch(name, expected, found) => write!(
+                f,
+                "external function {name} is used as {}, but the plugins give {}",
+                expected.to_type(),
+                found.to_type()
+            ),
+            ErrorKind::DivisionByZero => write!(f, "integer division by zero"),
+            ErrorKind::IndexOutOfRange(len, i) => write!(
+                f,
+                "array index out of range: the length is {len} but the index was {i}"
+            ),
         }
     }
 }
@@ -32,7 +54,7 @@ impl std::fmt::Display for Error {
 impl std::error::Error for Error {}
 
 impl ReportableError for Error {
-    fn get_span(&self) -> std::ops::Range<usize> {
+    fn get_span(&self) -> Span {
         self.1.clone()
     }
 }
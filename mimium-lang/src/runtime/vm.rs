@@ -1,11 +1,20 @@
 use core::slice;
 use slotmap::{DefaultKey, SlotMap};
-use std::{cell::RefCell, cmp::Ordering, collections::HashMap, ops::Range, rc::Rc, sync::Arc};
+use std::{
+    cell::RefCell, cmp::Ordering, collections::HashMap, ops::Range, path::PathBuf, rc::Rc,
+    sync::Arc,
+};
 
+mod array;
+pub mod assembler;
+pub mod binary;
 pub mod builtin;
 pub mod bytecode;
+#[cfg(feature = "jit")]
+pub mod jit;
 pub mod program;
 mod ringbuffer;
+pub use array::{ArrayIdx, ArrayStorage, ArrayValue};
 pub use bytecode::*;
 use ringbuffer::Ringbuffer;
 
@@ -14,11 +23,15 @@ pub use program::{FuncProto, Program};
 
 use crate::{
     compiler::bytecodegen::ByteCodeGenerator,
-    interner::{Symbol, TypeNodeId},
+    interner::{with_session_globals, Symbol, TypeNodeId},
+    runtime,
     types::{Type, TypeSize},
+    utils::metadata::Span,
 };
 pub type RawVal = u64;
 pub type ReturnCode = i64;
+/// Return code when the execution is aborted by a runtime error such as an out-of-range array access.
+pub const RUNTIME_ERROR_CODE: ReturnCode = -2;
 
 pub type ExtFunType = fn(&mut Machine) -> ReturnCode;
 pub type ExtClsType = Rc<RefCell<dyn FnMut(&mut Machine) -> ReturnCode>>;
@@ -100,7 +113,7 @@ impl LocalUpValueMap {
         let OpenUpValue { pos, .. } = ov;
         self.0
             .iter()
-            .find_map(|(i2, v)| (pos == *i2 as _).then_some(v.clone()))
+            .find_map(|(i2, v)| (pos == *i2 as usize).then_some(v.clone()))
             .unwrap_or_else(|| {
                 let v = Rc::new(RefCell::new(UpValue::Open(ov)));
                 self.0.push((pos as Reg, v.clone()));
@@ -192,7 +205,9 @@ pub struct Machine {
     pub prog: Program,
     stack: Vec<RawVal>,
     base_pointer: u64,
+    spill_stack: Vec<RawVal>,
     pub closures: ClosureStorage,
+    pub arrays: ArrayStorage,
     pub ext_fun_table: Vec<(Symbol, ExtFunType)>,
     pub ext_cls_table: Vec<(Symbol, ExtClsType)>,
     fn_map: HashMap<usize, ExtFnIdx>, //index from fntable index of program to it of machine.
@@ -202,6 +217,10 @@ pub struct Machine {
     delaysizes_pos_stack: Vec<usize>,
     global_vals: Vec<RawVal>,
     debug_stacktype: Vec<RawValType>,
+    // the first error occurred while running the program, kept until it is taken.
+    runtime_error: Option<runtime::Error>,
+    #[cfg(feature = "jit")]
+    jit: Option<jit::JitEngine>,
 }
 
 macro_rules! binop {
@@ -315,16 +334,31 @@ where
 }
 
 impl Machine {
+    /// Makes the machine for the program compiled with the same external functions. Panics if
+    /// they do not match; use [`Self::try_new`] for the programs loaded from the files.
     pub fn new<'a>(
         prog: Program,
         extfns: impl Iterator<Item = ExtFnInfo>,
         extcls: impl Iterator<Item = ExtClsInfo>,
     ) -> Self {
+        Self::try_new(prog, extfns, extcls).unwrap_or_else(|errs| panic!("{}", errs[0]))
+    }
+    /// Makes the machine in the same way as [`Self::new`], but returns the errors instead of
+    /// panicking when the external functions used in the program are missing in `extfns` and
+    /// `extcls`, or have the different types. This is for the programs loaded from the files,
+    /// which may be compiled with the other plugins.
+    pub fn try_new(
+        prog: Program,
+        extfns: impl Iterator<Item = ExtFnInfo>,
+        extcls: impl Iterator<Item = ExtClsInfo>,
+    ) -> Result<Self, Vec<runtime::Error>> {
         let mut res = Self {
             prog,
             stack: vec![],
             base_pointer: 0,
+            spill_stack: vec![],
             closures: Default::default(),
+            arrays: Default::default(),
             ext_fun_table: vec![],
             ext_cls_table: vec![],
             fn_map: HashMap::new(),
@@ -334,15 +368,21 @@ impl Machine {
             delaysizes_pos_stack: vec![0],
             global_vals: vec![],
             debug_stacktype: vec![RawValType::Int; 255],
+            runtime_error: None,
+            #[cfg(feature = "jit")]
+            jit: None,
         };
-        extfns.for_each(|(name, f, _)| {
+        let mut types = HashMap::new();
+        extfns.for_each(|(name, f, ty)| {
             let _ = res.install_extern_fn(name, f);
+            types.insert(name, ty);
         });
-        extcls.for_each(|(name, f, _)| {
+        extcls.for_each(|(name, f, ty)| {
             let _ = res.install_extern_cls(name, f);
+            types.insert(name, ty);
         });
-        res.link_functions();
-        res
+        res.link_functions(&types)?;
+        Ok(res)
     }
     pub fn clear_stack(&mut self) {
         self.stack.fill(0);
@@ -507,6 +547,52 @@ impl Machine {
         self.delaysizes_pos_stack.pop();
         nret
     }
+    fn call_closure(&mut self, func: Reg, nargs: u8, nret_req: u8) -> ReturnCode {
+        let addr = self.get_stack(func as i64);
+        let cls_i = Self::get_as::<ClosureIdx>(addr);
+        let cls = self.get_closure(cls_i);
+        let pos_of_f = cls.fn_proto_pos;
+        self.states_stack.push(cls_i);
+        let nret = self.call_function(func, nargs, nret_req, move |machine| {
+            machine.execute(pos_of_f, Some(cls_i))
+        });
+        self.states_stack.pop();
+        nret
+    }
+    fn call_internal(&mut self, func: Reg, nargs: u8, nret_req: u8) -> ReturnCode {
+        let pos_of_f = Self::get_as::<usize>(self.get_stack(func as i64));
+        self.call_function(func, nargs, nret_req, move |machine| {
+            machine.execute(pos_of_f, None)
+        })
+    }
+    fn call_ext_function(&mut self, func: Reg, nargs: u8, nret_req: u8) -> ReturnCode {
+        let ext_fn_idx = self.get_stack(func as i64) as usize;
+        let fidx = self.fn_map.get(&ext_fn_idx).unwrap();
+        let nret = match fidx {
+            ExtFnIdx::Fun(fi) => {
+                let f = self.ext_fun_table[*fi].1;
+                self.call_function(func, nargs, nret_req, f)
+            }
+            ExtFnIdx::Cls(ci) => {
+                let (_name, cls) = &self.ext_cls_table[*ci];
+                let cls = cls.clone();
+                self.call_function(func, nargs, nret_req, move |machine| {
+                    cls.borrow_mut()(machine)
+                })
+            }
+        };
+        if nret < 0 {
+            return nret;
+        }
+
+        // return
+        let base = self.base_pointer as usize;
+        let iret = base + func as usize + 1;
+        self.stack
+            .copy_within(iret..(iret + nret as usize), base + func as usize);
+        self.stack.truncate(base + func as usize + nret as usize);
+        nret
+    }
     fn allocate_closure(&mut self, fn_i: usize, upv_map: &mut LocalUpValueMap) -> ClosureIdx {
         let idx = self
             .closures
@@ -607,14 +693,96 @@ impl Machine {
             }
         }
     }
+    /// Keeps the error to be taken by [`Self::take_runtime_error`], and returns the code to stop
+    /// the execution. Only the first error is kept, so that an error occurring at every sample
+    /// is reported once.
+    pub(crate) fn raise(&mut self, kind: runtime::ErrorKind) -> ReturnCode {
+        if self.runtime_error.is_none() {
+            self.runtime_error = Some(runtime::Error(kind, self.program_span()));
+        }
+        RUNTIME_ERROR_CODE
+    }
+    /// Takes the error which made the execution return [`RUNTIME_ERROR_CODE`], if any.
+    pub fn take_runtime_error(&mut self) -> Option<runtime::Error> {
+        self.runtime_error.take()
+    }
+    fn check_int_divisor(&mut self, src: Reg) -> Result<(), ReturnCode> {
+        if Self::get_as::<i64>(self.get_stack(src as i64)) == 0 {
+            Err(self.raise(runtime::ErrorKind::DivisionByZero))
+        } else {
+            Ok(())
+        }
+    }
+    /// Power of integers. A negative exponent results in 0 unless the base is 1 or -1,
+    /// as same as the truncated result of the real number.
+    pub(crate) fn int_pow(base: i64, exp: i64) -> i64 {
+        match (base, exp) {
+            (_, 0..) => base.wrapping_pow(exp.min(u32::MAX as i64) as u32),
+            (1, _) => 1,
+            (-1, _) if exp % 2 == 0 => 1,
+            (-1, _) => -1,
+            _ => 0,
+        }
+    }
+    fn check_array_index(&mut self, arr: ArrayIdx, i: i64) -> Result<usize, ReturnCode> {
+        let len = self.arrays.get(arr).len();
+        if i >= 0 && (i as usize) < len {
+            Ok(i as usize)
+        } else {
+            Err(self.raise(runtime::ErrorKind::IndexOutOfRange(len, i)))
+        }
+    }
+    fn load_array_elem(&mut self, dst: Reg, arr: Reg, idx: Reg) -> Result<(), ReturnCode> {
+        let arr_i = Self::get_as::<ArrayIdx>(self.get_stack(arr as i64));
+        let i = self.check_array_index(arr_i, Self::get_as::<i64>(self.get_stack(idx as i64)))?;
+        let v = self.arrays.get(arr_i).get_elem(i).unwrap();
+        set_vec_range(
+            &mut self.stack,
+            self.base_pointer as usize + dst as usize,
+            v,
+        );
+        Ok(())
+    }
+    fn store_array_elem(&mut self, arr: Reg, idx: Reg, src: Reg) -> Result<(), ReturnCode> {
+        let arr_i = Self::get_as::<ArrayIdx>(self.get_stack(arr as i64));
+        let i = self.check_array_index(arr_i, Self::get_as::<i64>(self.get_stack(idx as i64)))?;
+        let size = self.arrays.get(arr_i).elem_word_size() as usize;
+        let start = self.base_pointer as usize + src as usize;
+        let v = &self.stack[start..start + size];
+        self.arrays
+            .get_mut(arr_i)
+            .get_elem_mut(i)
+            .unwrap()
+            .copy_from_slice(v);
+        Ok(())
+    }
+    fn process_delay(&mut self, func_i: usize, input: RawVal, time: RawVal) -> RawVal {
+        let delaysize_i = unsafe { self.delaysizes_pos_stack.last().unwrap_unchecked() };
+        let size_in_samples = unsafe {
+            *self
+                .get_fnproto(func_i)
+                .delay_sizes
+                .get_unchecked(*delaysize_i)
+        };
+        let mut ringbuf = self.get_current_state().get_as_ringbuffer(size_in_samples);
+        ringbuf.process(input, time)
+    }
     fn get_fnproto(&self, func_i: usize) -> &FuncProto {
         &self.prog.global_fn_table[func_i].1
     }
     /// Execute function, return retcode.
     pub fn execute(&mut self, func_i: usize, cls_i: Option<ClosureIdx>) -> ReturnCode {
+        #[cfg(feature = "jit")]
+        if let Some(f) = self.jit.as_ref().and_then(|jit| jit.get(func_i)) {
+            return unsafe { f(self) };
+        }
         let mut local_closures: Vec<ClosureIdx> = vec![];
         let mut upv_map = LocalUpValueMap::default();
         let mut pcounter = 0;
+        // the spill slots of this call are placed after the ones of the callers.
+        let spill_base = self.spill_stack.len();
+        let spill_size = self.get_fnproto(func_i).spill_size as usize;
+        self.spill_stack.resize(spill_base + spill_size, 0);
         // if cfg!(test) {
         //     log::trace!("{:?}", func);
         // }
@@ -655,45 +823,22 @@ impl Machine {
                     self.move_stack_range(dst as i64, range);
                 }
                 Instruction::CallCls(func, nargs, nret_req) => {
-                    let addr = self.get_stack(func as i64);
-                    let cls_i = Self::get_as::<ClosureIdx>(addr);
-                    let cls = self.get_closure(cls_i);
-                    let pos_of_f = cls.fn_proto_pos;
-                    self.states_stack.push(cls_i);
-                    self.call_function(func, nargs, nret_req, move |machine| {
-                        machine.execute(pos_of_f, Some(cls_i))
-                    });
-                    self.states_stack.pop();
+                    let nret = self.call_closure(func, nargs, nret_req);
+                    if nret < 0 {
+                        return nret;
+                    }
                 }
                 Instruction::Call(func, nargs, nret_req) => {
-                    let pos_of_f = Self::get_as::<usize>(self.get_stack(func as i64));
-                    self.call_function(func, nargs, nret_req, move |machine| {
-                        machine.execute(pos_of_f, None)
-                    });
+                    let nret = self.call_internal(func, nargs, nret_req);
+                    if nret < 0 {
+                        return nret;
+                    }
                 }
                 Instruction::CallExtFun(func, nargs, nret_req) => {
-                    let ext_fn_idx = self.get_stack(func as i64) as usize;
-                    let fidx = self.fn_map.get(&ext_fn_idx).unwrap();
-                    let nret = match fidx {
-                        ExtFnIdx::Fun(fi) => {
-                            let f = self.ext_fun_table[*fi].1;
-                            self.call_function(func, nargs, nret_req, f)
-                        }
-                        ExtFnIdx::Cls(ci) => {
-                            let (_name, cls) = &self.ext_cls_table[*ci];
-                            let cls = cls.clone();
-                            self.call_function(func, nargs, nret_req, move |machine| {
-                                cls.borrow_mut()(machine)
-                            })
-                        }
-                    };
-
-                    // return
-                    let base = self.base_pointer as usize;
-                    let iret = base + func as usize + 1;
-                    self.stack
-                        .copy_within(iret..(iret + nret as usize), base + func as usize);
-                    self.stack.truncate(base + func as usize + nret as usize);
+                    let nret = self.call_ext_function(func, nargs, nret_req);
+                    if nret < 0 {
+                        return nret;
+                    }
                 }
                 Instruction::Closure(dst, fn_index) => {
                     let fn_proto_pos = self.get_stack(fn_index as i64) as usize;
@@ -706,11 +851,13 @@ impl Machine {
                 }
                 Instruction::Return0 => {
                     self.stack.truncate((self.base_pointer - 1) as usize);
+                    self.spill_stack.truncate(spill_base);
                     self.release_open_closures(&local_closures);
                     return 0;
                 }
                 Instruction::Return(iret, nret) => {
                     let _ = self.return_general(iret, nret);
+                    self.spill_stack.truncate(spill_base);
                     self.release_open_closures(&local_closures);
                     return nret.into();
                 }
@@ -773,24 +920,32 @@ impl Machine {
                         }
                     };
                 }
-                Instruction::GetGlobal(dst, gid, size) => {
-                    let gvs = unsafe {
-                        let vstart = self.global_vals.as_ptr().offset(gid as _);
-                        debug_assert!(!vstart.is_null());
-                        // debug_assert!(vstart.is_aligned());
-                        slice::from_raw_parts(vstart, size as _)
-                    };
-                    self.set_stack_range(dst as i64, gvs)
-                }
-                Instruction::SetGlobal(gid, src, size) => {
-                    let gvs = unsafe {
-                        let vstart = self.global_vals.as_mut_ptr().offset(gid as _);
-                        debug_assert!(!vstart.is_null());
-                        // debug_assert!(vstart.is_aligned());
-                        slice::from_raw_parts_mut(vstart, size as _)
-                    };
-                    let (_, slice) = self.get_stack_range(src as i64, size);
-                    gvs.copy_from_slice(slice);
+                Instruction::GetGlobal(dst, gid) => {
+                    self.set_stack(dst as i64, self.global_vals[gid as usize]);
+                }
+                Instruction::SetGlobal(gid, src) => {
+                    self.global_vals[gid as usize] = self.get_stack(src as i64);
+                }
+                Instruction::Spill(slot, src) => {
+                    self.spill_stack[spill_base + slot as usize] = self.get_stack(src as i64);
+                }
+                Instruction::Reload(dst, slot) => {
+                    self.set_stack(dst as i64, self.spill_stack[spill_base + slot as usize]);
+                }
+                Instruction::AllocArray(dst, len, elem_size) => {
+                    let len = self.get_stack(len as i64) as usize;
+                    let idx = self.arrays.alloc(len, elem_size);
+                    self.set_stack(dst as i64, Self::to_value(idx));
+                }
+                Instruction::GetArrayElem(dst, arr, idx) => {
+                    if let Err(code) = self.load_array_elem(dst, arr, idx) {
+                        return code;
+                    }
+                }
+                Instruction::SetArrayElem(arr, idx, src) => {
+                    if let Err(code) = self.store_array_elem(arr, idx, src) {
+                        return code;
+                    }
                 }
                 Instruction::Jmp(offset) => {
                     // -1 is for the offset in last increment
@@ -818,14 +973,34 @@ impl Machine {
                     binopmethod!(powf, f64, dst, src1, src2, self)
                 }
                 Instruction::LogF(dst, src1, src2) => binopmethod!(log, f64, dst, src1, src2, self),
-                Instruction::AddI(dst, src1, src2) => binop!(+,i64,dst,src1,src2,self),
-                Instruction::SubI(dst, src1, src2) => binop!(-,i64,dst,src1,src2,self),
-                Instruction::MulI(dst, src1, src2) => binop!(*,i64,dst,src1,src2,self),
-                Instruction::DivI(dst, src1, src2) => binop!(/,i64,dst,src1,src2,self),
-                Instruction::ModI(dst, src1, src2) => binop!(%,i64,dst,src1,src2,self),
-                Instruction::NegI(dst, src) => uniop!(-,i64,dst,src,self),
-                Instruction::AbsI(dst, src) => uniopmethod!(abs, i64, dst, src, self),
-                Instruction::PowI(dst, lhs, rhs) => binop!(^,i64,dst,lhs,rhs,self),
+                Instruction::AddI(dst, src1, src2) => {
+                    binopmethod!(wrapping_add, i64, dst, src1, src2, self)
+                }
+                Instruction::SubI(dst, src1, src2) => {
+                    binopmethod!(wrapping_sub, i64, dst, src1, src2, self)
+                }
+                Instruction::MulI(dst, src1, src2) => {
+                    binopmethod!(wrapping_mul, i64, dst, src1, src2, self)
+                }
+                Instruction::DivI(dst, src1, src2) => {
+                    if let Err(code) = self.check_int_divisor(src2) {
+                        return code;
+                    }
+                    binopmethod!(wrapping_div, i64, dst, src1, src2, self)
+                }
+                Instruction::ModI(dst, src1, src2) => {
+                    if let Err(code) = self.check_int_divisor(src2) {
+                        return code;
+                    }
+                    binopmethod!(wrapping_rem, i64, dst, src1, src2, self)
+                }
+                Instruction::NegI(dst, src) => uniopmethod!(wrapping_neg, i64, dst, src, self),
+                Instruction::AbsI(dst, src) => uniopmethod!(wrapping_abs, i64, dst, src, self),
+                Instruction::PowI(dst, lhs, rhs) => {
+                    let base = Self::get_as::<i64>(self.get_stack(lhs as i64));
+                    let exp = Self::get_as::<i64>(self.get_stack(rhs as i64));
+                    self.set_stack(dst as i64, Self::to_value::<i64>(Self::int_pow(base, exp)));
+                }
                 Instruction::LogI(_, _, _) => todo!(),
                 Instruction::Not(dst, src) => uniop_bool!(!, dst, src, self),
                 Instruction::Eq(dst, src1, src2) => binop_bool!(==,dst,src1,src2,self),
@@ -867,18 +1042,7 @@ impl Machine {
                 Instruction::Delay(dst, src, time) => {
                     let i = self.get_stack(src as i64);
                     let t = self.get_stack(time as i64);
-                    let delaysize_i =
-                        unsafe { self.delaysizes_pos_stack.last().unwrap_unchecked() };
-
-                    let size_in_samples = unsafe {
-                        *self
-                            .get_fnproto(func_i)
-                            .delay_sizes
-                            .get_unchecked(*delaysize_i)
-                    };
-                    let mut ringbuf = self.get_current_state().get_as_ringbuffer(size_in_samples);
-
-                    let res = ringbuf.process(i, t);
+                    let res = self.process_delay(func_i, i, t);
                     self.set_stack(dst as i64, res);
                 }
                 Instruction::Mem(dst, src) => {
@@ -905,32 +1069,81 @@ impl Machine {
         self.ext_cls_table.len() - 1
     }
 
-    fn link_functions(&mut self) {
+    /// Compiles the functions of the program to the native code, which is run instead of the
+    /// bytecode from the next call. The functions which cannot be compiled are interpreted as
+    /// before, and returned with the reasons.
+    #[cfg(feature = "jit")]
+    pub fn enable_jit(&mut self) -> Result<Vec<(Symbol, jit::Error)>, jit::Error> {
+        let (engine, errs) = jit::JitEngine::new(&self.prog)?;
+        self.jit = Some(engine);
+        Ok(errs)
+    }
+    /// Goes back to interpret all the functions.
+    #[cfg(feature = "jit")]
+    pub fn disable_jit(&mut self) {
+        self.jit = None;
+    }
+    /// Whether the function at the index runs as the native code.
+    #[cfg(feature = "jit")]
+    pub fn is_jit_compiled(&self, func_i: usize) -> bool {
+        self.jit
+            .as_ref()
+            .is_some_and(|jit| jit.get(func_i).is_some())
+    }
+
+    /// The head of the source file the program was compiled from, used to locate the errors
+    /// which are not tied to a particular expression. This is a dummy span when the source is
+    /// not loaded in this session, e.g. for the programs read from a binary file.
+    fn program_span(&self) -> Span {
+        let file = self.prog.file_path.and_then(|path| {
+            let path = PathBuf::from(path.to_string());
+            with_session_globals(|session_globals| session_globals.source_map.find_file(&path))
+        });
+        file.map_or_else(Span::default, |file| Span::new(file, 0..0))
+    }
+    fn link_functions(
+        &mut self,
+        types: &HashMap<Symbol, TypeNodeId>,
+    ) -> Result<(), Vec<runtime::Error>> {
         //link external functions
         self.global_vals = self.prog.global_vals.clone();
-        self.prog
-            .ext_fun_table
-            .iter_mut()
-            .enumerate()
-            .for_each(|(i, (name, _ty))| {
-                if let Some((j, _)) = self
-                    .ext_fun_table
-                    .iter()
-                    .enumerate()
-                    .find(|(_j, (fname, _fn))| name == fname)
-                {
-                    let _ = self.fn_map.insert(i, ExtFnIdx::Fun(j));
-                } else if let Some((j, _)) = self
-                    .ext_cls_table
-                    .iter()
-                    .enumerate()
-                    .find(|(_j, (fname, _fn))| name == fname)
+        let mut errs = vec![];
+        let span = self.program_span();
+        for (i, (name, ty)) in self.prog.ext_fun_table.iter().enumerate() {
+            let idx = if let Some(j) = self.ext_fun_table.iter().position(|(f, _)| name == f) {
+                ExtFnIdx::Fun(j)
+            } else if let Some(j) = self.ext_cls_table.iter().position(|(f, _)| name == f) {
+                ExtFnIdx::Cls(j)
+            } else {
+                errs.push(runtime::Error(
+                    runtime::ErrorKind::ExtFunNotFound(*name),
+                    span.clone(),
+                ));
+                continue;
+            };
+            // the types are unknown for the programs made by hand.
+            let is_unknown = |t: &TypeNodeId| matches!(t.to_type(), Type::Unknown);
+            match types.get(name) {
+                Some(ext_ty)
+                    if !is_unknown(ty)
+                        && !is_unknown(ext_ty)
+                        && !binary::is_same_type(*ty, *ext_ty) =>
                 {
-                    let _ = self.fn_map.insert(i, ExtFnIdx::Cls(j));
-                } else {
-                    panic!("external function {} cannot be found", name);
+                    errs.push(runtime::Error(
+                        runtime::ErrorKind::ExtFunTypeMismatch(*name, *ty, *ext_ty),
+                        span.clone(),
+                    ));
                 }
-            });
+                _ => {
+                    let _ = self.fn_map.insert(i, idx);
+                }
+            }
+        }
+        if errs.is_empty() {
+            Ok(())
+        } else {
+            Err(errs)
+        }
     }
     pub fn execute_idx(&mut self, idx: usize) -> ReturnCode {
         let (_name, func) = &self.prog.global_fn_table[idx];
@@ -941,7 +1154,11 @@ impl Machine {
                 self.stack[0] = 0;
             }
             self.base_pointer = 1;
-            self.execute(idx, None)
+            // the slots may be left by the execution aborted with an error.
+            self.spill_stack.clear();
+            let res = self.execute(idx, None);
+            self.collect_arrays();
+            res
         } else {
             0
         }
@@ -959,7 +1176,36 @@ impl Machine {
             .resize(self.prog.global_fn_table[0].1.state_size as usize);
         // 0 is always base pointer to the main function
         self.base_pointer += 1;
-        self.execute(0, None)
+        let res = self.execute(0, None);
+        self.collect_arrays();
+        res
+    }
+    /// Releases the arrays which are not reachable from the stack, the global values, the states
+    /// and the upvalues of closures.
+    fn collect_arrays(&mut self) {
+        if !self.arrays.needs_collection() {
+            return;
+        }
+        let upvalues = self
+            .closures
+            .values()
+            .flat_map(|cls| cls.upvalues.iter())
+            .filter_map(|v| match &*v.borrow() {
+                UpValue::Closed(v, _) => Some(v.clone()),
+                UpValue::Open(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let roots = [
+            &self.stack,
+            &self.spill_stack,
+            &self.global_vals,
+            &self.global_states.rawdata,
+        ]
+        .into_iter()
+        .chain(self.closures.values().map(|cls| &cls.state_storage.rawdata))
+        .chain(upvalues.iter())
+        .map(|v| v.as_slice());
+        self.arrays.collect_garbage(roots);
     }
 }
 
@@ -0,0 +1,119 @@
+use slotmap::{DefaultKey, SlotMap};
+use std::collections::{HashMap, HashSet};
+
+use super::{Machine, RawVal};
+use crate::types::TypeSize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArrayIdx(pub slotmap::DefaultKey);
+
+/// Heap-allocated array object. Elements are stored contiguously in the flat `data`,
+/// each of them occupies `elem_word_size` words (e.g. an array of tuples).
+#[derive(Debug, Default, PartialEq)]
+pub struct ArrayValue {
+    elem_word_size: TypeSize,
+    data: Vec<RawVal>,
+}
+impl ArrayValue {
+    pub fn new(len: usize, elem_word_size: TypeSize) -> Self {
+        Self {
+            elem_word_size,
+            data: vec![0; len * elem_word_size as usize],
+        }
+    }
+    pub fn len(&self) -> usize {
+        match self.elem_word_size {
+            0 => 0,
+            size => self.data.len() / size as usize,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn elem_word_size(&self) -> TypeSize {
+        self.elem_word_size
+    }
+    /// Returns `None` when the index is out of range.
+    pub fn get_elem(&self, i: usize) -> Option<&[RawVal]> {
+        let size = self.elem_word_size as usize;
+        self.data.get(i * size..(i + 1) * size)
+    }
+    /// Returns `None` when the index is out of range.
+    pub fn get_elem_mut(&mut self, i: usize) -> Option<&mut [RawVal]> {
+        let size = self.elem_word_size as usize;
+        self.data.get_mut(i * size..(i + 1) * size)
+    }
+}
+
+/// Storage of the arrays allocated in VM.
+/// The arrays are released when they become unreachable from the roots given to
+/// `collect_garbage`. As the words in VM have no types, a word equal to the id of a live array
+/// is regarded as a reference to it. This may keep an unused array a bit longer, but never
+/// releases the array in use.
+#[derive(Debug, Default)]
+pub struct ArrayStorage {
+    data: SlotMap<DefaultKey, ArrayValue>,
+    // the words allocated after the last collection, and the words left by it.
+    allocated_words: usize,
+    live_words: usize,
+    // buffers for marking the reachable arrays, kept to be reused by the next collection.
+    ids: HashMap<RawVal, DefaultKey>,
+    reachable: HashSet<DefaultKey>,
+    pending: Vec<DefaultKey>,
+}
+
+impl ArrayStorage {
+    /// The words to be allocated before the first collection. After that, a collection runs
+    /// when the allocated words exceed the words left by the previous one.
+    pub const MIN_COLLECTION_WORDS: usize = 1 << 14;
+    pub fn alloc(&mut self, len: usize, elem_word_size: TypeSize) -> ArrayIdx {
+        let arr = ArrayValue::new(len, elem_word_size);
+        // even an empty array occupies a slot.
+        self.allocated_words += arr.data.len().max(1);
+        ArrayIdx(self.data.insert(arr))
+    }
+    pub fn get(&self, idx: ArrayIdx) -> &ArrayValue {
+        self.data.get(idx.0).expect("Invalid Array Id referred")
+    }
+    pub fn get_mut(&mut self, idx: ArrayIdx) -> &mut ArrayValue {
+        self.data.get_mut(idx.0).expect("Invalid Array Id referred")
+    }
+    /// The number of the arrays not released yet.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    /// Returns true if enough words were allocated after the last collection. Until then, the
+    /// arrays which became unreachable are left, so that the cost of a collection, which scans
+    /// all the roots, is shared by many allocations.
+    pub fn needs_collection(&self) -> bool {
+        self.allocated_words >= self.live_words.max(Self::MIN_COLLECTION_WORDS)
+    }
+    /// Releases the arrays which are not reachable from the words in `roots`, directly or through
+    /// the elements of the other arrays.
+    pub fn collect_garbage<'a>(&mut self, roots: impl Iterator<Item = &'a [RawVal]>) {
+        let Self {
+            data,
+            allocated_words,
+            live_words,
+            ids,
+            reachable,
+            pending,
+        } = self;
+        ids.clear();
+        ids.extend(data.keys().map(|k| (Machine::to_value(ArrayIdx(k)), k)));
+        reachable.clear();
+        pending.clear();
+        pending.extend(roots.flatten().filter_map(|w| ids.get(w).copied()));
+        while let Some(k) = pending.pop() {
+            if reachable.insert(k) {
+                pending.extend(data[k].data.iter().filter_map(|w| ids.get(w).copied()));
+            }
+        }
+        data.retain(|k, _| reachable.contains(&k));
+        *allocated_words = 0;
+        *live_words = data.values().map(|arr| arr.data.len().max(1)).sum();
+    }
+}
@@ -0,0 +1,493 @@
+//! Assembler of the bytecode listing, in the syntax printed by the `Display` of [`Program`].
+//!
+//! Each function starts with its name on a line, followed by the lines of `key: value` for
+//! the fields of [`FuncProto`] (`nparams`, `nret`, `upindexes`, `state_size`, `delay_sizes`,
+//! `spill_size` and `constants`), which can be omitted when they are empty or zero. The
+//! instructions follow `instructions:`, each on an indented line. The listing ends with the
+//! sections of `ext_fun:`, `globals:` and `strings:`. `//` starts a comment until the end of
+//! the line.
+//!
+//! ```text
+//! dsp
+//! nparams:0 nret: 1
+//! constants:  [4611686018427387904]
+//! instructions:
+//!   movc       0 0 // 2.0
+//!   ret        0 1
+//! ```
+//!
+//! The types of the external functions are not in the listing, so they are left unknown and
+//! not checked on linking.
+
+use super::{
+    ConstPos, FuncProto, GlobalPos, Instruction, Offset, OpenUpValue, Program, Reg, SpillPos,
+};
+use crate::interner::ToSymbol;
+use crate::types::{Type, TypeSize};
+use crate::utils::half_float::HFloat;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    UnknownInstruction(String),
+    InvalidOperands(String),
+    InvalidValue(String),
+    NoFunction,
+}
+
+/// An error in the listing, with the line number starting from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error(pub ErrorKind, pub usize);
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: ", self.1)?;
+        match &self.0 {
+            ErrorKind::UnknownInstruction(name) => write!(f, "unknown instruction {name}"),
+            ErrorKind::InvalidOperands(inst) => write!(f, "invalid operands of {inst}"),
+            ErrorKind::InvalidValue(key) => write!(f, "invalid value of {key}"),
+            ErrorKind::NoFunction => write!(f, "no function is declared before this line"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+const FUNCTION_KEYS: [&str; 7] = [
+    "nparams",
+    "nret",
+    "upindexes",
+    "state_size",
+    "delay_sizes",
+    "spill_size",
+    "constants",
+];
+
+/// An operand of the instructions, written as a number.
+trait Operand: Sized {
+    fn parse(s: &str) -> Option<Self>;
+}
+
+macro_rules! int_operand {
+    ($($t:ty),*) => {
+        $(impl Operand for $t {
+            fn parse(s: &str) -> Option<Self> {
+                s.parse().ok()
+            }
+        })*
+    };
+}
+int_operand!(u8, u16, i16);
+
+// the half float is printed as the shortest decimal of the same single float.
+impl Operand for HFloat {
+    fn parse(s: &str) -> Option<Self> {
+        let v = s.parse::<f32>().ok()?;
+        let h = half::f16::from_f32(v);
+        (h.to_f32() == v).then_some(h.into())
+    }
+}
+
+macro_rules! mnemonics {
+    ($($name:literal => $variant:ident $(($($arg:ident: $t:ty),*))?,)*) => {
+        // None for the unknown mnemonic, and Some(None) for the invalid operands.
+        fn parse_operands(mnemonic: &str, ops: &[&str]) -> Option<Option<Instruction>> {
+            if ![$($name),*].contains(&mnemonic) {
+                return None;
+            }
+            let mut _ops = ops.iter();
+            let mut parse = || match mnemonic {
+                $($name => Some(Instruction::$variant $(($({
+                    let $arg = <$t as Operand>::parse(_ops.next()?)?;
+                    $arg
+                }),*))?),)*
+                _ => None,
+            };
+            let inst = parse();
+            Some(inst.filter(|_| _ops.next().is_none()))
+        }
+    };
+}
+
+mnemonics! {
+    "mov" => Move(d: Reg, s: Reg),
+    "movc" => MoveConst(d: Reg, s: ConstPos),
+    "movimmF" => MoveImmF(d: Reg, v: HFloat),
+    "call" => Call(f: Reg, n: u8, r: TypeSize),
+    "callcls" => CallCls(f: Reg, n: u8, r: TypeSize),
+    "callext" => CallExtFun(f: Reg, n: u8, r: TypeSize),
+    "closure" => Closure(d: Reg, s: Reg),
+    "close" => Close(s: Reg),
+    "getupv" => GetUpValue(d: Reg, s: Reg, n: TypeSize),
+    "setupv" => SetUpValue(d: Reg, s: Reg, n: TypeSize),
+    "getglobal" => GetGlobal(d: Reg, s: GlobalPos),
+    "setglobal" => SetGlobal(d: GlobalPos, s: Reg),
+    "spill" => Spill(d: SpillPos, s: Reg),
+    "reload" => Reload(d: Reg, s: SpillPos),
+    "allocarr" => AllocArray(d: Reg, l: Reg, n: TypeSize),
+    "getarr" => GetArrayElem(d: Reg, a: Reg, i: Reg),
+    "setarr" => SetArrayElem(a: Reg, i: Reg, s: Reg),
+    "getstate" => GetState(d: Reg, n: TypeSize),
+    "setstate" => SetState(s: Reg, n: TypeSize),
+    "shiftsttpos" => ShiftStatePos(o: Offset),
+    "ret0" => Return0,
+    "ret" => Return(s: Reg, n: TypeSize),
+    "delay" => Delay(d: Reg, s: Reg, t: Reg),
+    "mem" => Mem(d: Reg, s: Reg),
+    "jmp" => Jmp(o: Offset),
+    "jmpifneg" => JmpIfNeg(c: Reg, o: Offset),
+    "addf" => AddF(d: Reg, a: Reg, b: Reg),
+    "subf" => SubF(d: Reg, a: Reg, b: Reg),
+    "mulf" => MulF(d: Reg, a: Reg, b: Reg),
+    "divf" => DivF(d: Reg, a: Reg, b: Reg),
+    "modf" => ModF(d: Reg, a: Reg, b: Reg),
+    "negf" => NegF(d: Reg, s: Reg),
+    "absf" => AbsF(d: Reg, s: Reg),
+    "sqrt" => SqrtF(d: Reg, s: Reg),
+    "sin" => SinF(d: Reg, s: Reg),
+    "cos" => CosF(d: Reg, s: Reg),
+    "powf" => PowF(d: Reg, a: Reg, b: Reg),
+    "logf" => LogF(d: Reg, a: Reg, b: Reg),
+    "addi" => AddI(d: Reg, a: Reg, b: Reg),
+    "subi" => SubI(d: Reg, a: Reg, b: Reg),
+    "muli" => MulI(d: Reg, a: Reg, b: Reg),
+    "divi" => DivI(d: Reg, a: Reg, b: Reg),
+    "modi" => ModI(d: Reg, a: Reg, b: Reg),
+    "negi" => NegI(d: Reg, s: Reg),
+    "absi" => AbsI(d: Reg, s: Reg),
+    "powi" => PowI(d: Reg, a: Reg, b: Reg),
+    "logi" => LogI(d: Reg, a: Reg, b: Reg),
+    "not" => Not(d: Reg, s: Reg),
+    "eq" => Eq(d: Reg, a: Reg, b: Reg),
+    "ne" => Ne(d: Reg, a: Reg, b: Reg),
+    "gt" => Gt(d: Reg, a: Reg, b: Reg),
+    "ge" => Ge(d: Reg, a: Reg, b: Reg),
+    "lt" => Lt(d: Reg, a: Reg, b: Reg),
+    "le" => Le(d: Reg, a: Reg, b: Reg),
+    "and" => And(d: Reg, a: Reg, b: Reg),
+    "or" => Or(d: Reg, a: Reg, b: Reg),
+    "f2i" => CastFtoI(d: Reg, s: Reg),
+    "i2f" => CastItoF(d: Reg, s: Reg),
+    "i2b" => CastItoB(d: Reg, s: Reg),
+    "dummy" => Dummy,
+}
+
+// the range of the registers like `3-4`.
+fn parse_range(s: &str) -> Option<(Reg, Reg)> {
+    let (start, end) = s.split_once('-')?;
+    Some((start.parse().ok()?, end.parse().ok()?))
+}
+
+/// Parses an instruction in the syntax of its `Display`.
+pub fn parse_instruction(line: &str) -> Result<Instruction, ErrorKind> {
+    let mut tokens = line.split_whitespace();
+    let mnemonic = tokens.next().unwrap_or_default();
+    let ops = tokens.collect::<Vec<_>>();
+    let invalid = || ErrorKind::InvalidOperands(line.trim().to_string());
+    // `mov` of the multiple words is written with the ranges of the registers.
+    if let ("mov", [dst, src]) = (mnemonic, ops.as_slice()) {
+        if dst.contains('-') {
+            let ((d0, d1), (s0, s1)) =
+                parse_range(dst).zip(parse_range(src)).ok_or_else(invalid)?;
+            let size = d1
+                .checked_sub(d0)
+                .filter(|n| s1.checked_sub(s0) == Some(*n));
+            let size = size.and_then(|n| n.checked_add(1)).ok_or_else(invalid)?;
+            return Ok(Instruction::MoveRange(d0, s0, size));
+        }
+    }
+    match parse_operands(mnemonic, &ops) {
+        Some(Some(inst)) => Ok(inst),
+        Some(None) => Err(invalid()),
+        None => Err(ErrorKind::UnknownInstruction(mnemonic.to_string())),
+    }
+}
+
+// removes the comment after `//` outside of the string literals.
+fn strip_comment(line: &str) -> &str {
+    let mut in_str = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_str => escaped = true,
+            '"' => in_str = !in_str,
+            '/' if !in_str && line[i..].starts_with("//") => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+// reads a string literal in the syntax of `Debug`, returning it with the rest of the input.
+fn parse_string(s: &str) -> Option<(String, &str)> {
+    let mut chars = s.strip_prefix('"')?.char_indices();
+    let mut res = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((res, &s[i + 2..])),
+            '\\' => {
+                let c = match chars.next()?.1 {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    '0' => '\0',
+                    'u' => {
+                        let code = chars
+                            .by_ref()
+                            .map(|(_, c)| c)
+                            .skip_while(|c| *c == '{')
+                            .take_while(|c| *c != '}')
+                            .collect::<String>();
+                        char::from_u32(u32::from_str_radix(&code, 16).ok()?)?
+                    }
+                    c => c,
+                };
+                res.push(c);
+            }
+            c => res.push(c),
+        }
+    }
+    None
+}
+
+// the elements of the list like `[1, 2]`, split at the commas outside of the strings and the braces.
+fn parse_list(s: &str) -> Option<Vec<&str>> {
+    let inner = s.trim().strip_prefix('[')?.strip_suffix(']')?;
+    let mut res = vec![];
+    let (mut depth, mut in_str, mut escaped, mut start) = (0, false, false, 0);
+    for (i, c) in inner.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_str => escaped = true,
+            '"' => in_str = !in_str,
+            '{' | '[' if !in_str => depth += 1,
+            '}' | ']' if !in_str => depth -= 1,
+            ',' if !in_str && depth == 0 => {
+                res.push(inner[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    let last = inner[start..].trim();
+    if !last.is_empty() || !res.is_empty() {
+        res.push(last);
+    }
+    Some(res)
+}
+
+fn parse_numbers<T: std::str::FromStr>(s: &str) -> Option<Vec<T>> {
+    parse_list(s)?.into_iter().map(|e| e.parse().ok()).collect()
+}
+
+// an upvalue in the syntax of `Debug`, like `OpenUpValue { pos: 0, size: 1, is_closure: false }`.
+fn parse_upvalue(s: &str) -> Option<OpenUpValue> {
+    let fields = s.strip_prefix("OpenUpValue")?.trim();
+    let fields = fields.strip_prefix('{')?.strip_suffix('}')?;
+    let mut res = OpenUpValue {
+        pos: 0,
+        size: 0,
+        is_closure: false,
+    };
+    for field in fields.split(',').filter(|f| !f.trim().is_empty()) {
+        let (key, value) = field.split_once(':')?;
+        let value = value.trim();
+        match key.trim() {
+            "pos" => res.pos = value.parse().ok()?,
+            "size" => res.size = value.parse().ok()?,
+            "is_closure" => res.is_closure = value.parse().ok()?,
+            _ => return None,
+        }
+    }
+    Some(res)
+}
+
+// splits the line into the pairs of `key: value`, where the value is a word or a list.
+fn parse_fields(line: &str) -> Option<Vec<(&str, &str)>> {
+    let mut res = vec![];
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let (key, value) = rest.split_once(':')?;
+        let value = value.trim_start();
+        let len = if value.starts_with('[') {
+            let mut depth = 0;
+            value.find(|c| {
+                match c {
+                    '[' => depth += 1,
+                    ']' => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            })? + 1
+        } else {
+            value.find(char::is_whitespace).unwrap_or(value.len())
+        };
+        res.push((key.trim(), &value[..len]));
+        rest = value[len..].trim_start();
+    }
+    Some(res)
+}
+
+fn set_field(func: &mut FuncProto, key: &str, value: &str) -> Option<()> {
+    match key {
+        "nparams" => func.nparam = value.parse().ok()?,
+        "nret" => func.nret = value.parse().ok()?,
+        "upindexes" => {
+            func.upindexes = parse_list(value)?
+                .into_iter()
+                .map(parse_upvalue)
+                .collect::<Option<_>>()?
+        }
+        "state_size" => func.state_size = value.parse().ok()?,
+        "delay_sizes" => func.delay_sizes = parse_numbers(value)?,
+        "spill_size" => func.spill_size = value.parse().ok()?,
+        "constants" => func.constants = parse_numbers(value)?,
+        _ => return None,
+    }
+    Some(())
+}
+
+fn is_field_line(line: &str) -> bool {
+    FUNCTION_KEYS.iter().any(|key| {
+        line.strip_prefix(key)
+            .is_some_and(|rest| rest.trim_start().starts_with(':'))
+    })
+}
+
+/// Assembles the listing into the program. The listing printed by the `Display` of
+/// [`Program`] is assembled into the same program, except for the types of the external
+/// functions and the path of the source file.
+pub fn assemble(src: &str) -> Result<Program, Error> {
+    let mut prog = Program::default();
+    let mut lines = src
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, strip_comment(line).trim_end()))
+        .filter(|(_, line)| !line.trim().is_empty())
+        .peekable();
+    while let Some((n, line)) = lines.next() {
+        let err = |kind| Error(kind, n);
+        let trimmed = line.trim_start();
+        // the value of the section may be on the next line.
+        let mut section_value = |rest: &str, first: char| match lines.peek() {
+            Some((_, next)) if rest.trim().is_empty() && next.trim_start().starts_with(first) => {
+                lines.next().unwrap().1.trim().to_string()
+            }
+            _ => rest.trim().to_string(),
+        };
+        if line.starts_with(char::is_whitespace) {
+            let (_, func) = prog
+                .global_fn_table
+                .last_mut()
+                .ok_or(err(ErrorKind::NoFunction))?;
+            func.bytecodes
+                .push(parse_instruction(trimmed).map_err(err)?);
+        } else if line == "instructions:" {
+            if prog.global_fn_table.is_empty() {
+                return Err(err(ErrorKind::NoFunction));
+            }
+        } else if let Some(rest) = line.strip_prefix("ext_fun:") {
+            let value = section_value(rest, '"');
+            let names = if value.starts_with('"') {
+                parse_string(&value)
+                    .map(|(s, _)| s)
+                    .ok_or(err(ErrorKind::InvalidValue("ext_fun".to_string())))?
+            } else {
+                value
+            };
+            prog.ext_fun_table = names
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| (name.to_symbol(), Type::Unknown.into_id()))
+                .collect();
+        } else if let Some(rest) = line.strip_prefix("globals:") {
+            prog.global_vals = parse_numbers(&section_value(rest, '['))
+                .ok_or(err(ErrorKind::InvalidValue("globals".to_string())))?;
+        } else if let Some(rest) = line.strip_prefix("strings:") {
+            let invalid = || err(ErrorKind::InvalidValue("strings".to_string()));
+            prog.strings = parse_list(rest)
+                .ok_or_else(invalid)?
+                .into_iter()
+                .map(|s| parse_string(s).map(|(s, _)| s.to_symbol()))
+                .collect::<Option<_>>()
+                .ok_or_else(invalid)?;
+        } else if is_field_line(line) {
+            let (_, func) = prog
+                .global_fn_table
+                .last_mut()
+                .ok_or(err(ErrorKind::NoFunction))?;
+            let fields =
+                parse_fields(line).ok_or(err(ErrorKind::InvalidValue(line.to_string())))?;
+            for (key, value) in fields {
+                set_field(func, key, value).ok_or(err(ErrorKind::InvalidValue(key.to_string())))?;
+            }
+        } else {
+            prog.global_fn_table
+                .push((line.to_symbol(), FuncProto::default()));
+        }
+    }
+    Ok(prog)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler::Context;
+    use crate::runtime::vm::builtin::get_builtin_fn_types;
+
+    #[test]
+    fn roundtrip() {
+        let src = r#"
+fn counter(){
+    self + 1.0
+}
+fn dsp(){
+    let k = 2.0
+    let f = |x| x * k * counter()
+    let (a, b) = (1.0, 2.0)
+    probe(f(0.5)) + a * b
+}
+"#;
+        let prog = Context::new(get_builtin_fn_types(), None)
+            .emit_bytecode(src)
+            .unwrap();
+        let listing = prog.to_string();
+        let res = assemble(&listing).unwrap();
+        assert_eq!(res.global_fn_table, prog.global_fn_table);
+        assert_eq!(res.global_vals, prog.global_vals);
+        assert_eq!(res.to_string(), listing);
+    }
+
+    #[test]
+    fn instructions() {
+        let cases = [
+            ("mov 3-4 1-2", Instruction::MoveRange(3, 1, 2)),
+            (
+                "movimmF 0 0.5",
+                Instruction::MoveImmF(0, HFloat::try_from(0.5).unwrap()),
+            ),
+            ("jmp -3", Instruction::Jmp(-3)),
+            ("ret0", Instruction::Return0),
+        ];
+        for (src, ans) in cases {
+            assert_eq!(parse_instruction(src), Ok(ans));
+            assert_eq!(parse_instruction(&ans.to_string()), Ok(ans));
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let src = "dsp\ninstructions:\n  movc 0\n";
+        assert_eq!(
+            assemble(src),
+            Err(Error(ErrorKind::InvalidOperands("movc 0".to_string()), 3))
+        );
+        let src = "dsp\n  jump 1\n";
+        assert_eq!(
+            assemble(src),
+            Err(Error(ErrorKind::UnknownInstruction("jump".to_string()), 2))
+        );
+        assert_eq!(assemble("  ret0"), Err(Error(ErrorKind::NoFunction, 1)));
+    }
+}
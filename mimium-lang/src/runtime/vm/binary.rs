@@ -0,0 +1,509 @@
+//! Binary file format of the bytecode [`Program`], to compile the source once and run it later.
+//!
+//! The file starts with the magic bytes `MMBC` and the version of the format as a 16-bit
+//! integer, followed by the function table, the external function table, the global values,
+//! the strings and the path of the source file. All the integers are little endian. The
+//! symbols are written as their UTF-8 bytes prefixed with the length, and the types of the
+//! external functions are written as trees, so that they are interned again on loading.
+//!
+//! The format is not compatible between the versions. A file written by the other version is
+//! rejected on loading and has to be compiled again.
+
+use super::{FuncProto, Instruction, OpenUpValue, Program, RawVal};
+use crate::interner::{Symbol, ToSymbol, TypeNodeId};
+use crate::types::{PType, Type};
+use crate::utils::half_float::HFloat;
+
+const MAGIC: &[u8; 4] = b"MMBC";
+/// The version of the format, incremented when the layout or the instruction set changes.
+pub const FORMAT_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    NotBytecode,
+    UnsupportedVersion(u16),
+    UnexpectedEnd,
+    InvalidOpcode(u8),
+    InvalidTypeTag(u8),
+    InvalidString,
+    /// The number of the bytes left after the program.
+    TrailingBytes(usize),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotBytecode => write!(f, "The file is not a mimium bytecode."),
+            Error::UnsupportedVersion(v) => write!(
+                f,
+                "The bytecode has the format version {v}, but this runtime supports {FORMAT_VERSION}. Compile the source again."
+            ),
+            Error::UnexpectedEnd => write!(f, "The bytecode ends unexpectedly."),
+            Error::InvalidOpcode(op) => write!(f, "Unknown instruction {op} in the bytecode."),
+            Error::InvalidTypeTag(tag) => write!(f, "Unknown type {tag} in the bytecode."),
+            Error::InvalidString => write!(f, "The bytecode has a string not in UTF-8."),
+            Error::TrailingBytes(n) => {
+                write!(f, "The bytecode has {n} unexpected bytes after the program.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        let end = self.pos.checked_add(n).ok_or(Error::UnexpectedEnd)?;
+        let res = self.bytes.get(self.pos..end).ok_or(Error::UnexpectedEnd)?;
+        self.pos = end;
+        Ok(res)
+    }
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+}
+
+/// A value written in the fixed size.
+trait Field: Sized {
+    fn write(&self, out: &mut Vec<u8>);
+    fn read(r: &mut Reader) -> Result<Self, Error>;
+}
+
+macro_rules! int_field {
+    ($($t:ty),*) => {
+        $(impl Field for $t {
+            fn write(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+            fn read(r: &mut Reader) -> Result<Self, Error> {
+                Ok(<$t>::from_le_bytes(r.take_array()?))
+            }
+        })*
+    };
+}
+int_field!(u8, u16, i16, u32, u64);
+
+impl Field for HFloat {
+    fn write(&self, out: &mut Vec<u8>) {
+        let v: f64 = (*self).into();
+        half::f16::from_f64(v).to_bits().write(out)
+    }
+    fn read(r: &mut Reader) -> Result<Self, Error> {
+        Ok(half::f16::from_bits(u16::read(r)?).into())
+    }
+}
+
+impl Field for bool {
+    fn write(&self, out: &mut Vec<u8>) {
+        (*self as u8).write(out)
+    }
+    fn read(r: &mut Reader) -> Result<Self, Error> {
+        Ok(u8::read(r)? != 0)
+    }
+}
+
+impl Field for usize {
+    fn write(&self, out: &mut Vec<u8>) {
+        (*self as u64).write(out)
+    }
+    fn read(r: &mut Reader) -> Result<Self, Error> {
+        Ok(u64::read(r)? as usize)
+    }
+}
+
+impl Field for Symbol {
+    fn write(&self, out: &mut Vec<u8>) {
+        write_vec(self.as_str().as_bytes(), out)
+    }
+    fn read(r: &mut Reader) -> Result<Self, Error> {
+        let len = u32::read(r)? as usize;
+        let s = std::str::from_utf8(r.take(len)?).map_err(|_| Error::InvalidString)?;
+        Ok(s.to_symbol())
+    }
+}
+
+impl<T: Field> Field for Option<T> {
+    fn write(&self, out: &mut Vec<u8>) {
+        self.is_some().write(out);
+        if let Some(v) = self {
+            v.write(out)
+        }
+    }
+    fn read(r: &mut Reader) -> Result<Self, Error> {
+        if bool::read(r)? {
+            T::read(r).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<A: Field, B: Field> Field for (A, B) {
+    fn write(&self, out: &mut Vec<u8>) {
+        self.0.write(out);
+        self.1.write(out);
+    }
+    fn read(r: &mut Reader) -> Result<Self, Error> {
+        Ok((A::read(r)?, B::read(r)?))
+    }
+}
+
+fn write_vec<T: Field>(v: &[T], out: &mut Vec<u8>) {
+    (v.len() as u32).write(out);
+    v.iter().for_each(|e| e.write(out));
+}
+
+fn read_vec<T: Field>(r: &mut Reader) -> Result<Vec<T>, Error> {
+    let len = u32::read(r)? as usize;
+    // the length is not trusted for the allocation, as the file may be broken.
+    let mut res = Vec::with_capacity(len.min(r.bytes.len()));
+    for _ in 0..len {
+        res.push(T::read(r)?);
+    }
+    Ok(res)
+}
+
+// Each instruction is written as its opcode followed by the operands. The opcodes must not be
+// reused for the other instructions without incrementing `FORMAT_VERSION`.
+macro_rules! instructions {
+    ($($code:literal => $name:ident $(($($arg:ident),*))?,)*) => {
+        impl Field for Instruction {
+            fn write(&self, out: &mut Vec<u8>) {
+                match self {
+                    $(Instruction::$name $(($($arg),*))? => {
+                        out.push($code);
+                        $($($arg.write(out);)*)?
+                    })*
+                }
+            }
+            fn read(r: &mut Reader) -> Result<Self, Error> {
+                match u8::read(r)? {
+                    $($code => Ok(Instruction::$name $(($({
+                        let $arg = Field::read(r)?;
+                        $arg
+                    }),*))?),)*
+                    op => Err(Error::InvalidOpcode(op)),
+                }
+            }
+        }
+    };
+}
+
+instructions! {
+    0 => Move(a, b),
+    1 => MoveConst(a, b),
+    2 => MoveImmF(a, b),
+    3 => MoveRange(a, b, c),
+    4 => Call(a, b, c),
+    5 => CallCls(a, b, c),
+    6 => CallExtFun(a, b, c),
+    7 => Closure(a, b),
+    8 => Close(a),
+    9 => GetUpValue(a, b, c),
+    10 => SetUpValue(a, b, c),
+    11 => GetGlobal(a, b),
+    12 => SetGlobal(a, b),
+    13 => Spill(a, b),
+    14 => Reload(a, b),
+    15 => AllocArray(a, b, c),
+    16 => GetArrayElem(a, b, c),
+    17 => SetArrayElem(a, b, c),
+    18 => GetState(a, b),
+    19 => SetState(a, b),
+    20 => ShiftStatePos(a),
+    21 => Return0,
+    22 => Return(a, b),
+    23 => Delay(a, b, c),
+    24 => Mem(a, b),
+    25 => Jmp(a),
+    26 => JmpIfNeg(a, b),
+    27 => AddF(a, b, c),
+    28 => SubF(a, b, c),
+    29 => MulF(a, b, c),
+    30 => DivF(a, b, c),
+    31 => ModF(a, b, c),
+    32 => NegF(a, b),
+    33 => AbsF(a, b),
+    34 => SqrtF(a, b),
+    35 => SinF(a, b),
+    36 => CosF(a, b),
+    37 => PowF(a, b, c),
+    38 => LogF(a, b, c),
+    39 => AddI(a, b, c),
+    40 => SubI(a, b, c),
+    41 => MulI(a, b, c),
+    42 => DivI(a, b, c),
+    43 => ModI(a, b, c),
+    44 => NegI(a, b),
+    45 => AbsI(a, b),
+    46 => PowI(a, b, c),
+    47 => LogI(a, b, c),
+    48 => Not(a, b),
+    49 => Eq(a, b, c),
+    50 => Ne(a, b, c),
+    51 => Gt(a, b, c),
+    52 => Ge(a, b, c),
+    53 => Lt(a, b, c),
+    54 => Le(a, b, c),
+    55 => And(a, b, c),
+    56 => Or(a, b, c),
+    57 => CastFtoI(a, b),
+    58 => CastItoF(a, b),
+    59 => CastItoB(a, b),
+    60 => Dummy,
+}
+
+// the type variables resolved by the inference are written as the types they point to, and
+// the ones left unresolved as `Unknown`.
+impl Field for TypeNodeId {
+    fn write(&self, out: &mut Vec<u8>) {
+        match self.to_type() {
+            Type::Primitive(p) => {
+                out.push(0);
+                out.push(match p {
+                    PType::Unit => 0,
+                    PType::Int => 1,
+                    PType::Numeric => 2,
+                    PType::String => 3,
+                });
+            }
+            Type::Array(t) => {
+                out.push(1);
+                t.write(out);
+            }
+            Type::Tuple(ts) => {
+                out.push(2);
+                write_vec(&ts, out);
+            }
+            Type::Struct(fields) => {
+                out.push(3);
+                write_vec(&fields, out);
+            }
+            Type::Union(variants) => {
+                out.push(4);
+                (variants.len() as u32).write(out);
+                for (name, fields) in variants.iter() {
+                    name.write(out);
+                    write_vec(fields, out);
+                }
+            }
+            Type::Function(params, ret, state) => {
+                out.push(5);
+                write_vec(&params, out);
+                ret.write(out);
+                state.write(out);
+            }
+            Type::Ref(t) => {
+                out.push(6);
+                t.write(out);
+            }
+            Type::Alias(name, t) => {
+                out.push(7);
+                name.write(out);
+                t.write(out);
+            }
+            Type::Named(name, t) => {
+                out.push(8);
+                name.write(out);
+                t.write(out);
+            }
+            Type::TypeName(name) => {
+                out.push(9);
+                name.write(out);
+            }
+            Type::Code(t) => {
+                out.push(10);
+                t.write(out);
+            }
+            Type::TypeScheme(id) => {
+                out.push(11);
+                id.write(out);
+            }
+            Type::Instantiated(id) => {
+                out.push(12);
+                id.write(out);
+            }
+            Type::Intermediate(tv) => match tv.borrow().parent {
+                Some(p) => p.write(out),
+                None => out.push(13),
+            },
+            Type::Unknown => out.push(13),
+        }
+    }
+    fn read(r: &mut Reader) -> Result<Self, Error> {
+        let ty = match u8::read(r)? {
+            0 => Type::Primitive(match u8::read(r)? {
+                0 => PType::Unit,
+                1 => PType::Int,
+                2 => PType::Numeric,
+                3 => PType::String,
+                tag => return Err(Error::InvalidTypeTag(tag)),
+            }),
+            1 => Type::Array(Field::read(r)?),
+            2 => Type::Tuple(read_vec(r)?),
+            3 => Type::Struct(read_vec(r)?),
+            4 => {
+                let len = u32::read(r)?;
+                let variants = (0..len)
+                    .map(|_| Ok((Symbol::read(r)?, read_vec(r)?)))
+                    .collect::<Result<_, Error>>()?;
+                Type::Union(variants)
+            }
+            5 => Type::Function(read_vec(r)?, Field::read(r)?, Field::read(r)?),
+            6 => Type::Ref(Field::read(r)?),
+            7 => Type::Alias(Field::read(r)?, Field::read(r)?),
+            8 => Type::Named(Field::read(r)?, Field::read(r)?),
+            9 => Type::TypeName(Field::read(r)?),
+            10 => Type::Code(Field::read(r)?),
+            11 => Type::TypeScheme(Field::read(r)?),
+            12 => Type::Instantiated(Field::read(r)?),
+            13 => Type::Unknown,
+            tag => return Err(Error::InvalidTypeTag(tag)),
+        };
+        Ok(ty.into_id())
+    }
+}
+
+impl Field for OpenUpValue {
+    fn write(&self, out: &mut Vec<u8>) {
+        self.pos.write(out);
+        self.size.write(out);
+        self.is_closure.write(out);
+    }
+    fn read(r: &mut Reader) -> Result<Self, Error> {
+        Ok(Self {
+            pos: Field::read(r)?,
+            size: Field::read(r)?,
+            is_closure: Field::read(r)?,
+        })
+    }
+}
+
+impl Field for FuncProto {
+    fn write(&self, out: &mut Vec<u8>) {
+        self.nparam.write(out);
+        self.nret.write(out);
+        write_vec(&self.upindexes, out);
+        write_vec(&self.bytecodes, out);
+        write_vec(&self.constants, out);
+        self.state_size.write(out);
+        write_vec(&self.delay_sizes, out);
+        self.spill_size.write(out);
+    }
+    fn read(r: &mut Reader) -> Result<Self, Error> {
+        Ok(Self {
+            nparam: Field::read(r)?,
+            nret: Field::read(r)?,
+            upindexes: read_vec(r)?,
+            bytecodes: read_vec(r)?,
+            constants: read_vec::<RawVal>(r)?,
+            state_size: Field::read(r)?,
+            delay_sizes: read_vec(r)?,
+            spill_size: Field::read(r)?,
+        })
+    }
+}
+
+/// Compares the types in the way they are written in the file, so that the types interned in
+/// the different sessions or with the different locations can be compared.
+pub fn is_same_type(t1: TypeNodeId, t2: TypeNodeId) -> bool {
+    let (mut b1, mut b2) = (vec![], vec![]);
+    t1.write(&mut b1);
+    t2.write(&mut b2);
+    b1 == b2
+}
+
+impl Program {
+    /// Serializes the program into the binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        FORMAT_VERSION.write(&mut out);
+        write_vec(&self.global_fn_table, &mut out);
+        write_vec(&self.ext_fun_table, &mut out);
+        write_vec(&self.global_vals, &mut out);
+        write_vec(&self.strings, &mut out);
+        self.file_path.write(&mut out);
+        out
+    }
+    /// Loads the program written by [`Self::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader { bytes, pos: 0 };
+        if r.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(Error::NotBytecode);
+        }
+        let version = u16::read(&mut r)?;
+        if version != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let res = Self {
+            global_fn_table: read_vec(&mut r)?,
+            ext_fun_table: read_vec(&mut r)?,
+            global_vals: read_vec(&mut r)?,
+            strings: read_vec(&mut r)?,
+            file_path: Field::read(&mut r)?,
+        };
+        match r.bytes.len() - r.pos {
+            0 => Ok(res),
+            n => Err(Error::TrailingBytes(n)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler::Context;
+    use crate::runtime::vm::builtin::get_builtin_fn_types;
+
+    fn compile(src: &str) -> Program {
+        Context::new(get_builtin_fn_types(), None)
+            .emit_bytecode(src)
+            .unwrap()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let src = r#"
+fn counter(){
+    self + 1.0
+}
+fn dsp(){
+    let (a, b) = (counter(), 0.5)
+    sin(a * b)
+}
+"#;
+        let prog = compile(src);
+        let bytes = prog.to_bytes();
+        let loaded = Program::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.global_fn_table, prog.global_fn_table);
+        assert_eq!(loaded.global_vals, prog.global_vals);
+        assert_eq!(loaded.strings, prog.strings);
+        assert!(prog
+            .ext_fun_table
+            .iter()
+            .zip(loaded.ext_fun_table.iter())
+            .all(|((n1, t1), (n2, t2))| n1 == n2 && is_same_type(*t1, *t2)));
+        assert_eq!(loaded.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn broken_bytes() {
+        let bytes = compile("fn dsp(){ 1.0 }").to_bytes();
+        assert_eq!(
+            Program::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(Error::UnexpectedEnd)
+        );
+        assert_eq!(Program::from_bytes(b"hello"), Err(Error::NotBytecode));
+        let mut old = bytes.clone();
+        old[4..6].copy_from_slice(&0u16.to_le_bytes());
+        assert_eq!(Program::from_bytes(&old), Err(Error::UnsupportedVersion(0)));
+        let mut trailing = bytes.clone();
+        trailing.extend_from_slice(&[0, 0]);
+        assert_eq!(Program::from_bytes(&trailing), Err(Error::TrailingBytes(2)));
+    }
+}
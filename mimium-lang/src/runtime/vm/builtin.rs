@@ -1,9 +1,9 @@
 use crate::compiler::ExtFunTypeInfo;
 use crate::interner::{Symbol, ToSymbol, TypeNodeId};
 use crate::types::{PType, Type};
-use crate::{function, numeric};
+use crate::{function, integer, numeric};
 
-use super::{ExtFnInfo, Machine, ReturnCode};
+use super::{ArrayIdx, ExtFnInfo, Machine, ReturnCode};
 
 fn probef(machine: &mut Machine) -> ReturnCode {
     let rv = machine.get_stack(0);
@@ -35,7 +35,14 @@ fn max(machine: &mut Machine) -> ReturnCode {
     1
 }
 
-pub fn get_builtin_fns() -> [ExtFnInfo; 4] {
+fn len(machine: &mut Machine) -> ReturnCode {
+    let arr = super::Machine::get_as::<ArrayIdx>(machine.get_stack(0));
+    let len = machine.arrays.get(arr).len() as i64;
+    machine.set_stack(0, super::Machine::to_value(len));
+    1
+}
+
+pub fn get_builtin_fns() -> [ExtFnInfo; 5] {
     [
         (
             "probe".to_symbol(),
@@ -57,6 +64,14 @@ pub fn get_builtin_fns() -> [ExtFnInfo; 4] {
             max,
             function!(vec![numeric!(), numeric!()], numeric!()),
         ),
+        (
+            "len".to_symbol(),
+            len,
+            function!(
+                vec![Type::Array(Type::TypeScheme(0).into_id()).into_id()],
+                integer!()
+            ),
+        ),
     ]
 }
 
@@ -2,7 +2,8 @@ use crate::{types::TypeSize, utils::half_float::HFloat};
 
 pub type Reg = u8; // register position
 pub type ConstPos = u16;
-pub type GlobalPos = u8;
+pub type GlobalPos = u16;
+pub type SpillPos = u16;
 pub type Offset = i16;
 
 /// Instructions for bytecode. Currently, each instructon has the 64 bit size(Tag, up to 3 bytes arguments.)
@@ -35,9 +36,19 @@ pub enum Instruction {
     GetUpValue(Reg, Reg, TypeSize),
     SetUpValue(Reg, Reg, TypeSize),
 
-    /// destination,source
-    GetGlobal(Reg, GlobalPos, TypeSize),
-    SetGlobal(GlobalPos, Reg, TypeSize),
+    /// Move a word of global value. destination,source
+    GetGlobal(Reg, GlobalPos),
+    SetGlobal(GlobalPos, Reg),
+    /// Move a word to the spill slot of the function, for the variables which do not fit in the registers. destination,source
+    Spill(SpillPos, Reg),
+    /// Move a word back from the spill slot. destination,source
+    Reload(Reg, SpillPos),
+    /// Allocate new array on the heap. Destination, register of the length, word size of the element.
+    AllocArray(Reg, Reg, TypeSize),
+    /// Load an element of the array with bounds checking. Destination, array, index.
+    GetArrayElem(Reg, Reg, Reg),
+    /// Store a value into an element of the array with bounds checking. Array, index, source.
+    SetArrayElem(Reg, Reg, Reg),
     /// Call internal state over time, destination,source
     GetState(Reg, TypeSize),
     SetState(Reg, TypeSize),
@@ -141,11 +152,18 @@ impl std::fmt::Display for Instruction {
             Instruction::SetUpValue(dstup, src, size) => {
                 write!(f, "{:<10} {} {} {}", "setupv", dstup, src, size)
             }
-            Instruction::GetGlobal(dst, src, size) => {
-                write!(f, "{:<10} {} {} {}", "getglobal", dst, src, size)
+            Instruction::GetGlobal(dst, src) => write!(f, "{:<10} {} {}", "getglobal", dst, src),
+            Instruction::SetGlobal(dst, src) => write!(f, "{:<10} {} {}", "setglobal", dst, src),
+            Instruction::Spill(dst, src) => write!(f, "{:<10} {} {}", "spill", dst, src),
+            Instruction::Reload(dst, src) => write!(f, "{:<10} {} {}", "reload", dst, src),
+            Instruction::AllocArray(dst, len, size) => {
+                write!(f, "{:<10} {} {} {}", "allocarr", dst, len, size)
             }
-            Instruction::SetGlobal(dst, src, size) => {
-                write!(f, "{:<10} {} {} {}", "setglobal", dst, src, size)
+            Instruction::GetArrayElem(dst, arr, idx) => {
+                write!(f, "{:<10} {} {} {}", "getarr", dst, arr, idx)
+            }
+            Instruction::SetArrayElem(arr, idx, src) => {
+                write!(f, "{:<10} {} {} {}", "setarr", arr, idx, src)
             }
             Instruction::JmpIfNeg(dst, cond) => write!(f, "{:<10} {} {}", "jmpifneg", dst, cond),
             Instruction::AbsF(dst, src) => write!(f, "{:<10} {} {}", "absf", dst, src),
@@ -153,8 +171,8 @@ impl std::fmt::Display for Instruction {
             Instruction::SinF(dst, src) => write!(f, "{:<10} {} {}", "sin", dst, src),
             Instruction::CosF(dst, src) => write!(f, "{:<10} {} {}", "cos", dst, src),
             Instruction::SqrtF(dst, src) => write!(f, "{:<10} {} {}", "sqrt", dst, src),
-            Instruction::AbsI(dst, src) => write!(f, "{:<10} {} {}", "abs", dst, src),
-            Instruction::NegI(dst, src) => write!(f, "{:<10} {} {}", "neg", dst, src),
+            Instruction::AbsI(dst, src) => write!(f, "{:<10} {} {}", "absi", dst, src),
+            Instruction::NegI(dst, src) => write!(f, "{:<10} {} {}", "negi", dst, src),
             Instruction::Not(dst, src) => write!(f, "{:<10} {} {}", "not", dst, src),
             Instruction::CastFtoI(dst, src) => write!(f, "{:<10} {} {}", "f2i", dst, src),
             Instruction::CastItoF(dst, src) => write!(f, "{:<10} {} {}", "i2f", dst, src),
@@ -178,17 +196,17 @@ impl std::fmt::Display for Instruction {
             Instruction::ModF(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "modf", dst, lhs, rhs),
             Instruction::PowF(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "powf", dst, lhs, rhs),
             Instruction::LogF(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "logf", dst, lhs, rhs),
-            Instruction::AddI(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "add", dst, lhs, rhs),
-            Instruction::SubI(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "sub", dst, lhs, rhs),
-            Instruction::MulI(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "mul", dst, lhs, rhs),
-            Instruction::DivI(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "div", dst, lhs, rhs),
-            Instruction::ModI(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "mod", dst, lhs, rhs),
+            Instruction::AddI(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "addi", dst, lhs, rhs),
+            Instruction::SubI(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "subi", dst, lhs, rhs),
+            Instruction::MulI(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "muli", dst, lhs, rhs),
+            Instruction::DivI(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "divi", dst, lhs, rhs),
+            Instruction::ModI(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "modi", dst, lhs, rhs),
             Instruction::Eq(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "eq", dst, lhs, rhs),
             Instruction::Ne(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "ne", dst, lhs, rhs),
             Instruction::Gt(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "gt", dst, lhs, rhs),
             Instruction::Ge(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "ge", dst, lhs, rhs),
-            Instruction::Lt(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "le", dst, lhs, rhs),
-            Instruction::Le(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "lt", dst, lhs, rhs),
+            Instruction::Lt(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "lt", dst, lhs, rhs),
+            Instruction::Le(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "le", dst, lhs, rhs),
             Instruction::And(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "and", dst, lhs, rhs),
             Instruction::Or(dst, lhs, rhs) => write!(f, "{:<10} {} {} {}", "or", dst, lhs, rhs),
             Instruction::Dummy => write!(f, "dummy"),
@@ -0,0 +1,1049 @@
+//! Just-in-time compiler of the bytecode functions to the native code with [Cranelift](https://cranelift.dev/),
+//! enabled with the `jit` feature.
+//!
+//! The compiled function works on the same memory as the interpreter: the registers are the words
+//! of the VM stack from the base pointer, and the states are read from the current state storage.
+//! The instructions for the arithmetics, moves, branches and the state storage are translated to
+//! the native instructions, while the calls, delays, arrays and spill slots call back into the
+//! helper functions below, which share the implementation with [`Machine::execute`]. Thus the
+//! compiled and the interpreted functions can call each other freely.
+//!
+//! The functions which create or access the closures are not compiled, and they are interpreted
+//! as before.
+
+use std::collections::HashMap;
+
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{types, AbiParam, Block, FuncRef, InstBuilder, MemFlags, Type, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+
+use super::{
+    FuncProto, Instruction, Machine, Program, RawVal, Reg, ReturnCode, RUNTIME_ERROR_CODE,
+};
+use crate::interner::Symbol;
+use crate::runtime;
+
+/// The compiled function. It takes the machine, whose base pointer is set to the frame of the
+/// function, and returns the number of the return values as [`Machine::execute`] does.
+pub type JitFunction = unsafe extern "C" fn(*mut Machine) -> ReturnCode;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The host machine is not supported by Cranelift.
+    UnsupportedHost(String),
+    /// The function has the instruction which is only available in the interpreter.
+    UnsupportedInstruction(Instruction),
+    /// The jump destination is out of the function.
+    InvalidJump(usize),
+    InvalidConstant(usize),
+    Codegen(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnsupportedHost(msg) => write!(f, "JIT compilation is not available: {msg}"),
+            Error::UnsupportedInstruction(inst) => {
+                write!(
+                    f,
+                    "instruction \"{inst}\" is not supported by the JIT compiler"
+                )
+            }
+            Error::InvalidJump(pc) => write!(f, "jump at {pc} goes out of the function"),
+            Error::InvalidConstant(pc) => write!(f, "constant used at {pc} does not exist"),
+            Error::Codegen(msg) => write!(f, "code generation failed: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Ptr,
+    Int,
+    Float,
+}
+
+/// Declares the functions called from the compiled code.
+macro_rules! helpers {
+    ($($name:ident => $f:ident($($p:ident),*) $(-> $r:ident)?;)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        enum Helper {
+            $($name),*
+        }
+        impl Helper {
+            const ALL: &'static [Helper] = &[$(Helper::$name),*];
+            fn symbol(self) -> &'static str {
+                match self {
+                    $(Helper::$name => stringify!($f)),*
+                }
+            }
+            fn address(self) -> *const u8 {
+                match self {
+                    $(Helper::$name => $f as *const u8),*
+                }
+            }
+            fn params(self) -> &'static [Kind] {
+                match self {
+                    $(Helper::$name => &[$(Kind::$p),*]),*
+                }
+            }
+            fn returns(self) -> Option<Kind> {
+                match self {
+                    $(Helper::$name => helpers!(@ret $($r)?)),*
+                }
+            }
+        }
+    };
+    (@ret) => { None };
+    (@ret $r:ident) => { Some(Kind::$r) };
+}
+
+helpers! {
+    Regs => mmm_jit_regs(Ptr, Int) -> Ptr;
+    EnterSpill => mmm_jit_enter_spill(Ptr, Int) -> Int;
+    Spill => mmm_jit_spill(Ptr, Int, Int);
+    Reload => mmm_jit_reload(Ptr, Int) -> Int;
+    Return0 => mmm_jit_return0(Ptr, Int) -> Int;
+    Return => mmm_jit_return(Ptr, Int, Int, Int) -> Int;
+    Call => mmm_jit_call(Ptr, Int, Int, Int) -> Int;
+    CallCls => mmm_jit_call_cls(Ptr, Int, Int, Int) -> Int;
+    CallExtFun => mmm_jit_call_ext_fun(Ptr, Int, Int, Int) -> Int;
+    Globals => mmm_jit_globals(Ptr) -> Ptr;
+    State => mmm_jit_state(Ptr) -> Ptr;
+    ShiftState => mmm_jit_shift_state(Ptr, Int) -> Ptr;
+    Delay => mmm_jit_delay(Ptr, Int, Int, Int) -> Int;
+    AllocArray => mmm_jit_alloc_array(Ptr, Int, Int) -> Int;
+    GetArrayElem => mmm_jit_get_array_elem(Ptr, Int, Int, Int) -> Int;
+    SetArrayElem => mmm_jit_set_array_elem(Ptr, Int, Int, Int) -> Int;
+    DivByZero => mmm_jit_div_by_zero(Ptr) -> Int;
+    DivI => mmm_jit_div_i(Int, Int) -> Int;
+    ModI => mmm_jit_mod_i(Int, Int) -> Int;
+    PowI => mmm_jit_pow_i(Int, Int) -> Int;
+    ModF => mmm_jit_mod_f(Float, Float) -> Float;
+    PowF => mmm_jit_pow_f(Float, Float) -> Float;
+    LogF => mmm_jit_log_f(Float, Float) -> Float;
+    SinF => mmm_jit_sin_f(Float) -> Float;
+    CosF => mmm_jit_cos_f(Float) -> Float;
+}
+
+// The helpers are called only from the compiled functions, with the machine running them.
+
+/// Extends the stack to hold the frame of the function, and returns the address of its first register.
+extern "C" fn mmm_jit_regs(m: *mut Machine, frame_size: u64) -> *mut RawVal {
+    let m = unsafe { &mut *m };
+    let base = m.base_pointer as usize;
+    if m.stack.len() < base + frame_size as usize {
+        m.stack.resize(base + frame_size as usize, 0);
+    }
+    unsafe { m.stack.as_mut_ptr().add(base) }
+}
+extern "C" fn mmm_jit_enter_spill(m: *mut Machine, spill_size: u64) -> u64 {
+    let m = unsafe { &mut *m };
+    let spill_base = m.spill_stack.len();
+    m.spill_stack.resize(spill_base + spill_size as usize, 0);
+    spill_base as u64
+}
+extern "C" fn mmm_jit_spill(m: *mut Machine, pos: u64, v: RawVal) {
+    let m = unsafe { &mut *m };
+    m.spill_stack[pos as usize] = v;
+}
+extern "C" fn mmm_jit_reload(m: *mut Machine, pos: u64) -> RawVal {
+    let m = unsafe { &mut *m };
+    m.spill_stack[pos as usize]
+}
+extern "C" fn mmm_jit_return0(m: *mut Machine, spill_base: u64) -> ReturnCode {
+    let m = unsafe { &mut *m };
+    m.stack.truncate((m.base_pointer - 1) as usize);
+    m.spill_stack.truncate(spill_base as usize);
+    0
+}
+extern "C" fn mmm_jit_return(m: *mut Machine, iret: u64, nret: u64, spill_base: u64) -> ReturnCode {
+    let m = unsafe { &mut *m };
+    let _ = m.return_general(iret as Reg, nret as Reg);
+    m.spill_stack.truncate(spill_base as usize);
+    nret as ReturnCode
+}
+extern "C" fn mmm_jit_call(m: *mut Machine, func: u64, nargs: u64, nret_req: u64) -> ReturnCode {
+    let m = unsafe { &mut *m };
+    m.call_internal(func as _, nargs as _, nret_req as _)
+}
+extern "C" fn mmm_jit_call_cls(
+    m: *mut Machine,
+    func: u64,
+    nargs: u64,
+    nret_req: u64,
+) -> ReturnCode {
+    let m = unsafe { &mut *m };
+    m.call_closure(func as _, nargs as _, nret_req as _)
+}
+extern "C" fn mmm_jit_call_ext_fun(
+    m: *mut Machine,
+    func: u64,
+    nargs: u64,
+    nret_req: u64,
+) -> ReturnCode {
+    let m = unsafe { &mut *m };
+    m.call_ext_function(func as _, nargs as _, nret_req as _)
+}
+extern "C" fn mmm_jit_globals(m: *mut Machine) -> *mut RawVal {
+    let m = unsafe { &mut *m };
+    m.global_vals.as_mut_ptr()
+}
+extern "C" fn mmm_jit_state(m: *mut Machine) -> *mut RawVal {
+    let m = unsafe { &mut *m };
+    m.get_current_state().get_state_mut(0).as_mut_ptr()
+}
+extern "C" fn mmm_jit_shift_state(m: *mut Machine, offset: i64) -> *mut RawVal {
+    let m = unsafe { &mut *m };
+    let state = m.get_current_state();
+    state.shift_pos(offset as _);
+    state.get_state_mut(0).as_mut_ptr()
+}
+extern "C" fn mmm_jit_delay(m: *mut Machine, func_i: u64, input: RawVal, time: RawVal) -> RawVal {
+    let m = unsafe { &mut *m };
+    m.process_delay(func_i as _, input, time)
+}
+extern "C" fn mmm_jit_alloc_array(m: *mut Machine, len: u64, elem_size: u64) -> RawVal {
+    let m = unsafe { &mut *m };
+    Machine::to_value(m.arrays.alloc(len as _, elem_size as _))
+}
+extern "C" fn mmm_jit_get_array_elem(m: *mut Machine, dst: u64, arr: u64, idx: u64) -> ReturnCode {
+    let m = unsafe { &mut *m };
+    m.load_array_elem(dst as _, arr as _, idx as _)
+        .map_or_else(|code| code, |()| 0)
+}
+extern "C" fn mmm_jit_set_array_elem(m: *mut Machine, arr: u64, idx: u64, src: u64) -> ReturnCode {
+    let m = unsafe { &mut *m };
+    m.store_array_elem(arr as _, idx as _, src as _)
+        .map_or_else(|code| code, |()| 0)
+}
+extern "C" fn mmm_jit_div_by_zero(m: *mut Machine) -> ReturnCode {
+    let m = unsafe { &mut *m };
+    m.raise(runtime::ErrorKind::DivisionByZero)
+}
+extern "C" fn mmm_jit_div_i(a: i64, b: i64) -> i64 {
+    a.wrapping_div(b)
+}
+extern "C" fn mmm_jit_mod_i(a: i64, b: i64) -> i64 {
+    a.wrapping_rem(b)
+}
+extern "C" fn mmm_jit_pow_i(a: i64, b: i64) -> i64 {
+    Machine::int_pow(a, b)
+}
+extern "C" fn mmm_jit_mod_f(a: f64, b: f64) -> f64 {
+    a % b
+}
+extern "C" fn mmm_jit_pow_f(a: f64, b: f64) -> f64 {
+    a.powf(b)
+}
+extern "C" fn mmm_jit_log_f(a: f64, b: f64) -> f64 {
+    a.log(b)
+}
+extern "C" fn mmm_jit_sin_f(a: f64) -> f64 {
+    a.sin()
+}
+extern "C" fn mmm_jit_cos_f(a: f64) -> f64 {
+    a.cos()
+}
+
+/// The native code of the functions in a program. The function table is indexed same as
+/// [`Program::global_fn_table`], and has `None` for the functions left to the interpreter.
+pub struct JitEngine {
+    // kept in Option to release the memory of the code on drop.
+    module: Option<JITModule>,
+    functions: Vec<Option<JitFunction>>,
+}
+
+impl JitEngine {
+    /// Compiles all the functions in the program. The functions which cannot be compiled are
+    /// returned with the reasons.
+    pub fn new(prog: &Program) -> Result<(Self, Vec<(Symbol, Error)>), Error> {
+        let mut flags = settings::builder();
+        let flag_err = |e: settings::SetError| Error::UnsupportedHost(e.to_string());
+        flags
+            .set("use_colocated_libcalls", "false")
+            .map_err(flag_err)?;
+        flags.set("is_pic", "false").map_err(flag_err)?;
+        flags.set("opt_level", "speed").map_err(flag_err)?;
+        let isa = cranelift_native::builder()
+            .map_err(|msg| Error::UnsupportedHost(msg.to_string()))?
+            .finish(settings::Flags::new(flags))
+            .map_err(|e| Error::UnsupportedHost(e.to_string()))?;
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        for h in Helper::ALL {
+            builder.symbol(h.symbol(), h.address());
+        }
+        let mut module = JITModule::new(builder);
+        let ptr_ty = module.target_config().pointer_type();
+        let mut helper_ids = HashMap::new();
+        for h in Helper::ALL {
+            let mut sig = module.make_signature();
+            let ty = |k: Kind| match k {
+                Kind::Ptr => ptr_ty,
+                Kind::Int => types::I64,
+                Kind::Float => types::F64,
+            };
+            sig.params
+                .extend(h.params().iter().map(|k| AbiParam::new(ty(*k))));
+            sig.returns
+                .extend(h.returns().map(|k| AbiParam::new(ty(k))));
+            let id = module
+                .declare_function(h.symbol(), Linkage::Import, &sig)
+                .map_err(|e| Error::Codegen(e.to_string()))?;
+            helper_ids.insert(*h, id);
+        }
+
+        let mut errors = vec![];
+        let mut ids = vec![];
+        let mut ctx = module.make_context();
+        let mut fb_ctx = FunctionBuilderContext::new();
+        for (func_i, (name, func)) in prog.global_fn_table.iter().enumerate() {
+            let res = check_function(func).and_then(|leaders| {
+                ctx.func.signature.params.push(AbiParam::new(ptr_ty));
+                ctx.func.signature.returns.push(AbiParam::new(types::I64));
+                let builder = FunctionBuilder::new(&mut ctx.func, &mut fb_ctx);
+                let translator = FunctionTranslator {
+                    builder,
+                    module: &mut module,
+                    helper_ids: &helper_ids,
+                    helper_refs: HashMap::new(),
+                    ptr_ty,
+                    func_i,
+                    func,
+                    frame_size: frame_size(func),
+                    machine: Variable::from_u32(0),
+                    regs: Variable::from_u32(1),
+                    state: Variable::from_u32(2),
+                    globals: Variable::from_u32(3),
+                    spill_base: Variable::from_u32(4),
+                };
+                translator.translate(&leaders)?;
+                let id = module
+                    .declare_anonymous_function(&ctx.func.signature)
+                    .map_err(|e| Error::Codegen(e.to_string()))?;
+                module
+                    .define_function(id, &mut ctx)
+                    .map_err(|e| Error::Codegen(format!("{e:?}")))?;
+                Ok(id)
+            });
+            module.clear_context(&mut ctx);
+            match res {
+                Ok(id) => ids.push(Some(id)),
+                Err(e) => {
+                    errors.push((*name, e));
+                    ids.push(None);
+                }
+            }
+        }
+        module
+            .finalize_definitions()
+            .map_err(|e| Error::Codegen(e.to_string()))?;
+        let functions = ids
+            .into_iter()
+            .map(|id| {
+                id.map(|id| unsafe {
+                    std::mem::transmute::<*const u8, JitFunction>(module.get_finalized_function(id))
+                })
+            })
+            .collect();
+        let engine = Self {
+            module: Some(module),
+            functions,
+        };
+        Ok((engine, errors))
+    }
+    /// The compiled function at the index of the function table, if it could be compiled.
+    pub fn get(&self, func_i: usize) -> Option<JitFunction> {
+        self.functions.get(func_i).copied().flatten()
+    }
+}
+
+impl Drop for JitEngine {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // the functions are not referred from anywhere after the engine is dropped.
+            unsafe { module.free_memory() }
+        }
+    }
+}
+
+/// Checks that the function can be compiled, and returns the instructions which start the basic blocks.
+fn check_function(func: &FuncProto) -> Result<Vec<bool>, Error> {
+    let len = func.bytecodes.len();
+    // the extra element is for the end of the function.
+    let mut leaders = vec![false; len + 1];
+    leaders[0] = true;
+    for (pc, inst) in func.bytecodes.iter().enumerate() {
+        match *inst {
+            Instruction::Jmp(offset) | Instruction::JmpIfNeg(_, offset) => {
+                let dst = pc as i64 + offset as i64;
+                if dst < 0 || dst as usize >= len {
+                    return Err(Error::InvalidJump(pc));
+                }
+                leaders[dst as usize] = true;
+                leaders[pc + 1] = true;
+            }
+            Instruction::Return0 | Instruction::Return(_, _) => leaders[pc + 1] = true,
+            Instruction::MoveConst(_, pos) if pos as usize >= func.constants.len() => {
+                return Err(Error::InvalidConstant(pc))
+            }
+            Instruction::Closure(_, _)
+            | Instruction::Close(_)
+            | Instruction::GetUpValue(_, _, _)
+            | Instruction::SetUpValue(_, _, _)
+            | Instruction::LogI(_, _, _)
+            | Instruction::CastItoB(_, _)
+            | Instruction::Dummy => return Err(Error::UnsupportedInstruction(*inst)),
+            _ => {}
+        }
+    }
+    Ok(leaders)
+}
+
+/// The number of the registers used in the function, including the arguments and the return
+/// values of the calls.
+fn frame_size(func: &FuncProto) -> u64 {
+    let words = |r: Reg, n: u8| r as u64 + n as u64;
+    let max = |regs: &[Reg]| regs.iter().map(|r| *r as u64 + 1).max().unwrap_or(0);
+    let size = func.bytecodes.iter().map(|inst| match *inst {
+        Instruction::MoveRange(dst, src, n) => words(dst, n).max(words(src, n)),
+        Instruction::Call(f, nargs, nret)
+        | Instruction::CallCls(f, nargs, nret)
+        | Instruction::CallExtFun(f, nargs, nret) => words(f, nargs + 1).max(words(f, nret)),
+        Instruction::Return(iret, nret) => words(iret, nret),
+        Instruction::GetState(r, size) | Instruction::SetState(r, size) => words(r, size),
+        Instruction::Move(dst, src)
+        | Instruction::NegF(dst, src)
+        | Instruction::AbsF(dst, src)
+        | Instruction::SqrtF(dst, src)
+        | Instruction::SinF(dst, src)
+        | Instruction::CosF(dst, src)
+        | Instruction::NegI(dst, src)
+        | Instruction::AbsI(dst, src)
+        | Instruction::Not(dst, src)
+        | Instruction::CastFtoI(dst, src)
+        | Instruction::CastItoF(dst, src)
+        | Instruction::Mem(dst, src) => max(&[dst, src]),
+        Instruction::MoveConst(r, _)
+        | Instruction::MoveImmF(r, _)
+        | Instruction::GetGlobal(r, _)
+        | Instruction::SetGlobal(_, r)
+        | Instruction::Spill(_, r)
+        | Instruction::Reload(r, _)
+        | Instruction::JmpIfNeg(r, _) => max(&[r]),
+        Instruction::AllocArray(dst, len, _) => max(&[dst, len]),
+        Instruction::GetArrayElem(a, b, c)
+        | Instruction::SetArrayElem(a, b, c)
+        | Instruction::Delay(a, b, c)
+        | Instruction::AddF(a, b, c)
+        | Instruction::SubF(a, b, c)
+        | Instruction::MulF(a, b, c)
+        | Instruction::DivF(a, b, c)
+        | Instruction::ModF(a, b, c)
+        | Instruction::PowF(a, b, c)
+        | Instruction::LogF(a, b, c)
+        | Instruction::AddI(a, b, c)
+        | Instruction::SubI(a, b, c)
+        | Instruction::MulI(a, b, c)
+        | Instruction::DivI(a, b, c)
+        | Instruction::ModI(a, b, c)
+        | Instruction::PowI(a, b, c)
+        | Instruction::Eq(a, b, c)
+        | Instruction::Ne(a, b, c)
+        | Instruction::Gt(a, b, c)
+        | Instruction::Ge(a, b, c)
+        | Instruction::Lt(a, b, c)
+        | Instruction::Le(a, b, c)
+        | Instruction::And(a, b, c)
+        | Instruction::Or(a, b, c) => max(&[a, b, c]),
+        _ => 0,
+    });
+    size.max().unwrap_or(0)
+}
+
+struct FunctionTranslator<'a> {
+    builder: FunctionBuilder<'a>,
+    module: &'a mut JITModule,
+    helper_ids: &'a HashMap<Helper, FuncId>,
+    helper_refs: HashMap<Helper, FuncRef>,
+    ptr_ty: Type,
+    func_i: usize,
+    func: &'a FuncProto,
+    frame_size: u64,
+    machine: Variable,
+    // the address of the register 0.
+    regs: Variable,
+    // the address of the current position of the state storage.
+    state: Variable,
+    globals: Variable,
+    spill_base: Variable,
+}
+
+impl FunctionTranslator<'_> {
+    fn translate(mut self, leaders: &[bool]) -> Result<(), Error> {
+        let blocks: HashMap<usize, Block> = leaders
+            .iter()
+            .enumerate()
+            .filter(|(_, is_leader)| **is_leader)
+            .map(|(pc, _)| (pc, self.builder.create_block()))
+            .collect();
+
+        let entry = self.builder.create_block();
+        self.builder.append_block_params_for_function_params(entry);
+        self.builder.switch_to_block(entry);
+        let ptr_ty = self.ptr_ty;
+        self.builder.declare_var(self.machine, ptr_ty);
+        self.builder.declare_var(self.regs, ptr_ty);
+        self.builder.declare_var(self.state, ptr_ty);
+        self.builder.declare_var(self.globals, ptr_ty);
+        self.builder.declare_var(self.spill_base, types::I64);
+        let m = self.builder.block_params(entry)[0];
+        self.builder.def_var(self.machine, m);
+        self.refresh_regs();
+        let spill_size = self.iconst(self.func.spill_size as i64);
+        let spill_base = self.call_helper(Helper::EnterSpill, &[m, spill_size])[0];
+        self.builder.def_var(self.spill_base, spill_base);
+        let state = self.call_helper(Helper::State, &[m])[0];
+        self.builder.def_var(self.state, state);
+        let globals = self.call_helper(Helper::Globals, &[m])[0];
+        self.builder.def_var(self.globals, globals);
+        self.builder.ins().jump(blocks[&0], &[]);
+
+        let mut is_terminated = true;
+        for (pc, inst) in self.func.bytecodes.iter().enumerate() {
+            if let Some(block) = blocks.get(&pc) {
+                if !is_terminated {
+                    self.builder.ins().jump(*block, &[]);
+                }
+                self.builder.switch_to_block(*block);
+            }
+            is_terminated = self.translate_instruction(pc, *inst, &blocks);
+        }
+        // falling through the end of the function is an invalid bytecode.
+        let end = blocks[&self.func.bytecodes.len()];
+        if !is_terminated {
+            self.builder.ins().jump(end, &[]);
+        }
+        self.builder.switch_to_block(end);
+        let code = self.iconst(RUNTIME_ERROR_CODE);
+        self.builder.ins().return_(&[code]);
+
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+        Ok(())
+    }
+
+    /// Translates an instruction, and returns whether it terminates the block.
+    fn translate_instruction(
+        &mut self,
+        pc: usize,
+        inst: Instruction,
+        blocks: &HashMap<usize, Block>,
+    ) -> bool {
+        let f64t = types::F64;
+        let i64t = types::I64;
+        match inst {
+            Instruction::Move(dst, src) => {
+                let v = self.load_reg(i64t, src);
+                self.store_reg(dst, v);
+            }
+            Instruction::MoveConst(dst, pos) => {
+                let v = self.iconst(self.func.constants[pos as usize] as i64);
+                self.store_reg(dst, v);
+            }
+            Instruction::MoveImmF(dst, v) => {
+                let v = self.builder.ins().f64const(Into::<f64>::into(v));
+                self.store_reg(dst, v);
+            }
+            Instruction::MoveRange(dst, src, n) => {
+                // the ranges may overlap.
+                let vs = (0..n)
+                    .map(|i| self.load_reg(i64t, src + i))
+                    .collect::<Vec<_>>();
+                vs.into_iter()
+                    .enumerate()
+                    .for_each(|(i, v)| self.store_reg(dst + i as Reg, v));
+            }
+            Instruction::Call(func, nargs, nret_req) => {
+                self.call(Helper::Call, func, nargs, nret_req)
+            }
+            Instruction::CallCls(func, nargs, nret_req) => {
+                self.call(Helper::CallCls, func, nargs, nret_req)
+            }
+            Instruction::CallExtFun(func, nargs, nret_req) => {
+                self.call(Helper::CallExtFun, func, nargs, nret_req)
+            }
+            Instruction::Return0 => {
+                let m = self.builder.use_var(self.machine);
+                let spill_base = self.builder.use_var(self.spill_base);
+                let res = self.call_helper(Helper::Return0, &[m, spill_base])[0];
+                self.builder.ins().return_(&[res]);
+                return true;
+            }
+            Instruction::Return(iret, nret) => {
+                let m = self.builder.use_var(self.machine);
+                let iret = self.iconst(iret as i64);
+                let nret = self.iconst(nret as i64);
+                let spill_base = self.builder.use_var(self.spill_base);
+                let res = self.call_helper(Helper::Return, &[m, iret, nret, spill_base])[0];
+                self.builder.ins().return_(&[res]);
+                return true;
+            }
+            Instruction::GetGlobal(dst, gid) => {
+                let globals = self.builder.use_var(self.globals);
+                let v = self.load(i64t, globals, gid as i32);
+                self.store_reg(dst, v);
+            }
+            Instruction::SetGlobal(gid, src) => {
+                let v = self.load_reg(i64t, src);
+                let globals = self.builder.use_var(self.globals);
+                self.store(globals, gid as i32, v);
+            }
+            Instruction::Spill(slot, src) => {
+                let pos = self.spill_pos(slot);
+                let v = self.load_reg(i64t, src);
+                let m = self.builder.use_var(self.machine);
+                self.call_helper(Helper::Spill, &[m, pos, v]);
+            }
+            Instruction::Reload(dst, slot) => {
+                let pos = self.spill_pos(slot);
+                let m = self.builder.use_var(self.machine);
+                let v = self.call_helper(Helper::Reload, &[m, pos])[0];
+                self.store_reg(dst, v);
+            }
+            Instruction::AllocArray(dst, len, elem_size) => {
+                let m = self.builder.use_var(self.machine);
+                let len = self.load_reg(i64t, len);
+                let elem_size = self.iconst(elem_size as i64);
+                let v = self.call_helper(Helper::AllocArray, &[m, len, elem_size])[0];
+                self.store_reg(dst, v);
+            }
+            Instruction::GetArrayElem(a, b, c) | Instruction::SetArrayElem(a, b, c) => {
+                let helper = if matches!(inst, Instruction::GetArrayElem(..)) {
+                    Helper::GetArrayElem
+                } else {
+                    Helper::SetArrayElem
+                };
+                let m = self.builder.use_var(self.machine);
+                let args = [
+                    m,
+                    self.iconst(a as i64),
+                    self.iconst(b as i64),
+                    self.iconst(c as i64),
+                ];
+                let code = self.call_helper(helper, &args)[0];
+                self.return_if_error(code);
+                // the element may be wider than the frame.
+                self.refresh_regs();
+            }
+            Instruction::Jmp(offset) => {
+                let dst = (pc as i64 + offset as i64) as usize;
+                self.builder.ins().jump(blocks[&dst], &[]);
+                return true;
+            }
+            Instruction::JmpIfNeg(cond, offset) => {
+                let dst = (pc as i64 + offset as i64) as usize;
+                let v = self.load_reg(f64t, cond);
+                let zero = self.builder.ins().f64const(0.0);
+                let is_neg = self.builder.ins().fcmp(FloatCC::LessThanOrEqual, v, zero);
+                self.builder
+                    .ins()
+                    .brif(is_neg, blocks[&dst], &[], blocks[&(pc + 1)], &[]);
+                return true;
+            }
+            Instruction::AddF(dst, a, b) => {
+                self.binop(f64t, dst, a, b, |b, x, y| b.ins().fadd(x, y))
+            }
+            Instruction::SubF(dst, a, b) => {
+                self.binop(f64t, dst, a, b, |b, x, y| b.ins().fsub(x, y))
+            }
+            Instruction::MulF(dst, a, b) => {
+                self.binop(f64t, dst, a, b, |b, x, y| b.ins().fmul(x, y))
+            }
+            Instruction::DivF(dst, a, b) => {
+                self.binop(f64t, dst, a, b, |b, x, y| b.ins().fdiv(x, y))
+            }
+            Instruction::ModF(dst, a, b) => self.binop_helper(f64t, Helper::ModF, dst, a, b),
+            Instruction::PowF(dst, a, b) => self.binop_helper(f64t, Helper::PowF, dst, a, b),
+            Instruction::LogF(dst, a, b) => self.binop_helper(f64t, Helper::LogF, dst, a, b),
+            Instruction::NegF(dst, src) => self.uniop(f64t, dst, src, |b, x| b.ins().fneg(x)),
+            Instruction::AbsF(dst, src) => self.uniop(f64t, dst, src, |b, x| b.ins().fabs(x)),
+            Instruction::SqrtF(dst, src) => self.uniop(f64t, dst, src, |b, x| b.ins().sqrt(x)),
+            Instruction::SinF(dst, src) => {
+                let v = self.load_reg(f64t, src);
+                let res = self.call_helper(Helper::SinF, &[v])[0];
+                self.store_reg(dst, res);
+            }
+            Instruction::CosF(dst, src) => {
+                let v = self.load_reg(f64t, src);
+                let res = self.call_helper(Helper::CosF, &[v])[0];
+                self.store_reg(dst, res);
+            }
+            Instruction::AddI(dst, a, b) => {
+                self.binop(i64t, dst, a, b, |b, x, y| b.ins().iadd(x, y))
+            }
+            Instruction::SubI(dst, a, b) => {
+                self.binop(i64t, dst, a, b, |b, x, y| b.ins().isub(x, y))
+            }
+            Instruction::MulI(dst, a, b) => {
+                self.binop(i64t, dst, a, b, |b, x, y| b.ins().imul(x, y))
+            }
+            Instruction::DivI(dst, a, b) | Instruction::ModI(dst, a, b) => {
+                let divisor = self.load_reg(i64t, b);
+                let is_zero = self.builder.ins().icmp_imm(IntCC::Equal, divisor, 0);
+                let err_block = self.builder.create_block();
+                let ok_block = self.builder.create_block();
+                self.builder.set_cold_block(err_block);
+                self.builder
+                    .ins()
+                    .brif(is_zero, err_block, &[], ok_block, &[]);
+                self.builder.switch_to_block(err_block);
+                let m = self.builder.use_var(self.machine);
+                let code = self.call_helper(Helper::DivByZero, &[m])[0];
+                self.builder.ins().return_(&[code]);
+                self.builder.switch_to_block(ok_block);
+                let helper = if matches!(inst, Instruction::DivI(..)) {
+                    Helper::DivI
+                } else {
+                    Helper::ModI
+                };
+                self.binop_helper(i64t, helper, dst, a, b)
+            }
+            Instruction::PowI(dst, a, b) => self.binop_helper(i64t, Helper::PowI, dst, a, b),
+            Instruction::NegI(dst, src) => self.uniop(i64t, dst, src, |b, x| b.ins().ineg(x)),
+            Instruction::AbsI(dst, src) => self.uniop(i64t, dst, src, |b, x| b.ins().iabs(x)),
+            Instruction::Not(dst, src) => self.uniop(f64t, dst, src, |b, x| {
+                let zero = b.ins().f64const(0.0);
+                let c = b.ins().fcmp(FloatCC::GreaterThan, x, zero);
+                let (t, f) = (b.ins().f64const(1.0), b.ins().f64const(0.0));
+                b.ins().select(c, f, t)
+            }),
+            Instruction::Eq(dst, a, b) => self.compare(FloatCC::Equal, dst, a, b),
+            Instruction::Ne(dst, a, b) => self.compare(FloatCC::NotEqual, dst, a, b),
+            Instruction::Gt(dst, a, b) => self.compare(FloatCC::GreaterThan, dst, a, b),
+            Instruction::Ge(dst, a, b) => self.compare(FloatCC::GreaterThanOrEqual, dst, a, b),
+            Instruction::Lt(dst, a, b) => self.compare(FloatCC::LessThan, dst, a, b),
+            Instruction::Le(dst, a, b) => self.compare(FloatCC::LessThanOrEqual, dst, a, b),
+            Instruction::And(dst, a, b) | Instruction::Or(dst, a, b) => {
+                let is_and = matches!(inst, Instruction::And(..));
+                self.binop(f64t, dst, a, b, |b, x, y| {
+                    let zero = b.ins().f64const(0.0);
+                    let cx = b.ins().fcmp(FloatCC::GreaterThan, x, zero);
+                    let cy = b.ins().fcmp(FloatCC::GreaterThan, y, zero);
+                    let c = if is_and {
+                        b.ins().band(cx, cy)
+                    } else {
+                        b.ins().bor(cx, cy)
+                    };
+                    let (t, f) = (b.ins().f64const(1.0), b.ins().f64const(0.0));
+                    b.ins().select(c, t, f)
+                })
+            }
+            Instruction::CastFtoI(dst, src) => {
+                let v = self.load_reg(f64t, src);
+                let res = self.builder.ins().fcvt_to_sint_sat(i64t, v);
+                self.store_reg(dst, res);
+            }
+            Instruction::CastItoF(dst, src) => {
+                let v = self.load_reg(i64t, src);
+                let res = self.builder.ins().fcvt_from_sint(f64t, v);
+                self.store_reg(dst, res);
+            }
+            Instruction::GetState(dst, size) => {
+                let state = self.builder.use_var(self.state);
+                for i in 0..size {
+                    let v = self.load(i64t, state, i as i32);
+                    self.store_reg(dst + i, v);
+                }
+            }
+            Instruction::SetState(src, size) => {
+                let state = self.builder.use_var(self.state);
+                for i in 0..size {
+                    let v = self.load_reg(i64t, src + i);
+                    self.store(state, i as i32, v);
+                }
+            }
+            Instruction::ShiftStatePos(offset) => {
+                let m = self.builder.use_var(self.machine);
+                let offset = self.iconst(offset as i64);
+                let state = self.call_helper(Helper::ShiftState, &[m, offset])[0];
+                self.builder.def_var(self.state, state);
+            }
+            Instruction::Delay(dst, src, time) => {
+                let m = self.builder.use_var(self.machine);
+                let func_i = self.iconst(self.func_i as i64);
+                let input = self.load_reg(i64t, src);
+                let time = self.load_reg(i64t, time);
+                let res = self.call_helper(Helper::Delay, &[m, func_i, input, time])[0];
+                self.store_reg(dst, res);
+            }
+            Instruction::Mem(dst, src) => {
+                let v = self.load_reg(i64t, src);
+                let state = self.builder.use_var(self.state);
+                let prev = self.load(i64t, state, 0);
+                self.store_reg(dst, prev);
+                self.store(state, 0, v);
+            }
+            Instruction::Closure(..)
+            | Instruction::Close(..)
+            | Instruction::GetUpValue(..)
+            | Instruction::SetUpValue(..)
+            | Instruction::LogI(..)
+            | Instruction::CastItoB(..)
+            | Instruction::Dummy => unreachable!("rejected by check_function"),
+        }
+        false
+    }
+
+    fn iconst(&mut self, v: i64) -> Value {
+        self.builder.ins().iconst(types::I64, v)
+    }
+    /// Loads the word at the index from the address.
+    fn load(&mut self, ty: Type, addr: Value, index: i32) -> Value {
+        self.builder
+            .ins()
+            .load(ty, MemFlags::trusted(), addr, index * 8)
+    }
+    fn store(&mut self, addr: Value, index: i32, v: Value) {
+        self.builder
+            .ins()
+            .store(MemFlags::trusted(), v, addr, index * 8);
+    }
+    fn load_reg(&mut self, ty: Type, reg: Reg) -> Value {
+        let regs = self.builder.use_var(self.regs);
+        self.load(ty, regs, reg as i32)
+    }
+    fn store_reg(&mut self, reg: Reg, v: Value) {
+        let regs = self.builder.use_var(self.regs);
+        self.store(regs, reg as i32, v)
+    }
+    /// Gets the address of the registers again, as the stack may be reallocated by the helpers.
+    fn refresh_regs(&mut self) {
+        let m = self.builder.use_var(self.machine);
+        let frame_size = self.iconst(self.frame_size as i64);
+        let regs = self.call_helper(Helper::Regs, &[m, frame_size])[0];
+        self.builder.def_var(self.regs, regs);
+    }
+    fn spill_pos(&mut self, slot: u16) -> Value {
+        let spill_base = self.builder.use_var(self.spill_base);
+        self.builder.ins().iadd_imm(spill_base, slot as i64)
+    }
+    fn call_helper(&mut self, helper: Helper, args: &[Value]) -> Vec<Value> {
+        let fref = *self.helper_refs.entry(helper).or_insert_with(|| {
+            self.module
+                .declare_func_in_func(self.helper_ids[&helper], self.builder.func)
+        });
+        let call = self.builder.ins().call(fref, args);
+        self.builder.inst_results(call).to_vec()
+    }
+    /// Exits from the function with the return code if it is negative.
+    fn return_if_error(&mut self, code: Value) {
+        let is_err = self.builder.ins().icmp_imm(IntCC::SignedLessThan, code, 0);
+        let err_block = self.builder.create_block();
+        let ok_block = self.builder.create_block();
+        self.builder.set_cold_block(err_block);
+        self.builder
+            .ins()
+            .brif(is_err, err_block, &[], ok_block, &[]);
+        self.builder.switch_to_block(err_block);
+        self.builder.ins().return_(&[code]);
+        self.builder.switch_to_block(ok_block);
+    }
+    fn call(&mut self, helper: Helper, func: Reg, nargs: u8, nret_req: u8) {
+        let m = self.builder.use_var(self.machine);
+        let args = [
+            m,
+            self.iconst(func as i64),
+            self.iconst(nargs as i64),
+            self.iconst(nret_req as i64),
+        ];
+        let nret = self.call_helper(helper, &args)[0];
+        self.return_if_error(nret);
+        // the stack was shrunk to the return values by the callee.
+        self.refresh_regs();
+    }
+    fn uniop(
+        &mut self,
+        ty: Type,
+        dst: Reg,
+        src: Reg,
+        op: impl FnOnce(&mut FunctionBuilder, Value) -> Value,
+    ) {
+        let v = self.load_reg(ty, src);
+        let res = op(&mut self.builder, v);
+        self.store_reg(dst, res);
+    }
+    fn binop(
+        &mut self,
+        ty: Type,
+        dst: Reg,
+        a: Reg,
+        b: Reg,
+        op: impl FnOnce(&mut FunctionBuilder, Value, Value) -> Value,
+    ) {
+        let x = self.load_reg(ty, a);
+        let y = self.load_reg(ty, b);
+        let res = op(&mut self.builder, x, y);
+        self.store_reg(dst, res);
+    }
+    fn binop_helper(&mut self, ty: Type, helper: Helper, dst: Reg, a: Reg, b: Reg) {
+        let x = self.load_reg(ty, a);
+        let y = self.load_reg(ty, b);
+        let res = self.call_helper(helper, &[x, y])[0];
+        self.store_reg(dst, res);
+    }
+    fn compare(&mut self, cc: FloatCC, dst: Reg, a: Reg, b: Reg) {
+        self.binop(types::F64, dst, a, b, |b, x, y| {
+            let c = b.ins().fcmp(cc, x, y);
+            let (t, f) = (b.ins().f64const(1.0), b.ins().f64const(0.0));
+            b.ins().select(c, t, f)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::compiler::{Context, ExtFunTypeInfo};
+    use crate::interner::ToSymbol;
+    use crate::runtime::vm::{builtin, ExtClsInfo, ExtClsType};
+    use crate::{function, numeric, types::PType, types::Type};
+
+    // an external closure which sums up the arguments.
+    fn accumulator() -> ExtClsInfo {
+        let sum = Rc::new(RefCell::new(0.0));
+        let cls = Rc::new(RefCell::new(move |m: &mut Machine| -> ReturnCode {
+            *sum.borrow_mut() += Machine::get_as::<f64>(m.get_stack(0));
+            m.set_stack(0, Machine::to_value(*sum.borrow()));
+            1
+        }));
+        (
+            "accumulate".to_symbol(),
+            cls as ExtClsType,
+            function!(vec![numeric!()], numeric!()),
+        )
+    }
+
+    fn prepare(src: &str, jit: bool) -> Machine {
+        let types = builtin::get_builtin_fns()
+            .into_iter()
+            .map(|(name, _, ty)| ExtFunTypeInfo { name, ty })
+            .chain([accumulator()].map(|(name, _, ty)| ExtFunTypeInfo { name, ty }))
+            .collect::<Vec<_>>();
+        let prog = Context::new(types, None)
+            .emit_bytecode(src)
+            .unwrap_or_else(|errs| panic!("{}", errs[0]));
+        let mut machine = Machine::new(
+            prog,
+            builtin::get_builtin_fns().into_iter(),
+            [accumulator()].into_iter(),
+        );
+        if jit {
+            let _ = machine.enable_jit().unwrap();
+        }
+        let _ = machine.execute_main();
+        machine
+    }
+
+    /// Runs `dsp` with and without the JIT compilation, and checks that the outputs are same sample by sample.
+    fn compare_with_vm(src: &str, times: usize) -> bool {
+        let mut vm = prepare(src, false);
+        let mut jit = prepare(src, true);
+        let dsp_i = jit.prog.get_fun_index(&"dsp".to_symbol()).unwrap();
+        for i in 0..times {
+            let (code_vm, code_jit) = (vm.execute_idx(dsp_i), jit.execute_idx(dsp_i));
+            assert_eq!(code_vm, code_jit, "return code at sample {i}");
+            if code_vm < 0 {
+                break;
+            }
+            assert_eq!(
+                vm.get_top_n(code_vm as _),
+                jit.get_top_n(code_jit as _),
+                "output at sample {i}"
+            );
+        }
+        jit.is_jit_compiled(dsp_i)
+    }
+
+    #[test]
+    fn stateful() {
+        let src = r#"
+fn counter(inc){
+    self + inc
+}
+fn prev(x){
+    mem(x)
+}
+fn dsp(){
+    let c = counter(1.0)
+    let d = delay(10.0, c, 3.0)
+    let m = prev(c)
+    let i = toint(c)
+    let n = if (c > 5.0) tofloat(i * 3 % 4 + 2 ^ i) else sin(c) * cos(c)
+    let cmp = (c <= 20.0) + (c == 2.0) + (c != 3.0) + (c >= 4.0) + (c < 7.0)
+    (max(d, m) + n + cmp + pow(c, 0.5) + c % 3.0 - abs(-c) + sqrt(c), accumulate(c))
+}
+"#;
+        assert!(compare_with_vm(src, 50));
+    }
+
+    #[test]
+    fn array() {
+        let src = r#"
+let table = [1.0, 2.0, 3.0, 4.0]
+fn counter(){
+    self + 1
+}
+fn dsp(){
+    let i = counter()
+    table[i % len(table)]
+}
+"#;
+        assert!(compare_with_vm(src, 10));
+    }
+
+    #[test]
+    fn runtime_error() {
+        let src = r#"
+fn counter(){
+    self + 1
+}
+fn dsp(){
+    let i = counter()
+    tofloat(10 / (3 - i))
+}
+"#;
+        // the division by zero is reported at the fourth sample in both.
+        assert!(compare_with_vm(src, 5));
+        let mut machine = prepare(src, true);
+        let codes = (0..4).map(|_| machine.execute_entry(&"dsp".to_symbol()));
+        assert_eq!(codes.collect::<Vec<_>>(), vec![1, 1, 1, RUNTIME_ERROR_CODE]);
+    }
+
+    #[test]
+    fn fallback() {
+        let src = r#"
+fn makeadder(x){
+    |y| x + y
+}
+fn dsp(){
+    let adder = makeadder(1.0)
+    adder(2.0)
+}
+"#;
+        // the closure called from the compiled dsp is interpreted.
+        compare_with_vm(src, 3);
+        let machine = prepare(src, true);
+        let n_interpreted = (0..machine.prog.global_fn_table.len())
+            .filter(|i| !machine.is_jit_compiled(*i))
+            .count();
+        assert!(n_interpreted > 0);
+    }
+}
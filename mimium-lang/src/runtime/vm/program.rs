@@ -14,6 +14,8 @@ pub struct FuncProto {
     pub constants: Vec<RawVal>,
     pub state_size: u64,
     pub delay_sizes: Vec<u64>,
+    /// The number of the words for the variables spilled out of the registers.
+    pub spill_size: u64,
 }
 impl FuncProto {
     pub fn new(nparam: usize, nret: usize) -> Self {
@@ -68,7 +70,9 @@ impl std::fmt::Display for Program {
             let _ = write!(f, "{}\n", fns.0);
             let _ = write!(f, "nparams:{} nret: {}\n", fns.1.nparam, fns.1.nret);
             let _ = write!(f, "upindexes: {:?}  ", fns.1.upindexes);
-            let _ = write!(f, "state_size: {}  \n", fns.1.state_size);
+            let _ = write!(f, "state_size: {}  ", fns.1.state_size);
+            let _ = write!(f, "delay_sizes: {:?}  ", fns.1.delay_sizes);
+            let _ = write!(f, "spill_size: {}\n", fns.1.spill_size);
             let _ = write!(f, "constants:  {:?}\n", fns.1.constants);
             let _ = write!(f, "instructions:\n");
             for inst in fns.1.bytecodes.iter() {
@@ -86,7 +90,7 @@ impl std::fmt::Display for Program {
                     format!("{s}, {f}")
                 })
         );
-        let _ = write!(f, "globals:\n{:?}", self.global_vals);
+        let _ = write!(f, "globals:\n{:?}\n", self.global_vals);
         write!(
             f,
             "strings:  {:?}\n",
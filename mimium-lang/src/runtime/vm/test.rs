@@ -277,3 +277,67 @@ fn closure_gc_closed() {
     //closed closure should be kept.
     assert_eq!(machine.closures.len(), 1);
 }
+
+#[test]
+fn array_gc_budget() {
+    let mut arrays = ArrayStorage::default();
+    let kept = Machine::to_value(arrays.alloc(2, 1));
+    //unreachable arrays are left until enough words are allocated.
+    while !arrays.needs_collection() {
+        arrays.alloc(2, 1);
+    }
+    assert_eq!(arrays.len(), ArrayStorage::MIN_COLLECTION_WORDS / 2);
+    arrays.collect_garbage([[kept].as_slice()].into_iter());
+    assert_eq!(arrays.len(), 1);
+    assert!(!arrays.needs_collection());
+}
+
+#[test]
+fn link_error() {
+    let prog = Program {
+        ext_fun_table: vec![
+            ("probe".to_symbol(), function!(vec![], numeric!())),
+            ("nothing".to_symbol(), function!(vec![], numeric!())),
+        ],
+        ..Default::default()
+    };
+    let errs = Machine::try_new(prog, builtin::get_builtin_fns().into_iter(), [].into_iter())
+        .err()
+        .unwrap();
+    assert_eq!(errs.len(), 2);
+    assert!(matches!(
+        errs[0].0,
+        crate::runtime::ErrorKind::ExtFunTypeMismatch(..)
+    ));
+    assert!(matches!(
+        errs[1].0,
+        crate::runtime::ErrorKind::ExtFunNotFound(name) if name == "nothing".to_symbol()
+    ));
+}
+
+#[test]
+fn assembled_counter() {
+    //fn dsp(){
+    //  self + 1.0
+    //}
+    let src = "
+dsp
+nparams:0 nret: 1
+upindexes: []  state_size: 1
+instructions:
+  getstate   0 1 // load self
+  movimmF    1 1
+  addf       1 0 1
+  setstate   1 1
+  ret        0 1
+";
+    let prog = assembler::assemble(src).unwrap();
+    let mut machine = Machine::new(prog, [].into_iter(), [].into_iter());
+    let res = (0..3)
+        .map(|_| {
+            machine.execute_idx(0);
+            Machine::get_as::<f64>(machine.get_top_n(1)[0])
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(res, vec![0.0, 1.0, 2.0]);
+}
@@ -38,9 +38,18 @@ pub enum Type {
     Array(TypeNodeId),
     Tuple(Vec<TypeNodeId>),
     Struct(Vec<(Symbol, TypeNodeId)>),
+    //Tagged union of the variants, each of them has a name and the types of its fields.
+    //The index of the variant is used as the tag.
+    Union(Vec<(Symbol, Vec<TypeNodeId>)>),
     //Function that has a vector of parameters, return type, and type for internal states.
     Function(Vec<TypeNodeId>, TypeNodeId, Option<TypeNodeId>),
     Ref(TypeNodeId),
+    //Alias declared with `type alias`. It is interchangeable with the aliased type.
+    Alias(Symbol, TypeNodeId),
+    //Nominal type declared with `type`. It is distinguished from the other types even if the structure is the same.
+    Named(Symbol, TypeNodeId),
+    //A type referred by its name in the source, resolved into `Alias` or `Named` on type inference.
+    TypeName(Symbol),
     //(experimental) code-type for multi-stage computation that will be evaluated on the next stage
     Code(TypeNodeId),
     Intermediate(Rc<RefCell<TypeVar>>),
@@ -60,6 +69,10 @@ impl Type {
             Type::Function(_, _, _) => true,
             Type::Tuple(t) => t.iter().any(|t| t.to_type().contains_function()),
             Type::Struct(t) => t.iter().any(|(_s, t)| t.to_type().contains_function()),
+            Type::Union(v) => v
+                .iter()
+                .any(|(_, fields)| fields.iter().any(|t| t.to_type().contains_function())),
+            Type::Alias(_, t) | Type::Named(_, t) => t.to_type().contains_function(),
             _ => false,
         }
     }
@@ -79,6 +92,32 @@ impl Type {
             _ => None,
         }
     }
+    /// Element types of a tuple or a struct, in the order of their memory layout.
+    pub fn get_aggregate_elems(&self) -> Option<Vec<TypeNodeId>> {
+        match self {
+            Type::Tuple(types) => Some(types.clone()),
+            Type::Struct(fields) => Some(fields.iter().map(|(_, t)| *t).collect()),
+            _ => None,
+        }
+    }
+    /// Position of the field in the struct type. This is also the index of the element in its memory layout.
+    pub fn get_field_index(&self, name: Symbol) -> Option<usize> {
+        match self {
+            Type::Struct(fields) => fields.iter().position(|(s, _)| *s == name),
+            _ => None,
+        }
+    }
+
+    /// Tag and field types of the variant in the union type.
+    pub fn get_variant(&self, name: Symbol) -> Option<(usize, &[TypeNodeId])> {
+        match self {
+            Type::Union(variants) => variants
+                .iter()
+                .enumerate()
+                .find_map(|(i, (n, fields))| (*n == name).then_some((i, fields.as_slice()))),
+            _ => None,
+        }
+    }
 
     pub fn into_id(self) -> TypeNodeId {
         with_session_globals(|session_globals| session_globals.store_type(self))
@@ -88,15 +127,25 @@ impl Type {
         with_session_globals(|session_globals| session_globals.store_type_with_span(self, span))
     }
 
-    pub fn to_string_for_error(&self) -> String {
+    /// Prints the type for the error messages. The type variables are named with `names`, so
+    /// that the same variable is printed with the same name throughout a message.
+    pub fn to_string_for_error(&self, names: &mut TypeVarNames) -> String {
+        self.to_string_with(names, false)
+    }
+    /// Prints the type in the same way as [`Self::to_string_for_error`], but with the state slot
+    /// of the function types, like `(number)->number [state: none]`.
+    pub fn to_string_with_state(&self, names: &mut TypeVarNames) -> String {
+        self.to_string_with(names, true)
+    }
+    fn to_string_with(&self, names: &mut TypeVarNames, show_state: bool) -> String {
         match self {
             Type::Array(a) => {
-                format!("[{}, ...]", a.to_type().to_string_for_error())
+                format!("[{}, ...]", a.to_type().to_string_with(names, show_state))
             }
             Type::Tuple(v) => {
                 let vf = format_vec!(
                     v.iter()
-                        .map(|x| x.to_type().to_string_for_error())
+                        .map(|x| x.to_type().to_string_with(names, show_state))
                         .collect::<Vec<_>>(),
                     ","
                 );
@@ -108,25 +157,41 @@ impl Type {
                         .map(|(s, x)| format!(
                             "{}: {}",
                             s.as_str(),
-                            x.to_type().to_string_for_error()
+                            x.to_type().to_string_with(names, show_state)
                         ))
                         .collect::<Vec<_>>(),
                     ","
                 );
-                format!("({vf})")
+                format!("{{{vf}}}")
             }
-            Type::Function(p, r, _s) => {
+            Type::Function(p, r, s) => {
                 let args = format_vec!(
                     p.iter()
-                        .map(|x| x.to_type().to_string_for_error())
+                        .map(|x| x.to_type().to_string_with(names, show_state))
                         .collect::<Vec<_>>(),
                     ","
                 );
-                format!("({args})->{}", r.to_type().to_string_for_error())
+                let ret = r.to_type().to_string_with(names, show_state);
+                match (show_state, s) {
+                    (false, _) => format!("({args})->{ret}"),
+                    (true, None) => format!("({args})->{ret} [state: none]"),
+                    (true, Some(s)) => {
+                        let state = s.to_type().to_string_with(names, show_state);
+                        format!("({args})->{ret} [state: {state}]")
+                    }
+                }
             }
-            Type::Ref(x) => format!("&{}", x.to_type().to_string_for_error()),
+            Type::Ref(x) => format!("&{}", x.to_type().to_string_with(names, show_state)),
             Type::Code(_c) => "<...code...>".to_string(),
-            Type::Intermediate(_id) => "?".to_string(),
+            Type::Intermediate(cell) => {
+                let tv = cell.borrow().clone();
+                match tv.parent {
+                    Some(p) => p.to_type().to_string_with(names, show_state),
+                    None => names.name(TypeVarKey::Intermediate(tv.var)),
+                }
+            }
+            Type::TypeScheme(id) => names.name(TypeVarKey::Scheme(*id)),
+            Type::Instantiated(id) => names.name(TypeVarKey::Instantiated(*id)),
             // if no special treatment is needed, forward to the Display implementation
             x => x.to_string(),
         }
@@ -152,19 +217,33 @@ impl TypeNodeId {
         let result = match self.to_type() {
             Type::Array(a) => Type::Array(apply_scalar(a, &mut closure)),
             Type::Tuple(v) => Type::Tuple(apply_vec(&v, &mut closure)),
-            Type::Struct(_s) => todo!(),
+            Type::Struct(s) => Type::Struct(
+                s.iter()
+                    .map(|(name, t)| (*name, apply_scalar(*t, &mut closure)))
+                    .collect(),
+            ),
             Type::Function(p, r, s) => {
                 let at = apply_vec(&p, &mut closure);
                 let rt = apply_scalar(r, &mut closure);
                 Type::Function(at, rt, s.map(|t| apply_scalar(t, &mut closure)))
             }
             Type::Ref(x) => Type::Ref(apply_scalar(x, &mut closure)),
-            Type::Code(_c) => todo!(),
+            Type::Union(v) => Type::Union(
+                v.iter()
+                    .map(|(name, fields)| (*name, apply_vec(fields, &mut closure)))
+                    .collect(),
+            ),
+            Type::Alias(name, t) => Type::Alias(name, apply_scalar(t, &mut closure)),
+            Type::Named(name, t) => Type::Named(name, apply_scalar(t, &mut closure)),
+            Type::Code(c) => Type::Code(apply_scalar(c, &mut closure)),
             Type::Intermediate(id) => Type::Intermediate(id.clone()),
             _ => self.to_type(),
         };
-
-        result.into_id()
+        // the location where the type came from is kept for the error messages.
+        match with_session_globals(|session_globals| session_globals.get_span(*self).cloned()) {
+            Some(span) => result.into_id_with_span(span),
+            None => result.into_id(),
+        }
     }
 
     pub fn fold<F, R>(&self, _closure: F) -> R
@@ -210,7 +289,30 @@ impl fmt::Display for Type {
                 write!(f, "({vf})")
             }
             Type::Struct(v) => {
-                write!(f, "{v:?}")
+                let vf = format_vec!(
+                    v.iter()
+                        .map(|(s, x)| format!("{s}:{}", x.to_type()))
+                        .collect::<Vec<_>>(),
+                    ","
+                );
+                write!(f, "{{{vf}}}")
+            }
+            Type::Union(v) => {
+                let vf = v
+                    .iter()
+                    .map(|(s, fields)| match fields.as_slice() {
+                        [] => s.to_string(),
+                        fields => {
+                            let ff = format_vec!(
+                                fields.iter().map(|x| x.to_type()).collect::<Vec<_>>(),
+                                ","
+                            );
+                            format!("{s}({ff})")
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" | ");
+                write!(f, "{vf}")
             }
             Type::Function(p, r, _s) => {
                 let args = format_vec!(
@@ -220,6 +322,9 @@ impl fmt::Display for Type {
                 write!(f, "({args})->{}", r.to_type())
             }
             Type::Ref(x) => write!(f, "&{}", x.to_type()),
+            Type::Alias(name, _) | Type::Named(name, _) | Type::TypeName(name) => {
+                write!(f, "{name}")
+            }
 
             Type::Code(c) => write!(f, "<{}>", c.to_type()),
             Type::Intermediate(id) => {
@@ -236,6 +341,55 @@ impl fmt::Display for Type {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TypeVarKey {
+    Intermediate(u64),
+    Scheme(u64),
+    Instantiated(u64),
+}
+
+/// Names of the type variables printed in a message, given as `'a`, `'b`, ... in the order of
+/// their appearance.
+#[derive(Clone, Debug, Default)]
+pub struct TypeVarNames(Vec<TypeVarKey>);
+
+impl TypeVarNames {
+    fn name_of_index(idx: usize) -> String {
+        let c = (b'a' + (idx % 26) as u8) as char;
+        match idx / 26 {
+            0 => format!("'{c}"),
+            n => format!("'{c}{n}"),
+        }
+    }
+    pub fn name(&mut self, key: TypeVarKey) -> String {
+        let idx = self.0.iter().position(|k| *k == key).unwrap_or_else(|| {
+            self.0.push(key);
+            self.0.len() - 1
+        });
+        Self::name_of_index(idx)
+    }
+    /// The names given so far.
+    pub fn names(&self) -> Vec<String> {
+        (0..self.0.len()).map(Self::name_of_index).collect()
+    }
+    /// The names given to the generalized type variables so far.
+    pub fn scheme_names(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .enumerate()
+            .filter(|(_, k)| matches!(k, TypeVarKey::Scheme(_)))
+            .map(|(i, _)| Self::name_of_index(i))
+            .collect()
+    }
+}
+
+/// Sort the fields of a struct type (or a record literal/pattern) by their names.
+/// Struct types are always kept in this order so that the same record type has
+/// the same memory layout regardless of the field order written in the source.
+pub fn sort_fields<T>(fields: &mut [(Symbol, T)]) {
+    fields.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()))
+}
+
 pub mod builder;
 
 // #[cfg(test)]
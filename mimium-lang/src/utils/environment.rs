@@ -34,15 +34,29 @@ impl<T: Clone> Environment<T> {
         self.0.front_mut().unwrap().extend_from_slice(binds);
     }
 
+    /// Number of the bindings in the innermost scope, used with `truncate_current`.
+    pub fn current_len(&self) -> usize {
+        self.0.front().map_or(0, |v| v.len())
+    }
+    /// Remove the bindings added to the innermost scope after `current_len` was taken.
+    pub fn truncate_current(&mut self, len: usize) {
+        if let Some(v) = self.0.front_mut() {
+            v.truncate(len)
+        }
+    }
+
     pub fn lookup_cls(&self, name: &Symbol) -> LookupRes<&T> {
         match self
             .0
             .iter()
             .enumerate()
-            .find(|(_level, vec)| vec.iter().find(|(n, _)| n == name).is_some())
-            .map(|(level, vec)| vec.iter().find(|(n, _)| n == name).map(|(_, v)| (level, v)))
-            .flatten()
-        {
+            // the latest binding shadows the former ones with the same name.
+            .find_map(|(level, vec)| {
+                vec.iter()
+                    .rev()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, v)| (level, v))
+            }) {
             None => LookupRes::None,
             Some((level, e)) if level >= self.0.len() - 1 => LookupRes::Global(e),
             Some((0, e)) if self.0.len() <= 1 => LookupRes::Global(e),
@@ -50,6 +64,10 @@ impl<T: Clone> Environment<T> {
             Some((level, e)) => LookupRes::UpValue(level, e),
         }
     }
+    /// All the names visible from the current scope, including the shadowed ones.
+    pub fn names(&self) -> impl Iterator<Item = &Symbol> {
+        self.0.iter().flat_map(|vec| vec.iter().map(|(n, _)| n))
+    }
     pub fn lookup(&self, name: &Symbol) -> Option<&T> {
         match self.lookup_cls(name) {
             LookupRes::None => None,
@@ -1,10 +1,12 @@
-use ariadne::{Color, ColorGenerator, Label, Report, ReportKind, Source};
-use std::path;
+use super::metadata::Span;
+use crate::interner::with_session_globals;
+use ariadne::{sources, Color, ColorGenerator, Label, Report, ReportKind};
+use serde_json::json;
 
 pub trait ReportableError: std::error::Error {
     /// message is used for reporting verbose message for ariadne.
     ///
-    fn get_span(&self) -> std::ops::Range<usize>;
+    fn get_span(&self) -> Span;
     fn get_message(&self, _color: Color) -> String {
         self.to_string()
     }
@@ -12,12 +14,24 @@ pub trait ReportableError: std::error::Error {
     fn get_label(&self, _color: Color) -> String {
         self.to_string()
     }
+    /// labels for the other locations related to the error, e.g. where the expected type came from.
+    fn get_secondary_labels(&self) -> Vec<(Span, String)> {
+        vec![]
+    }
+    /// notes are shown below the source, to explain the error further.
+    fn get_notes(&self) -> Vec<String> {
+        vec![]
+    }
+    /// warnings are reported in the same way but do not stop the compilation.
+    fn is_warning(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug)]
 pub struct ReportableErrorDyn {
     pub message: String,
-    pub span: std::ops::Range<usize>,
+    pub span: Span,
 }
 impl std::fmt::Display for ReportableErrorDyn {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -26,29 +40,136 @@ impl std::fmt::Display for ReportableErrorDyn {
 }
 impl std::error::Error for ReportableErrorDyn {}
 impl ReportableError for ReportableErrorDyn {
-    fn get_span(&self) -> std::ops::Range<usize> {
+    fn get_span(&self) -> Span {
         self.span.clone()
     }
 }
 
-pub fn report<T>(src: &String, srcpath: T, errs: &Vec<Box<dyn ReportableError>>)
-where
-    T: AsRef<path::Path>,
-{
-    let path = srcpath.as_ref().to_str().unwrap_or_default();
+/// Prints the errors with ariadne. Each error is rendered against the source
+/// file its span points to, looked up from the source map of the session. The
+/// secondary labels may point to the other files.
+pub fn report(errs: &[Box<dyn ReportableError>]) {
     let mut colors = ColorGenerator::new();
     for e in errs {
         let color = colors.next();
         let span = e.get_span();
-        // let a_span = (src.source(), span);color
-        let label = Label::new((path, span.clone()))
-            .with_message(e.get_label(color))
-            .with_color(color);
-        let builder = Report::build(ReportKind::Error, "test", 4)
-            .with_message(e.get_message(color))
-            .with_label(label)
-            .finish();
-        builder.eprint((path, Source::from(src))).unwrap();
+        // the errors without location (e.g. the ones from the compiler-generated nodes) have no label.
+        let labels = std::iter::once((span.clone(), e.get_label(color), color))
+            .chain(
+                e.get_secondary_labels()
+                    .into_iter()
+                    .map(|(s, l)| (s, l, Color::Fixed(246))),
+            )
+            .filter_map(|(s, l, c)| {
+                let file = with_session_globals(|session_globals| {
+                    session_globals.source_map.get_file(s.file).cloned()
+                })?;
+                Some((file, s, l, c))
+            })
+            .collect::<Vec<_>>();
+        let name = labels
+            .first()
+            .filter(|(_, s, _, _)| *s == span)
+            .map_or_else(|| "(unknown)".to_string(), |(f, _, _, _)| f.name());
+        let kind = if e.is_warning() {
+            ReportKind::Warning
+        } else {
+            ReportKind::Error
+        };
+        let mut builder =
+            Report::build(kind, name.clone(), span.start()).with_message(e.get_message(color));
+        for (file, s, l, c) in labels.iter() {
+            let label = Label::new((file.name(), s.range.clone()))
+                .with_message(l)
+                .with_color(*c);
+            builder = builder.with_label(label);
+        }
+        for note in e.get_notes() {
+            builder = builder.with_note(note);
+        }
+        let files = labels
+            .into_iter()
+            .map(|(f, _, _, _)| (f.name(), f.content))
+            .chain(std::iter::once(("(unknown)".to_string(), String::new())));
+        builder.finish().eprint(sources(files)).unwrap();
+    }
+}
+
+fn strip_ansi(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // skips the escape sequence until its final letter, e.g. `\x1b[31m`.
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+/// The message of the error without the colors for the terminal, for the tools other than ariadne.
+pub fn plain_message(e: &dyn ReportableError) -> String {
+    strip_ansi(&e.get_message(Color::Primary))
+}
+
+// the file, the byte range and the line/column of both ends of the span.
+fn span_to_json(span: &Span) -> serde_json::Value {
+    let file = with_session_globals(|session_globals| {
+        session_globals.source_map.get_file(span.file).cloned()
+    });
+    match file {
+        Some(file) => {
+            let (start_line, start_column) = file.line_column(span.start());
+            let (end_line, end_column) = file.line_column(span.end());
+            json!({
+                "file": file.name(),
+                "span": {
+                    "start": file.byte_offset(span.start()),
+                    "end": file.byte_offset(span.end()),
+                },
+                "start": { "line": start_line, "column": start_column },
+                "end": { "line": end_line, "column": end_column },
+            })
+        }
+        // the errors without location.
+        None => json!({ "file": null, "span": null, "start": null, "end": null }),
+    }
+}
+
+/// Converts the error into a JSON object for the tools which cannot read the reports of
+/// ariadne, like editor integrations and CI bots. The object has `message`, `severity`, the
+/// location of the error (`file`, the byte range in `span`, the 1-based `start` and `end` lines
+/// and columns), `labels` with their own locations, the first of which is the primary one, and
+/// `notes`.
+pub fn to_json(e: &dyn ReportableError) -> serde_json::Value {
+    let span = e.get_span();
+    let labels = std::iter::once((span.clone(), e.get_label(Color::Primary), true))
+        .chain(
+            e.get_secondary_labels()
+                .into_iter()
+                .map(|(s, l)| (s, l, false)),
+        )
+        .map(|(s, l, primary)| {
+            let mut label = span_to_json(&s);
+            label["message"] = strip_ansi(&l).into();
+            label["primary"] = primary.into();
+            label
+        })
+        .collect::<Vec<_>>();
+    let mut res = span_to_json(&span);
+    res["message"] = plain_message(e).into();
+    res["severity"] = if e.is_warning() { "warning" } else { "error" }.into();
+    res["labels"] = labels.into();
+    res["notes"] = e.get_notes().into();
+    res
+}
+
+/// Prints the errors to stderr as JSON lines, an object made by [`to_json`] for each line.
+pub fn report_json(errs: &[Box<dyn ReportableError>]) {
+    for e in errs {
+        eprintln!("{}", to_json(e.as_ref()));
     }
 }
 
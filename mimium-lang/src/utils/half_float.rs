@@ -1,7 +1,8 @@
 use half::f16;
 use std::fmt::Display;
 /// Half-Precision floating point type that can be converted from 64bit float with truncation checking.
-pub const ALLOWED_ERROR: f64 = 0.00001;
+/// Only the values representable exactly are converted, so that the constants computed by the
+/// compiler keep their precision.
 
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
 pub struct HFloat(f16);
@@ -28,9 +29,8 @@ impl TryFrom<f64> for HFloat {
 
     fn try_from(value: f64) -> Result<Self, Self::Error> {
         let hv = f16::from_f64(value);
-        let error = (hv.to_f64() - value).abs();
-        if error < ALLOWED_ERROR {
-            Ok(Self(f16::from_f64(value)))
+        if hv.to_f64() == value {
+            Ok(Self(hv))
         } else {
             Err(())
         }
@@ -46,6 +46,8 @@ mod test {
         let f64v: f64 = 2.0;
         let hv = HFloat::try_from(f64v);
         assert!(hv.is_ok());
-        assert!((hv.unwrap().0.to_f64() -  f64v).abs() < ALLOWED_ERROR)
+        assert_eq!(hv.unwrap().0.to_f64(), f64v);
+        assert!(HFloat::try_from(0.1).is_err());
+        assert!(HFloat::try_from(1.0e-7).is_err());
     }
 }
@@ -1,4 +1,148 @@
-pub type Span = std::ops::Range<usize>;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Identifier of a source file registered in the [`SourceMap`] of the session globals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId(pub usize);
+
+impl FileId {
+    /// A file id that does not point to any source, used for the nodes generated by the compiler.
+    pub const DUMMY: Self = FileId(usize::MAX);
+}
+
+/// Location of a node: a range of character offsets in a specific source file.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub file: FileId,
+    pub range: Range<usize>,
+}
+
+impl Span {
+    pub fn new(file: FileId, range: Range<usize>) -> Self {
+        Self { file, range }
+    }
+    pub fn start(&self) -> usize {
+        self.range.start
+    }
+    pub fn end(&self) -> usize {
+        self.range.end
+    }
+    /// Make a span which covers from the start of `self` to the end of `other`.
+    /// If `other` is in another file (e.g. an included one), `self` is returned as is.
+    pub fn to(&self, other: &Span) -> Self {
+        if self.file == other.file {
+            Self::new(self.file, self.start()..other.end())
+        } else {
+            self.clone()
+        }
+    }
+}
+
+impl Default for Span {
+    fn default() -> Self {
+        Self::new(FileId::DUMMY, 0..0)
+    }
+}
+
+impl chumsky::Span for Span {
+    type Context = FileId;
+    type Offset = usize;
+    fn new(context: Self::Context, range: Range<Self::Offset>) -> Self {
+        Span::new(context, range)
+    }
+    fn context(&self) -> Self::Context {
+        self.file
+    }
+    fn start(&self) -> Self::Offset {
+        self.range.start
+    }
+    fn end(&self) -> Self::Offset {
+        self.range.end
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SourceFile {
+    pub path: Option<PathBuf>,
+    pub content: String,
+    /// The location of the `include` which loaded this file.
+    pub included_from: Option<Span>,
+}
+
+impl SourceFile {
+    /// The name of the file used in the error reports.
+    pub fn name(&self) -> String {
+        self.path
+            .as_ref()
+            .map_or_else(|| "(anonymous)".to_string(), |p| p.display().to_string())
+    }
+    /// The byte offset in the content for the character offset used in [`Span`].
+    pub fn byte_offset(&self, offset: usize) -> usize {
+        self.content
+            .char_indices()
+            .nth(offset)
+            .map_or(self.content.len(), |(i, _)| i)
+    }
+    /// The 1-based line and column, counted in characters, for the character offset.
+    pub fn line_column(&self, offset: usize) -> (usize, usize) {
+        self.content
+            .chars()
+            .take(offset)
+            .fold((1, 1), |(line, col), c| match c {
+                '\n' => (line + 1, 1),
+                _ => (line, col + 1),
+            })
+    }
+}
+
+/// Keeps the content of every source file loaded in the session so that the
+/// spans in any file can be rendered later.
+#[derive(Clone, Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    /// Registers a source file. Parsing the same path again replaces its content and keeps
+    /// the id, and an anonymous source identical to a registered one reuses its id, so that
+    /// re-parsing in a long-running session does not grow the map.
+    pub fn add_file(&mut self, path: Option<PathBuf>, content: &str) -> FileId {
+        let existing = self.files.iter().position(|f| match &path {
+            Some(_) => f.path == path,
+            None => f.path.is_none() && f.content == content,
+        });
+        if let Some(i) = existing {
+            let f = &mut self.files[i];
+            content.clone_into(&mut f.content);
+            f.included_from = None;
+            return FileId(i);
+        }
+        self.files.push(SourceFile {
+            path,
+            content: content.to_string(),
+            included_from: None,
+        });
+        FileId(self.files.len() - 1)
+    }
+    pub fn set_included_from(&mut self, file: FileId, span: Span) {
+        if let Some(f) = self.files.get_mut(file.0) {
+            f.included_from = Some(span)
+        }
+    }
+    pub fn get_file(&self, file: FileId) -> Option<&SourceFile> {
+        self.files.get(file.0)
+    }
+    /// The id of the file registered with the path, if any.
+    pub fn find_file(&self, path: &Path) -> Option<FileId> {
+        self.files
+            .iter()
+            .position(|f| f.path.as_deref() == Some(path))
+            .map(FileId)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (FileId, &SourceFile)> {
+        self.files.iter().enumerate().map(|(i, f)| (FileId(i), f))
+    }
+}
 
 // #[derive(Clone, Debug, PartialEq)]
 // pub struct WithMeta<T>{
@@ -19,3 +163,24 @@ pub(crate) const GLOBAL_LABEL: &str = "_mimium_global";
 //         WithMeta(f(self.0), self.1)
 //     }
 // }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn add_same_file() {
+        let mut map = SourceMap::default();
+        let path = Some(PathBuf::from("/tmp/a.mmm"));
+        let a = map.add_file(path.clone(), "fn dsp(){ 0.0 }");
+        let b = map.add_file(path, "fn dsp(){ 1.0 }");
+        assert_eq!(a, b);
+        assert_eq!(map.get_file(a).unwrap().content, "fn dsp(){ 1.0 }");
+        let c = map.add_file(None, "1.0");
+        let d = map.add_file(None, "1.0");
+        let e = map.add_file(None, "2.0");
+        assert_eq!(c, d);
+        assert_ne!(c, e);
+        assert_eq!(map.iter().count(), 3);
+    }
+}
@@ -0,0 +1,58 @@
+//! Suggestions of the similar names for the "did you mean" messages.
+
+/// Levenshtein distance between two strings, counted in characters.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let replace = prev[j] + usize::from(ca != *cb);
+            cur[j + 1] = replace.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// Picks the candidates close enough to `name`, ordered from the closest one. The distance
+/// allowed grows with the length of the name, so that short names do not match everything.
+pub fn similar_names<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Vec<&'a str> {
+    const MAX_SUGGESTIONS: usize = 3;
+    let limit = (name.chars().count() / 3).max(1);
+    let mut res = candidates
+        .into_iter()
+        .filter(|c| *c != name)
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= limit)
+        .collect::<Vec<_>>();
+    res.sort();
+    res.dedup();
+    res.into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, c)| c)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn distance() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("phasor", "phasor"), 0);
+    }
+
+    #[test]
+    fn similar() {
+        let candidates = ["phasor", "pahsor", "sin", "sinosc", "phase"];
+        assert_eq!(similar_names("phaser", candidates), vec!["phase", "phasor"]);
+        // too short names are not matched with the long ones.
+        assert!(similar_names("x", candidates).is_empty());
+    }
+}
@@ -0,0 +1,301 @@
+//! Analysis of a document for the language server: the errors, the types of the expressions and
+//! the definitions of the names referred.
+//!
+//! The locations are the character offsets in the document, in the same way as [`Span`].
+use mimium_lang::ast::{sub_exprs, Expr};
+use mimium_lang::compiler::{lint, mirgen, parser};
+use mimium_lang::interner::{with_session_globals, ExprNodeId, Symbol, TypeNodeId};
+use mimium_lang::pattern::pattern_names;
+use mimium_lang::utils::error::{plain_message, ReportableError};
+use mimium_lang::utils::metadata::{FileId, Span};
+use std::ops::Range;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub range: Range<usize>,
+    pub is_warning: bool,
+}
+
+#[derive(Default)]
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    /// The types of the expressions in the document. Empty if the type inference failed.
+    pub types: Vec<(Range<usize>, TypeNodeId)>,
+    /// The names referred in the document with their definitions, which may be in the other files.
+    pub definitions: Vec<(Range<usize>, Span)>,
+    /// The `include`s in the document with the paths of the included files.
+    pub includes: Vec<(Range<usize>, PathBuf)>,
+    /// The names defined at the toplevel, including the ones from the included files.
+    pub globals: Vec<Symbol>,
+}
+
+// the smallest range containing the offset. The end of the range is included so that the
+// cursor just after a name points to it, but only when no range contains the offset itself.
+fn find_at<T>(items: &[(Range<usize>, T)], offset: usize) -> Option<&T> {
+    items
+        .iter()
+        .filter(|(r, _)| r.start <= offset && offset <= r.end)
+        .min_by_key(|(r, _)| (r.end == offset && !r.is_empty(), r.len()))
+        .map(|(_, t)| t)
+}
+
+impl Analysis {
+    pub fn type_at(&self, offset: usize) -> Option<TypeNodeId> {
+        find_at(&self.types, offset).copied()
+    }
+    pub fn definition_at(&self, offset: usize) -> Option<&Span> {
+        find_at(&self.definitions, offset)
+    }
+    pub fn include_at(&self, offset: usize) -> Option<&PathBuf> {
+        find_at(&self.includes, offset)
+    }
+}
+
+// the location in the file being analyzed. The errors in the included files are located at the
+// `include` which loaded them, with the name of the file.
+fn locate(span: &Span) -> Option<(Range<usize>, Option<String>)> {
+    with_session_globals(|session_globals| {
+        let mut span = span.clone();
+        let mut from = None;
+        loop {
+            let file = session_globals.source_map.get_file(span.file)?;
+            match &file.included_from {
+                Some(include) => {
+                    from = from.or(Some(file.name()));
+                    span = include.clone();
+                }
+                None => return Some((span.range, from)),
+            }
+        }
+    })
+}
+
+fn to_diagnostic(e: &dyn ReportableError) -> Diagnostic {
+    let message = plain_message(e);
+    let (range, message) = match locate(&e.get_span()) {
+        Some((range, None)) => (range, message),
+        Some((range, Some(file))) => (range, format!("{file}: {message}")),
+        // the errors without location are shown at the beginning of the document.
+        None => (0..0, message),
+    };
+    Diagnostic {
+        message,
+        range,
+        is_warning: e.is_warning(),
+    }
+}
+
+#[derive(Default)]
+struct Resolver {
+    // the names in scope, the innermost at the last.
+    scopes: Vec<(Symbol, Span)>,
+    definitions: Vec<(Span, Span)>,
+}
+
+impl Resolver {
+    fn bind_pattern(&mut self, pat: &mimium_lang::pattern::TypedPattern) {
+        let mut names = vec![];
+        pattern_names(&pat.pat, &mut names);
+        let span = pat.to_span();
+        self.scopes
+            .extend(names.into_iter().map(|n| (n, span.clone())));
+    }
+    fn walk(&mut self, e: ExprNodeId) {
+        let len = self.scopes.len();
+        match e.to_expr() {
+            Expr::Var(name) => {
+                let def = self.scopes.iter().rev().find(|(n, _)| *n == name);
+                if let Some((_, def)) = def {
+                    self.definitions.push((e.to_span(), def.clone()));
+                }
+            }
+            Expr::Lambda(params, _, body) => {
+                self.scopes
+                    .extend(params.iter().map(|p| (p.id, p.to_span())));
+                self.walk(body);
+            }
+            Expr::Let(pat, body, then) => {
+                self.walk(body);
+                self.bind_pattern(&pat);
+                then.into_iter().for_each(|t| self.walk(t));
+            }
+            Expr::LetRec(id, body, then) => {
+                self.scopes.push((id.id, id.to_span()));
+                self.walk(body);
+                then.into_iter().for_each(|t| self.walk(t));
+            }
+            Expr::Match(scrutinee, arms) => {
+                self.walk(scrutinee);
+                for (pat, arm) in arms {
+                    self.bind_pattern(&pat);
+                    self.walk(arm);
+                    self.scopes.truncate(len);
+                }
+            }
+            e => sub_exprs(&e).into_iter().for_each(|e| self.walk(e)),
+        }
+        self.scopes.truncate(len);
+    }
+}
+
+fn toplevel_names(root: ExprNodeId) -> Vec<Symbol> {
+    let mut res = vec![];
+    let mut next = Some(root);
+    while let Some(e) = next {
+        next = match e.to_expr() {
+            Expr::Let(pat, _, then) => {
+                pattern_names(&pat.pat, &mut res);
+                then
+            }
+            Expr::LetRec(id, _, then) => {
+                res.push(id.id);
+                then
+            }
+            Expr::TypeDecl(_, then) | Expr::Then(_, then) => then,
+            _ => None,
+        };
+    }
+    res
+}
+
+/// Analyzes the source of the document. `ext_types` are the types of the functions given by the
+/// plugins, as the ones passed to [`mimium_lang::compiler::Context`].
+pub fn analyze(src: &str, path: Option<PathBuf>, ext_types: &[(Symbol, TypeNodeId)]) -> Analysis {
+    let (ast, errs) = parser::parse_recovery(src, path);
+    let mut res = Analysis {
+        diagnostics: errs.iter().map(|e| to_diagnostic(e.as_ref())).collect(),
+        ..Default::default()
+    };
+    let Some(ast) = ast else {
+        return res;
+    };
+    let file = ast.to_span().file;
+    let in_file = |span: &Span| (span.file == file).then(|| span.range.clone());
+    if errs.is_empty() {
+        res.diagnostics
+            .extend(lint::lint(ast).iter().map(|w| to_diagnostic(w)));
+    }
+    match mirgen::infer_expr_types(parser::add_global_context(ast), ext_types, &[]) {
+        Ok((types, bindings)) => {
+            res.types = types
+                .into_iter()
+                .map(|(e, t)| (e.to_span(), t))
+                .chain(bindings)
+                .filter_map(|(span, t)| Some((in_file(&span)?, t)))
+                .collect();
+        }
+        Err(e) => res.diagnostics.push(to_diagnostic(e.as_ref())),
+    }
+    let mut resolver = Resolver::default();
+    resolver.walk(ast);
+    res.definitions = resolver
+        .definitions
+        .into_iter()
+        .filter_map(|(used, def)| Some((in_file(&used)?, def)))
+        .collect();
+    res.includes = with_session_globals(|session_globals| {
+        session_globals
+            .source_map
+            .iter()
+            .filter_map(|(_, f)| {
+                let range = in_file(f.included_from.as_ref()?)?;
+                Some((range, f.path.clone()?))
+            })
+            .collect()
+    });
+    res.globals = toplevel_names(ast);
+    res
+}
+
+/// The content of the file registered in the session, to locate the definitions in the other
+/// files.
+pub fn file_content(file: FileId) -> Option<(Option<PathBuf>, String)> {
+    with_session_globals(|session_globals| {
+        let f = session_globals.source_map.get_file(file)?;
+        Some((f.path.clone(), f.content.clone()))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mimium_lang::types::{Type, TypeVarNames};
+
+    fn type_name(t: TypeNodeId) -> String {
+        t.to_type()
+            .to_string_for_error(&mut TypeVarNames::default())
+    }
+
+    #[test]
+    fn diagnostics() {
+        let src = "fn dsp(){
+    let unused = 1.0
+    fraq
+}";
+        let res = analyze(src, None, &[]);
+        let messages = res
+            .diagnostics
+            .iter()
+            .map(|d| (d.is_warning, &src[d.range.clone()]))
+            .collect::<Vec<_>>();
+        assert_eq!(messages, vec![(true, "unused"), (false, "fraq")]);
+    }
+
+    #[test]
+    fn hover_and_definition() {
+        let src = "fn dsp(){
+    let x = 1.0
+    x
+}";
+        let res = analyze(src, None, &[]);
+        let use_offset = src.rfind('x').unwrap();
+        let ty = res.type_at(use_offset).map(type_name);
+        assert_eq!(ty.as_deref(), Some("number"));
+        let def = res.definition_at(use_offset).unwrap();
+        assert_eq!(def.range, 18..19);
+        assert_eq!(
+            res.globals,
+            vec![mimium_lang::interner::ToSymbol::to_symbol(&"dsp")]
+        );
+        // a use of the name with no definition in the document is not resolved.
+        assert!(res.definition_at(2).is_none());
+        assert!(matches!(
+            res.type_at(src.find("1.0").unwrap()).map(|t| t.to_type()),
+            Some(Type::Primitive(_))
+        ));
+    }
+
+    #[test]
+    fn hover_binding_site() {
+        let src = "fn id(x){
+    x
+}
+fn dsp(){
+    let ii = id(3)
+    ii
+    1.0
+}";
+        let res = analyze(src, None, &[]);
+        let ty = |name: &str| res.type_at(src.find(name).unwrap()).map(type_name);
+        assert_eq!(ty("ii").as_deref(), Some("int"));
+        assert_eq!(ty("x){").as_deref(), ty("x\n").as_deref());
+        assert!(matches!(
+            res.type_at(src.find("id(x)").unwrap()).map(|t| t.to_type()),
+            Some(Type::Function(..))
+        ));
+    }
+
+    #[test]
+    fn reanalyze_same_file() {
+        let path = PathBuf::from("/tmp/untitled.mmm");
+        let count =
+            || with_session_globals(|session_globals| session_globals.source_map.iter().count());
+        let _ = analyze("fn dsp(){ 0.0 }", Some(path.clone()), &[]);
+        let before = count();
+        let res = analyze("fn dsp(){ 1.0 }", Some(path), &[]);
+        assert_eq!(count(), before);
+        assert!(res.diagnostics.is_empty());
+    }
+}
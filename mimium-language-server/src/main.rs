@@ -0,0 +1,279 @@
+//! Language server for mimium, communicating with the editors through stdio.
+//!
+//! The documents are analyzed on every change: the errors and warnings are published as the
+//! diagnostics, and the results of the last analysis are used for hover, go-to-definition and
+//! completion.
+mod analysis;
+mod position;
+
+use analysis::{analyze, file_content, Analysis};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as NotificationTrait, PublishDiagnostics,
+};
+use lsp_types::request::{Completion, GotoDefinition, HoverRequest, Request as RequestTrait};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    Diagnostic, DiagnosticSeverity, GotoDefinitionParams, GotoDefinitionResponse, Hover,
+    HoverContents, HoverParams, HoverProviderCapability, InitializeParams, Location, MarkupContent,
+    MarkupKind, OneOf, PublishDiagnosticsParams, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, Url,
+};
+use mimium_lang::compiler::typing::InferContext;
+use mimium_lang::compiler::ExtFunTypeInfo;
+use mimium_lang::interner::{Symbol, TypeNodeId};
+use mimium_lang::log;
+use mimium_lang::plugin::Plugin;
+use mimium_lang::types::TypeVarNames;
+use mimium_lang::ExecContext;
+use mimium_symphonia::SamplerPlugin;
+use position::{to_offset, to_range};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::PathBuf;
+
+type ServerResult<T> = Result<T, Box<dyn Error + Sync + Send>>;
+
+struct Document {
+    text: String,
+    analysis: Analysis,
+}
+
+struct Server {
+    documents: HashMap<Url, Document>,
+    // the types of the functions from the plugins, the same as the ones of the CLI.
+    ext_types: Vec<(Symbol, TypeNodeId)>,
+}
+
+fn get_default_context() -> ExecContext {
+    let plugins: Vec<Box<dyn Plugin>> = vec![Box::new(SamplerPlugin)];
+    let mut ctx = ExecContext::new(plugins.into_iter(), None);
+    ctx.add_system_plugin(mimium_scheduler::get_default_scheduler_plugin());
+    ctx
+}
+
+// the plugins opening the devices are not instantiated, only their signatures are used.
+#[cfg(feature = "device-plugins")]
+fn get_device_plugin_types() -> Vec<ExtFunTypeInfo> {
+    #[allow(unused_mut)]
+    let mut signatures = mimium_midi::MidiPlugin::signatures();
+    #[cfg(not(target_arch = "wasm32"))]
+    signatures.extend(mimium_guitools::GuiToolPlugin::signatures());
+    mimium_lang::plugin::get_signature_types(signatures)
+}
+#[cfg(not(feature = "device-plugins"))]
+fn get_device_plugin_types() -> Vec<ExtFunTypeInfo> {
+    vec![]
+}
+
+fn type_to_string(t: TypeNodeId) -> String {
+    t.to_type()
+        .to_string_for_error(&mut TypeVarNames::default())
+}
+
+impl Server {
+    fn new() -> Self {
+        let ext_types = get_default_context()
+            .get_extfun_types()
+            .iter()
+            .copied()
+            .chain(get_device_plugin_types())
+            .map(|ExtFunTypeInfo { name, ty }| (name, ty))
+            .collect();
+        Self {
+            documents: HashMap::new(),
+            ext_types,
+        }
+    }
+
+    fn update(&mut self, uri: Url, text: String) -> PublishDiagnosticsParams {
+        // the documents not saved as files are registered by their uris, so that every change
+        // replaces the same file in the source map of the session.
+        let path = uri
+            .to_file_path()
+            .unwrap_or_else(|_| PathBuf::from(uri.as_str()));
+        let analysis = analyze(&text, Some(path), &self.ext_types);
+        let diagnostics = analysis
+            .diagnostics
+            .iter()
+            .map(|d| {
+                let severity = if d.is_warning {
+                    DiagnosticSeverity::WARNING
+                } else {
+                    DiagnosticSeverity::ERROR
+                };
+                let mut res = Diagnostic::new_simple(to_range(&text, &d.range), d.message.clone());
+                res.severity = Some(severity);
+                res.source = Some("mimium".to_string());
+                res
+            })
+            .collect();
+        self.documents
+            .insert(uri.clone(), Document { text, analysis });
+        PublishDiagnosticsParams::new(uri, diagnostics, None)
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let pos = params.text_document_position_params;
+        let doc = self.documents.get(&pos.text_document.uri)?;
+        let ty = doc.analysis.type_at(to_offset(&doc.text, pos.position))?;
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("```mimium\n{}\n```", type_to_string(ty)),
+            }),
+            range: None,
+        })
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let pos = params.text_document_position_params;
+        let doc = self.documents.get(&pos.text_document.uri)?;
+        let offset = to_offset(&doc.text, pos.position);
+        if let Some(path) = doc.analysis.include_at(offset) {
+            let uri = Url::from_file_path(path).ok()?;
+            let location = Location::new(uri, lsp_types::Range::default());
+            return Some(GotoDefinitionResponse::Scalar(location));
+        }
+        let def = doc.analysis.definition_at(offset)?;
+        let (path, content) = file_content(def.file)?;
+        let uri = path
+            .and_then(|p| Url::from_file_path(p).ok())
+            .unwrap_or(pos.text_document.uri);
+        let location = Location::new(uri, to_range(&content, &def.range));
+        Some(GotoDefinitionResponse::Scalar(location))
+    }
+
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let doc = self
+            .documents
+            .get(&params.text_document_position.text_document.uri)?;
+        let globals = doc.analysis.globals.iter().map(|name| CompletionItem {
+            label: name.to_string(),
+            kind: Some(CompletionItemKind::VARIABLE),
+            ..Default::default()
+        });
+        let functions = self
+            .ext_types
+            .iter()
+            .cloned()
+            .chain(InferContext::intrinsic_types())
+            .map(|(name, ty)| CompletionItem {
+                label: name.to_string(),
+                kind: Some(CompletionItemKind::FUNCTION),
+                detail: Some(type_to_string(ty)),
+                ..Default::default()
+            });
+        // the definitions in the document come first, as they shadow the builtins.
+        let mut labels = HashSet::new();
+        let items = globals
+            .chain(functions)
+            .filter(|item| labels.insert(item.label.clone()))
+            .collect();
+        Some(CompletionResponse::Array(items))
+    }
+
+    fn handle_request(&self, req: Request) -> Response {
+        let id = req.id.clone();
+        let result = match req.method.as_str() {
+            HoverRequest::METHOD => serde_json::from_value(req.params)
+                .map(|params| serde_json::to_value(self.hover(params))),
+            GotoDefinition::METHOD => serde_json::from_value(req.params)
+                .map(|params| serde_json::to_value(self.definition(params))),
+            Completion::METHOD => serde_json::from_value(req.params)
+                .map(|params| serde_json::to_value(self.completion(params))),
+            method => {
+                let message = format!("unsupported request {method}");
+                return Response::new_err(id, ErrorCode::MethodNotFound as i32, message);
+            }
+        };
+        match result {
+            Ok(Ok(value)) => Response::new_ok(id, value),
+            Ok(Err(e)) | Err(e) => {
+                Response::new_err(id, ErrorCode::InvalidParams as i32, e.to_string())
+            }
+        }
+    }
+
+    // returns the diagnostics to be published if the document is changed.
+    fn handle_notification(
+        &mut self,
+        not: Notification,
+    ) -> ServerResult<Option<PublishDiagnosticsParams>> {
+        match not.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams =
+                    serde_json::from_value(not.params)?;
+                let doc = params.text_document;
+                Ok(Some(self.update(doc.uri, doc.text)))
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams =
+                    serde_json::from_value(not.params)?;
+                // the server requests the full text on every change.
+                let text = params.content_changes.into_iter().last().map(|c| c.text);
+                Ok(text.map(|text| self.update(params.text_document.uri, text)))
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams =
+                    serde_json::from_value(not.params)?;
+                self.documents.remove(&params.text_document.uri);
+                // clears the diagnostics of the closed document.
+                Ok(Some(PublishDiagnosticsParams::new(
+                    params.text_document.uri,
+                    vec![],
+                    None,
+                )))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // takes the connection so that it is closed before the IO threads are joined.
+    fn main_loop(&mut self, connection: Connection) -> ServerResult<()> {
+        for msg in &connection.receiver {
+            match msg {
+                Message::Request(req) => {
+                    if connection.handle_shutdown(&req)? {
+                        return Ok(());
+                    }
+                    let res = self.handle_request(req);
+                    connection.sender.send(Message::Response(res))?;
+                }
+                Message::Notification(not) => {
+                    if let Some(params) = self.handle_notification(not)? {
+                        let not = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+                        connection.sender.send(Message::Notification(not))?;
+                    }
+                }
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn main() -> ServerResult<()> {
+    // the logs are written to stderr, as stdout is used for the protocol.
+    colog::default_builder().init();
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = serde_json::to_value(ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        ..Default::default()
+    })?;
+    let init_params: InitializeParams =
+        serde_json::from_value(connection.initialize(capabilities)?)?;
+    log::info!(
+        "mimium language server started for {}",
+        init_params
+            .client_info
+            .map_or("unknown client".to_string(), |c| c.name)
+    );
+    Server::new().main_loop(connection)?;
+    io_threads.join()?;
+    Ok(())
+}
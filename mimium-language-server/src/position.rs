@@ -0,0 +1,52 @@
+//! Conversion between the character offsets used in the compiler and the positions of LSP, whose
+//! columns are counted in UTF-16 code units.
+use lsp_types::Position;
+use std::ops::Range;
+
+pub fn to_position(text: &str, offset: usize) -> Position {
+    let (line, character) = text
+        .chars()
+        .take(offset)
+        .fold((0, 0), |(line, col), c| match c {
+            '\n' => (line + 1, 0),
+            _ => (line, col + c.len_utf16() as u32),
+        });
+    Position::new(line, character)
+}
+
+pub fn to_range(text: &str, range: &Range<usize>) -> lsp_types::Range {
+    lsp_types::Range::new(to_position(text, range.start), to_position(text, range.end))
+}
+
+/// The character offset of the position. The positions beyond the end of the line are clamped to
+/// the end of it.
+pub fn to_offset(text: &str, pos: Position) -> usize {
+    let (mut line, mut col) = (0, 0);
+    for (i, c) in text.chars().enumerate() {
+        if line == pos.line && (col >= pos.character || c == '\n') {
+            return i;
+        }
+        match c {
+            '\n' => (line, col) = (line + 1, 0),
+            _ => col += c.len_utf16() as u32,
+        }
+    }
+    text.chars().count()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn utf16_columns() {
+        // "𝄞" is a surrogate pair in UTF-16.
+        let text = "let a = 1.0\n// 𝄞\nlet b = a";
+        let offset = text.chars().position(|c| c == 'b').unwrap();
+        assert_eq!(to_position(text, offset), Position::new(2, 4));
+        assert_eq!(to_offset(text, Position::new(2, 4)), offset);
+        let eol = text.chars().position(|c| c == '𝄞').unwrap() + 1;
+        assert_eq!(to_position(text, eol), Position::new(1, 5));
+        assert_eq!(to_offset(text, Position::new(1, 100)), eol);
+    }
+}
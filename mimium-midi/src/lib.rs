@@ -61,6 +61,20 @@ impl Default for MidiPlugin {
     }
 }
 impl MidiPlugin {
+    /// The functions given by the plugin. They are available without opening the MIDI input, e.g.
+    /// for the tools which only need their types.
+    pub fn signatures() -> Vec<SysPluginSignature> {
+        let ty = function!(
+            vec![numeric!(), numeric!(), numeric!()],
+            function!(vec![], tuple!(numeric!(), numeric!()))
+        );
+        let fun: SystemPluginFnType<Self> = Self::bind_midi_note_mono;
+        let bindnote = SysPluginSignature::new("bind_midi_note_mono", fun, ty);
+        let ty = function!(vec![string_t!()], unit!());
+        let fun: SystemPluginFnType<Self> = Self::set_midi_port;
+        let setport = SysPluginSignature::new("set_midi_port", fun, ty);
+        vec![setport, bindnote]
+    }
     fn add_note_callback(&mut self, chan: u8, cb: NoteCallBack) {
         match self.note_callbacks.as_mut() {
             Some(v) if chan < 15 => {
@@ -174,15 +188,6 @@ impl SystemPlugin for MidiPlugin {
     }
 
     fn gen_interfaces(&self) -> Vec<SysPluginSignature> {
-        let ty = function!(
-            vec![numeric!(), numeric!(), numeric!()],
-            function!(vec![], tuple!(numeric!(), numeric!()))
-        );
-        let fun: SystemPluginFnType<Self> = Self::bind_midi_note_mono;
-        let bindnote = SysPluginSignature::new("bind_midi_note_mono", fun, ty);
-        let ty = function!(vec![string_t!()], unit!());
-        let fun: SystemPluginFnType<Self> = Self::set_midi_port;
-        let setport = SysPluginSignature::new("set_midi_port", fun, ty);
-        vec![setport, bindnote]
+        Self::signatures()
     }
 }
@@ -1,3 +1,32 @@
+use mimium_lang::{
+    compiler::Context, interner::ToSymbol, runtime::vm::builtin::get_builtin_fn_types,
+};
+
+/// Test files exported as standalone Rust modules for `tests/rustgen_test.rs`.
+const RUSTGEN_FILES: [&str; 9] = [
+    "counter",
+    "delay",
+    "fb_mem",
+    "array",
+    "array_global",
+    "int",
+    "nested_if",
+    "state_tuple",
+    "return_state",
+];
+
 fn main() {
-    println!("cargo:rustc-env=TEST_ROOT={}", env!("CARGO_MANIFEST_DIR"));
+    let root = env!("CARGO_MANIFEST_DIR");
+    println!("cargo:rustc-env=TEST_ROOT={root}");
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    for name in RUSTGEN_FILES {
+        let path = format!("{root}/tests/mmm/{name}.mmm");
+        println!("cargo:rerun-if-changed={path}");
+        let src = std::fs::read_to_string(&path).unwrap();
+        let ctx = Context::new(get_builtin_fn_types(), Some(path.to_symbol()));
+        let code = ctx
+            .emit_rust(&src)
+            .unwrap_or_else(|errs| panic!("failed to export {path}: {}", errs[0]));
+        std::fs::write(format!("{out_dir}/{name}.rs"), code).unwrap();
+    }
 }
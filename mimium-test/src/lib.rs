@@ -9,6 +9,7 @@ use mimium_lang::{
     utils::{
         error::{report, ReportableError},
         fileloader,
+        metadata::Span,
     },
     ExecContext,
 };
@@ -21,10 +22,10 @@ pub fn run_bytecode_test(
     if retcode >= 0 {
         Ok(vm::Machine::get_as_array::<f64>(machine.get_top_n(n)))
     } else {
-        Err(vec![Box::new(runtime::Error(
-            runtime::ErrorKind::Unknown,
-            0..0,
-        ))])
+        let e = machine
+            .take_runtime_error()
+            .unwrap_or(runtime::Error(runtime::ErrorKind::Unknown, Span::default()));
+        Err(vec![Box::new(e)])
     }
 }
 
@@ -34,7 +35,7 @@ pub fn run_bytecode_test_multiple(
     stereo: bool,
 ) -> Result<Vec<f64>, Vec<Box<dyn ReportableError>>> {
     let mut ctx = ExecContext::new([].into_iter(), None);
-    let _ = ctx.prepare_machine_with_bytecode(bytecodes);
+    ctx.prepare_machine_with_bytecode(bytecodes)?;
     let mut machine = ctx.vm.unwrap();
     let _retcode = machine.execute_main();
     let n = if stereo { 2 } else { 1 };
@@ -107,7 +108,7 @@ pub fn run_file_with_plugins(
     match res {
         Ok(res) => Ok(res),
         Err(errs) => {
-            report(&src, file, &errs);
+            report(&errs);
             Err(())
         }
     }
@@ -122,12 +123,48 @@ pub fn run_file_test(path: &str, times: u64, stereo: bool) -> Result<Vec<f64>, (
     match res {
         Ok(res) => Ok(res),
         Err(errs) => {
-            report(&src, file, &errs);
+            report(&errs);
             Err(())
         }
     }
 }
 
+/// Runs the file with the interpreter and with the functions compiled by the JIT compiler side by
+/// side, and panics if their outputs differ at any sample. Returns the outputs.
+#[cfg(feature = "jit")]
+pub fn run_file_jit_test(path: &str, times: u64, stereo: bool) -> Result<Vec<f64>, ()> {
+    let (file, src) = load_src(path);
+    let path_sym = file.to_string_lossy().to_symbol();
+    let prepare = |jit: bool| -> Result<vm::Machine, ()> {
+        let mut ctx = ExecContext::new([].into_iter(), Some(path_sym));
+        ctx.prepare_machine(&src).map_err(|errs| report(&errs))?;
+        let mut machine = ctx.vm.unwrap();
+        if jit {
+            let _ = machine
+                .enable_jit()
+                .expect("JIT compilation is not available");
+        }
+        let _retcode = machine.execute_main();
+        Ok(machine)
+    };
+    let mut interpreted = prepare(false)?;
+    let mut compiled = prepare(true)?;
+    let n = if stereo { 2 } else { 1 };
+    let mut ret = Vec::with_capacity(times as usize * n);
+    for i in 0..times {
+        let expected = run_bytecode_test(&mut interpreted, n)
+            .map_err(|errs| report(&errs))?
+            .to_vec();
+        let res = run_bytecode_test(&mut compiled, n).map_err(|errs| report(&errs))?;
+        assert_eq!(
+            expected, res,
+            "JIT output differs from the VM at sample {i}"
+        );
+        ret.extend_from_slice(res);
+    }
+    Ok(ret)
+}
+
 pub fn load_src(path: &str) -> (PathBuf, String) {
     let crate_root = std::env::var("TEST_ROOT").expect(
         r#"You must set TEST_ROOT environment variable to run test.
@@ -165,7 +202,7 @@ pub fn test_state_sizes<T: IntoIterator<Item = (&'static str, u64)>>(path: &str,
     // let bytecode = match ctx.compiler.emit_bytecode(&src) {
     //     Ok(res) => res,
     //     Err(errs) => {
-    //         report(&src, file, &errs);
+    //         report(&errs);
     //         panic!("failed to emit bytecode");
     //     }
     // };
@@ -1,6 +1,7 @@
-use mimium_lang::utils::error::report;
+use mimium_lang::compiler::lint::WarningKind;
+use mimium_lang::interner::{with_session_globals, ToSymbol};
+use mimium_lang::utils::error::{dump_to_string, report, to_json};
 use mimium_test::*;
-use std::path::Path;
 
 fn run_simple_test(expr: &str, expect: f64, times: u64) {
     let src = format!(
@@ -18,7 +19,7 @@ fn dsp(){{
             assert_eq!(res, ans, "expr: {expr}");
         }
         Err(errs) => {
-            report(&src, Path::new("(from template)"), &errs);
+            report(&errs);
             panic!("invalid syntax");
         }
     }
@@ -322,6 +323,49 @@ fn include_file() {
     assert_eq!(res, ans);
 }
 
+#[test]
+fn include_error_span() {
+    let (file, src) = load_src("include_error.mmm");
+    let path = file.to_string_lossy().to_symbol();
+    let errs = run_source_test(&src, 1, false, Some(path)).unwrap_err();
+    let span = errs[0].get_span();
+    let (name, content) = with_session_globals(|session_globals| {
+        let file = session_globals.source_map.get_file(span.file).unwrap();
+        (file.name(), file.content.clone())
+    });
+    assert!(name.ends_with("include_error_target.mmm"), "{name}");
+    let snippet = content
+        .chars()
+        .skip(span.start())
+        .take(span.end() - span.start());
+    assert!(snippet.collect::<String>().contains("0.5"));
+}
+
+#[test]
+fn unused_include_warning() {
+    let (file, src) = load_src("unused_include.mmm");
+    let path = file.to_string_lossy().to_symbol();
+    let ctx = mimium_lang::compiler::Context::new([], Some(path));
+    ctx.emit_mir(&src).unwrap();
+    let warnings = ctx.take_warnings();
+    assert_eq!(warnings.len(), 1, "{warnings:?}");
+    assert_eq!(
+        warnings[0].0,
+        WarningKind::UnusedInclude("unused_include_target.mmm".to_string())
+    );
+    // the warning points to the second `include` in the main file.
+    let span = warnings[0].1.clone();
+    let name = with_session_globals(|session_globals| {
+        session_globals
+            .source_map
+            .get_file(span.file)
+            .unwrap()
+            .name()
+    });
+    assert!(name.ends_with("unused_include.mmm"), "{name}");
+    assert_eq!(&src[span.range], "include(\"unused_include_target.mmm\")");
+}
+
 #[test]
 fn if_state() {
     let res = run_file_test_stereo("if_state.mmm", 10).unwrap();
@@ -331,3 +375,458 @@ fn if_state() {
     ];
     assert_eq!(res, ans);
 }
+
+#[test]
+fn if_assign() {
+    let res = run_file_test_mono("if_assign.mmm", 1).unwrap();
+    let ans = vec![43.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn record() {
+    let res = run_file_test_mono("record.mmm", 2).unwrap();
+    let ans = vec![720.0, 720.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn record_nested() {
+    let res = run_file_test_mono("record_nested.mmm", 1).unwrap();
+    let ans = vec![440.5];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn record_state() {
+    let res = run_file_test_mono("record_state.mmm", 3).unwrap();
+    let ans = vec![0.0, 1.5, 3.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn record_infer() {
+    let res = run_file_test_mono("record_infer.mmm", 1).unwrap();
+    let ans = vec![442.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn array() {
+    let res = run_file_test_mono("array.mmm", 6).unwrap();
+    let ans = vec![1.0, 2.0, 3.0, 4.0, 1.0, 2.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn array_local() {
+    let res = run_file_test_mono("array_local.mmm", 3).unwrap();
+    let ans = vec![9.0, 9.0, 9.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn array_global() {
+    let res = run_file_test_mono("array_global.mmm", 3).unwrap();
+    let ans = vec![0.0, 2.0, 4.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn array_closure() {
+    let res = run_file_test_mono("array_closure.mmm", 3).unwrap();
+    let ans = vec![0.0, 1.0, 2.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn array_out_of_range() {
+    let res = run_file_test_mono("array_out_of_range.mmm", 1);
+    assert!(res.is_err());
+}
+
+#[test]
+fn int() {
+    let res = run_file_test_mono("int.mmm", 4).unwrap();
+    let ans = vec![8.0, 21.0, 140.0, 179.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn int_float_mismatch() {
+    let res = run_file_test_mono("int_float_mismatch.mmm", 1);
+    assert!(res.is_err());
+}
+
+#[test]
+fn type_alias() {
+    let res = run_file_test_stereo("type_alias.mmm", 1).unwrap();
+    let ans = vec![2.0, 1.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn type_alias_error_message() {
+    let src = "type alias Stereo = (float, float)
+fn dsp() -> Stereo {
+    (1.0, 2.0, 3.0)
+}";
+    let errs = run_source_test(src, 1, true, None).unwrap_err();
+    assert!(errs[0].to_string().contains("Stereo"), "{}", errs[0]);
+}
+
+#[test]
+fn type_mismatch_origin_label() {
+    let src = "fn f(x:float){
+    x
+}
+fn dsp(){
+    f(\"s\")
+}";
+    let errs = run_source_test(src, 1, false, None).unwrap_err();
+    let labels = errs[0].get_secondary_labels();
+    assert_eq!(labels.len(), 1, "{}", errs[0]);
+    // the expected type came from the annotation of the parameter.
+    let (span, label) = &labels[0];
+    assert_eq!(&src[span.range.clone()], "float");
+    assert!(label.contains("number is expected"), "{label}");
+}
+
+#[test]
+fn variable_not_found_suggestion() {
+    let src = "fn dsp(){
+    let freq = 440.0
+    sin(fraq) + make_probe(\"x\")
+}";
+    let errs = run_source_test(src, 1, false, None).unwrap_err();
+    let notes = errs[0].get_notes();
+    assert!(notes.iter().any(|n| n.contains("`freq`")), "{notes:?}");
+    let src = "fn dsp(){
+    make_probe(\"x\")
+}";
+    let mut ctx = mimium_lang::ExecContext::new([].into_iter(), None);
+    ctx.add_unloaded_system_plugin(&ProbePlugin);
+    let errs = ctx.prepare_machine(src).unwrap_err();
+    let notes = errs[0].get_notes();
+    assert!(notes.iter().any(|n| n.contains("ProbePlugin")), "{notes:?}");
+}
+
+// a plugin which gives `make_probe`, only registered as the one not loaded.
+struct ProbePlugin;
+impl mimium_lang::plugin::SystemPlugin for ProbePlugin {
+    fn gen_interfaces(&self) -> Vec<mimium_lang::plugin::SysPluginSignature> {
+        use mimium_lang::plugin::SysPluginSignature;
+        use mimium_lang::runtime::vm::{Machine, ReturnCode};
+        use mimium_lang::types::{PType, Type};
+        use mimium_lang::{function, numeric, string_t};
+        let fun: fn(&mut Self, &mut Machine) -> ReturnCode = |_, _| 0;
+        let ty = function!(vec![string_t!()], numeric!());
+        vec![SysPluginSignature::new("make_probe", fun, ty)]
+    }
+}
+
+#[test]
+fn error_json() {
+    // the comment has a multibyte character, so the byte offsets differ from the character ones.
+    let src = "// é
+fn dsp(){
+    fraq
+}";
+    let errs = run_source_test(src, 1, false, None).unwrap_err();
+    let json = to_json(errs[0].as_ref());
+    assert_eq!(json["severity"], "error");
+    assert!(json["message"].as_str().unwrap().contains("fraq"), "{json}");
+    assert_eq!(json["span"]["start"], 20);
+    assert_eq!(json["span"]["end"], 24);
+    assert_eq!(json["start"]["line"], 3);
+    assert_eq!(json["start"]["column"], 5);
+    assert_eq!(json["labels"][0]["primary"], true);
+    assert_eq!(json["labels"][0]["span"], json["span"]);
+}
+
+#[test]
+fn error_json_runtime() {
+    let src = "fn counter(){
+    self + 1
+}
+fn dsp(){
+    tofloat(10 / counter())
+}";
+    let path = Some("div_zero.mmm".to_symbol());
+    let errs = run_source_test(src, 1, false, path).unwrap_err();
+    let json = to_json(errs[0].as_ref());
+    assert_eq!(json["severity"], "error");
+    assert!(
+        json["message"]
+            .as_str()
+            .unwrap()
+            .contains("integer division by zero"),
+        "{json}"
+    );
+    // the runtime errors are located at the head of the file.
+    assert_eq!(json["file"], "div_zero.mmm");
+    assert_eq!(json["start"]["line"], 1);
+}
+
+#[test]
+fn emit_types() {
+    let src = "let id = |x| x
+let gain = 0.5
+fn ident(x){ x }
+fn dsp(){
+    gain * 2.0
+}";
+    let ctx = mimium_lang::compiler::Context::new([], None);
+    let list = ctx.emit_types(src, true).unwrap().to_string();
+    let mut lines = list.lines();
+    assert_eq!(lines.next(), Some("id: forall 'a. ('a)->'a [state: none]"));
+    assert_eq!(lines.next(), Some("gain: number"));
+    // the functions defined by `fn` are not generalized.
+    assert_eq!(lines.next(), Some("ident: ('a)->'a [state: none]"));
+    assert_eq!(lines.next(), Some("dsp: ()->number [state: none]"));
+    assert_eq!(lines.next(), Some(""));
+    assert!(list.contains("5:5\tgain * 2.0\t: number"), "{list}");
+}
+
+#[test]
+fn type_variable_names() {
+    let src = "fn id(x){ x }
+fn dsp(){
+    let h = id
+    h(1.0, 2.0)
+}";
+    let errs = run_source_test(src, 1, false, None).unwrap_err();
+    let msg = errs[0].to_string();
+    assert!(msg.contains("('a)->'a") && msg.contains("->'b"), "{msg}");
+    assert_eq!(errs[0].get_notes().len(), 1);
+}
+
+#[test]
+fn type_nominal() {
+    let res = run_file_test_mono("type_nominal.mmm", 1).unwrap();
+    let ans = vec![6.5];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn type_nominal_mismatch() {
+    let res = run_file_test_mono("type_nominal_mismatch.mmm", 1);
+    assert!(res.is_err());
+}
+
+#[test]
+fn sum_type() {
+    let res = run_file_test_mono("sum_type.mmm", 1).unwrap();
+    let ans = vec![4221.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn early_return() {
+    let res = run_file_test_mono("return.mmm", 1).unwrap();
+    let ans = vec![41.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn early_return_state() {
+    let res = run_file_test_mono("return_state.mmm", 5).unwrap();
+    let ans = vec![0.0, 1022.0, 2043.0, 3063.0, 83.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn early_return_mismatch() {
+    let res = run_file_test_mono("return_mismatch.mmm", 1);
+    assert!(res.is_err());
+}
+
+#[test]
+fn parse_error_multiple() {
+    let (file, src) = load_src("parse_error_multiple.mmm");
+    let path = file.to_string_lossy().to_symbol();
+    let errs = run_source_test(&src, 1, false, Some(path)).unwrap_err();
+    // every syntax error is reported at once, followed by the type error in the parsed part.
+    assert_eq!(errs.len(), 5, "{}", dump_to_string(&errs));
+    let span = errs[4].get_span();
+    assert_eq!(&src[span.range], "out", "{}", errs[4]);
+}
+
+#[test]
+fn macro_filterbank() {
+    let res = run_file_test_mono("macro.mmm", 1).unwrap();
+    let ans = vec![700.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn macro_non_code() {
+    let res = run_file_test_mono("macro_non_code.mmm", 1);
+    assert!(res.is_err());
+}
+
+#[test]
+fn module() {
+    let res = run_file_test_mono("module.mmm", 1).unwrap();
+    let ans = vec![602.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn module_private() {
+    let src = "mod filter {
+    fn gain() {
+        2.0
+    }
+}
+fn dsp() {
+    filter::gain()
+}";
+    let errs = run_source_test(src, 1, false, None).unwrap_err();
+    assert!(errs[0].to_string().contains("private"), "{}", errs[0]);
+}
+
+#[test]
+fn sum_type_non_exhaustive() {
+    let src = "type Wave = Sine | Saw(float) | Pulse(float, float)
+fn dsp() {
+    match Saw(1.0) {
+        Sine => 0.0,
+        Saw(a) => a,
+    }
+}";
+    let errs = run_source_test(src, 1, true, None).unwrap_err();
+    assert!(errs[0].to_string().contains("Pulse"), "{}", errs[0]);
+}
+
+#[test]
+fn sum_type_duplicate() {
+    let src = "type Wave = Sine | Saw(float)
+type Wave = Pulse(float)
+fn dsp() {
+    0.0
+}";
+    let errs = run_source_test(src, 1, false, None).unwrap_err();
+    assert!(errs[0].to_string().contains("Wave"), "{}", errs[0]);
+    let labels = errs[0].get_secondary_labels();
+    assert_eq!(&src[labels[0].0.range.clone()], "Sine | Saw(float)");
+    assert_eq!(&src[errs[0].get_span().range], "Pulse(float)");
+}
+
+#[test]
+fn sum_type_duplicate_variant() {
+    let src = "type Wave = Sine | Saw(float)
+type Shape = Saw(float) | Square
+fn dsp() {
+    0.0
+}";
+    let errs = run_source_test(src, 1, false, None).unwrap_err();
+    let msg = errs[0].to_string();
+    assert!(msg.contains("Saw") && msg.contains("Wave"), "{msg}");
+    let labels = errs[0].get_secondary_labels();
+    assert_eq!(&src[labels[0].0.range.clone()], "Sine | Saw(float)");
+    assert_eq!(&src[errs[0].get_span().range], "Saw(float) | Square");
+}
+
+#[test]
+fn inline() {
+    let res = run_file_test_mono("inline.mmm", 5).unwrap();
+    let ans = vec![-1.0, 2.0, 5.0, 8.0, 10.0];
+    assert_eq!(res, ans);
+}
+
+// the compiler recurses as deep as the nested `let`s, beyond the stack of the test thread.
+fn run_large_source(src: String) -> Result<Vec<f64>, String> {
+    let run = move || run_source_test(&src, 1, false, None).map_err(|errs| errs[0].to_string());
+    let thread = std::thread::Builder::new().stack_size(64 << 20);
+    thread.spawn(run).unwrap().join().unwrap()
+}
+
+#[test]
+fn many_variables() {
+    // the variables alive at once exceed the registers, so some of them are spilled.
+    let lets = (0..300)
+        .map(|i| format!("    let a{i} = x + {i}.0\n"))
+        .collect::<String>();
+    let sum = (0..300).map(|i| format!("a{i}")).collect::<Vec<_>>();
+    let src = format!(
+        "#[noinline]\nfn f(x){{\n{lets}    {}\n}}\nfn dsp(){{\n    f(1.0)\n}}",
+        sum.join(" + ")
+    );
+    let res = run_large_source(src).unwrap();
+    assert_eq!(res, vec![45150.0]);
+}
+
+#[test]
+fn many_temporaries() {
+    // every left operand of the nested additions is alive until the innermost one.
+    let sum = (0..300).fold("x".to_string(), |acc, i| format!("(x + {i}.0) + ({acc})"));
+    let src = format!("#[noinline]\nfn f(x){{\n    {sum}\n}}\nfn dsp(){{\n    f(1.0)\n}}");
+    let res = run_large_source(src).unwrap();
+    assert_eq!(res, vec![45151.0]);
+}
+
+#[test]
+fn many_globals() {
+    let lets = (0..300)
+        .map(|i| format!("let g{i} = id({i}.0)\n"))
+        .collect::<String>();
+    let src = format!("fn id(x){{\n    x\n}}\n{lets}fn dsp(){{\n    g0 + g150 + g299\n}}");
+    let res = run_large_source(src).unwrap();
+    assert_eq!(res, vec![449.0]);
+}
+
+#[test]
+fn many_tuple_variables() {
+    // the tuples are spilled with their elements, which are read through `getelement`.
+    let lets = (0..150)
+        .map(|i| format!("    let t{i} = ({i}.0, 2.0)\n"))
+        .collect::<String>();
+    let assigns = (0..150)
+        .map(|i| format!("    t{i} = (1.0, {i}.0)\n"))
+        .collect::<String>();
+    let patterns = (0..150)
+        .map(|i| format!("    let (a{i}, b{i}) = t{i}\n"))
+        .collect::<String>();
+    let sum = (0..150).map(|i| format!("b{i}")).collect::<Vec<_>>();
+    let src = format!(
+        "fn dsp(){{\n{lets}{assigns}{patterns}    {}\n}}",
+        sum.join(" + ")
+    );
+    let res = run_large_source(src).unwrap();
+    assert_eq!(res, vec![11175.0]);
+}
+
+#[test]
+fn too_many_registers() {
+    // the tuples are copied through the registers as whole values.
+    let elems = (0..150)
+        .map(|i| format!("{i}.0"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let names = |p: &str| {
+        (0..150)
+            .map(|i| format!("{p}{i}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let (a, b) = (names("a"), names("b"));
+    let src = format!(
+        "fn dsp(){{\n    let t1 = ({elems})\n    let t2 = ({elems})\n    let ({a}) = t1\n    let ({b}) = t2\n    a1 + b2\n}}"
+    );
+    let err = run_large_source(src).unwrap_err();
+    assert!(err.contains("registers"), "{err}");
+}
+
+#[test]
+fn bytecode_file() {
+    let (file, src) = load_src("counter.mmm");
+    let path = Some(file.to_string_lossy().to_symbol());
+    let mut ctx = mimium_lang::ExecContext::new([].into_iter(), path);
+    ctx.prepare_machine(&src).unwrap();
+    let bytes = ctx.vm.unwrap().prog.to_bytes();
+    let prog = mimium_lang::runtime::vm::Program::from_bytes(&bytes).unwrap();
+    let res = run_bytecode_test_multiple(prog, 10, false).unwrap();
+    let ans = run_file_test_mono("counter.mmm", 10).unwrap();
+    assert_eq!(res, ans);
+}
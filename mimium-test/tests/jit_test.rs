@@ -0,0 +1,62 @@
+//! Runs the test files with the JIT compiler, comparing the outputs with the VM sample by sample.
+//! Run with `cargo test --features jit`.
+#![cfg(feature = "jit")]
+
+use mimium_test::*;
+
+#[test]
+fn jit_counter() {
+    let res = run_file_jit_test("counter.mmm", 10, false).unwrap();
+    let ans = vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn jit_delay() {
+    let res = run_file_jit_test("delay.mmm", 10, false).unwrap();
+    let ans = vec![0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 3.0, 4.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn jit_fb_mem() {
+    let res = run_file_jit_test("fb_mem.mmm", 10, true).unwrap();
+    let ans = vec![
+        0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 2.0, 1.0, 3.0, 2.0, 4.0, 3.0, 5.0, 4.0, 6.0, 5.0, 7.0, 6.0,
+        8.0, 7.0,
+    ];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn jit_array() {
+    let res = run_file_jit_test("array.mmm", 6, false).unwrap();
+    let ans = vec![1.0, 2.0, 3.0, 4.0, 1.0, 2.0];
+    assert_eq!(res, ans);
+}
+
+#[test]
+fn jit_matches_vm() {
+    let mono = [
+        "closure_counter.mmm",
+        "closure_escape_3nested.mmm",
+        "delay2.mmm",
+        "hof_state.mmm",
+        "if_state.mmm",
+        "int.mmm",
+        "loopcounter.mmm",
+        "mem.mmm",
+        "primitive_sin.mmm",
+        "record_state.mmm",
+        "recursion.mmm",
+        "return_state.mmm",
+        "statefn2.mmm",
+        "stateful_closure.mmm",
+    ];
+    for file in mono {
+        run_file_jit_test(file, 20, false).unwrap();
+    }
+    for file in ["fb_mem2.mmm", "fb_mem3.mmm", "simple_stereo.mmm"] {
+        run_file_jit_test(file, 20, true).unwrap();
+    }
+}
@@ -0,0 +1,35 @@
+//! Runs the Rust modules exported from the test files by `build.rs`, comparing the outputs with
+//! the VM driven by `LocalBufferDriver` sample by sample.
+
+use mimium_test::*;
+
+macro_rules! rustgen_test {
+    ($name:ident, $times:expr) => {
+        // The tests use only a part of the exported items.
+        #[allow(dead_code)]
+        mod $name {
+            include!(concat!(env!("OUT_DIR"), "/", stringify!($name), ".rs"));
+        }
+
+        #[test]
+        fn $name() {
+            let mut patch = $name::Patch::new(()).unwrap();
+            let res = (0..$times)
+                .flat_map(|_| patch.process().unwrap())
+                .collect::<Vec<f64>>();
+            let file = concat!(stringify!($name), ".mmm");
+            let ans = run_file_with_plugins(file, $times, [].into_iter(), false).unwrap();
+            assert_eq!(res, ans);
+        }
+    };
+}
+
+rustgen_test!(counter, 10);
+rustgen_test!(delay, 10);
+rustgen_test!(fb_mem, 10);
+rustgen_test!(array, 10);
+rustgen_test!(array_global, 10);
+rustgen_test!(int, 10);
+rustgen_test!(nested_if, 10);
+rustgen_test!(state_tuple, 10);
+rustgen_test!(return_state, 10);